@@ -0,0 +1,139 @@
+// Kernel Command Line for Raspberry Pi 5
+// Parses /chosen/bootargs from the device tree (or the built-in default)
+//
+// Recognised options:
+//   init=<path>            first program to run (default /bin/sh, the built-in shell)
+//   quiet                  only print warnings and errors while booting (loglevel=4)
+//   loglevel=<0-8>         print boot messages whose level is below this value
+//   klog=<spec>            kernel log filters, e.g. klog=info,ipc=off (see klog.rs)
+//   selftest=<n>           run the hardware self-test <n> times, 0 to skip it
+//   console=<dev>[,<baud>] console device; ttyAMA<N> selects the PL011 of
+//                          alias serial<N>, pl011,<addr> one by address
+//
+// Anything else (the firmware passes many Linux options) is ignored.
+
+use crate::warn;
+
+// Used when the device tree has no bootargs
+const DEFAULT_CMDLINE: &str = "console=ttyAMA0,115200 loglevel=7 selftest=1 init=/bin/sh";
+
+const DEFAULT_LOGLEVEL: u8 = 7;
+const QUIET_LOGLEVEL: u8 = 4;
+const MAX_LOGLEVEL: u8 = 8;
+
+pub struct KernelParams {
+    pub raw: &'static str,
+    pub init: &'static str,
+    pub quiet: bool,
+    pub loglevel: u8,
+    pub klog: &'static str,             // Filter spec for the kernel log
+    pub selftest_cycles: u32,
+    pub console: &'static str,          // Device name, e.g. "ttyAMA10" or "pl011"
+    pub console_addr: Option<u64>,      // MMIO address for console=pl011,<addr>
+    pub console_baud: Option<u32>,
+}
+
+impl KernelParams {
+    pub const fn new() -> Self {
+        Self {
+            raw: "",
+            init: "/bin/sh",
+            quiet: false,
+            loglevel: DEFAULT_LOGLEVEL,
+            klog: "",
+            selftest_cycles: 1,
+            console: "",
+            console_addr: None,
+            console_baud: None,
+        }
+    }
+
+    fn parse(&mut self, cmdline: &'static str) {
+        self.raw = cmdline;
+        let mut loglevel = None;
+
+        for arg in cmdline.split_whitespace() {
+            let (key, value) = match arg.find('=') {
+                Some(pos) => (&arg[..pos], Some(&arg[pos + 1..])),
+                None => (arg, None),
+            };
+
+            match (key, value) {
+                ("init", Some(path)) if !path.is_empty() => self.init = path,
+                ("quiet", None) => self.quiet = true,
+                ("loglevel", Some(level)) => match level.parse::<u8>() {
+                    Ok(level) if level <= MAX_LOGLEVEL => loglevel = Some(level),
+                    _ => warn_bad_value(arg),
+                },
+                ("klog", Some(spec)) => self.klog = spec,
+                ("selftest", Some(count)) => match count.parse::<u32>() {
+                    Ok(count) => self.selftest_cycles = count,
+                    Err(_) => warn_bad_value(arg),
+                },
+                // The last console= wins, as on Linux
+                ("console", Some(spec)) => self.parse_console(spec),
+                _ => {}
+            }
+        }
+
+        // An explicit loglevel= overrides quiet
+        self.loglevel = match loglevel {
+            Some(level) => level,
+            None if self.quiet => QUIET_LOGLEVEL,
+            None => DEFAULT_LOGLEVEL,
+        };
+    }
+
+    /// "ttyAMA10,115200n8" or "pl011,0x9000000,115200"
+    fn parse_console(&mut self, spec: &'static str) {
+        let mut fields = spec.split(',');
+        self.console = fields.next().unwrap_or("");
+        self.console_addr = None;
+        self.console_baud = None;
+
+        if self.console == "pl011" {
+            match fields.next().and_then(parse_number) {
+                Some(addr) => self.console_addr = Some(addr),
+                None => warn_bad_value(spec),
+            }
+        }
+
+        // Baud rate is the leading digits of "115200n8"
+        if let Some(options) = fields.next() {
+            let digits = options.find(|c: char| !c.is_ascii_digit()).unwrap_or(options.len());
+            self.console_baud = options[..digits].parse().ok();
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn warn_bad_value(arg: &str) {
+    warn!("cmdline: ignoring bad option '{}'", arg);
+}
+
+// Global kernel parameters
+static mut PARAMS: KernelParams = KernelParams::new();
+
+/// Parse `bootargs`, or the built-in default command line if it is empty
+pub fn init(bootargs: &'static str) {
+    let cmdline = if bootargs.trim().is_empty() { DEFAULT_CMDLINE } else { bootargs };
+    unsafe {
+        PARAMS = KernelParams::new();
+        PARAMS.parse(cmdline);
+    }
+}
+
+pub fn params() -> &'static KernelParams {
+    unsafe { &*core::ptr::addr_of!(PARAMS) }
+}
+
+/// Full command line, for /proc/cmdline
+pub fn raw() -> &'static str {
+    params().raw
+}
@@ -0,0 +1,699 @@
+// Flattened Device Tree (DTB) parser for Raspberry Pi 5
+// Reads the blob whose address the firmware (or QEMU) passes in x0 at boot
+//
+// Only what the kernel needs is extracted: RAM regions, reserved memory,
+// the console UART, the GIC, the system timer, the RP1 GPIO block,
+// /chosen/bootargs and the initramfs the firmware loaded (linux,initrd-start
+// and -end, which may be one or two cells wide). Bus addresses are translated into CPU physical addresses
+// through the `ranges` property of every parent bus.
+//
+// All multi-byte values in the blob are big-endian and only 4-byte aligned,
+// so everything is read byte-wise (the MMU may still be off at this point).
+
+use crate::smp::MAX_CPUS;
+use crate::info;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_MAX_SIZE: u32 = 0x20_0000;  // Sanity limit for a blob (2MB)
+
+// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+// QEMU's virt machine leaves x0 at zero for bare-metal (ELF) kernels and
+// puts the blob at the start of RAM instead
+const QEMU_VIRT_DTB_ADDR: u64 = 0x4000_0000;
+
+const MAX_DEPTH: usize = 16;
+const MAX_MEMORY_REGIONS: usize = 8;
+const MAX_RESERVED_REGIONS: usize = 16;
+const MAX_MMIO_REGIONS: usize = 8;
+
+/// A node found while walking the tree
+#[derive(Clone, Copy)]
+pub struct Node {
+    pub name: &'static str,
+    props: usize,           // Offset of the first token after the node name
+}
+
+pub struct Fdt {
+    data: &'static [u8],
+    struct_off: usize,
+    strings_off: usize,
+    rsvmap_off: usize,
+}
+
+impl Fdt {
+    /// Validate the header of the blob at `addr`
+    pub unsafe fn from_addr(addr: u64) -> Result<Self, &'static str> {
+        if addr == 0 || !addr.is_multiple_of(8) {
+            return Err("No device tree passed by the bootloader");
+        }
+
+        let header = core::slice::from_raw_parts(addr as *const u8, FDT_HEADER_SIZE);
+        let field = |off| be32(header, off).unwrap_or(0);
+        if field(0) != FDT_MAGIC {
+            return Err("Bad device tree magic");
+        }
+
+        let total_size = field(4);
+        if (total_size as usize) < FDT_HEADER_SIZE || total_size > FDT_MAX_SIZE {
+            return Err("Bad device tree size");
+        }
+        // Version 17 blobs (compatible back to 16) are all that bootloaders produce
+        if field(24) < 16 {
+            return Err("Unsupported device tree version");
+        }
+        let (struct_off, strings_off, rsvmap_off) = (field(8), field(12), field(16));
+        if struct_off >= total_size || strings_off > total_size || rsvmap_off > total_size {
+            return Err("Bad device tree layout");
+        }
+
+        let data = core::slice::from_raw_parts(addr as *const u8, total_size as usize);
+        Ok(Self {
+            data,
+            struct_off: struct_off as usize,
+            strings_off: strings_off as usize,
+            rsvmap_off: rsvmap_off as usize,
+        })
+    }
+
+    pub fn base(&self) -> u64 {
+        self.data.as_ptr() as u64
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Entries of the /memreserve/ block
+    pub fn for_each_reserved(&self, mut f: impl FnMut(u64, u64)) {
+        let mut off = self.rsvmap_off;
+        while let (Some(addr), Some(size)) = (be64(self.data, off), be64(self.data, off + 8)) {
+            if addr == 0 && size == 0 {
+                break;
+            }
+            f(addr, size);
+            off += 16;
+        }
+    }
+
+    /// NUL-terminated string starting at `off`
+    fn str_at(&self, off: usize) -> &'static str {
+        let bytes = &self.data[off.min(self.data.len())..];
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        core::str::from_utf8(&bytes[..len]).unwrap_or("")
+    }
+
+    /// Token at `off`; None past the end of the blob
+    fn token(&self, off: usize) -> Option<u32> {
+        be32(self.data, off)
+    }
+
+    /// Visit every node; `f` receives the path from the root to the current node.
+    /// A structure block that runs off the end of the blob is an error.
+    pub fn walk(&self, mut f: impl FnMut(&Fdt, &[Node])) -> Result<(), &'static str> {
+        let mut stack = [Node { name: "", props: 0 }; MAX_DEPTH];
+        let mut depth = 0;
+        let mut off = self.struct_off;
+
+        loop {
+            match self.token(off).ok_or("Truncated device tree")? {
+                FDT_BEGIN_NODE => {
+                    if depth == MAX_DEPTH {
+                        return Err("Device tree nested too deeply");
+                    }
+                    let name = self.str_at(off + 4);
+                    off = align4(off + 4 + name.len() + 1);
+                    stack[depth] = Node { name, props: off };
+                    depth += 1;
+                    f(self, &stack[..depth]);
+                }
+                FDT_END_NODE => {
+                    if depth == 0 {
+                        return Err("Unbalanced device tree nodes");
+                    }
+                    depth -= 1;
+                    off += 4;
+                }
+                FDT_PROP => {
+                    let len = be32(self.data, off + 4).ok_or("Truncated device tree")? as usize;
+                    let end = (off + 12).checked_add(len)
+                        .filter(|&end| end <= self.data.len())
+                        .ok_or("Truncated device tree")?;
+                    off = align4(end);
+                }
+                FDT_NOP => off += 4,
+                FDT_END => return Ok(()),
+                _ => return Err("Bad device tree token"),
+            }
+        }
+    }
+
+    /// Raw value of property `name` of `node`
+    pub fn property(&self, node: &Node, name: &str) -> Option<&'static [u8]> {
+        let mut off = node.props;
+        loop {
+            match self.token(off)? {
+                FDT_PROP => {
+                    let len = be32(self.data, off + 4)? as usize;
+                    let name_off = be32(self.data, off + 8)? as usize;
+                    let value_off = off + 12;
+                    if value_off.checked_add(len)? > self.data.len() {
+                        return None;
+                    }
+                    if self.str_at(self.strings_off + name_off) == name {
+                        return Some(&self.data[value_off..value_off + len]);
+                    }
+                    off = align4(value_off + len);
+                }
+                FDT_NOP => off += 4,
+                _ => return None,
+            }
+        }
+    }
+
+    /// String property without its terminating NUL
+    pub fn property_str(&self, node: &Node, name: &str) -> Option<&'static str> {
+        let value = self.property(node, name)?;
+        let len = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        core::str::from_utf8(&value[..len]).ok()
+    }
+
+    fn cells(&self, node: &Node, name: &str, default: u32) -> u32 {
+        self.property(node, name)
+            .and_then(|value| be32(value, 0))
+            .unwrap_or(default)
+    }
+
+    /// Does any entry of the node's `compatible` list equal `compat`?
+    pub fn is_compatible(&self, node: &Node, compat: &str) -> bool {
+        match self.property(node, "compatible") {
+            Some(value) => value
+                .split(|&b| b == 0)
+                .any(|entry| entry == compat.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Nodes without a status property, or with "okay", are in use
+    pub fn is_enabled(&self, node: &Node) -> bool {
+        match self.property_str(node, "status") {
+            Some(status) => status == "okay" || status == "ok",
+            None => true,
+        }
+    }
+
+    /// Entry `index` of the `reg` property of the last node in `path`,
+    /// translated into a CPU physical address
+    pub fn reg(&self, path: &[Node], index: usize) -> Option<(u64, u64)> {
+        let (addr, size) = self.reg_raw(path, index)?;
+        let addr = self.translate(&path[..path.len() - 1], addr)?;
+        Some((addr, size))
+    }
+
+    /// Entry `index` of the `reg` property as written, without bus translation
+    pub fn reg_raw(&self, path: &[Node], index: usize) -> Option<(u64, u64)> {
+        if path.len() < 2 {
+            return None;
+        }
+        let node = &path[path.len() - 1];
+        let parent = &path[path.len() - 2];
+        let address_cells = self.cells(parent, "#address-cells", 2) as usize;
+        let size_cells = self.cells(parent, "#size-cells", 1) as usize;
+
+        let reg = self.property(node, "reg")?;
+        let entry_size = (address_cells + size_cells) * 4;
+        let off = index * entry_size;
+        if entry_size == 0 || off + entry_size > reg.len() {
+            return None;
+        }
+
+        let addr = read_cells(reg, off, address_cells)?;
+        let size = read_cells(reg, off + address_cells * 4, size_cells)?;
+        Some((addr, size))
+    }
+
+    /// Translate a bus address of a child of `path`'s last node up to the root
+    fn translate(&self, path: &[Node], mut addr: u64) -> Option<u64> {
+        for level in (1..path.len()).rev() {
+            let bus = &path[level];
+            let parent = &path[level - 1];
+
+            // No ranges: the bus is not memory mapped. Empty ranges: identity.
+            let ranges = self.property(bus, "ranges")?;
+            if ranges.is_empty() {
+                continue;
+            }
+
+            let child_cells = self.cells(bus, "#address-cells", 2) as usize;
+            let parent_cells = self.cells(parent, "#address-cells", 2) as usize;
+            let size_cells = self.cells(bus, "#size-cells", 1) as usize;
+            let entry_size = (child_cells + parent_cells + size_cells) * 4;
+
+            let mut translated = None;
+            let mut off = 0;
+            while entry_size != 0 && off + entry_size <= ranges.len() {
+                let child = read_cells(ranges, off, child_cells)?;
+                let parent_addr = read_cells(ranges, off + child_cells * 4, parent_cells)?;
+                let size = read_cells(ranges, off + (child_cells + parent_cells) * 4, size_cells)?;
+                if addr >= child && addr - child < size {
+                    translated = Some(parent_addr + (addr - child));
+                    break;
+                }
+                off += entry_size;
+            }
+            addr = translated?;
+        }
+        Some(addr)
+    }
+
+    /// GIC interrupt ID of entry `index` of a node's `interrupts`, for the usual
+    /// three-cell GIC specifier (type, number, flags); None for other controllers
+    pub fn gic_interrupt(&self, node: &Node, index: usize) -> Option<u32> {
+        let interrupts = self.property(node, "interrupts")?;
+        if interrupts.len() % 12 != 0 || (index + 1) * 12 > interrupts.len() {
+            return None;
+        }
+        let kind = be32(interrupts, index * 12)?;
+        let number = be32(interrupts, index * 12 + 4)?;
+        match kind {
+            0 => Some(number + 32),  // SPI
+            1 => Some(number + 16),  // PPI
+            _ => None,
+        }
+    }
+
+    /// Offset from a CPU physical address to the address DMA masters on the
+    /// bus `path` use, from the bus's first `dma-ranges` entry (0 if none)
+    pub fn dma_offset(&self, path: &[Node]) -> u64 {
+        if path.len() < 2 {
+            return 0;
+        }
+        let bus = &path[path.len() - 1];
+        let parent = &path[path.len() - 2];
+        let ranges = match self.property(bus, "dma-ranges") {
+            Some(ranges) => ranges,
+            None => return 0,
+        };
+
+        let child_cells = self.cells(bus, "#address-cells", 2) as usize;
+        let parent_cells = self.cells(parent, "#address-cells", 2) as usize;
+        let size_cells = self.cells(bus, "#size-cells", 1) as usize;
+        if ranges.len() < (child_cells + parent_cells + size_cells) * 4 {
+            return 0;
+        }
+        match (read_cells(ranges, 0, child_cells), read_cells(ranges, child_cells * 4, parent_cells)) {
+            (Some(child), Some(parent_addr)) => child.wrapping_sub(parent_addr),
+            _ => 0,
+        }
+    }
+}
+
+/// Big-endian word at `off`; None if the data is too short
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn be64(data: &[u8], off: usize) -> Option<u64> {
+    Some(((be32(data, off)? as u64) << 32) | be32(data, off + 4)? as u64)
+}
+
+/// Read a `cells`-wide number; only the low 64 bits are kept (PCI addresses
+/// carry a flags cell on top)
+fn read_cells(data: &[u8], off: usize, cells: usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..cells {
+        value = (value << 32) | be32(data, off + i * 4)? as u64;
+    }
+    Some(value)
+}
+
+/// linux,initrd-start/-end: a single u32 or u64, whatever #address-cells says
+fn initrd_address(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        4 => be32(value, 0).map(u64::from),
+        8 => be64(value, 0),
+        _ => None,
+    }
+}
+
+fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// Does `path` (root first) name the node at `target`, e.g. "/soc/serial@7d001000"?
+/// A target component without a unit address matches any unit address.
+fn path_matches(path: &[Node], target: &str) -> bool {
+    let mut components = target.split('/').filter(|c| !c.is_empty());
+    for node in &path[1..] {
+        let component = match components.next() {
+            Some(c) => c,
+            None => return false,
+        };
+        let matches = node.name == component
+            || (!component.contains('@') && node.name.split('@').next() == Some(component));
+        if !matches {
+            return false;
+        }
+    }
+    components.next().is_none()
+}
+
+/// Hardware description extracted from the device tree
+pub struct PlatformInfo {
+    pub model: &'static str,
+    pub bootargs: &'static str,
+    pub initrd: Option<(u64, u64)>,    // Initramfs loaded by the firmware (start, end)
+    memory: [(u64, u64); MAX_MEMORY_REGIONS],
+    memory_count: usize,
+    reserved: [(u64, u64); MAX_RESERVED_REGIONS],
+    reserved_count: usize,
+    mmio: [(u64, u64); MAX_MMIO_REGIONS],
+    mmio_count: usize,
+    pub uart_base: Option<u64>,
+    pub uart_irq: Option<u32>,
+    pub uart_clock: Option<u32>,       // PL011 reference clock (Hz)
+    pub gic_dist_base: Option<u64>,
+    pub gic_cpu_base: Option<u64>,
+    pub gic_redist_base: Option<u64>,  // GICv3 redistributors (first region)
+    pub gic_redist_stride: u64,        // 0: as GICR_TYPER says
+    pub timer_base: Option<u64>,
+    pub gpio_base: Option<u64>,
+    pub rtc_base: Option<u64>,       // PL031 (QEMU virt)
+    pub mailbox_base: Option<u64>,   // VideoCore firmware mailbox
+    pub mailbox_bus_offset: u64,     // Added to a physical address to get the mailbox's view
+    pub firmware_rtc: bool,          // RTC behind the firmware (Pi 5)
+    pub psci_method: &'static str,   // "smc" or "hvc"
+    cpus: [u64; MAX_CPUS],           // MPIDR affinity of every core
+    cpu_count: usize,
+}
+
+impl PlatformInfo {
+    pub const fn new() -> Self {
+        Self {
+            model: "",
+            bootargs: "",
+            initrd: None,
+            memory: [(0, 0); MAX_MEMORY_REGIONS],
+            memory_count: 0,
+            reserved: [(0, 0); MAX_RESERVED_REGIONS],
+            reserved_count: 0,
+            mmio: [(0, 0); MAX_MMIO_REGIONS],
+            mmio_count: 0,
+            uart_base: None,
+            uart_irq: None,
+            uart_clock: None,
+            gic_dist_base: None,
+            gic_cpu_base: None,
+            gic_redist_base: None,
+            gic_redist_stride: 0,
+            timer_base: None,
+            gpio_base: None,
+            rtc_base: None,
+            mailbox_base: None,
+            mailbox_bus_offset: 0,
+            firmware_rtc: false,
+            psci_method: "",
+            cpus: [0; MAX_CPUS],
+            cpu_count: 0,
+        }
+    }
+
+    fn add_memory(&mut self, base: u64, size: u64) {
+        if size != 0 && self.memory_count < MAX_MEMORY_REGIONS {
+            self.memory[self.memory_count] = (base, size);
+            self.memory_count += 1;
+        }
+    }
+
+    fn add_reserved(&mut self, base: u64, size: u64) {
+        if size != 0 && self.reserved_count < MAX_RESERVED_REGIONS {
+            self.reserved[self.reserved_count] = (base, size);
+            self.reserved_count += 1;
+        }
+    }
+
+    fn add_mmio(&mut self, base: u64, size: u64) {
+        if size != 0 && self.mmio_count < MAX_MMIO_REGIONS {
+            self.mmio[self.mmio_count] = (base, size);
+            self.mmio_count += 1;
+        }
+    }
+
+    fn parse(&mut self, fdt: &Fdt) -> Result<(), &'static str> {
+        // The blob itself and the /memreserve/ entries must survive
+        self.add_reserved(fdt.base(), fdt.total_size());
+        fdt.for_each_reserved(|addr, size| self.add_reserved(addr, size));
+
+        // First pass: root, /chosen and /aliases, needed to pick the console
+        let mut stdout_path = "";
+        let mut aliases = None;
+        fdt.walk(|fdt, path| match path.len() {
+            1 => self.model = fdt.property_str(&path[0], "model").unwrap_or(""),
+            2 if path[1].name == "chosen" => {
+                self.bootargs = fdt.property_str(&path[1], "bootargs").unwrap_or("");
+                let initrd_start = fdt.property(&path[1], "linux,initrd-start").and_then(initrd_address);
+                let initrd_end = fdt.property(&path[1], "linux,initrd-end").and_then(initrd_address);
+                if let (Some(start), Some(end)) = (initrd_start, initrd_end) {
+                    if end > start {
+                        self.initrd = Some((start, end));
+                        // Keep the frame allocator away from the archive
+                        self.add_reserved(start, end - start);
+                    }
+                }
+                stdout_path = fdt
+                    .property_str(&path[1], "stdout-path")
+                    .or_else(|| fdt.property_str(&path[1], "linux,stdout-path"))
+                    .unwrap_or("");
+            }
+            2 if path[1].name == "aliases" => aliases = Some(path[1]),
+            _ => {}
+        })?;
+
+        // "serial0:115200n8" -> alias "serial0" -> "/soc/serial@7d001000"
+        let mut stdout_path = stdout_path.split(':').next().unwrap_or("");
+        if !stdout_path.is_empty() && !stdout_path.starts_with('/') {
+            stdout_path = aliases
+                .and_then(|node| fdt.property_str(&node, stdout_path))
+                .unwrap_or("");
+        }
+
+        // Second pass: memory and devices
+        let mut uart_clock_phandle = None;
+        fdt.walk(|fdt, path| {
+            let node = &path[path.len() - 1];
+            if !fdt.is_enabled(node) {
+                return;
+            }
+
+            let is_memory = fdt.property_str(node, "device_type") == Some("memory");
+            if path.len() == 2 && is_memory {
+                let mut index = 0;
+                while let Some((base, size)) = fdt.reg(path, index) {
+                    self.add_memory(base, size);
+                    index += 1;
+                }
+            } else if path.len() == 3 && path[1].name == "cpus"
+                && fdt.property_str(node, "device_type") == Some("cpu")
+            {
+                // /cpus has no ranges: reg is the MPIDR affinity, not an address
+                if let Some((mpidr, _)) = fdt.reg_raw(path, 0) {
+                    if self.cpu_count < MAX_CPUS {
+                        self.cpus[self.cpu_count] = mpidr;
+                        self.cpu_count += 1;
+                    }
+                }
+            } else if path.len() == 2 && node.name.starts_with("psci") {
+                self.psci_method = fdt.property_str(node, "method").unwrap_or("");
+            } else if path.len() == 3 && path[1].name == "reserved-memory" {
+                if let Some((base, size)) = fdt.reg(path, 0) {
+                    self.add_reserved(base, size);
+                }
+            } else if fdt.is_compatible(node, "arm,pl011") {
+                // Prefer the console named by stdout-path, else the first PL011
+                let is_console = !stdout_path.is_empty() && path_matches(path, stdout_path);
+                if is_console || self.uart_base.is_none() {
+                    if let Some((base, size)) = fdt.reg(path, 0) {
+                        self.uart_base = Some(base);
+                        self.uart_irq = fdt.gic_interrupt(node, 0);
+                        // "uartclk" comes first in clocks, before "apb_pclk"
+                        self.uart_clock = fdt.property(node, "clock-frequency")
+                            .and_then(|value| be32(value, 0));
+                        uart_clock_phandle = fdt.property(node, "clocks")
+                            .and_then(|value| be32(value, 0));
+                        self.add_mmio(base, size);
+                    }
+                }
+            } else if fdt.is_compatible(node, "arm,gic-400")
+                || fdt.is_compatible(node, "arm,cortex-a15-gic")
+            {
+                if let (Some(dist), Some(cpu)) = (fdt.reg(path, 0), fdt.reg(path, 1)) {
+                    self.gic_dist_base = Some(dist.0);
+                    self.gic_cpu_base = Some(cpu.0);
+                    self.add_mmio(dist.0, dist.1);
+                    self.add_mmio(cpu.0, cpu.1);
+                }
+            } else if fdt.is_compatible(node, "arm,gic-v3") {
+                // Only the first redistributor region is used
+                if let (Some(dist), Some(redist)) = (fdt.reg(path, 0), fdt.reg(path, 1)) {
+                    self.gic_dist_base = Some(dist.0);
+                    self.gic_redist_base = Some(redist.0);
+                    self.gic_redist_stride = fdt.property(node, "redistributor-stride")
+                        .and_then(|stride| read_cells(stride, 0, stride.len() / 4))
+                        .unwrap_or(0);
+                    self.add_mmio(dist.0, dist.1);
+                    self.add_mmio(redist.0, redist.1);
+                }
+            } else if fdt.is_compatible(node, "brcm,bcm2835-system-timer") {
+                if let Some((base, size)) = fdt.reg(path, 0) {
+                    self.timer_base = Some(base);
+                    self.add_mmio(base, size);
+                }
+            } else if fdt.is_compatible(node, "raspberrypi,rp1-gpio") {
+                if let Some((base, size)) = fdt.reg(path, 0) {
+                    self.gpio_base = Some(base);
+                    self.add_mmio(base, size);
+                }
+            } else if fdt.is_compatible(node, "arm,pl031") {
+                if let Some((base, size)) = fdt.reg(path, 0) {
+                    self.rtc_base = Some(base);
+                    self.add_mmio(base, size);
+                }
+            } else if fdt.is_compatible(node, "brcm,bcm2835-mbox") {
+                if let Some((base, size)) = fdt.reg(path, 0) {
+                    self.mailbox_base = Some(base);
+                    self.mailbox_bus_offset = fdt.dma_offset(&path[..path.len() - 1]);
+                    self.add_mmio(base, size);
+                }
+            } else if fdt.is_compatible(node, "raspberrypi,rpi-rtc") {
+                self.firmware_rtc = true;
+            }
+        })?;
+
+        // Third pass: the clock the console UART refers to (a fixed-clock)
+        if let (None, Some(phandle)) = (self.uart_clock, uart_clock_phandle) {
+            fdt.walk(|fdt, path| {
+                let node = &path[path.len() - 1];
+                let matches = fdt.property(node, "phandle")
+                    .or_else(|| fdt.property(node, "linux,phandle"))
+                    .and_then(|value| be32(value, 0)) == Some(phandle);
+                if matches {
+                    self.uart_clock = fdt.property(node, "clock-frequency")
+                        .and_then(|value| be32(value, 0));
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
+// Parsed platform description; stays empty when no valid DTB was passed
+static mut PLATFORM: PlatformInfo = PlatformInfo::new();
+static mut DTB_BASE: u64 = 0;
+
+/// Parse the device tree at `dtb_addr` (x0 at entry)
+pub fn init(dtb_addr: u64) -> Result<(), &'static str> {
+    let dtb_addr = if dtb_addr == 0 { QEMU_VIRT_DTB_ADDR } else { dtb_addr };
+    let fdt = unsafe { Fdt::from_addr(dtb_addr)? };
+
+    unsafe {
+        PLATFORM = PlatformInfo::new();
+        if let Err(e) = PLATFORM.parse(&fdt) {
+            // Nothing half-parsed: the built-in addresses apply
+            PLATFORM = PlatformInfo::new();
+            return Err(e);
+        }
+        DTB_BASE = dtb_addr;
+    }
+    Ok(())
+}
+
+/// Log what was found (after the command line is known, so quiet applies)
+pub fn report() {
+    let info = platform();
+    info!("DTB: {} at {:#x}, {} memory region(s)",
+          if info.model.is_empty() { "unknown board" } else { info.model },
+          unsafe { DTB_BASE }, info.memory_regions().len());
+}
+
+impl PlatformInfo {
+    /// RAM as (base, size); empty without a device tree
+    pub fn memory_regions(&self) -> &[(u64, u64)] {
+        &self.memory[..self.memory_count]
+    }
+
+    /// Memory that must not be handed out: the blob, /memreserve/, /reserved-memory, the initramfs
+    pub fn reserved_regions(&self) -> &[(u64, u64)] {
+        &self.reserved[..self.reserved_count]
+    }
+
+    /// MPIDR affinity values of the cores listed under /cpus
+    pub fn cpu_mpidrs(&self) -> &[u64] {
+        &self.cpus[..self.cpu_count]
+    }
+
+    /// Register windows of the devices found, for the MMU device mappings
+    pub fn mmio_regions(&self) -> &[(u64, u64)] {
+        &self.mmio[..self.mmio_count]
+    }
+}
+
+pub fn platform() -> &'static PlatformInfo {
+    unsafe { &*core::ptr::addr_of!(PLATFORM) }
+}
+
+/// console=ttyAMA<N>: make the PL011 that alias "serial<N>" names the
+/// console, as Linux numbers them. Other device names keep the UART that
+/// stdout-path chose.
+pub fn select_console(name: &str) -> Result<(), &'static str> {
+    let Some(index) = name.strip_prefix("ttyAMA") else { return Ok(()) };
+    let index: u32 = index.parse().map_err(|_| "bad ttyAMA number")?;
+    if !is_present() {
+        return Err("no device tree to find it in");
+    }
+    let fdt = unsafe { Fdt::from_addr(DTB_BASE)? };
+
+    let mut alias: heapless::String<16> = heapless::String::new();
+    let _ = core::fmt::Write::write_fmt(&mut alias, format_args!("serial{}", index));
+    let mut target = None;
+    fdt.walk(|fdt, path| {
+        if path.len() == 2 && path[1].name == "aliases" {
+            target = fdt.property_str(&path[1], &alias);
+        }
+    })?;
+    let target = target.ok_or("no such serial alias")?;
+
+    let info = unsafe { &mut *core::ptr::addr_of_mut!(PLATFORM) };
+    let mut found = false;
+    fdt.walk(|fdt, path| {
+        let node = &path[path.len() - 1];
+        if found || !path_matches(path, target) || !fdt.is_compatible(node, "arm,pl011") || !fdt.is_enabled(node) {
+            return;
+        }
+        if let Some((base, size)) = fdt.reg(path, 0) {
+            info.uart_base = Some(base);
+            info.uart_irq = fdt.gic_interrupt(node, 0);
+            // The PL011s of one SoC share their reference clock
+            if let Some(hz) = fdt.property(node, "clock-frequency").and_then(|value| be32(value, 0)) {
+                info.uart_clock = Some(hz);
+            }
+            info.add_mmio(base, size);
+            found = true;
+        }
+    })?;
+    if found { Ok(()) } else { Err("serial alias is not a usable PL011") }
+}
+
+pub fn is_present() -> bool {
+    unsafe { DTB_BASE != 0 }
+}
+
+pub fn bootargs() -> &'static str {
+    platform().bootargs
+}
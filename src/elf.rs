@@ -0,0 +1,380 @@
+// ELF64 loader for user programs
+//
+// Loads a statically linked AArch64 executable into a fresh user address
+// space: every PT_LOAD segment gets its own zeroed frames, mapped read-only,
+// read-write or read-execute as its p_flags say (writable and executable at
+// once is refused, the kernel keeps W^X for user pages too). Executables
+// with an interpreter (PT_INTERP) are dynamically linked and refused. A
+// static musl program has the system calls its startup, stdio, fork and wait
+// make; glibc's startup also wants brk, mmap and uname, which are missing.
+//
+// User space starts at 512 GB (mmu::USER_SPACE_BASE), so an ET_EXEC must be
+// linked there (e.g. -Wl,-Ttext-segment=0x8000000000); a static PIE
+// (ET_DYN) is placed at ET_DYN_BASE wherever it was linked.
+//
+// The stack is laid out as the Linux ABI has it, from sp upwards:
+//   argc, argv[0..argc], NULL, envp[..], NULL, auxv pairs ending in AT_NULL,
+// then the AT_RANDOM bytes and the argument and environment strings.
+
+use alloc::vec::Vec;
+
+use crate::memory;
+use crate::mmu::{self, AddressSpace, MemoryKind, PAGE_SIZE};
+use crate::timer;
+use crate::users;
+
+// e_ident
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_AARCH64: u16 = 183;
+
+// Program header types and flags
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+// Auxiliary vector keys
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_FLAGS: u64 = 8;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_HWCAP: u64 = 16;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+const HWCAP_FP: u64 = 1 << 0;
+const HWCAP_ASIMD: u64 = 1 << 1;
+
+/// sysconf(_SC_CLK_TCK): the unit of times() and waitid's CPU times
+pub const CLK_TCK: u64 = 100;
+
+const MAX_PHDRS: usize = 64;
+
+/// Load address of a static PIE
+const ET_DYN_BASE: u64 = mmu::USER_SPACE_BASE + 0x4000_0000;
+
+/// The user stack ends one (unmapped) page below the top of user space
+pub const USER_STACK_TOP: u64 = mmu::USER_SPACE_END - PAGE_SIZE;
+const USER_STACK_SIZE: u64 = 256 * 1024;
+
+/// Room for the strings and pointers set up for main(); the rest of the stack is the program's
+pub const MAX_ARG_SIZE: usize = 64 * 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf64Ehdr {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf64Phdr {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+/// What execve puts on the new program's stack besides the auxiliary vector
+#[derive(Clone, Copy)]
+struct StackArgs<'a> {
+    argv: &'a [&'a [u8]],
+    envp: &'a [&'a [u8]],
+    execfn: &'a str,    // For AT_EXECFN
+}
+
+/// A program ready to run: eret to `entry` with `stack_pointer` in SP_EL0
+/// and `space` in TTBR0
+pub struct Program {
+    pub space: AddressSpace,
+    pub entry: u64,
+    pub stack_pointer: u64,
+}
+
+fn read_struct<T: Copy>(image: &[u8], offset: u64) -> Option<T> {
+    let offset = usize::try_from(offset).ok()?;
+    let end = offset.checked_add(core::mem::size_of::<T>())?;
+    let bytes = image.get(offset..end)?;
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+fn page_down(addr: u64) -> u64 {
+    addr & !(PAGE_SIZE - 1)
+}
+
+/// The ELF header, if this is an executable the kernel can run
+fn check_header(image: &[u8]) -> Result<Elf64Ehdr, i64> {
+    const ENOEXEC: i64 = -8; // ENOEXEC - Exec format error
+    let ehdr: Elf64Ehdr = read_struct(image, 0).ok_or(ENOEXEC)?;
+    let ident = &ehdr.e_ident;
+    if &ident[..4] != ELF_MAGIC
+        || ident[4] != ELFCLASS64
+        || ident[5] != ELFDATA2LSB
+        || ident[6] != EV_CURRENT
+    {
+        return Err(ENOEXEC);
+    }
+    if !matches!(ehdr.e_type, ET_EXEC | ET_DYN) || ehdr.e_machine != EM_AARCH64 {
+        return Err(ENOEXEC);
+    }
+    if ehdr.e_phentsize as usize != core::mem::size_of::<Elf64Phdr>()
+        || ehdr.e_phnum == 0
+        || ehdr.e_phnum as usize > MAX_PHDRS
+    {
+        return Err(ENOEXEC);
+    }
+    Ok(ehdr)
+}
+
+/// Program headers, with the PT_LOAD segments checked against the image and user space
+fn check_segments(image: &[u8], ehdr: &Elf64Ehdr, bias: u64) -> Result<Vec<Elf64Phdr>, i64> {
+    const ENOEXEC: i64 = -8; // ENOEXEC - Exec format error
+    let mut phdrs = Vec::with_capacity(ehdr.e_phnum as usize);
+    for i in 0..ehdr.e_phnum as u64 {
+        let offset = ehdr.e_phoff.checked_add(i * ehdr.e_phentsize as u64).ok_or(ENOEXEC)?;
+        phdrs.push(read_struct::<Elf64Phdr>(image, offset).ok_or(ENOEXEC)?);
+    }
+
+    let mut loadable = false;
+    for phdr in &phdrs {
+        match phdr.p_type {
+            PT_INTERP => return Err(ENOEXEC), // Dynamically linked
+            PT_LOAD => {}
+            _ => continue,
+        }
+        let file_end = phdr.p_offset.checked_add(phdr.p_filesz).ok_or(ENOEXEC)?;
+        let start = phdr.p_vaddr.checked_add(bias).ok_or(ENOEXEC)?;
+        let end = start.checked_add(phdr.p_memsz).ok_or(ENOEXEC)?;
+        if phdr.p_filesz > phdr.p_memsz
+            || file_end > image.len() as u64
+            || phdr.p_vaddr % PAGE_SIZE != phdr.p_offset % PAGE_SIZE
+            || start < mmu::USER_SPACE_BASE
+            || end > USER_STACK_TOP - USER_STACK_SIZE
+            || phdr.p_flags & (PF_W | PF_X) == PF_W | PF_X
+        {
+            return Err(ENOEXEC);
+        }
+        loadable |= phdr.p_memsz > 0;
+    }
+    if !loadable {
+        return Err(ENOEXEC);
+    }
+    Ok(phdrs)
+}
+
+/// Make sure [start, end) is backed by zeroed frames mapped as `kind`.
+/// A page shared with an earlier segment keeps that segment's mapping.
+fn map_zeroed(space: &mut AddressSpace, start: u64, end: u64, kind: MemoryKind) -> Result<(), i64> {
+    let mut va = page_down(start);
+    while va < end {
+        if space.translate(va).is_none() {
+            let frame = memory::alloc_frame().ok_or(-12i64)?; // ENOMEM
+            unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE as usize) };
+            if space.map(va, frame, PAGE_SIZE, kind).is_err() {
+                memory::free_frame(frame);
+                return Err(-12); // ENOMEM - Out of memory for translation tables
+            }
+        }
+        va += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// Copy `data` to `va` in `space`, page by page, through the kernel's identity map
+fn copy_to_space(space: &AddressSpace, va: u64, data: &[u8]) {
+    let mut done = 0;
+    while done < data.len() {
+        let addr = va + done as u64;
+        let Some(pa) = space.translate(addr) else { return };
+        let chunk = (PAGE_SIZE - addr % PAGE_SIZE).min((data.len() - done) as u64) as usize;
+        unsafe {
+            core::ptr::copy_nonoverlapping(data[done..].as_ptr(), pa as *mut u8, chunk);
+        }
+        done += chunk;
+    }
+}
+
+/// 16 bytes for AT_RANDOM; there is no entropy source, so mix the counter
+fn random_bytes() -> [u8; 16] {
+    let mut state = timer::counter() ^ 0x9E37_79B9_7F4A_7C15;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    bytes
+}
+
+/// Build the initial stack below USER_STACK_TOP and return sp
+fn setup_stack(space: &mut AddressSpace, args: &StackArgs, auxv: &[(u64, u64)]) -> Result<u64, i64> {
+    let StackArgs { argv, envp, execfn } = *args;
+    map_zeroed(space, USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_TOP, MemoryKind::UserData)?;
+
+    // Strings at the top: argv, envp, then the file name for AT_EXECFN
+    let strings_size: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum::<usize>()
+        + execfn.len() + 1;
+    // argc, argv and NULL, envp and NULL, auxv with AT_RANDOM, AT_EXECFN and AT_NULL
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 3);
+    if strings_size + 16 + words * 8 + 16 > MAX_ARG_SIZE {
+        return Err(-7); // E2BIG - Argument list too long
+    }
+
+    let strings = USER_STACK_TOP - strings_size as u64;
+    let random = (strings & !15) - 16;
+    let sp = (random - words as u64 * 8) & !15;
+
+    // Everything from sp to the top, built here and copied in one go
+    let mut stack = alloc::vec![0u8; (USER_STACK_TOP - sp) as usize];
+    let mut put_word = |index: usize, value: u64| {
+        stack[index * 8..index * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    };
+
+    let mut next_string = strings;
+    let mut string_addrs = Vec::with_capacity(argv.len() + envp.len());
+    for s in argv.iter().chain(envp.iter()) {
+        string_addrs.push(next_string);
+        next_string += s.len() as u64 + 1;
+    }
+    let execfn_addr = next_string;
+
+    let mut word = 0;
+    put_word(word, argv.len() as u64);
+    word += 1;
+    for &addr in &string_addrs[..argv.len()] {
+        put_word(word, addr);
+        word += 1;
+    }
+    word += 1; // NULL
+    for &addr in &string_addrs[argv.len()..] {
+        put_word(word, addr);
+        word += 1;
+    }
+    word += 1; // NULL
+    for &(key, value) in auxv.iter().chain([(AT_RANDOM, random), (AT_EXECFN, execfn_addr)].iter()) {
+        put_word(word, key);
+        put_word(word + 1, value);
+        word += 2;
+    }
+    put_word(word, AT_NULL);
+
+    let random_offset = (random - sp) as usize;
+    stack[random_offset..random_offset + 16].copy_from_slice(&random_bytes());
+    let mut offset = (strings - sp) as usize;
+    for s in argv.iter().chain(envp.iter()).chain([execfn.as_bytes()].iter()) {
+        stack[offset..offset + s.len()].copy_from_slice(s);
+        offset += s.len() + 1; // The NUL is already there
+    }
+
+    copy_to_space(space, sp, &stack);
+    Ok(sp)
+}
+
+/// Load `image` into a new address space with `argv` and `envp` on its stack
+pub fn load(image: &[u8], argv: &[&[u8]], envp: &[&[u8]], execfn: &str) -> Result<Program, i64> {
+    let ehdr = check_header(image)?;
+    let bias = if ehdr.e_type == ET_DYN { ET_DYN_BASE } else { 0 };
+    let phdrs = check_segments(image, &ehdr, bias)?;
+
+    let mut space = AddressSpace::new_user(mmu::alloc_asid()).map_err(|_| -12i64)?; // ENOMEM
+    let args = StackArgs { argv, envp, execfn };
+    match populate(&mut space, image, &ehdr, &phdrs, bias, &args) {
+        Ok(stack_pointer) => Ok(Program { space, entry: ehdr.e_entry + bias, stack_pointer }),
+        Err(errno) => {
+            space.destroy();
+            Err(errno)
+        }
+    }
+}
+
+fn populate(space: &mut AddressSpace, image: &[u8], ehdr: &Elf64Ehdr, phdrs: &[Elf64Phdr], bias: u64,
+            args: &StackArgs) -> Result<u64, i64> {
+    let mut phdr_addr = 0;
+    for phdr in phdrs.iter().filter(|p| p.p_type == PT_LOAD) {
+        let kind = if phdr.p_flags & PF_X != 0 {
+            MemoryKind::UserText
+        } else if phdr.p_flags & PF_W != 0 {
+            MemoryKind::UserData
+        } else {
+            MemoryKind::UserRodata
+        };
+        let start = phdr.p_vaddr + bias;
+        map_zeroed(space, start, start + phdr.p_memsz, kind)?;
+        let file = &image[phdr.p_offset as usize..(phdr.p_offset + phdr.p_filesz) as usize];
+        copy_to_space(space, start, file);
+
+        if kind == MemoryKind::UserText {
+            let mut va = page_down(start);
+            while va < start + phdr.p_memsz {
+                if let Some(pa) = space.translate(va) {
+                    mmu::sync_icache_range(pa, pa + PAGE_SIZE);
+                }
+                va += PAGE_SIZE;
+            }
+        }
+
+        // The program headers, as mapped by the segment that holds them
+        if (phdr.p_offset..phdr.p_offset + phdr.p_filesz).contains(&ehdr.e_phoff) {
+            phdr_addr = start + (ehdr.e_phoff - phdr.p_offset);
+        }
+    }
+    if let Some(phdr) = phdrs.iter().find(|p| p.p_type == PT_PHDR) {
+        phdr_addr = phdr.p_vaddr + bias;
+    }
+
+    let (uid, gid) = users::get_current_user();
+    let auxv = [
+        (AT_PHDR, phdr_addr),
+        (AT_PHENT, ehdr.e_phentsize as u64),
+        (AT_PHNUM, ehdr.e_phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, ehdr.e_entry + bias),
+        (AT_UID, uid as u64),
+        (AT_EUID, uid as u64),
+        (AT_GID, gid as u64),
+        (AT_EGID, gid as u64),
+        (AT_HWCAP, HWCAP_FP | HWCAP_ASIMD),
+        (AT_CLKTCK, CLK_TCK),
+        (AT_SECURE, 0),
+    ];
+    setup_stack(space, args, &auxv)
+}
@@ -0,0 +1,470 @@
+// Exception vectors and synchronous exception handling (AArch64, EL1)
+//
+// Every vector saves a full TrapFrame on the EL1 stack before calling into
+// Rust, and `exception_return` restores it (including ELR/SPSR/SP_EL0, which
+// a handler may change) before the eret.
+//
+// Synchronous exceptions are decoded from ESR_EL1 (exception class and ISS),
+// FAR_EL1 and ELR_EL1. A fault taken from EL1 is a kernel bug: the report is
+// printed and the kernel panics. A fault taken from EL0 is turned into a
+// signal for the current process, as on Linux (SIGSEGV for translation and
+// permission faults, SIGBUS for alignment and external aborts, SIGILL for
+// undefined instructions, SIGTRAP for BRK). `svc #0` from EL0 is a system
+// call and goes to `syscalls::handle_syscall`.
+//
+// A write permission fault on a copy-on-write page (a user write, or the
+// kernel writing to user memory in a system call) is not an error: the page
+// gets a private copy and the write is retried. Any other fault in the
+// kernel's user-memory accessors (uaccess.rs) resumes at the fixup that the
+// exception table gives for the faulting instruction, and the system call
+// returns EFAULT.
+
+use core::fmt::{self, Write};
+
+use crate::mmu;
+use crate::process::{self, PROCESS_MANAGER, ProcessState};
+use crate::signals::{self, Signal};
+use crate::syscalls;
+use crate::uaccess;
+use crate::uart::UART;
+
+/// Registers saved on exception entry; layout is fixed by the vector code below
+#[repr(C)]
+pub struct TrapFrame {
+    pub regs: [u64; 31],     // x0-x30
+    pub sp_el0: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    pub vregs: [u128; 32],   // q0-q31
+}
+
+const TRAP_FRAME_SIZE: usize = 816;
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == TRAP_FRAME_SIZE);
+
+// Exception classes (ESR_EL1.EC)
+const EC_UNKNOWN: u64 = 0x00;
+const EC_WFX: u64 = 0x01;
+const EC_FP_ACCESS: u64 = 0x07;
+const EC_ILLEGAL_STATE: u64 = 0x0E;
+const EC_SVC64: u64 = 0x15;
+const EC_HVC64: u64 = 0x16;
+const EC_SMC64: u64 = 0x17;
+const EC_SYSREG: u64 = 0x18;
+const EC_SVE: u64 = 0x19;
+const EC_IABT_LOWER: u64 = 0x20;
+const EC_IABT_CURRENT: u64 = 0x21;
+const EC_PC_ALIGNMENT: u64 = 0x22;
+const EC_DABT_LOWER: u64 = 0x24;
+const EC_DABT_CURRENT: u64 = 0x25;
+const EC_SP_ALIGNMENT: u64 = 0x26;
+const EC_FP_EXCEPTION: u64 = 0x2C;
+const EC_SERROR: u64 = 0x2F;
+const EC_BREAKPOINT_LOWER: u64 = 0x30;
+const EC_BREAKPOINT_CURRENT: u64 = 0x31;
+const EC_SOFTWARE_STEP_LOWER: u64 = 0x32;
+const EC_SOFTWARE_STEP_CURRENT: u64 = 0x33;
+const EC_WATCHPOINT_LOWER: u64 = 0x34;
+const EC_WATCHPOINT_CURRENT: u64 = 0x35;
+const EC_BRK: u64 = 0x3C;
+
+// ISS fields of instruction and data aborts
+const ISS_FSC_MASK: u64 = 0x3F;
+const ISS_WNR: u64 = 1 << 6;
+const ISS_FNV: u64 = 1 << 10;
+
+// Which of the 16 vectors was taken, for unexpected exceptions
+const VECTOR_NAMES: [&str; 16] = [
+    "EL1t Sync", "EL1t IRQ", "EL1t FIQ", "EL1t SError",
+    "EL1h Sync", "EL1h IRQ", "EL1h FIQ", "EL1h SError",
+    "EL0 64-bit Sync", "EL0 64-bit IRQ", "EL0 64-bit FIQ", "EL0 64-bit SError",
+    "EL0 32-bit Sync", "EL0 32-bit IRQ", "EL0 32-bit FIQ", "EL0 32-bit SError",
+];
+
+core::arch::global_asm!(
+    r#"
+    .macro SAVE_FRAME
+    sub     sp, sp, #816
+    stp     x0, x1, [sp, #0]
+    stp     x2, x3, [sp, #16]
+    stp     x4, x5, [sp, #32]
+    stp     x6, x7, [sp, #48]
+    stp     x8, x9, [sp, #64]
+    stp     x10, x11, [sp, #80]
+    stp     x12, x13, [sp, #96]
+    stp     x14, x15, [sp, #112]
+    stp     x16, x17, [sp, #128]
+    stp     x18, x19, [sp, #144]
+    stp     x20, x21, [sp, #160]
+    stp     x22, x23, [sp, #176]
+    stp     x24, x25, [sp, #192]
+    stp     x26, x27, [sp, #208]
+    stp     x28, x29, [sp, #224]
+    mrs     x9, sp_el0
+    stp     x30, x9, [sp, #240]
+    mrs     x10, elr_el1
+    mrs     x11, spsr_el1
+    stp     x10, x11, [sp, #256]
+    mrs     x12, esr_el1
+    mrs     x13, far_el1
+    stp     x12, x13, [sp, #272]
+    mrs     x14, fpcr
+    mrs     x15, fpsr
+    stp     x14, x15, [sp, #288]
+    stp     q0, q1, [sp, #304]
+    stp     q2, q3, [sp, #336]
+    stp     q4, q5, [sp, #368]
+    stp     q6, q7, [sp, #400]
+    stp     q8, q9, [sp, #432]
+    stp     q10, q11, [sp, #464]
+    stp     q12, q13, [sp, #496]
+    stp     q14, q15, [sp, #528]
+    stp     q16, q17, [sp, #560]
+    stp     q18, q19, [sp, #592]
+    stp     q20, q21, [sp, #624]
+    stp     q22, q23, [sp, #656]
+    stp     q24, q25, [sp, #688]
+    stp     q26, q27, [sp, #720]
+    stp     q28, q29, [sp, #752]
+    stp     q30, q31, [sp, #784]
+    .endm
+
+    // Save a frame, call handler(frame, vector) and return through the frame
+    .macro VECTOR_STUB name, handler, vector
+\name:
+    SAVE_FRAME
+    mov     x0, sp
+    mov     x1, #\vector
+    bl      \handler
+    b       exception_return
+    .endm
+
+    .section .text._start_vectors
+    .balign 0x800
+    .global _start_vectors
+_start_vectors:
+    // Current EL with SP0
+    .balign 0x80
+    b   sync_exception_sp0
+    .balign 0x80
+    b   irq_exception_sp0
+    .balign 0x80
+    b   fiq_exception_sp0
+    .balign 0x80
+    b   serror_exception_sp0
+
+    // Current EL with SPx
+    .balign 0x80
+    b   sync_exception_spx
+    .balign 0x80
+    b   irq_exception_spx
+    .balign 0x80
+    b   fiq_exception_spx
+    .balign 0x80
+    b   serror_exception_spx
+
+    // Lower EL using AArch64
+    .balign 0x80
+    b   sync_exception_aarch64
+    .balign 0x80
+    b   irq_exception_aarch64
+    .balign 0x80
+    b   fiq_exception_aarch64
+    .balign 0x80
+    b   serror_exception_aarch64
+
+    // Lower EL using AArch32
+    .balign 0x80
+    b   sync_exception_aarch32
+    .balign 0x80
+    b   irq_exception_aarch32
+    .balign 0x80
+    b   fiq_exception_aarch32
+    .balign 0x80
+    b   serror_exception_aarch32
+
+    VECTOR_STUB sync_exception_sp0, handle_unexpected_exception, 0
+    VECTOR_STUB irq_exception_sp0, rust_irq_handler, 1
+    VECTOR_STUB fiq_exception_sp0, handle_unexpected_exception, 2
+    VECTOR_STUB serror_exception_sp0, handle_unexpected_exception, 3
+
+    VECTOR_STUB sync_exception_spx, handle_sync_exception, 4
+    VECTOR_STUB irq_exception_spx, rust_irq_handler, 5
+    VECTOR_STUB fiq_exception_spx, handle_unexpected_exception, 6
+    VECTOR_STUB serror_exception_spx, handle_unexpected_exception, 7
+
+    VECTOR_STUB sync_exception_aarch64, handle_sync_exception, 8
+    VECTOR_STUB irq_exception_aarch64, rust_irq_handler, 9
+    VECTOR_STUB fiq_exception_aarch64, handle_unexpected_exception, 10
+    VECTOR_STUB serror_exception_aarch64, handle_unexpected_exception, 11
+
+    VECTOR_STUB sync_exception_aarch32, handle_unexpected_exception, 12
+    VECTOR_STUB irq_exception_aarch32, handle_unexpected_exception, 13
+    VECTOR_STUB fiq_exception_aarch32, handle_unexpected_exception, 14
+    VECTOR_STUB serror_exception_aarch32, handle_unexpected_exception, 15
+
+    // Restore the frame at sp and return to ELR_EL1 in SPSR_EL1's mode
+    .global exception_return
+exception_return:
+    ldp     q0, q1, [sp, #304]
+    ldp     q2, q3, [sp, #336]
+    ldp     q4, q5, [sp, #368]
+    ldp     q6, q7, [sp, #400]
+    ldp     q8, q9, [sp, #432]
+    ldp     q10, q11, [sp, #464]
+    ldp     q12, q13, [sp, #496]
+    ldp     q14, q15, [sp, #528]
+    ldp     q16, q17, [sp, #560]
+    ldp     q18, q19, [sp, #592]
+    ldp     q20, q21, [sp, #624]
+    ldp     q22, q23, [sp, #656]
+    ldp     q24, q25, [sp, #688]
+    ldp     q26, q27, [sp, #720]
+    ldp     q28, q29, [sp, #752]
+    ldp     q30, q31, [sp, #784]
+    ldp     x14, x15, [sp, #288]
+    msr     fpcr, x14
+    msr     fpsr, x15
+    ldp     x10, x11, [sp, #256]
+    msr     elr_el1, x10
+    msr     spsr_el1, x11
+    ldp     x30, x9, [sp, #240]
+    msr     sp_el0, x9
+    ldp     x0, x1, [sp, #0]
+    ldp     x2, x3, [sp, #16]
+    ldp     x4, x5, [sp, #32]
+    ldp     x6, x7, [sp, #48]
+    ldp     x8, x9, [sp, #64]
+    ldp     x10, x11, [sp, #80]
+    ldp     x12, x13, [sp, #96]
+    ldp     x14, x15, [sp, #112]
+    ldp     x16, x17, [sp, #128]
+    ldp     x18, x19, [sp, #144]
+    ldp     x20, x21, [sp, #160]
+    ldp     x22, x23, [sp, #176]
+    ldp     x24, x25, [sp, #192]
+    ldp     x26, x27, [sp, #208]
+    ldp     x28, x29, [sp, #224]
+    add     sp, sp, #816
+    eret
+    "#
+);
+
+impl TrapFrame {
+    /// SPSR_EL1.M[3:0] is 0 (EL0t) when the exception came from user space
+    pub fn is_from_user(&self) -> bool {
+        self.spsr & 0xF == 0
+    }
+
+    pub fn exception_class(&self) -> u64 {
+        (self.esr >> 26) & 0x3F
+    }
+
+    pub fn iss(&self) -> u64 {
+        self.esr & 0x1FF_FFFF
+    }
+}
+
+/// Install the vector table on the calling core
+pub fn init_exceptions() {
+    unsafe {
+        extern "C" {
+            static _start_vectors: u8;
+        }
+        let vbar = core::ptr::addr_of!(_start_vectors) as u64;
+        core::arch::asm!("msr vbar_el1, {}", "isb", in(reg) vbar);
+    }
+}
+
+fn exception_class_name(ec: u64) -> &'static str {
+    match ec {
+        EC_UNKNOWN => "Unknown reason (undefined instruction)",
+        EC_WFX => "Trapped WFI/WFE",
+        EC_FP_ACCESS => "Trapped FP/SIMD access",
+        EC_ILLEGAL_STATE => "Illegal execution state",
+        EC_SVC64 => "SVC (AArch64)",
+        EC_HVC64 => "HVC (AArch64)",
+        EC_SMC64 => "SMC (AArch64)",
+        EC_SYSREG => "Trapped MSR/MRS/system instruction",
+        EC_SVE => "Trapped SVE access",
+        EC_IABT_LOWER => "Instruction abort from a lower EL",
+        EC_IABT_CURRENT => "Instruction abort from the same EL",
+        EC_PC_ALIGNMENT => "PC alignment fault",
+        EC_DABT_LOWER => "Data abort from a lower EL",
+        EC_DABT_CURRENT => "Data abort from the same EL",
+        EC_SP_ALIGNMENT => "SP alignment fault",
+        EC_FP_EXCEPTION => "Floating-point exception",
+        EC_SERROR => "SError",
+        EC_BREAKPOINT_LOWER | EC_BREAKPOINT_CURRENT => "Hardware breakpoint",
+        EC_SOFTWARE_STEP_LOWER | EC_SOFTWARE_STEP_CURRENT => "Software step",
+        EC_WATCHPOINT_LOWER | EC_WATCHPOINT_CURRENT => "Watchpoint",
+        EC_BRK => "BRK instruction",
+        _ => "Reserved exception class",
+    }
+}
+
+/// Instruction/data fault status code (ISS.IFSC/DFSC)
+fn fault_status_name(fsc: u64) -> &'static str {
+    match fsc {
+        0x00..=0x03 => "address size fault",
+        0x04..=0x07 => "translation fault",
+        0x09..=0x0B => "access flag fault",
+        0x0D..=0x0F => "permission fault",
+        0x10 => "synchronous external abort",
+        0x11 => "synchronous tag check fault",
+        0x14..=0x17 => "external abort on table walk",
+        0x18 => "synchronous parity/ECC error",
+        0x21 => "alignment fault",
+        0x30 => "TLB conflict abort",
+        0x31 => "unsupported atomic hardware update",
+        _ => "unknown fault",
+    }
+}
+
+fn is_abort(ec: u64) -> bool {
+    matches!(ec, EC_IABT_LOWER | EC_IABT_CURRENT | EC_DABT_LOWER | EC_DABT_CURRENT)
+}
+
+/// A data write to a read-only page with a valid FAR, as copy-on-write pages produce
+fn is_write_permission_fault(frame: &TrapFrame) -> bool {
+    let ec = frame.exception_class();
+    let iss = frame.iss();
+    matches!(ec, EC_DABT_LOWER | EC_DABT_CURRENT)
+        && matches!(iss & ISS_FSC_MASK, 0x0D..=0x0F)
+        && iss & ISS_WNR != 0
+        && iss & ISS_FNV == 0
+}
+
+/// Signal that a fault from user space turns into, as Linux does it
+fn fault_signal(frame: &TrapFrame) -> Signal {
+    let ec = frame.exception_class();
+    if is_abort(ec) {
+        return match frame.iss() & ISS_FSC_MASK {
+            0x21 | 0x10 | 0x14..=0x18 => Signal::SIGBUS,
+            _ => Signal::SIGSEGV,
+        };
+    }
+    match ec {
+        EC_PC_ALIGNMENT | EC_SP_ALIGNMENT => Signal::SIGBUS,
+        EC_FP_EXCEPTION => Signal::SIGFPE,
+        EC_BRK | EC_BREAKPOINT_LOWER | EC_SOFTWARE_STEP_LOWER | EC_WATCHPOINT_LOWER => Signal::SIGTRAP,
+        _ => Signal::SIGILL,
+    }
+}
+
+struct FaultReport<'a>(&'a TrapFrame);
+
+impl fmt::Display for FaultReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = self.0;
+        let ec = frame.exception_class();
+        let iss = frame.iss();
+
+        writeln!(f, "{} at {:#018x}", exception_class_name(ec), frame.elr)?;
+        writeln!(f, "  ESR_EL1 {:#010x} (EC {:#04x}, IL {}, ISS {:#09x})",
+                 frame.esr, ec, (frame.esr >> 25) & 1, iss)?;
+        if is_abort(ec) {
+            let fsc = iss & ISS_FSC_MASK;
+            let access = if ec == EC_IABT_LOWER || ec == EC_IABT_CURRENT {
+                "instruction fetch"
+            } else if iss & ISS_WNR != 0 {
+                "write"
+            } else {
+                "read"
+            };
+            write!(f, "  {} on {}", fault_status_name(fsc), access)?;
+            if fsc <= 0x0F {
+                write!(f, ", level {}", fsc & 3)?;
+            }
+            writeln!(f)?;
+            if iss & ISS_FNV == 0 {
+                writeln!(f, "  FAR_EL1 {:#018x}", frame.far)?;
+            } else {
+                writeln!(f, "  FAR_EL1 not valid")?;
+            }
+        } else if ec == EC_PC_ALIGNMENT {
+            writeln!(f, "  FAR_EL1 {:#018x}", frame.far)?;
+        }
+        writeln!(f, "  SPSR_EL1 {:#010x}  SP_EL0 {:#018x}", frame.spsr, frame.sp_el0)?;
+
+        for (i, pair) in frame.regs.chunks(2).enumerate() {
+            write!(f, "  x{:<2} {:#018x}", i * 2, pair[0])?;
+            if let Some(reg) = pair.get(1) {
+                write!(f, "  x{:<2} {:#018x}", i * 2 + 1, reg)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rust side of the synchronous vectors for EL1h and lower-EL AArch64
+#[no_mangle]
+extern "C" fn handle_sync_exception(frame: &mut TrapFrame, _vector: u64) {
+    let mut uart = UART;
+
+    if is_write_permission_fault(frame) && mmu::resolve_cow_fault(frame.far) {
+        return;
+    }
+
+    if !frame.is_from_user() {
+        // A system call given a bad user pointer
+        if frame.exception_class() == EC_DABT_CURRENT {
+            if let Some(fixup) = uaccess::search_exception_table(frame.elr) {
+                frame.elr = fixup;
+                return;
+            }
+        }
+        let _ = write!(uart, "\r\nKernel fault: {}", FaultReport(frame));
+        panic!("unhandled kernel exception (EC {:#04x}) at {:#x}", frame.exception_class(), frame.elr);
+    }
+
+    if frame.exception_class() == EC_SVC64 {
+        handle_svc(frame);
+        return;
+    }
+
+    let signal = fault_signal(frame);
+    let pid = unsafe { PROCESS_MANAGER.current_pid() };
+    let _ = write!(uart, "\r\nUser fault in PID {}: {}", pid, FaultReport(frame));
+    // Returning would re-run the faulting instruction, and no handler frame
+    // can be set up to get past it, so the fault always kills the process
+    if let Err(e) = signals::force_signal(pid, signal) {
+        panic!("cannot deliver {} to PID {}: {}", signal.name(), pid, e);
+    }
+    // A terminated process is never picked again, so this does not return
+    process::schedule();
+}
+
+/// `svc #0` from EL0: Linux arm64 convention, number in x8, arguments in
+/// x0-x5, result in x0. ELR_EL1 already points past the svc instruction.
+fn handle_svc(frame: &mut TrapFrame) {
+    let ret = if frame.iss() & 0xFFFF == 0 {
+        let r = &frame.regs;
+        syscalls::handle_syscall(r[8], r[0], r[1], r[2], r[3], r[4], r[5])
+    } else {
+        -38  // ENOSYS: only svc #0 is a system call
+    };
+    frame.regs[0] = ret as u64;
+
+    // The call may have stopped or killed the caller (kill or raise on itself)
+    let running = unsafe {
+        PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid())
+            .is_none_or(|p| p.state == ProcessState::Running)
+    };
+    if !running {
+        process::schedule();
+    }
+}
+
+/// FIQ, SError, SP0 and AArch32 vectors: none of these are expected
+#[no_mangle]
+extern "C" fn handle_unexpected_exception(frame: &mut TrapFrame, vector: u64) {
+    let mut uart = UART;
+    let name = VECTOR_NAMES[vector as usize & 0xF];
+    let _ = write!(uart, "\r\nUnexpected exception ({}): {}", name, FaultReport(frame));
+    panic!("unexpected {} exception at {:#x}", name, frame.elr);
+}
@@ -0,0 +1,339 @@
+// Open files
+//
+// Every process has a table of file descriptors (`FdTable`, in `Process`).
+// A descriptor refers to an open file description (`OpenFile`), which holds
+// the file offset and the status flags. dup, dup2/dup3 and fork make more
+// descriptors for the same description, so they share its offset as on
+// Unix, and the description is closed with its last descriptor: a pty
+// master hangs up its slave, a pipe end tells the other end. O_CLOEXEC
+// belongs to the descriptor, not the description, and execve closes it.
+// New descriptors get the lowest free number.
+
+use alloc::vec::Vec;
+use heapless::String;
+
+use crate::ipc;
+use crate::process::{self, WaitQueue, MAX_PATH};
+use crate::pty;
+use crate::debug;
+
+/// Descriptors per process (RLIMIT_NOFILE)
+pub const MAX_OPEN_FILES: usize = 32;
+
+// open(2) flags (arm64 values)
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_NONBLOCK: u32 = 0o4000;
+pub const O_CLOEXEC: u32 = 0o2000000;
+
+/// What an open file description is backed by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Path,               // A device or a file of the VFS, by `path`
+    PtyMaster(usize),   // /dev/ptmx: master end of pty N
+    PipeRead(i32),      // Ends of an ipc::Pipe
+    PipeWrite(i32),
+}
+
+/// An open file description
+#[derive(Clone, Debug)]
+pub struct OpenFile {
+    pub path: String<MAX_PATH>,
+    pub flags: u32,          // Access mode and status flags
+    pub offset: usize,
+    pub kind: FileKind,
+    refs: u32,               // Descriptors in all processes
+}
+
+// Descriptions by id; a closed one leaves its slot free for the next
+static mut OPEN_FILES: Vec<Option<OpenFile>> = Vec::new();
+
+// Readers and writers waiting for a pipe
+static mut PIPE_WAIT: WaitQueue = WaitQueue::new();
+
+fn open_files() -> &'static mut Vec<Option<OpenFile>> {
+    unsafe { &mut *core::ptr::addr_of_mut!(OPEN_FILES) }
+}
+
+fn pipe_wait() -> &'static mut WaitQueue {
+    unsafe { &mut *core::ptr::addr_of_mut!(PIPE_WAIT) }
+}
+
+/// A new description with no descriptors yet; install it in a table
+pub fn open(path: &str, flags: u32, kind: FileKind) -> usize {
+    let mut name = String::new();
+    // Cut at a character boundary, never inside one
+    let _ = name.push_str(&path[..path.floor_char_boundary(MAX_PATH)]);
+    let file = OpenFile { path: name, flags: flags & !O_CLOEXEC, offset: 0, kind, refs: 0 };
+
+    let files = open_files();
+    match files.iter().position(|slot| slot.is_none()) {
+        Some(id) => {
+            files[id] = Some(file);
+            id
+        }
+        None => {
+            files.push(Some(file));
+            files.len() - 1
+        }
+    }
+}
+
+/// A copy of description `id`
+pub fn get(id: usize) -> Option<OpenFile> {
+    open_files().get(id)?.clone()
+}
+
+/// Move the shared offset of description `id` on by `count`
+pub fn advance(id: usize, count: usize) {
+    if let Some(Some(file)) = open_files().get_mut(id) {
+        file.offset += count;
+    }
+}
+
+/// F_SETFL: only the status flags change, not the access mode
+pub fn set_status_flags(id: usize, flags: u32) {
+    if let Some(Some(file)) = open_files().get_mut(id) {
+        file.flags = (file.flags & O_ACCMODE) | (flags & O_NONBLOCK);
+    }
+}
+
+/// Close a description that never got a descriptor
+pub fn discard(id: usize) {
+    hold(id);
+    release(id);
+}
+
+fn hold(id: usize) {
+    if let Some(Some(file)) = open_files().get_mut(id) {
+        file.refs += 1;
+    }
+}
+
+/// One descriptor less; the last one closes the description
+fn release(id: usize) {
+    let files = open_files();
+    let Some(Some(file)) = files.get_mut(id) else { return };
+    file.refs -= 1;
+    if file.refs > 0 {
+        return;
+    }
+    let file = files[id].take().unwrap();
+    debug!("file: closing {} ({:?})", file.path, file.kind);
+
+    match file.kind {
+        FileKind::PtyMaster(index) => pty::close_master(index),
+        FileKind::PipeRead(pipe) | FileKind::PipeWrite(pipe) => {
+            let _ = ipc::close_pipe(pipe);
+            // The other end sees EOF or EPIPE
+            pipe_wait().wake_all();
+        }
+        FileKind::Path => {
+            if let Some(index) = pts_index(&file.path) {
+                pty::close_slave(index);
+            }
+        }
+    }
+}
+
+/// N of "/dev/pts/N"
+pub fn pts_index(path: &str) -> Option<usize> {
+    path.strip_prefix("/dev/pts/")?.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Fd {
+    file: usize,
+    cloexec: bool,
+}
+
+/// File descriptors of a process. Cloning copies the numbers only; a
+/// table that is to stay in use comes from `fork`, which counts them.
+#[derive(Clone, Debug)]
+pub struct FdTable {
+    fds: [Option<Fd>; MAX_OPEN_FILES],
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { fds: [None; MAX_OPEN_FILES] }
+    }
+
+    /// stdin, stdout and stderr: one description of the controlling terminal
+    pub fn standard() -> Self {
+        let mut table = Self::new();
+        let tty = open("/dev/tty", O_RDWR, FileKind::Path);
+        for _ in 0..3 {
+            let _ = table.install(tty, 0, false);
+        }
+        table
+    }
+
+    /// Description behind `fd`
+    pub fn get(&self, fd: i32) -> Option<usize> {
+        let slot = usize::try_from(fd).ok()?;
+        self.fds.get(slot)?.map(|fd| fd.file)
+    }
+
+    pub fn cloexec(&self, fd: i32) -> Option<bool> {
+        let slot = usize::try_from(fd).ok()?;
+        self.fds.get(slot)?.map(|fd| fd.cloexec)
+    }
+
+    pub fn set_cloexec(&mut self, fd: i32, cloexec: bool) -> Result<(), i32> {
+        let slot = usize::try_from(fd).map_err(|_| -9)?;
+        match self.fds.get_mut(slot) {
+            Some(Some(fd)) => {
+                fd.cloexec = cloexec;
+                Ok(())
+            }
+            _ => Err(-9), // EBADF
+        }
+    }
+
+    /// New descriptor for description `file`: the lowest free one from `min`
+    pub fn install(&mut self, file: usize, min: usize, cloexec: bool) -> Result<i32, i32> {
+        let slot = (min..MAX_OPEN_FILES)
+            .find(|&slot| self.fds[slot].is_none())
+            .ok_or(-24)?; // EMFILE - Too many open files
+        hold(file);
+        self.fds[slot] = Some(Fd { file, cloexec });
+        Ok(slot as i32)
+    }
+
+    pub fn close(&mut self, fd: i32) -> Result<(), i32> {
+        let slot = usize::try_from(fd).map_err(|_| -9)?;
+        let fd = self.fds.get_mut(slot).and_then(Option::take).ok_or(-9)?; // EBADF
+        release(fd.file);
+        Ok(())
+    }
+
+    /// dup / F_DUPFD: the lowest free descriptor from `min`
+    pub fn dup(&mut self, oldfd: i32, min: usize, cloexec: bool) -> Result<i32, i32> {
+        let file = self.get(oldfd).ok_or(-9)?; // EBADF
+        if min >= MAX_OPEN_FILES {
+            return Err(-22); // EINVAL
+        }
+        self.install(file, min, cloexec)
+    }
+
+    /// dup2 / dup3: `newfd` refers to what `oldfd` does, closing it first if open
+    pub fn dup2(&mut self, oldfd: i32, newfd: i32, cloexec: bool) -> Result<i32, i32> {
+        let file = self.get(oldfd).ok_or(-9)?; // EBADF
+        let slot = usize::try_from(newfd).ok()
+            .filter(|&slot| slot < MAX_OPEN_FILES)
+            .ok_or(-9)?; // EBADF
+        if oldfd == newfd {
+            return Ok(newfd);
+        }
+        // Hold the description before a close of the last other reference
+        hold(file);
+        if let Some(old) = self.fds[slot].take() {
+            release(old.file);
+        }
+        self.fds[slot] = Some(Fd { file, cloexec });
+        Ok(newfd)
+    }
+
+    /// The table of a forked child: the same descriptions, one more reference each
+    pub fn fork(&self) -> Self {
+        for fd in self.fds.iter().flatten() {
+            hold(fd.file);
+        }
+        self.clone()
+    }
+
+    /// execve: close the descriptors marked O_CLOEXEC
+    pub fn close_on_exec(&mut self) {
+        for slot in &mut self.fds {
+            if slot.is_some_and(|fd| fd.cloexec) {
+                release(slot.take().unwrap().file);
+            }
+        }
+    }
+
+    /// Process exit
+    pub fn close_all(&mut self) {
+        for slot in &mut self.fds {
+            if let Some(fd) = slot.take() {
+                release(fd.file);
+            }
+        }
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A new pipe: descriptions for its read and write ends
+pub fn pipe(flags: u32) -> Result<(usize, usize), i64> {
+    let (read_end, write_end) = ipc::create_pipe().map_err(|_| -23i64)?; // ENFILE
+    let status = flags & O_NONBLOCK;
+    let reader = open("pipe:", O_RDONLY | status, FileKind::PipeRead(read_end));
+    let writer = open("pipe:", O_WRONLY | status, FileKind::PipeWrite(write_end));
+    Ok((reader, writer))
+}
+
+/// Read from a pipe, sleeping while it is empty and has writers (0 at EOF)
+pub fn pipe_read(pipe: i32, buf: &mut [u8], nonblock: bool) -> Result<usize, i64> {
+    loop {
+        let daif = process::irq_save();
+        let Some((buffered, _, writers)) = ipc::pipe_status(pipe) else {
+            process::irq_restore(daif);
+            return Err(-9); // EBADF
+        };
+        if buffered > 0 || writers == 0 || buf.is_empty() {
+            let result = ipc::pipe_read(pipe, buf).map_err(|_| -9i64);
+            // Room for the writers
+            pipe_wait().wake_all();
+            process::irq_restore(daif);
+            return result;
+        }
+        if nonblock {
+            process::irq_restore(daif);
+            return Err(-11); // EAGAIN
+        }
+        pipe_wait().sleep();
+        process::irq_restore(daif);
+    }
+}
+
+/// Write all of `data` to a pipe, sleeping while it is full. EPIPE when
+/// there is no reader left (the caller raises SIGPIPE).
+pub fn pipe_write(pipe: i32, data: &[u8], nonblock: bool) -> Result<usize, i64> {
+    let mut written = 0;
+    loop {
+        let daif = process::irq_save();
+        let Some((_, readers, _)) = ipc::pipe_status(pipe) else {
+            process::irq_restore(daif);
+            return Err(-9); // EBADF
+        };
+        if readers == 0 {
+            process::irq_restore(daif);
+            return Err(-32); // EPIPE - Broken pipe
+        }
+        let n = ipc::pipe_write(pipe, &data[written..]).unwrap_or(0);
+        written += n;
+        if n > 0 {
+            pipe_wait().wake_all();
+        }
+        if written == data.len() || (nonblock && written > 0) {
+            process::irq_restore(daif);
+            return Ok(written);
+        }
+        if nonblock {
+            process::irq_restore(daif);
+            return Err(-11); // EAGAIN
+        }
+        pipe_wait().sleep();
+        process::irq_restore(daif);
+    }
+}
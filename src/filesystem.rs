@@ -2,11 +2,8 @@
 // Provides /proc, /dev, and basic file operations
 
 use crate::uart::Uart;
-use heapless::{String, Vec};
-
-const MAX_FILES: usize = 32;
-const MAX_FILENAME: usize = 64;
-const MAX_CONTENT: usize = 1024;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
@@ -18,27 +15,21 @@ pub enum FileType {
 
 #[derive(Debug, Clone)]
 pub struct VirtualFile {
-    pub name: String<MAX_FILENAME>,
+    pub name: String,
     pub file_type: FileType,
-    pub content: String<MAX_CONTENT>,
+    pub content: String,
     pub size: usize,
     pub permissions: u32, // Unix-style permissions
 }
 
 impl VirtualFile {
     pub fn new(name: &str, file_type: FileType, content: &str) -> Self {
-        let mut file_name = String::new();
-        let _ = file_name.push_str(name);
-        
-        let mut file_content = String::new();
-        let _ = file_content.push_str(content);
-        
         let size = content.len();
         
         Self {
-            name: file_name,
+            name: String::from(name),
             file_type,
-            content: file_content,
+            content: String::from(content),
             size,
             permissions: match file_type {
                 FileType::Directory => 0o755,
@@ -51,7 +42,7 @@ impl VirtualFile {
 }
 
 pub struct VirtualFileSystem {
-    files: Vec<VirtualFile, MAX_FILES>,
+    files: Vec<VirtualFile>,
     uart: &'static mut Uart,
 }
 
@@ -75,10 +66,11 @@ impl VirtualFileSystem {
         self.add_file("/proc", FileType::Directory, "");
         self.add_file("/proc/version", FileType::Proc, 
                      "Minimal Pi5 OS version 0.1.0 (root@pi5) (aarch64) #1");
-        self.add_file("/proc/cpuinfo", FileType::Proc,
-                     "processor\t: 0\nBogoMIPS\t: 108.00\nFeatures\t: fp asimd evtstrm crc32 cpuid\nCPU implementer\t: 0x41\nCPU architecture: 8");
-        self.add_file("/proc/meminfo", FileType::Proc,
-                     "MemTotal:     8388608 kB\nMemFree:      7340032 kB\nMemAvailable: 7340032 kB");
+        self.add_file("/proc/cpuinfo", FileType::Proc, "");
+        self.add_file("/proc/cmdline", FileType::Proc, "");
+        self.add_file("/proc/interrupts", FileType::Proc, "");
+        self.add_file("/proc/kmsg", FileType::Proc, "");
+        self.add_file("/proc/meminfo", FileType::Proc, "");
         self.add_file("/proc/uptime", FileType::Proc, "");
         self.add_file("/proc/loadavg", FileType::Proc, "0.00 0.00 0.00 1/1 1");
         
@@ -87,6 +79,9 @@ impl VirtualFileSystem {
         self.add_file("/dev/null", FileType::Device, "");
         self.add_file("/dev/zero", FileType::Device, "");
         self.add_file("/dev/uart0", FileType::Device, "");
+        self.add_file("/dev/tty", FileType::Device, "");
+        self.add_file("/dev/ptmx", FileType::Device, "");
+        self.add_file("/dev/pts", FileType::Directory, "");
         self.add_file("/dev/mem", FileType::Device, "");
         
         // /sys directory for system information
@@ -106,13 +101,10 @@ impl VirtualFileSystem {
     }
 
     fn add_file(&mut self, name: &str, file_type: FileType, content: &str) {
-        if !self.files.is_full() {
-            let file = VirtualFile::new(name, file_type, content);
-            let _ = self.files.push(file);
-        }
+        self.files.push(VirtualFile::new(name, file_type, content));
     }
 
-    pub fn list_directory(&self, path: &str) -> Vec<&VirtualFile, MAX_FILES> {
+    pub fn list_directory(&self, path: &str) -> Vec<&VirtualFile> {
         let mut entries = Vec::new();
         
         // Normalize path
@@ -125,9 +117,7 @@ impl VirtualFileSystem {
                 // Root directory - show top-level entries
                 if file_path != "/" && !file_path.contains('/') || 
                    (file_path.starts_with('/') && file_path[1..].chars().filter(|&c| c == '/').count() == 0) {
-                    if !entries.is_full() {
-                        let _ = entries.push(file);
-                    }
+                    entries.push(file);
                 }
             } else {
                 // Show direct children of the specified directory
@@ -135,8 +125,8 @@ impl VirtualFileSystem {
                     let suffix = &file_path[normalized_path.len()..];
                     if suffix.starts_with('/') {
                         let remaining = &suffix[1..];
-                        if !remaining.contains('/') && !entries.is_full() {
-                            let _ = entries.push(file);
+                        if !remaining.contains('/') {
+                            entries.push(file);
                         }
                     }
                 }
@@ -146,16 +136,39 @@ impl VirtualFileSystem {
         entries
     }
 
-    pub fn read_file(&mut self, path: &str) -> Option<String<MAX_CONTENT>> {
+    pub fn read_file(&mut self, path: &str) -> Option<String> {
         // Handle dynamic files
         match path {
             "/proc/uptime" => {
                 let uptime = crate::timer::get_uptime_seconds();
                 let mut content = String::new();
                 self.format_number(&mut content, uptime);
-                let _ = content.push_str(".00 ");
+                content.push_str(".00 ");
                 self.format_number(&mut content, uptime);
-                let _ = content.push_str(".00");
+                content.push_str(".00");
+                return Some(content);
+            }
+            "/proc/cpuinfo" => {
+                return Some(crate::smp::cpuinfo());
+            }
+            "/proc/interrupts" => {
+                return Some(crate::interrupt::proc_interrupts());
+            }
+            "/proc/cmdline" => {
+                let mut content = String::new();
+                content.push_str(crate::cmdline::raw());
+                return Some(content);
+            }
+            "/proc/meminfo" => {
+                let (total, free) = crate::memory::get_memory_stats();
+                let mut content = String::new();
+                content.push_str("MemTotal:     ");
+                self.format_number(&mut content, (total / 1024) as u32);
+                content.push_str(" kB\nMemFree:      ");
+                self.format_number(&mut content, (free / 1024) as u32);
+                content.push_str(" kB\nMemAvailable: ");
+                self.format_number(&mut content, (free / 1024) as u32);
+                content.push_str(" kB");
                 return Some(content);
             }
             _ => {}
@@ -194,20 +207,24 @@ impl VirtualFileSystem {
             return false; // File already exists
         }
         
-        if !self.files.is_full() {
-            let file = VirtualFile::new(path, FileType::RegularFile, content);
-            let _ = self.files.push(file);
-            true
-        } else {
-            false // No space
+        self.files.push(VirtualFile::new(path, FileType::RegularFile, content));
+        true
+    }
+
+    pub fn create_directory(&mut self, path: &str) -> bool {
+        if self.file_exists(path) {
+            return false;
         }
+        
+        self.files.push(VirtualFile::new(path, FileType::Directory, ""));
+        true
     }
 
     pub fn write_file(&mut self, path: &str, content: &str) -> bool {
         for file in &mut self.files {
             if file.name.as_str() == path && file.file_type != FileType::Proc {
                 file.content.clear();
-                let _ = file.content.push_str(content);
+                file.content.push_str(content);
                 file.size = content.len();
                 return true;
             }
@@ -225,13 +242,23 @@ impl VirtualFileSystem {
         false
     }
 
-    fn format_number(&self, string: &mut String<MAX_CONTENT>, num: u32) {
+    pub fn remove_directory(&mut self, path: &str) -> bool {
+        for (i, file) in self.files.iter().enumerate() {
+            if file.name.as_str() == path && file.file_type == FileType::Directory {
+                self.files.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn format_number(&self, string: &mut String, num: u32) {
         let mut buffer = [0u8; 10];
         let mut pos = 0;
         let mut n = num;
         
         if n == 0 {
-            let _ = string.push('0');
+            string.push('0');
             return;
         }
         
@@ -243,17 +270,42 @@ impl VirtualFileSystem {
         
         // Add digits in reverse order
         for i in (0..pos).rev() {
-            let _ = string.push(buffer[i] as char);
+            string.push(buffer[i] as char);
         }
     }
 
+    /// (number of files, bytes of file content)
     pub fn get_stats(&self) -> (usize, usize) {
-        let used = self.files.len();
-        let total = MAX_FILES;
-        (used, total)
+        let bytes = self.files.iter().map(|f| f.content.len()).sum();
+        (self.files.len(), bytes)
     }
 }
 
+/// Absolute, normalized form of `path` as seen from `cwd`:
+/// ("/usr", "bin/../lib/./x") -> "/usr/lib/x"
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    let mut resolved = String::new();
+    for component in &components {
+        resolved.push('/');
+        resolved.push_str(component);
+    }
+    if resolved.is_empty() {
+        resolved.push('/');
+    }
+    resolved
+}
+
 // Global file system instance
 static mut VFS: Option<VirtualFileSystem> = None;
 
@@ -269,7 +321,7 @@ pub fn get_filesystem() -> Option<&'static mut VirtualFileSystem> {
 }
 
 // Convenience functions
-pub fn list_directory(path: &str) -> Vec<&'static VirtualFile, MAX_FILES> {
+pub fn list_directory(path: &str) -> Vec<&'static VirtualFile> {
     if let Some(vfs) = get_filesystem() {
         vfs.list_directory(path)
     } else {
@@ -277,7 +329,7 @@ pub fn list_directory(path: &str) -> Vec<&'static VirtualFile, MAX_FILES> {
     }
 }
 
-pub fn read_file(path: &str) -> Option<String<MAX_CONTENT>> {
+pub fn read_file(path: &str) -> Option<String> {
     if let Some(vfs) = get_filesystem() {
         vfs.read_file(path)
     } else {
@@ -301,6 +353,30 @@ pub fn create_file(path: &str, content: &str) -> bool {
     }
 }
 
+pub fn create_directory(path: &str) -> bool {
+    if let Some(vfs) = get_filesystem() {
+        vfs.create_directory(path)
+    } else {
+        false
+    }
+}
+
+pub fn delete_file(path: &str) -> bool {
+    if let Some(vfs) = get_filesystem() {
+        vfs.delete_file(path)
+    } else {
+        false
+    }
+}
+
+pub fn remove_directory(path: &str) -> bool {
+    if let Some(vfs) = get_filesystem() {
+        vfs.remove_directory(path)
+    } else {
+        false
+    }
+}
+
 pub fn write_file(path: &str, content: &str) -> bool {
     if let Some(vfs) = get_filesystem() {
         vfs.write_file(path, content)
@@ -0,0 +1,206 @@
+// Raspberry Pi 5 Interrupt Controller (GIC-400)
+// Based on ARM Generic Interrupt Controller v2.0 specification
+//
+// The memory-mapped backend of `interrupt::IrqChip`: a distributor plus a
+// CPU interface at the same address on every core. SGI and PPI registers of
+// the distributor are banked per core.
+
+use crate::info;
+use crate::interrupt::{IrqChip, FIRST_SPI, MAX_IRQS, NR_SGIS};
+
+// Distributor registers
+const GICD_CTLR: u32 = 0x000;      // Distributor Control Register
+const GICD_TYPER: u32 = 0x004;     // Interrupt Controller Type Register
+const GICD_ISENABLER: u32 = 0x100; // Interrupt Set-Enable Registers
+const GICD_ICENABLER: u32 = 0x180; // Interrupt Clear-Enable Registers
+const GICD_ICPENDR: u32 = 0x280;   // Interrupt Clear-Pending Registers
+const GICD_IPRIORITYR: u32 = 0x400; // Interrupt Priority Registers
+const GICD_ITARGETSR: u32 = 0x800; // Interrupt Processor Targets Registers
+const GICD_ICFGR: u32 = 0xC00;     // Interrupt Configuration Registers
+const GICD_SGIR: u32 = 0xF00;      // Software Generated Interrupt Register
+
+// CPU Interface registers
+const GICC_CTLR: u32 = 0x000;      // CPU Interface Control Register
+const GICC_PMR: u32 = 0x004;       // Interrupt Priority Mask Register
+const GICC_IAR: u32 = 0x00C;       // Interrupt Acknowledge Register
+const GICC_EOIR: u32 = 0x010;      // End of Interrupt Register
+
+const PRIORITY_MASK_ALL: u32 = 0xFF;
+
+pub struct Gicv2 {
+    gic_dist_base: u64,
+    gic_cpu_base: u64,
+    num_lines: u32,
+}
+
+impl Gicv2 {
+    pub const fn new(dist_base: u64, cpu_base: u64) -> Self {
+        Self {
+            gic_dist_base: dist_base,
+            gic_cpu_base: cpu_base,
+            num_lines: 0,
+        }
+    }
+
+    fn init_distributor(&mut self) -> Result<(), &'static str> {
+        // Disable distributor
+        self.write_distributor_reg(GICD_CTLR, 0);
+
+        // Read number of interrupt lines
+        let typer = self.read_distributor_reg(GICD_TYPER);
+        self.num_lines = (((typer & 0x1F) + 1) * 32).min(MAX_IRQS as u32);
+
+        // Disable all shared interrupts
+        for i in (FIRST_SPI..self.num_lines).step_by(32) {
+            self.write_distributor_reg(GICD_ICENABLER + (i / 8), 0xFFFFFFFF);
+        }
+
+        // Clear all pending shared interrupts
+        for i in (FIRST_SPI..self.num_lines).step_by(32) {
+            self.write_distributor_reg(GICD_ICPENDR + (i / 8), 0xFFFFFFFF);
+        }
+
+        // Set priority for all shared interrupts (lower number = higher priority)
+        for i in (FIRST_SPI..self.num_lines).step_by(4) {
+            self.write_distributor_reg(GICD_IPRIORITYR + i, 0xA0A0A0A0);
+        }
+
+        // Set target processor to CPU0 for all interrupts
+        for i in (FIRST_SPI..self.num_lines).step_by(4) {
+            self.write_distributor_reg(GICD_ITARGETSR + i, 0x01010101);
+        }
+
+        // Level-sensitive until a driver asks otherwise
+        for i in (FIRST_SPI..self.num_lines).step_by(16) {
+            self.write_distributor_reg(GICD_ICFGR + (i / 4), 0);
+        }
+
+        // Enable distributor
+        self.write_distributor_reg(GICD_CTLR, 1);
+
+        Ok(())
+    }
+
+    /// Replace one byte of the byte-per-interrupt registers (priority, targets)
+    fn write_byte_field(&mut self, base: u32, irq: u32, value: u8) {
+        let reg = base + (irq & !3);
+        let shift = (irq % 4) * 8;
+        let current = self.read_distributor_reg(reg);
+        self.write_distributor_reg(reg, (current & !(0xFF << shift)) | ((value as u32) << shift));
+    }
+
+    fn read_distributor_reg(&self, offset: u32) -> u32 {
+        unsafe {
+            core::ptr::read_volatile((self.gic_dist_base + offset as u64) as *const u32)
+        }
+    }
+
+    fn write_distributor_reg(&mut self, offset: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.gic_dist_base + offset as u64) as *mut u32, value);
+        }
+    }
+
+    fn read_cpu_reg(&self, offset: u32) -> u32 {
+        unsafe {
+            core::ptr::read_volatile((self.gic_cpu_base + offset as u64) as *const u32)
+        }
+    }
+
+    fn write_cpu_reg(&mut self, offset: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.gic_cpu_base + offset as u64) as *mut u32, value);
+        }
+    }
+}
+
+impl IrqChip for Gicv2 {
+    fn name(&self) -> &'static str {
+        "GICv2"
+    }
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        info!("Initializing GIC-400 interrupt controller...");
+
+        // Initialize Distributor
+        self.init_distributor()?;
+
+        // Initialize CPU Interface
+        self.init_cpu()?;
+
+        info!("GIC-400 initialized successfully");
+        Ok(())
+    }
+
+    /// Banked per core: the SGI/PPI half of the distributor and the CPU interface
+    fn init_cpu(&mut self) -> Result<(), &'static str> {
+        // Start from a clean slate for the private interrupts, then enable the SGIs
+        self.write_distributor_reg(GICD_ICENABLER, 0xFFFFFFFF);
+        self.write_distributor_reg(GICD_ICPENDR, 0xFFFFFFFF);
+        for i in (0..FIRST_SPI).step_by(4) {
+            self.write_distributor_reg(GICD_IPRIORITYR + i, 0xA0A0A0A0);
+        }
+        self.write_distributor_reg(GICD_ISENABLER, (1 << NR_SGIS) - 1);
+
+        // Set priority mask to allow all interrupts
+        self.write_cpu_reg(GICC_PMR, PRIORITY_MASK_ALL);
+
+        // Enable CPU interface
+        self.write_cpu_reg(GICC_CTLR, 1);
+
+        Ok(())
+    }
+
+    fn num_irqs(&self) -> u32 {
+        self.num_lines
+    }
+
+    fn enable(&mut self, irq: u32) {
+        let reg_offset = (irq / 32) * 4;
+        let bit_offset = irq % 32;
+
+        // Writing 0 bits has no effect, so no read-modify-write is needed
+        self.write_distributor_reg(GICD_ISENABLER + reg_offset, 1 << bit_offset);
+    }
+
+    fn disable(&mut self, irq: u32) {
+        let reg_offset = (irq / 32) * 4;
+        let bit_offset = irq % 32;
+        let reg_addr = GICD_ICENABLER + reg_offset;
+
+        self.write_distributor_reg(reg_addr, 1 << bit_offset);
+    }
+
+    fn set_priority(&mut self, irq: u32, priority: u8) {
+        self.write_byte_field(GICD_IPRIORITYR, irq, priority);
+    }
+
+    /// SGIs are always edge-triggered and the PPI setting is read-only on the GIC-400
+    fn set_trigger(&mut self, irq: u32, edge: bool) {
+        let reg = GICD_ICFGR + (irq / 16) * 4;
+        let bit = 1 << ((irq % 16) * 2 + 1);
+        let current = self.read_distributor_reg(reg);
+        self.write_distributor_reg(reg, if edge { current | bit } else { current & !bit });
+    }
+
+    /// ITARGETSR bit n is CPU interface n, which follows the order the cores came up in
+    fn set_affinity(&mut self, irq: u32, cpu_mask: u8) {
+        self.write_byte_field(GICD_ITARGETSR, irq, cpu_mask);
+    }
+
+    fn send_sgi(&mut self, sgi: u32, cpu_mask: u8) {
+        // TargetListFilter 0: the cores in CPUTargetList
+        unsafe { core::arch::asm!("dsb ishst") };
+        self.write_distributor_reg(GICD_SGIR, ((cpu_mask as u32) << 16) | (sgi & 0xF));
+    }
+
+    fn acknowledge(&mut self) -> (u32, u32) {
+        let iar = self.read_cpu_reg(GICC_IAR);
+        (iar & 0x3FF, iar)
+    }
+
+    /// `token` is the whole IAR value, with the source core of an SGI still in it
+    fn end_of_interrupt(&mut self, token: u32) {
+        self.write_cpu_reg(GICC_EOIR, token);
+    }
+}
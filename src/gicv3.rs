@@ -0,0 +1,358 @@
+// GICv3 interrupt controller (QEMU virt,gic-version=3 and later boards)
+// Based on ARM Generic Interrupt Controller v3/v4 specification
+//
+// The distributor (GICD) only handles shared interrupts here: with affinity
+// routing enabled, SPIs are routed by MPIDR through GICD_IROUTER, and SGIs
+// and PPIs live in one redistributor (GICR) per core. The CPU interface is
+// reached through the ICC_* system registers instead of memory; enter_el1 in
+// startup.s lets EL1 use them when the firmware starts us at EL2.
+
+use crate::interrupt::{IrqChip, FIRST_SPI, MAX_IRQS, NR_SGIS};
+use crate::smp::{self, MAX_CPUS};
+use crate::info;
+
+// Distributor registers
+const GICD_CTLR: u64 = 0x0000;
+const GICD_TYPER: u64 = 0x0004;
+const GICD_IGROUPR: u64 = 0x0080;
+const GICD_ISENABLER: u64 = 0x0100;
+const GICD_ICENABLER: u64 = 0x0180;
+const GICD_ICPENDR: u64 = 0x0280;
+const GICD_IPRIORITYR: u64 = 0x0400;
+const GICD_ICFGR: u64 = 0x0C00;
+const GICD_IROUTER: u64 = 0x6000;
+
+const GICD_CTLR_ENABLE_G1: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_G1A: u32 = 1 << 1;
+const GICD_CTLR_ARE_NS: u32 = 1 << 4;
+const GICD_CTLR_RWP: u32 = 1 << 31;
+const GICD_IROUTER_IRM: u64 = 1 << 31;
+
+// Redistributor: RD_base frame, then the SGI_base frame 64KB on
+const GICR_TYPER: u64 = 0x0008;
+const GICR_WAKER: u64 = 0x0014;
+const GICR_SGI_BASE: u64 = 0x1_0000;
+const GICR_IGROUPR0: u64 = 0x0080;
+const GICR_ISENABLER0: u64 = 0x0100;
+const GICR_ICENABLER0: u64 = 0x0180;
+const GICR_ICPENDR0: u64 = 0x0280;
+const GICR_IPRIORITYR: u64 = 0x0400;
+const GICR_ICFGR0: u64 = 0x0C00;
+
+const GICR_TYPER_VLPIS: u64 = 1 << 1;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+// Redistributor frame sizes: RD + SGI, plus VLPI + reserved on GICv4
+const GICR_FRAMES_V3: u64 = 0x2_0000;
+const GICR_FRAMES_V4: u64 = 0x4_0000;
+
+// ICC_SRE_EL1: system register interface, FIQ and IRQ bypass disabled
+const ICC_SRE_SRE: u64 = 1 << 0;
+const ICC_SRE_DFB: u64 = 1 << 1;
+const ICC_SRE_DIB: u64 = 1 << 2;
+
+const PRIORITY_MASK_ALL: u64 = 0xFF;
+const RWP_TIMEOUT: u32 = 1_000_000;
+
+pub struct Gicv3 {
+    dist_base: u64,
+    redist_base: u64,
+    redist_stride: u64,
+    num_lines: u32,
+    rd_bases: [u64; MAX_CPUS],   // Redistributor of each logical core, 0 until found
+}
+
+fn read_mpidr() -> u64 {
+    let mpidr: u64;
+    unsafe { core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr) };
+    mpidr
+}
+
+/// MPIDR affinity in the Aff3.Aff2.Aff1.Aff0 layout of GICR_TYPER[63:32]
+fn packed_affinity(mpidr: u64) -> u32 {
+    ((((mpidr >> 32) & 0xFF) << 24) | (mpidr & 0xFF_FFFF)) as u32
+}
+
+/// MPIDR affinity in the layout of GICD_IROUTER
+fn router_affinity(mpidr: u64) -> u64 {
+    mpidr & 0xFF_00FF_FFFF
+}
+
+fn read32(addr: u64) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
+}
+
+fn write32(addr: u64, value: u32) {
+    unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+}
+
+fn read64(addr: u64) -> u64 {
+    unsafe { core::ptr::read_volatile(addr as *const u64) }
+}
+
+fn write64(addr: u64, value: u64) {
+    unsafe { core::ptr::write_volatile(addr as *mut u64, value) }
+}
+
+impl Gicv3 {
+    pub const fn new(dist_base: u64, redist_base: u64, redist_stride: u64) -> Self {
+        Self {
+            dist_base,
+            redist_base,
+            redist_stride,
+            num_lines: 0,
+            rd_bases: [0; MAX_CPUS],
+        }
+    }
+
+    /// Wait for a GICD_CTLR write to take effect
+    fn wait_for_rwp(&self) -> Result<(), &'static str> {
+        for _ in 0..RWP_TIMEOUT {
+            if read32(self.dist_base + GICD_CTLR) & GICD_CTLR_RWP == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err("GICv3 distributor register write timed out")
+    }
+
+    fn init_distributor(&mut self) -> Result<(), &'static str> {
+        let dist = self.dist_base;
+
+        // Disable distributor
+        write32(dist + GICD_CTLR, 0);
+        self.wait_for_rwp()?;
+
+        // Read number of interrupt lines
+        let typer = read32(dist + GICD_TYPER);
+        self.num_lines = (((typer & 0x1F) + 1) * 32).min(MAX_IRQS as u32);
+
+        // Shared interrupts: non-secure group 1, disabled, not pending, level-sensitive
+        for i in (FIRST_SPI..self.num_lines).step_by(32) {
+            let reg = (i / 8) as u64;
+            write32(dist + GICD_IGROUPR + reg, 0xFFFFFFFF);
+            write32(dist + GICD_ICENABLER + reg, 0xFFFFFFFF);
+            write32(dist + GICD_ICPENDR + reg, 0xFFFFFFFF);
+        }
+        for i in (FIRST_SPI..self.num_lines).step_by(4) {
+            write32(dist + GICD_IPRIORITYR + i as u64, 0xA0A0A0A0);
+        }
+        for i in (FIRST_SPI..self.num_lines).step_by(16) {
+            write32(dist + GICD_ICFGR + (i / 4) as u64, 0);
+        }
+        self.wait_for_rwp()?;
+
+        // Enable with affinity routing
+        write32(dist + GICD_CTLR, GICD_CTLR_ARE_NS | GICD_CTLR_ENABLE_G1A | GICD_CTLR_ENABLE_G1);
+        self.wait_for_rwp()?;
+
+        // All shared interrupts go to the boot core until a driver asks otherwise
+        let boot = router_affinity(smp::cpu(0).mpidr);
+        for irq in FIRST_SPI..self.num_lines {
+            write64(dist + GICD_IROUTER + irq as u64 * 8, boot);
+        }
+        Ok(())
+    }
+
+    /// Find the redistributor whose GICR_TYPER affinity matches this core
+    fn find_redistributor(&self, mpidr: u64) -> Option<u64> {
+        let affinity = packed_affinity(mpidr);
+        let mut rd = self.redist_base;
+        loop {
+            let typer = read64(rd + GICR_TYPER);
+            if (typer >> 32) as u32 == affinity {
+                return Some(rd);
+            }
+            if typer & GICR_TYPER_LAST != 0 {
+                return None;
+            }
+            rd += match self.redist_stride {
+                0 if typer & GICR_TYPER_VLPIS != 0 => GICR_FRAMES_V4,
+                0 => GICR_FRAMES_V3,
+                stride => stride,
+            };
+        }
+    }
+
+    /// SGI_base frame of the calling core's redistributor
+    fn sgi_base(&self) -> Option<u64> {
+        match self.rd_bases[smp::cpu_id()] {
+            0 => None,
+            rd => Some(rd + GICR_SGI_BASE),
+        }
+    }
+
+    /// Register for `irq` in a bit-per-interrupt bank: the redistributor for
+    /// SGIs and PPIs, the distributor for the rest
+    fn bit_reg(&self, dist_offset: u64, redist_offset: u64, irq: u32) -> Option<u64> {
+        if irq < FIRST_SPI {
+            self.sgi_base().map(|base| base + redist_offset)
+        } else {
+            Some(self.dist_base + dist_offset + (irq / 32) as u64 * 4)
+        }
+    }
+
+    fn write_byte_field(&self, addr: u64, irq: u32, value: u8) {
+        let reg = addr + (irq & !3) as u64;
+        let shift = (irq % 4) * 8;
+        let current = read32(reg);
+        write32(reg, (current & !(0xFF << shift)) | ((value as u32) << shift));
+    }
+}
+
+impl IrqChip for Gicv3 {
+    fn name(&self) -> &'static str {
+        "GICv3"
+    }
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        info!("Initializing GICv3 interrupt controller...");
+
+        self.init_distributor()?;
+        self.init_cpu()?;
+
+        info!("GICv3 initialized successfully");
+        Ok(())
+    }
+
+    fn init_cpu(&mut self) -> Result<(), &'static str> {
+        let rd = self.find_redistributor(read_mpidr()).ok_or("no redistributor for this core")?;
+        self.rd_bases[smp::cpu_id()] = rd;
+
+        // Wake the redistributor up
+        let waker = read32(rd + GICR_WAKER);
+        write32(rd + GICR_WAKER, waker & !GICR_WAKER_PROCESSOR_SLEEP);
+        let mut timeout = RWP_TIMEOUT;
+        while read32(rd + GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+            timeout -= 1;
+            if timeout == 0 {
+                return Err("redistributor did not wake up");
+            }
+            core::hint::spin_loop();
+        }
+
+        // SGIs and PPIs: group 1, only the SGIs enabled
+        let sgi = rd + GICR_SGI_BASE;
+        write32(sgi + GICR_IGROUPR0, 0xFFFFFFFF);
+        write32(sgi + GICR_ICENABLER0, 0xFFFFFFFF);
+        write32(sgi + GICR_ICPENDR0, 0xFFFFFFFF);
+        for i in (0..FIRST_SPI).step_by(4) {
+            write32(sgi + GICR_IPRIORITYR + i as u64, 0xA0A0A0A0);
+        }
+        write32(sgi + GICR_ISENABLER0, (1 << NR_SGIS) - 1);
+
+        // CPU interface through the system registers
+        unsafe {
+            let mut sre: u64;
+            core::arch::asm!("mrs {}, icc_sre_el1", out(reg) sre);
+            sre |= ICC_SRE_SRE | ICC_SRE_DFB | ICC_SRE_DIB;
+            core::arch::asm!("msr icc_sre_el1, {}", "isb", in(reg) sre);
+            core::arch::asm!("mrs {}, icc_sre_el1", out(reg) sre);
+            if sre & ICC_SRE_SRE == 0 {
+                return Err("GICv3 system register interface disabled by a higher EL");
+            }
+
+            // All priorities, no preemption groups, EOI also deactivates
+            core::arch::asm!(
+                "msr icc_pmr_el1, {}",
+                "msr icc_bpr1_el1, xzr",
+                "msr icc_ctlr_el1, xzr",
+                "msr icc_igrpen1_el1, {}",
+                "isb",
+                in(reg) PRIORITY_MASK_ALL,
+                in(reg) 1u64,
+            );
+        }
+        Ok(())
+    }
+
+    fn num_irqs(&self) -> u32 {
+        self.num_lines
+    }
+
+    fn enable(&mut self, irq: u32) {
+        if let Some(reg) = self.bit_reg(GICD_ISENABLER, GICR_ISENABLER0, irq) {
+            write32(reg, 1 << (irq % 32));
+        }
+    }
+
+    fn disable(&mut self, irq: u32) {
+        if let Some(reg) = self.bit_reg(GICD_ICENABLER, GICR_ICENABLER0, irq) {
+            write32(reg, 1 << (irq % 32));
+        }
+    }
+
+    fn set_priority(&mut self, irq: u32, priority: u8) {
+        let base = if irq < FIRST_SPI {
+            match self.sgi_base() {
+                Some(base) => base + GICR_IPRIORITYR,
+                None => return,
+            }
+        } else {
+            self.dist_base + GICD_IPRIORITYR
+        };
+        self.write_byte_field(base, irq, priority);
+    }
+
+    /// SGIs are always edge-triggered
+    fn set_trigger(&mut self, irq: u32, edge: bool) {
+        if irq < NR_SGIS {
+            return;
+        }
+        let reg = if irq < FIRST_SPI {
+            match self.sgi_base() {
+                Some(base) => base + GICR_ICFGR0 + 4,
+                None => return,
+            }
+        } else {
+            self.dist_base + GICD_ICFGR + (irq / 16) as u64 * 4
+        };
+        let bit = 1 << ((irq % 16) * 2 + 1);
+        let current = read32(reg);
+        write32(reg, if edge { current | bit } else { current & !bit });
+    }
+
+    /// One core by affinity, or any core (1 of N routing) when several are asked for
+    fn set_affinity(&mut self, irq: u32, cpu_mask: u8) {
+        if irq < FIRST_SPI || cpu_mask == 0 {
+            return;
+        }
+        let route = if cpu_mask.count_ones() > 1 {
+            GICD_IROUTER_IRM
+        } else {
+            router_affinity(smp::cpu(cpu_mask.trailing_zeros() as usize).mpidr)
+        };
+        write64(self.dist_base + GICD_IROUTER + irq as u64 * 8, route);
+    }
+
+    fn send_sgi(&mut self, sgi: u32, cpu_mask: u8) {
+        unsafe { core::arch::asm!("dsb ishst") };
+        for id in 0..MAX_CPUS {
+            if (cpu_mask as u64 >> id) & 1 == 0 {
+                continue;
+            }
+            // ICC_SGI1R_EL1: INTID, Aff3/Aff2/Aff1 of the cluster and a target list of Aff0s
+            let mpidr = smp::cpu(id).mpidr;
+            let value = ((sgi as u64 & 0xF) << 24)
+                | (((mpidr >> 32) & 0xFF) << 48)
+                | (((mpidr >> 16) & 0xFF) << 32)
+                | (((mpidr >> 8) & 0xFF) << 16)
+                | (1 << (mpidr & 0xF));
+            unsafe { core::arch::asm!("msr icc_sgi1r_el1, {}", in(reg) value) };
+        }
+        unsafe { core::arch::asm!("isb") };
+    }
+
+    fn acknowledge(&mut self) -> (u32, u32) {
+        let iar: u64;
+        unsafe { core::arch::asm!("mrs {}, icc_iar1_el1", out(reg) iar) };
+        let intid = (iar & 0xFF_FFFF) as u32;
+        (intid, intid)
+    }
+
+    fn end_of_interrupt(&mut self, token: u32) {
+        unsafe { core::arch::asm!("msr icc_eoir1_el1, {}", "isb", in(reg) token as u64) };
+    }
+}
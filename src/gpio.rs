@@ -6,6 +6,9 @@ use crate::uart::Uart;
 // RP1 GPIO base address (Ubuntu kernel verified)
 const RP1_GPIO_BASE: u64 = 0x1f000d0000;
 
+// GPIO base in use; replaced by the value found in the device tree
+static mut GPIO_BASE: u64 = RP1_GPIO_BASE;
+
 // GPIO register offsets
 const GPIO_CTRL: u32 = 0x0004;
 const GPIO_STATUS: u32 = 0x0000;
@@ -39,7 +42,7 @@ const GPIO_STATUS_INFROMPAD: u32 = 0x20000;
 const GPIO_STATUS_INTOPERI: u32 = 0x40000;
 
 // SIO (Software I/O) registers for direct GPIO control
-const SIO_BASE: u64 = 0x1f000d0000 + 0x000000;
+const SIO_OFFSET: u64 = 0x000000;
 const SIO_GPIO_OUT: u32 = 0x010;
 const SIO_GPIO_OUT_SET: u32 = 0x014;
 const SIO_GPIO_OUT_CLR: u32 = 0x018;
@@ -92,8 +95,8 @@ pub struct GpioController {
 impl GpioController {
     pub fn new(uart: &'static mut Uart) -> Self {
         Self {
-            gpio_base: RP1_GPIO_BASE,
-            sio_base: SIO_BASE,
+            gpio_base: unsafe { GPIO_BASE },
+            sio_base: unsafe { GPIO_BASE } + SIO_OFFSET,
             uart,
         }
     }
@@ -306,6 +309,15 @@ impl GpioController {
 // GPIO controller instance
 static mut GPIO_CONTROLLER: Option<GpioController> = None;
 
+/// Use another RP1 GPIO block (must be called before init_gpio)
+pub fn set_base(base: u64) {
+    unsafe { GPIO_BASE = base; }
+}
+
+pub fn base() -> u64 {
+    unsafe { GPIO_BASE }
+}
+
 pub fn init_gpio(uart: &'static mut Uart) -> Result<(), &'static str> {
     unsafe {
         GPIO_CONTROLLER = Some(GpioController::new(uart));
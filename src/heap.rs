@@ -0,0 +1,236 @@
+// Kernel Heap for Raspberry Pi 5
+// Linked-list allocator backed by the page frame allocator
+//
+// Free blocks are kept in an address-ordered singly linked list so that
+// neighbours can be merged on free. When no block is large enough the heap
+// grows by asking the frame allocator for another contiguous run of frames.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::memory;
+use crate::mmu::PAGE_SIZE;
+use crate::{error, info};
+
+const INITIAL_HEAP_SIZE: u64 = 0x10_0000;  // 1MB
+const MIN_GROW_SIZE: u64 = 0x4_0000;       // 256KB
+// Every block is a multiple of 16 bytes and 16-byte aligned, so any split
+// leaves either nothing or enough room for a FreeBlock header
+const MIN_BLOCK_SIZE: usize = 16;
+const MIN_ALIGN: usize = 16;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+pub struct LinkedListHeap {
+    head: *mut FreeBlock,
+    total_bytes: usize,
+    used_bytes: usize,
+}
+
+impl LinkedListHeap {
+    pub const fn new() -> Self {
+        Self {
+            head: core::ptr::null_mut(),
+            total_bytes: 0,
+            used_bytes: 0,
+        }
+    }
+
+    /// Hand a new memory region to the heap
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.total_bytes += size;
+        self.insert_free(start, size);
+    }
+
+    /// Insert a free block in address order, merging with its neighbours
+    unsafe fn insert_free(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() && (cur as usize) < addr {
+            prev = cur;
+            cur = (*cur).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        (*block).size = size;
+        (*block).next = cur;
+
+        // Merge with the following block
+        if !cur.is_null() && addr + size == cur as usize {
+            (*block).size += (*cur).size;
+            (*block).next = (*cur).next;
+        }
+
+        // Merge with the preceding block
+        if !prev.is_null() && prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else if prev.is_null() {
+            self.head = block;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    fn block_size(layout: &Layout) -> (usize, usize) {
+        let align = core::cmp::max(layout.align(), MIN_ALIGN);
+        let size = core::cmp::max(layout.size(), MIN_BLOCK_SIZE);
+        (align_up(size, MIN_ALIGN), align)
+    }
+
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_size(&layout);
+
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let block_start = cur as usize;
+            let block_end = block_start + (*cur).size;
+
+            let alloc_start = align_up(block_start, align);
+            let alloc_end = alloc_start + size;
+
+            if alloc_end <= block_end {
+                let next = (*cur).next;
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+
+                if alloc_start > block_start {
+                    self.insert_free(block_start, alloc_start - block_start);
+                }
+                if block_end > alloc_end {
+                    self.insert_free(alloc_end, block_end - alloc_end);
+                }
+
+                self.used_bytes += size;
+                return alloc_start as *mut u8;
+            }
+
+            prev = cur;
+            cur = (*cur).next;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_size(&layout);
+        self.used_bytes -= size;
+        self.insert_free(ptr as usize, size);
+    }
+
+    /// (total, used) bytes
+    pub fn get_stats(&self) -> (usize, usize) {
+        (self.total_bytes, self.used_bytes)
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+// Global allocator: a spinlock-protected heap with interrupts masked while held
+pub struct KernelAllocator {
+    lock: AtomicBool,
+    heap: core::cell::UnsafeCell<LinkedListHeap>,
+}
+
+unsafe impl Sync for KernelAllocator {}
+
+impl KernelAllocator {
+    const fn new() -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            heap: core::cell::UnsafeCell::new(LinkedListHeap::new()),
+        }
+    }
+
+    fn with_heap<R>(&self, f: impl FnOnce(&mut LinkedListHeap) -> R) -> R {
+        let daif: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif);
+        }
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        let result = f(unsafe { &mut *self.heap.get() });
+
+        self.lock.store(false, Ordering::Release);
+        unsafe {
+            core::arch::asm!("msr daif, {}", in(reg) daif);
+        }
+        result
+    }
+
+    /// Grow the heap by at least `min_bytes` using contiguous page frames
+    fn grow(heap: &mut LinkedListHeap, min_bytes: u64) -> bool {
+        let bytes = core::cmp::max(align_up(min_bytes as usize, PAGE_SIZE as usize) as u64, MIN_GROW_SIZE);
+        match memory::alloc_frames(bytes / PAGE_SIZE) {
+            Some(addr) => {
+                unsafe { heap.add_region(addr as usize, bytes as usize) };
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| {
+            let ptr = heap.alloc(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+
+            // Worst case the request needs its size plus alignment padding
+            let needed = (layout.size() + layout.align() + MIN_BLOCK_SIZE) as u64;
+            if Self::grow(heap, needed) {
+                let ptr = heap.alloc(layout);
+                if !ptr.is_null() {
+                    return ptr;
+                }
+            }
+
+            // The caller turns a null return into a panic via the alloc error handler
+            let (total, used) = heap.get_stats();
+            error!("Kernel heap exhausted: request {} bytes, heap {}/{} bytes used",
+                   layout.size(), used, total);
+            core::ptr::null_mut()
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_heap(|heap| heap.dealloc(ptr, layout))
+    }
+}
+
+#[global_allocator]
+static KERNEL_HEAP: KernelAllocator = KernelAllocator::new();
+
+pub fn init_heap() -> Result<(), &'static str> {
+    let ok = KERNEL_HEAP.with_heap(|heap| KernelAllocator::grow(heap, INITIAL_HEAP_SIZE));
+    if !ok {
+        return Err("No memory for kernel heap");
+    }
+
+    let (total, _) = get_heap_stats();
+    info!("Kernel heap: {} bytes", total);
+    Ok(())
+}
+
+/// (total, used) bytes in the kernel heap
+pub fn get_heap_stats() -> (usize, usize) {
+    KERNEL_HEAP.with_heap(|heap| heap.get_stats())
+}
@@ -0,0 +1,125 @@
+// Initial RAM filesystem
+//
+// The firmware loads the archive named by `initramfs` in config.txt and
+// passes its location in /chosen (linux,initrd-start/-end). It must be an
+// uncompressed cpio archive in the "newc" format, as made by
+// `find . | cpio -o -H newc`; files are served straight from the archive,
+// which stays where the firmware put it (the DTB parser reserves it).
+// Symbolic links are followed, so a busybox-style tree works.
+
+use crate::dtb;
+use crate::filesystem;
+use crate::{info, warn};
+
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+const MAX_SYMLINKS: usize = 8;
+
+// st_mode file types
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFLNK: u32 = 0o120000;
+
+static mut ARCHIVE: &[u8] = &[];
+
+/// A file in the archive
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub name: &'static str,     // Without the leading "/" or "./"
+    pub mode: u32,
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    pub fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+}
+
+fn hex_field(header: &[u8], index: usize) -> Option<u32> {
+    let start = 6 + index * 8;
+    let text = core::str::from_utf8(header.get(start..start + 8)?).ok()?;
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Walks the headers of a newc archive
+struct Entries {
+    archive: &'static [u8],
+    offset: usize,
+}
+
+impl Iterator for Entries {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let header = self.archive.get(self.offset..self.offset + HEADER_SIZE)?;
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return None;
+        }
+        let mode = hex_field(header, 1)?;
+        let file_size = hex_field(header, 6)? as usize;
+        let name_size = hex_field(header, 11)? as usize;
+
+        let name_start = self.offset + HEADER_SIZE;
+        let name = self.archive.get(name_start..name_start + name_size.checked_sub(1)?)?;
+        let name = core::str::from_utf8(name).ok()?;
+        if name == TRAILER {
+            return None;
+        }
+        let data_start = align4(name_start + name_size);
+        let data = self.archive.get(data_start..data_start + file_size)?;
+        self.offset = align4(data_start + file_size);
+
+        let name = name.trim_start_matches("./").trim_start_matches('/');
+        Some(Entry { name, mode, data })
+    }
+}
+
+/// Every entry of the archive, in archive order
+pub fn entries() -> impl Iterator<Item = Entry> {
+    Entries { archive: unsafe { ARCHIVE }, offset: 0 }
+}
+
+/// The entry for an absolute `path`, following symbolic links
+pub fn lookup(path: &str) -> Option<Entry> {
+    let mut target = alloc::string::String::from(path);
+    for _ in 0..MAX_SYMLINKS {
+        let path = filesystem::resolve_path("/", &target);
+        let name = path.trim_start_matches('/');
+        let entry = entries().find(|entry| entry.name == name)?;
+        if entry.file_type() != S_IFLNK {
+            return Some(entry);
+        }
+        let link = core::str::from_utf8(entry.data).ok()?;
+        target = if link.starts_with('/') {
+            alloc::string::String::from(link)
+        } else {
+            // Relative to the directory holding the link
+            let dir = entry.name.rfind('/').map_or("", |pos| &entry.name[..pos]);
+            alloc::format!("/{}/{}", dir, link)
+        };
+    }
+    None // ELOOP
+}
+
+/// Find the archive the firmware loaded
+pub fn init() {
+    let Some((start, end)) = dtb::platform().initrd else { return };
+    let archive = unsafe { core::slice::from_raw_parts(start as *const u8, (end - start) as usize) };
+
+    if archive.starts_with(&[0x1f, 0x8b]) {
+        warn!("initramfs: gzip-compressed archives are not supported, ignoring it");
+        return;
+    }
+    if !archive.starts_with(b"07070") {
+        warn!("initramfs: not a newc cpio archive, ignoring it");
+        return;
+    }
+    unsafe { ARCHIVE = archive };
+    info!("initramfs: {} entries, {} kB at {:#x}", entries().count(), archive.len() / 1024, start);
+}
@@ -1,363 +1,320 @@
-// Raspberry Pi 5 Interrupt Controller (GIC-400)
-// Based on ARM Generic Interrupt Controller v2.0 specification
-
-use crate::uart::Uart;
+// Interrupt handling for Raspberry Pi 5 and QEMU virt
+//
+// Drivers claim an interrupt with `request_irq(irq, handler, flags, name)`,
+// which sets its trigger type, priority and target core and enables it.
+// `rust_irq_handler` acknowledges interrupts, counts them per core for
+// /proc/interrupts and calls the registered handler.
+//
+// The controller itself is behind the `IrqChip` trait: the GIC-400 on the
+// Pi 5 (gicv2.rs) or a GICv3 (gicv3.rs), as the device tree says. Interrupt
+// IDs are the same on both: 0-15 are SGIs (software generated, used for
+// IPIs), 16-31 are PPIs (private to each core, e.g. the generic timer) and
+// 32 and up are SPIs (shared peripherals). SGIs and PPIs are configured per
+// core, so every core sets its own up in `init_cpu`.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::gicv2::Gicv2;
+use crate::gicv3::Gicv3;
+use crate::process;
+use crate::smp::{self, MAX_CPUS};
+use crate::warn;
 
 // GIC-400 Base addresses for Pi5
 const GIC_DISTRIBUTOR_BASE: u64 = 0x2000_1000;
 const GIC_CPU_INTERFACE_BASE: u64 = 0x2000_2000;
 
-// Distributor registers
-const GICD_CTLR: u32 = 0x000;      // Distributor Control Register
-const GICD_TYPER: u32 = 0x004;     // Interrupt Controller Type Register
-const GICD_ISENABLER: u32 = 0x100; // Interrupt Set-Enable Registers
-const GICD_ICENABLER: u32 = 0x180; // Interrupt Clear-Enable Registers
-const GICD_ISPENDR: u32 = 0x200;   // Interrupt Set-Pending Registers
-const GICD_ICPENDR: u32 = 0x280;   // Interrupt Clear-Pending Registers
-const GICD_IPRIORITYR: u32 = 0x400; // Interrupt Priority Registers
-const GICD_ITARGETSR: u32 = 0x800; // Interrupt Processor Targets Registers
-
-// CPU Interface registers
-const GICC_CTLR: u32 = 0x000;      // CPU Interface Control Register
-const GICC_PMR: u32 = 0x004;       // Interrupt Priority Mask Register
-const GICC_IAR: u32 = 0x00C;       // Interrupt Acknowledge Register
-const GICC_EOIR: u32 = 0x010;      // End of Interrupt Register
-
-// Interrupt numbers for Pi5
-const IRQ_TIMER: u32 = 64;          // System Timer
-const IRQ_UART0: u32 = 153;         // UART0 (RP1)
-const IRQ_GPIO: u32 = 113;          // GPIO controller
-
-pub struct InterruptController {
-    gic_dist_base: u64,
-    gic_cpu_base: u64,
-    uart: &'static mut Uart,
+// Interrupt ID ranges
+pub const NR_SGIS: u32 = 16;
+pub const FIRST_SPI: u32 = 32;
+pub const MAX_IRQS: usize = 1020;  // IDs 1020-1023 are special
+
+// SGIs used as inter-processor interrupts
+pub const SGI_RESCHEDULE: u32 = 0;
+
+// request_irq flags (the Linux values)
+pub const IRQF_TRIGGER_RISING: u32 = 0x0000_0001;
+pub const IRQF_TRIGGER_HIGH: u32 = 0x0000_0004;
+pub const IRQF_PERCPU: u32 = 0x0000_0400;
+
+// Priorities: lower number = higher priority
+pub const DEFAULT_PRIORITY: u8 = 0xA0;
+
+pub type IrqHandler = fn(irq: u32);
+
+/// An interrupt controller backend
+pub trait IrqChip {
+    /// Shown in /proc/interrupts
+    fn name(&self) -> &'static str;
+    /// Shared state and the calling (boot) core
+    fn init(&mut self) -> Result<(), &'static str>;
+    /// Per-core state of the calling core; may run before `init`
+    fn init_cpu(&mut self) -> Result<(), &'static str>;
+    /// Interrupt IDs the controller implements (valid after `init`)
+    fn num_irqs(&self) -> u32;
+    fn enable(&mut self, irq: u32);
+    fn disable(&mut self, irq: u32);
+    fn set_priority(&mut self, irq: u32, priority: u8);
+    /// Edge-triggered (rising) or level-sensitive (active high)
+    fn set_trigger(&mut self, irq: u32, edge: bool);
+    /// Route a shared interrupt to the logical cores in `cpu_mask`
+    fn set_affinity(&mut self, irq: u32, cpu_mask: u8);
+    /// Raise SGI `sgi` on the logical cores in `cpu_mask`
+    fn send_sgi(&mut self, sgi: u32, cpu_mask: u8);
+    /// Take the highest priority pending interrupt: (ID, token for end_of_interrupt).
+    /// IDs of MAX_IRQS and up mean nothing is pending.
+    fn acknowledge(&mut self) -> (u32, u32);
+    fn end_of_interrupt(&mut self, token: u32);
+}
+
+/// What is registered on one interrupt line
+struct IrqDesc {
+    handler: Option<IrqHandler>,
+    name: &'static str,
+    flags: u32,
+    counts: [AtomicU64; MAX_CPUS],
 }
 
-impl InterruptController {
-    pub fn new(uart: &'static mut Uart) -> Self {
+impl IrqDesc {
+    const fn new() -> Self {
         Self {
-            gic_dist_base: GIC_DISTRIBUTOR_BASE,
-            gic_cpu_base: GIC_CPU_INTERFACE_BASE,
-            uart,
+            handler: None,
+            name: "",
+            flags: 0,
+            counts: [const { AtomicU64::new(0) }; MAX_CPUS],
         }
     }
+}
 
-    pub fn init(&mut self) -> Result<(), &'static str> {
-        self.uart.write_str("Initializing GIC-400 interrupt controller...\r\n");
-
-        // Initialize Distributor
-        self.init_distributor()?;
-        
-        // Initialize CPU Interface
-        self.init_cpu_interface()?;
+static mut IRQ_DESCS: [IrqDesc; MAX_IRQS] = [const { IrqDesc::new() }; MAX_IRQS];
 
-        // Enable specific interrupts
-        self.enable_interrupt(IRQ_TIMER);
-        self.enable_interrupt(IRQ_UART0);
-        self.enable_interrupt(IRQ_GPIO);
+// Interrupts nobody had registered (they are disabled when they arrive)
+static UNHANDLED_IRQS: AtomicU64 = AtomicU64::new(0);
 
-        self.uart.write_str("GIC-400 initialized successfully\r\n");
-        Ok(())
-    }
+fn desc(irq: u32) -> &'static mut IrqDesc {
+    unsafe { &mut (*core::ptr::addr_of_mut!(IRQ_DESCS))[irq as usize] }
+}
 
-    fn init_distributor(&mut self) -> Result<(), &'static str> {
-        // Disable distributor
-        self.write_distributor_reg(GICD_CTLR, 0);
-
-        // Read number of interrupt lines
-        let typer = self.read_distributor_reg(GICD_TYPER);
-        let num_lines = ((typer & 0x1F) + 1) * 32;
-        
-        // Disable all interrupts
-        for i in (0..num_lines).step_by(32) {
-            self.write_distributor_reg(GICD_ICENABLER + (i / 8), 0xFFFFFFFF);
-        }
+// The backends; set_gic_base / set_gicv3_base pick one before the other cores start
+static mut GICV2: Gicv2 = Gicv2::new(GIC_DISTRIBUTOR_BASE, GIC_CPU_INTERFACE_BASE);
+static mut GICV3: Gicv3 = Gicv3::new(0, 0, 0);
+static mut USE_GICV3: bool = false;
 
-        // Clear all pending interrupts
-        for i in (0..num_lines).step_by(32) {
-            self.write_distributor_reg(GICD_ICPENDR + (i / 8), 0xFFFFFFFF);
-        }
+// Set once init_interrupts has set up the shared state
+static mut INITIALIZED: bool = false;
 
-        // Set priority for all interrupts (lower number = higher priority)
-        for i in (0..num_lines).step_by(4) {
-            self.write_distributor_reg(GICD_IPRIORITYR + i, 0xA0A0A0A0);
+fn chip() -> &'static mut dyn IrqChip {
+    unsafe {
+        if USE_GICV3 {
+            &mut *core::ptr::addr_of_mut!(GICV3)
+        } else {
+            &mut *core::ptr::addr_of_mut!(GICV2)
         }
+    }
+}
 
-        // Set target processor to CPU0 for all interrupts
-        for i in (32..num_lines).step_by(4) {
-            self.write_distributor_reg(GICD_ITARGETSR + i, 0x01010101);
-        }
+fn controller() -> Option<&'static mut dyn IrqChip> {
+    if unsafe { INITIALIZED } { Some(chip()) } else { None }
+}
 
-        // Enable distributor
-        self.write_distributor_reg(GICD_CTLR, 1);
+fn handle_interrupt(ic: &mut dyn IrqChip) -> Option<u32> {
+    let (irq, token) = ic.acknowledge();
 
-        Ok(())
+    // Check if it's a spurious interrupt
+    if irq >= MAX_IRQS as u32 {
+        return None;
     }
 
-    fn init_cpu_interface(&mut self) -> Result<(), &'static str> {
-        // Set priority mask to allow all interrupts
-        self.write_cpu_reg(GICC_PMR, 0xFF);
+    let desc = desc(irq);
+    desc.counts[smp::cpu_id()].fetch_add(1, Ordering::Relaxed);
+    match desc.handler {
+        Some(handler) => handler(irq),
+        None => {
+            // Nobody will clear a level-sensitive source, so stop it firing
+            UNHANDLED_IRQS.fetch_add(1, Ordering::Relaxed);
+            ic.disable(irq);
+            warn!("Unhandled interrupt {}, disabled", irq);
+        }
+    }
 
-        // Enable CPU interface
-        self.write_cpu_reg(GICC_CTLR, 1);
+    ic.end_of_interrupt(token);
+    Some(irq)
+}
 
-        Ok(())
+// Called from the IRQ vectors in exception.rs
+#[no_mangle]
+extern "C" fn rust_irq_handler() {
+    if let Some(ic) = controller() {
+        // Everything that is pending, not just the interrupt that got us here
+        while handle_interrupt(ic).is_some() {}
     }
 
-    pub fn enable_interrupt(&mut self, irq: u32) {
-        let reg_offset = (irq / 32) * 4;
-        let bit_offset = irq % 32;
-        let reg_addr = GICD_ISENABLER + reg_offset;
-        
-        let current = self.read_distributor_reg(reg_addr);
-        self.write_distributor_reg(reg_addr, current | (1 << bit_offset));
+    // After EOI, so that the GIC keeps delivering to this core while another process runs
+    process::irq_exit();
+}
+
+/// Use a GICv2 with this distributor/CPU interface (must be called before init_interrupts)
+pub fn set_gic_base(dist_base: u64, cpu_base: u64) {
+    unsafe {
+        GICV2 = Gicv2::new(dist_base, cpu_base);
+        USE_GICV3 = false;
     }
+}
 
-    pub fn disable_interrupt(&mut self, irq: u32) {
-        let reg_offset = (irq / 32) * 4;
-        let bit_offset = irq % 32;
-        let reg_addr = GICD_ICENABLER + reg_offset;
-        
-        self.write_distributor_reg(reg_addr, 1 << bit_offset);
+/// Use a GICv3 with this distributor and redistributor region (must be
+/// called before init_interrupts); `redist_stride` 0 means the GICR_TYPER layout
+pub fn set_gicv3_base(dist_base: u64, redist_base: u64, redist_stride: u64) {
+    unsafe {
+        GICV3 = Gicv3::new(dist_base, redist_base, redist_stride);
+        USE_GICV3 = true;
     }
+}
 
-    pub fn handle_interrupt(&mut self) -> Option<u32> {
-        // Read interrupt acknowledge register
-        let iar = self.read_cpu_reg(GICC_IAR);
-        let irq = iar & 0x3FF;
-        
-        // Check if it's a spurious interrupt
-        if irq >= 1020 {
-            return None;
-        }
+pub fn init_interrupts() -> Result<(), &'static str> {
+    chip().init()?;
+    unsafe { INITIALIZED = true };
 
-        // Handle specific interrupts
-        match irq {
-            IRQ_TIMER => {
-                self.uart.write_str("Timer interrupt received\r\n");
-            }
-            IRQ_UART0 => {
-                self.uart.write_str("UART interrupt received\r\n");
-            }
-            IRQ_GPIO => {
-                self.uart.write_str("GPIO interrupt received\r\n");
-            }
-            _ => {
-                self.uart.write_str("Unknown interrupt: ");
-                self.uart.put_hex(irq);
-                self.uart.write_str("\r\n");
-            }
-        }
+    // Enable interrupts
+    unsafe {
+        core::arch::asm!(
+            "msr daifclr, #2"  // Clear IRQ mask
+        );
+    }
 
-        // End of interrupt
-        self.write_cpu_reg(GICC_EOIR, iar);
+    Ok(())
+}
 
-        Some(irq)
+/// Per-core interrupt setup for a secondary core; interrupts stay masked in DAIF
+pub fn init_cpu() {
+    let ic = chip();
+    if ic.init_cpu().is_err() {
+        return;
     }
 
-    fn read_distributor_reg(&self, offset: u32) -> u32 {
-        unsafe {
-            core::ptr::read_volatile((self.gic_dist_base + offset as u64) as *const u32)
+    // Per-core interrupts registered before this core came up
+    for irq in NR_SGIS..FIRST_SPI {
+        if desc(irq).handler.is_some() {
+            ic.enable(irq);
         }
     }
+}
 
-    fn write_distributor_reg(&mut self, offset: u32, value: u32) {
-        unsafe {
-            core::ptr::write_volatile((self.gic_dist_base + offset as u64) as *mut u32, value);
-        }
+/// Install `handler` for `irq` and enable it. `flags` selects the trigger
+/// (IRQF_TRIGGER_RISING or IRQF_TRIGGER_HIGH); IRQF_PERCPU marks a PPI that
+/// each core enables for itself.
+pub fn request_irq(irq: u32, handler: IrqHandler, flags: u32, name: &'static str) -> Result<(), &'static str> {
+    if irq as usize >= MAX_IRQS {
+        return Err("invalid interrupt number");
+    }
+    let ic = controller().ok_or("interrupt controller not initialized")?;
+    if irq >= FIRST_SPI && irq >= ic.num_irqs() {
+        return Err("interrupt not implemented by the GIC");
     }
 
-    fn read_cpu_reg(&self, offset: u32) -> u32 {
-        unsafe {
-            core::ptr::read_volatile((self.gic_cpu_base + offset as u64) as *const u32)
-        }
+    let daif = process::irq_save();
+    let desc = desc(irq);
+    if desc.handler.is_some() {
+        process::irq_restore(daif);
+        return Err("interrupt already requested");
     }
+    desc.handler = Some(handler);
+    desc.name = name;
+    desc.flags = flags;
 
-    fn write_cpu_reg(&mut self, offset: u32, value: u32) {
-        unsafe {
-            core::ptr::write_volatile((self.gic_cpu_base + offset as u64) as *mut u32, value);
-        }
+    if irq >= NR_SGIS {
+        ic.set_trigger(irq, flags & IRQF_TRIGGER_RISING != 0);
     }
+    ic.set_priority(irq, DEFAULT_PRIORITY);
+    if irq >= FIRST_SPI {
+        ic.set_affinity(irq, 1 << smp::cpu_id());
+    }
+    ic.enable(irq);
+    process::irq_restore(daif);
+    Ok(())
 }
 
-// Exception vector table setup
-core::arch::global_asm!(
-    "
-    .section .text._start_vectors
-    .balign 0x800
-    .global _start_vectors
-_start_vectors:
-    // Current EL with SP0
-    .balign 0x80
-    b   sync_exception_sp0
-    .balign 0x80
-    b   irq_exception_sp0
-    .balign 0x80
-    b   fiq_exception_sp0
-    .balign 0x80
-    b   serror_exception_sp0
-
-    // Current EL with SPx
-    .balign 0x80
-    b   sync_exception_spx
-    .balign 0x80
-    b   irq_exception_spx
-    .balign 0x80
-    b   fiq_exception_spx
-    .balign 0x80
-    b   serror_exception_spx
-
-    // Lower EL using AArch64
-    .balign 0x80
-    b   sync_exception_aarch64
-    .balign 0x80
-    b   irq_exception_aarch64
-    .balign 0x80
-    b   fiq_exception_aarch64
-    .balign 0x80
-    b   serror_exception_aarch64
-
-    // Lower EL using AArch32
-    .balign 0x80
-    b   sync_exception_aarch32
-    .balign 0x80
-    b   irq_exception_aarch32
-    .balign 0x80
-    b   fiq_exception_aarch32
-    .balign 0x80
-    b   serror_exception_aarch32
-
-sync_exception_sp0:
-    b   sync_exception_sp0
-
-irq_exception_sp0:
-    b   irq_handler
-
-fiq_exception_sp0:
-    b   fiq_exception_sp0
-
-serror_exception_sp0:
-    b   serror_exception_sp0
-
-sync_exception_spx:
-    b   sync_exception_spx
-
-irq_exception_spx:
-    b   irq_handler
-
-fiq_exception_spx:
-    b   fiq_exception_spx
-
-serror_exception_spx:
-    b   serror_exception_spx
-
-sync_exception_aarch64:
-    b   sync_exception_aarch64
-
-irq_exception_aarch64:
-    b   irq_handler
-
-fiq_exception_aarch64:
-    b   fiq_exception_aarch64
-
-serror_exception_aarch64:
-    b   serror_exception_aarch64
-
-sync_exception_aarch32:
-    b   sync_exception_aarch32
-
-irq_exception_aarch32:
-    b   irq_handler
-
-fiq_exception_aarch32:
-    b   fiq_exception_aarch32
-
-serror_exception_aarch32:
-    b   serror_exception_aarch32
-
-irq_handler:
-    // Save registers
-    stp x29, x30, [sp, #-16]!
-    stp x27, x28, [sp, #-16]!
-    stp x25, x26, [sp, #-16]!
-    stp x23, x24, [sp, #-16]!
-    stp x21, x22, [sp, #-16]!
-    stp x19, x20, [sp, #-16]!
-    stp x17, x18, [sp, #-16]!
-    stp x15, x16, [sp, #-16]!
-    stp x13, x14, [sp, #-16]!
-    stp x11, x12, [sp, #-16]!
-    stp x9, x10, [sp, #-16]!
-    stp x7, x8, [sp, #-16]!
-    stp x5, x6, [sp, #-16]!
-    stp x3, x4, [sp, #-16]!
-    stp x1, x2, [sp, #-16]!
-    str x0, [sp, #-16]!
-
-    // Call Rust interrupt handler
-    bl  rust_irq_handler
-
-    // Restore registers
-    ldr x0, [sp], #16
-    ldp x1, x2, [sp], #16
-    ldp x3, x4, [sp], #16
-    ldp x5, x6, [sp], #16
-    ldp x7, x8, [sp], #16
-    ldp x9, x10, [sp], #16
-    ldp x11, x12, [sp], #16
-    ldp x13, x14, [sp], #16
-    ldp x15, x16, [sp], #16
-    ldp x17, x18, [sp], #16
-    ldp x19, x20, [sp], #16
-    ldp x21, x22, [sp], #16
-    ldp x23, x24, [sp], #16
-    ldp x25, x26, [sp], #16
-    ldp x27, x28, [sp], #16
-    ldp x29, x30, [sp], #16
-
-    eret
-    "
-);
-
-// Interrupt controller instance
-static mut INTERRUPT_CONTROLLER: Option<InterruptController> = None;
-
-#[no_mangle]
-extern "C" fn rust_irq_handler() {
-    unsafe {
-        if let Some(ref mut ic) = INTERRUPT_CONTROLLER {
-            ic.handle_interrupt();
-        }
+/// Disable `irq` and remove its handler
+pub fn free_irq(irq: u32) {
+    if irq as usize >= MAX_IRQS {
+        return;
     }
+    let daif = process::irq_save();
+    if let Some(ic) = controller() {
+        ic.disable(irq);
+    }
+    let desc = desc(irq);
+    desc.handler = None;
+    desc.name = "";
+    desc.flags = 0;
+    process::irq_restore(daif);
 }
 
-pub fn init_interrupts(uart: &'static mut Uart) -> Result<(), &'static str> {
-    unsafe {
-        INTERRUPT_CONTROLLER = Some(InterruptController::new(uart));
-        if let Some(ref mut ic) = INTERRUPT_CONTROLLER {
-            ic.init()?;
-        }
+pub fn enable_irq(irq: u32) {
+    if let Some(ic) = controller() {
+        ic.enable(irq);
     }
+}
 
-    // Install vector table
-    unsafe {
-        extern "C" {
-            static _start_vectors: u8;
-        }
-        let vbar = &_start_vectors as *const u8 as u64;
-        core::arch::asm!(
-            "msr vbar_el1, {}",
-            in(reg) vbar
-        );
+pub fn disable_irq(irq: u32) {
+    if let Some(ic) = controller() {
+        ic.disable(irq);
     }
+}
 
-    // Enable interrupts
-    unsafe {
-        core::arch::asm!(
-            "msr daifclr, #2"  // Clear IRQ mask
-        );
+pub fn set_irq_priority(irq: u32, priority: u8) {
+    if let Some(ic) = controller() {
+        ic.set_priority(irq, priority);
     }
+}
 
+/// Route a shared interrupt to the logical cores in `cpu_mask`
+pub fn set_irq_affinity(irq: u32, cpu_mask: u8) -> Result<(), &'static str> {
+    if irq < FIRST_SPI || irq as usize >= MAX_IRQS {
+        return Err("only shared interrupts can be routed");
+    }
+    if cpu_mask == 0 {
+        return Err("empty CPU mask");
+    }
+    let ic = controller().ok_or("interrupt controller not initialized")?;
+    ic.set_affinity(irq, cpu_mask);
     Ok(())
 }
+
+/// Inter-processor interrupt: raise SGI `sgi` on logical core `cpu`
+pub fn send_ipi(cpu: usize, sgi: u32) {
+    if let Some(ic) = controller() {
+        ic.send_sgi(sgi, 1 << cpu);
+    }
+}
+
+/// /proc/interrupts contents: per-core counts of every requested interrupt
+pub fn proc_interrupts() -> String {
+    let cpus: alloc::vec::Vec<usize> = (0..smp::possible_cpus())
+        .filter(|&id| smp::cpu(id).online.load(Ordering::Acquire))
+        .collect();
+    let chip_name = chip().name();
+
+    let mut out = String::new();
+    let _ = write!(out, "     ");
+    for id in &cpus {
+        let _ = write!(out, "       CPU{:<2}", id);
+    }
+    let _ = writeln!(out);
+
+    for irq in 0..MAX_IRQS as u32 {
+        let desc = desc(irq);
+        if desc.handler.is_none() {
+            continue;
+        }
+        let _ = write!(out, "{:>4}:", irq);
+        for &id in &cpus {
+            let _ = write!(out, " {:>11}", desc.counts[id].load(Ordering::Relaxed));
+        }
+        let trigger = if irq < NR_SGIS || desc.flags & IRQF_TRIGGER_RISING != 0 { "Edge " } else { "Level" };
+        let _ = writeln!(out, "  {} {:>3} {}  {}", chip_name, irq, trigger, desc.name);
+    }
+    let _ = writeln!(out, " Err: {:>11}", UNHANDLED_IRQS.load(Ordering::Relaxed));
+    out
+}
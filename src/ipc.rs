@@ -1,21 +1,21 @@
 // Inter-Process Communication (IPC) for UNIX Compatibility
 // Pipes, message queues, and shared memory implementation
 
-use crate::uart::UART;
-use heapless::{String, Vec, FnvIndexMap};
+use crate::{debug, info};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 
-const MAX_PIPES: usize = 32;
-const PIPE_BUFFER_SIZE: usize = 4096;
-const MAX_MESSAGE_QUEUES: usize = 16;
-const MAX_MESSAGE_SIZE: usize = 1024;
-const MAX_MESSAGES_PER_QUEUE: usize = 16;
+// Per-object limits (POSIX semantics); the number of objects is unbounded
+const PIPE_BUFFER_SIZE: usize = 65536;     // Pipe capacity, as on Linux
+const MAX_MESSAGE_SIZE: usize = 8192;      // msgmax
+const MAX_MESSAGES_PER_QUEUE: usize = 256;
 
 // Pipe implementation
 #[derive(Debug, Clone)]
 pub struct Pipe {
     pub read_fd: i32,
     pub write_fd: i32,
-    pub buffer: Vec<u8, PIPE_BUFFER_SIZE>,
+    pub buffer: VecDeque<u8>,
     pub readers: u32,
     pub writers: u32,
     pub is_active: bool,
@@ -26,7 +26,7 @@ impl Pipe {
         Self {
             read_fd,
             write_fd,
-            buffer: Vec::new(),
+            buffer: VecDeque::new(),
             readers: 1,
             writers: 1,
             is_active: true,
@@ -41,15 +41,9 @@ impl Pipe {
         let available_space = PIPE_BUFFER_SIZE - self.buffer.len();
         let bytes_to_write = core::cmp::min(data.len(), available_space);
         
-        for i in 0..bytes_to_write {
-            if self.buffer.push(data[i]).is_err() {
-                break;
-            }
-        }
+        self.buffer.extend(&data[..bytes_to_write]);
         
-        UART.write_str("Pipe write: ");
-        UART.put_hex(bytes_to_write as u32);
-        UART.write_str(" bytes\n");
+        debug!("Pipe write: {} bytes", bytes_to_write);
         
         Ok(bytes_to_write)
     }
@@ -61,13 +55,11 @@ impl Pipe {
         
         let bytes_to_read = core::cmp::min(buf.len(), self.buffer.len());
         
-        for i in 0..bytes_to_read {
-            buf[i] = self.buffer.remove(0);
+        for (dst, src) in buf.iter_mut().zip(self.buffer.drain(..bytes_to_read)) {
+            *dst = src;
         }
         
-        UART.write_str("Pipe read: ");
-        UART.put_hex(bytes_to_read as u32);
-        UART.write_str(" bytes\n");
+        debug!("Pipe read: {} bytes", bytes_to_read);
         
         if bytes_to_read == 0 && self.writers == 0 {
             // EOF - no more writers
@@ -109,7 +101,7 @@ impl Pipe {
 pub struct Message {
     pub msg_type: i32,
     pub size: usize,
-    pub data: Vec<u8, MAX_MESSAGE_SIZE>,
+    pub data: Vec<u8>,
 }
 
 impl Message {
@@ -118,15 +110,10 @@ impl Message {
             return Err("Message too large");
         }
         
-        let mut message_data = Vec::new();
-        for &byte in data {
-            let _ = message_data.push(byte);
-        }
-        
         Ok(Self {
             msg_type,
             size: data.len(),
-            data: message_data,
+            data: data.to_vec(),
         })
     }
 }
@@ -134,7 +121,7 @@ impl Message {
 #[derive(Debug)]
 pub struct MessageQueue {
     pub id: i32,
-    pub messages: Vec<Message, MAX_MESSAGES_PER_QUEUE>,
+    pub messages: VecDeque<Message>,
     pub max_size: usize,
     pub permissions: u32,
     pub created_by: u32, // PID of creator
@@ -144,7 +131,7 @@ impl MessageQueue {
     pub fn new(id: i32, permissions: u32, creator_pid: u32) -> Self {
         Self {
             id,
-            messages: Vec::new(),
+            messages: VecDeque::new(),
             max_size: MAX_MESSAGES_PER_QUEUE,
             permissions,
             created_by: creator_pid,
@@ -152,14 +139,12 @@ impl MessageQueue {
     }
     
     pub fn send_message(&mut self, message: Message) -> Result<(), &'static str> {
-        if self.messages.is_full() {
+        if self.messages.len() >= self.max_size {
             return Err("Message queue full");
         }
         
-        let _ = self.messages.push(message);
-        UART.write_str("Message sent to queue ");
-        UART.put_hex(self.id as u32);
-        UART.write_str("\n");
+        self.messages.push_back(message);
+        debug!("Message sent to queue {}", self.id);
         
         Ok(())
     }
@@ -175,10 +160,8 @@ impl MessageQueue {
         }
         
         if let Some(i) = index {
-            let message = self.messages.remove(i);
-            UART.write_str("Message received from queue ");
-            UART.put_hex(self.id as u32);
-            UART.write_str("\n");
+            let message = self.messages.remove(i)?;
+            debug!("Message received from queue {}", self.id);
             Some(message)
         } else {
             None
@@ -195,9 +178,9 @@ impl MessageQueue {
 pub struct SharedMemorySegment {
     pub id: i32,
     pub size: usize,
-    pub data: Vec<u8, 4096>, // Simplified fixed size
+    pub data: Vec<u8>,
     pub permissions: u32,
-    pub attached_processes: Vec<u32, 32>, // PIDs of attached processes
+    pub attached_processes: Vec<u32>, // PIDs of attached processes
     pub created_by: u32,
 }
 
@@ -205,7 +188,7 @@ impl SharedMemorySegment {
     pub fn new(id: i32, size: usize, permissions: u32, creator_pid: u32) -> Self {
         Self {
             id,
-            size: core::cmp::min(size, 4096), // Cap at 4KB for simplicity
+            size,
             data: Vec::new(),
             permissions,
             attached_processes: Vec::new(),
@@ -214,22 +197,14 @@ impl SharedMemorySegment {
     }
     
     pub fn attach_process(&mut self, pid: u32) -> Result<(), &'static str> {
-        if self.attached_processes.is_full() {
-            return Err("Too many attached processes");
-        }
-        
         for &attached_pid in &self.attached_processes {
             if attached_pid == pid {
                 return Err("Process already attached");
             }
         }
         
-        let _ = self.attached_processes.push(pid);
-        UART.write_str("Process ");
-        UART.put_hex(pid);
-        UART.write_str(" attached to shared memory ");
-        UART.put_hex(self.id as u32);
-        UART.write_str("\n");
+        self.attached_processes.push(pid);
+        debug!("Process {} attached to shared memory {}", pid, self.id);
         
         Ok(())
     }
@@ -238,11 +213,7 @@ impl SharedMemorySegment {
         for (i, &attached_pid) in self.attached_processes.iter().enumerate() {
             if attached_pid == pid {
                 self.attached_processes.remove(i);
-                UART.write_str("Process ");
-                UART.put_hex(pid);
-                UART.write_str(" detached from shared memory ");
-                UART.put_hex(self.id as u32);
-                UART.write_str("\n");
+                debug!("Process {} detached from shared memory {}", pid, self.id);
                 return Ok(());
             }
         }
@@ -258,18 +229,12 @@ impl SharedMemorySegment {
         let available_space = self.size - offset;
         let bytes_to_write = core::cmp::min(data.len(), available_space);
         
-        // Ensure data vector is large enough
-        while self.data.len() < offset + bytes_to_write {
-            if self.data.push(0).is_err() {
-                break;
-            }
+        // Grow the backing store on first touch
+        if self.data.len() < offset + bytes_to_write {
+            self.data.resize(offset + bytes_to_write, 0);
         }
         
-        for i in 0..bytes_to_write {
-            if offset + i < self.data.len() {
-                self.data[offset + i] = data[i];
-            }
-        }
+        self.data[offset..offset + bytes_to_write].copy_from_slice(&data[..bytes_to_write]);
         
         Ok(bytes_to_write)
     }
@@ -296,9 +261,9 @@ impl SharedMemorySegment {
 
 // IPC Manager
 pub struct IPCManager {
-    pipes: Vec<Pipe, MAX_PIPES>,
-    message_queues: Vec<MessageQueue, MAX_MESSAGE_QUEUES>,
-    shared_memory: Vec<SharedMemorySegment, 16>,
+    pipes: Vec<Pipe>,
+    message_queues: Vec<MessageQueue>,
+    shared_memory: Vec<SharedMemorySegment>,
     next_pipe_id: i32,
     next_msgq_id: i32,
     next_shm_id: i32,
@@ -317,22 +282,14 @@ impl IPCManager {
     }
     
     pub fn create_pipe(&mut self) -> Result<(i32, i32), &'static str> {
-        if self.pipes.is_full() {
-            return Err("Too many pipes");
-        }
-        
         let read_fd = self.next_pipe_id;
         let write_fd = self.next_pipe_id + 1;
         self.next_pipe_id += 2;
         
         let pipe = Pipe::new(read_fd, write_fd);
-        let _ = self.pipes.push(pipe);
+        self.pipes.push(pipe);
         
-        UART.write_str("Created pipe: read_fd=");
-        UART.put_hex(read_fd as u32);
-        UART.write_str(", write_fd=");
-        UART.put_hex(write_fd as u32);
-        UART.write_str("\n");
+        debug!("Created pipe: read_fd={}, write_fd={}", read_fd, write_fd);
         
         Ok((read_fd, write_fd))
     }
@@ -349,10 +306,10 @@ impl IPCManager {
                 pipe.close_write_end();
             }
             
-            UART.write_str("Closed pipe fd ");
-            UART.put_hex(fd as u32);
-            UART.write_str("\n");
+            debug!("Closed pipe fd {}", fd);
             
+            // Both ends closed: nothing refers to it any more
+            self.pipes.retain(|pipe| pipe.is_active);
             Ok(())
         } else {
             Err("Pipe not found")
@@ -360,21 +317,13 @@ impl IPCManager {
     }
     
     pub fn create_message_queue(&mut self, key: i32, permissions: u32, creator_pid: u32) -> Result<i32, &'static str> {
-        if self.message_queues.is_full() {
-            return Err("Too many message queues");
-        }
-        
         let id = self.next_msgq_id;
         self.next_msgq_id += 1;
         
         let msgq = MessageQueue::new(id, permissions, creator_pid);
-        let _ = self.message_queues.push(msgq);
+        self.message_queues.push(msgq);
         
-        UART.write_str("Created message queue ");
-        UART.put_hex(id as u32);
-        UART.write_str(" with key ");
-        UART.put_hex(key as u32);
-        UART.write_str("\n");
+        debug!("Created message queue {} with key {:#x}", id, key);
         
         Ok(id)
     }
@@ -384,23 +333,13 @@ impl IPCManager {
     }
     
     pub fn create_shared_memory(&mut self, key: i32, size: usize, permissions: u32, creator_pid: u32) -> Result<i32, &'static str> {
-        if self.shared_memory.is_full() {
-            return Err("Too many shared memory segments");
-        }
-        
         let id = self.next_shm_id;
         self.next_shm_id += 1;
         
         let shm = SharedMemorySegment::new(id, size, permissions, creator_pid);
-        let _ = self.shared_memory.push(shm);
+        self.shared_memory.push(shm);
         
-        UART.write_str("Created shared memory ");
-        UART.put_hex(id as u32);
-        UART.write_str(" with key ");
-        UART.put_hex(key as u32);
-        UART.write_str(" size ");
-        UART.put_hex(size as u32);
-        UART.write_str("\n");
+        debug!("Created shared memory {} with key {:#x} size {}", id, key, size);
         
         Ok(id)
     }
@@ -424,9 +363,7 @@ impl IPCManager {
             let _ = shm.detach_process(pid);
         }
         
-        UART.write_str("Cleaned up IPC for process ");
-        UART.put_hex(pid);
-        UART.write_str("\n");
+        debug!("Cleaned up IPC for process {}", pid);
     }
     
     pub fn get_stats(&self) -> (usize, usize, usize) {
@@ -448,7 +385,7 @@ pub fn init_ipc() {
     unsafe {
         GLOBAL_IPC_MANAGER = IPCManager::new();
     }
-    UART.write_str("IPC system initialized\n");
+    info!("IPC system initialized");
 }
 
 pub fn create_pipe() -> Result<(i32, i32), &'static str> {
@@ -483,6 +420,13 @@ pub fn pipe_read(fd: i32, buf: &mut [u8]) -> Result<usize, &'static str> {
     }
 }
 
+/// Bytes buffered, and the open read and write ends
+pub fn pipe_status(fd: i32) -> Option<(usize, u32, u32)> {
+    unsafe {
+        GLOBAL_IPC_MANAGER.get_pipe_mut(fd).map(|pipe| (pipe.buffer.len(), pipe.readers, pipe.writers))
+    }
+}
+
 pub fn close_pipe(fd: i32) -> Result<(), &'static str> {
     unsafe { GLOBAL_IPC_MANAGER.close_pipe(fd) }
 }
@@ -0,0 +1,306 @@
+// Kernel log
+//
+// `error!`, `warn!`, `info!`, `debug!` and `trace!` work like the macros of
+// the `log` crate; the target of a message is the module that logs it
+// ("ipc", "syscalls", ...). Every message that passes the filter of its
+// target is stamped with the monotonic clock and kept in a fixed-size ring
+// buffer, where `dmesg` and /proc/kmsg read it; the oldest messages make
+// room for new ones. Messages below the console loglevel (loglevel= on the
+// command line, `dmesg -n`) are printed on the console UART as well, as
+// printk does.
+//
+// Filters are set with klog=<spec> on the command line or the `klog` shell
+// command: a comma separated list of <target>=<level> and a bare <level>
+// for the targets not listed, e.g. "info,ipc=off,syscalls=warn".
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use heapless::{String, Vec};
+
+use crate::cmdline;
+use crate::process;
+use crate::timer;
+use crate::uart::UART;
+
+const LOG_BUF_SIZE: usize = 16 * 1024;
+const MAX_LINE: usize = 256;          // Longest record, "<6>[    0.000000] " included
+const MAX_FILTERS: usize = 16;
+const MAX_TARGET: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// syslog priority, as in "<3>" and loglevel= (trace is debug there)
+    pub fn priority(&self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+}
+
+/// Most verbose level a target records; None is off
+pub type LevelFilter = Option<Level>;
+
+/// "off", "error" ... "trace"
+pub fn parse_filter(name: &str) -> Option<LevelFilter> {
+    if name == "off" {
+        return Some(None);
+    }
+    Level::ALL.iter().find(|level| level.name() == name).map(|&level| Some(level))
+}
+
+pub fn filter_name(filter: LevelFilter) -> &'static str {
+    filter.map_or("off", |level| level.name())
+}
+
+struct LogBuffer {
+    buf: [u8; LOG_BUF_SIZE],
+    start: u64,        // Offset of the oldest record still in `buf`
+    end: u64,          // Offset the next record goes to
+    clear: u64,        // dmesg -C: dmesg shows what comes after
+    kmsg: u64,         // Next record for /proc/kmsg
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self { buf: [0; LOG_BUF_SIZE], start: 0, end: 0, clear: 0, kmsg: 0 }
+    }
+
+    fn byte(&self, offset: u64) -> u8 {
+        self.buf[(offset % LOG_BUF_SIZE as u64) as usize]
+    }
+
+    /// Append a record (ending in '\n'), dropping the oldest ones to make room
+    fn push(&mut self, record: &[u8]) {
+        while (self.end - self.start) as usize + record.len() > LOG_BUF_SIZE {
+            while self.start < self.end {
+                let byte = self.byte(self.start);
+                self.start += 1;
+                if byte == b'\n' {
+                    break;
+                }
+            }
+        }
+        for &byte in record {
+            self.buf[(self.end % LOG_BUF_SIZE as u64) as usize] = byte;
+            self.end += 1;
+        }
+    }
+
+    fn copy_from(&self, from: u64) -> alloc::vec::Vec<u8> {
+        (from.max(self.start)..self.end).map(|offset| self.byte(offset)).collect()
+    }
+}
+
+struct Filters {
+    default: LevelFilter,
+    targets: Vec<(String<MAX_TARGET>, LevelFilter), MAX_FILTERS>,
+}
+
+impl Filters {
+    fn get(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(name, _)| name == target)
+            .map_or(self.default, |&(_, filter)| filter)
+    }
+}
+
+static LOCK: AtomicBool = AtomicBool::new(false);
+static mut LOG_BUF: LogBuffer = LogBuffer::new();
+static mut FILTERS: Filters = Filters { default: Some(Level::Debug), targets: Vec::new() };
+static mut CONSOLE_LOGLEVEL: u8 = 7;
+
+/// Run `f` on the buffer with IRQs masked and the other cores kept out
+fn with_buffer<R>(f: impl FnOnce(&mut LogBuffer) -> R) -> R {
+    let daif = process::irq_save();
+    while LOCK.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        core::hint::spin_loop();
+    }
+    let result = f(unsafe { &mut *core::ptr::addr_of_mut!(LOG_BUF) });
+    LOCK.store(false, Ordering::Release);
+    process::irq_restore(daif);
+    result
+}
+
+/// A record being formatted; what does not fit is cut off
+struct Line {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // One byte stays free for the '\n'
+        let room = MAX_LINE - 1 - self.len;
+        let mut n = s.len().min(room);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// "minimal_pi5_os::ipc" -> "ipc"
+fn target_of(module_path: &str) -> &str {
+    module_path.rsplit("::").next().unwrap_or(module_path)
+}
+
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    let filters = unsafe { &*core::ptr::addr_of!(FILTERS) };
+    filters.get(target_of(module_path)).is_some_and(|max| level <= max)
+}
+
+/// Record a message; the macros call this
+pub fn log(level: Level, module_path: &str, args: fmt::Arguments) {
+    if !enabled(level, module_path) {
+        return;
+    }
+
+    let ns = timer::monotonic_ns();
+    let mut line = Line { buf: [0; MAX_LINE], len: 0 };
+    let _ = write!(line, "<{}>[{:5}.{:06}] ", level.priority(), ns / 1_000_000_000, ns % 1_000_000_000 / 1000);
+    let prefix = line.len;
+    let _ = line.write_fmt(args);
+    while line.len > prefix && matches!(line.buf[line.len - 1], b'\n' | b'\r') {
+        line.len -= 1;
+    }
+    line.buf[line.len] = b'\n';
+    line.len += 1;
+    let record = &line.buf[..line.len];
+
+    with_buffer(|log_buf| log_buf.push(record));
+
+    if level.priority() < console_loglevel() {
+        // Without the "<N>"
+        let start = record.iter().position(|&c| c == b'>').map_or(0, |pos| pos + 1);
+        if let Ok(text) = core::str::from_utf8(&record[start..]) {
+            UART.write_str(text);
+        }
+    }
+}
+
+/// dmesg: the records since boot (or the last clear), "<N>" prefixes included
+pub fn read_all() -> alloc::vec::Vec<u8> {
+    with_buffer(|log_buf| log_buf.copy_from(log_buf.clear))
+}
+
+/// dmesg -C
+pub fn clear() {
+    with_buffer(|log_buf| log_buf.clear = log_buf.end);
+}
+
+/// /proc/kmsg: the records not read from it yet
+pub fn read_kmsg() -> alloc::vec::Vec<u8> {
+    with_buffer(|log_buf| {
+        let data = log_buf.copy_from(log_buf.kmsg);
+        log_buf.kmsg = log_buf.end;
+        data
+    })
+}
+
+pub fn console_loglevel() -> u8 {
+    unsafe { CONSOLE_LOGLEVEL }
+}
+
+/// dmesg -n
+pub fn set_console_loglevel(level: u8) {
+    unsafe { CONSOLE_LOGLEVEL = level };
+}
+
+/// Apply a filter spec, "info,ipc=off,syscalls=warn"; nothing changes if it is invalid
+pub fn set_filters(spec: &str) -> Result<(), &'static str> {
+    let current = unsafe { &*core::ptr::addr_of!(FILTERS) };
+    let mut filters = Filters { default: current.default, targets: current.targets.clone() };
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (target, level) = match item.find('=') {
+            Some(pos) => (Some(&item[..pos]), &item[pos + 1..]),
+            None => (None, item),
+        };
+        let filter = parse_filter(level).ok_or("unknown level")?;
+        match target {
+            None => filters.default = filter,
+            Some(target) => {
+                let mut name = String::new();
+                name.push_str(target).map_err(|_| "target name too long")?;
+                match filters.targets.iter_mut().find(|(n, _)| *n == name) {
+                    Some(entry) => entry.1 = filter,
+                    None => filters.targets.push((name, filter)).map_err(|_| "too many filters")?,
+                }
+            }
+        }
+    }
+    unsafe { FILTERS = filters };
+    Ok(())
+}
+
+/// Level for the targets without a filter of their own, and those filters
+pub fn filters() -> (LevelFilter, &'static [(String<MAX_TARGET>, LevelFilter)]) {
+    let filters = unsafe { &*core::ptr::addr_of!(FILTERS) };
+    (filters.default, &filters.targets)
+}
+
+/// Console loglevel and filters from the command line
+pub fn init() {
+    let params = cmdline::params();
+    set_console_loglevel(params.loglevel);
+    if let Err(e) = set_filters(params.klog) {
+        crate::warn!("klog: {} in '{}', ignored", e, params.klog);
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::klog::log($level, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::klog::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::klog::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::klog::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::klog::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::klog::Level::Trace, $($arg)+) };
+}
@@ -0,0 +1,230 @@
+// Kernel Timers for Raspberry Pi 5
+// Sleep queue, alarm() and interval timers on the monotonic clock
+//
+// Deadlines are kept in a min-heap; the earliest one is programmed into the
+// generic timer as a one-shot (timer::set_oneshot) and its interrupt runs
+// every entry that has expired. Entries are never taken out of the heap:
+// waking a sleeper early or re-arming an alarm changes the sequence number
+// recorded for the process, and entries that no longer match are dropped
+// when they reach the top.
+//
+// setitimer(ITIMER_REAL) has one timer per process, which the C library's
+// alarm() also uses (arm64 has no alarm syscall); it delivers SIGALRM
+// through `signals`.
+
+use alloc::collections::{BTreeMap, BinaryHeap};
+use core::cmp::{Ordering, Reverse};
+
+use crate::process::{self, PROCESS_MANAGER, ProcessState};
+use crate::signals::{self, Signal};
+use crate::timer;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Wake(u32),   // Make a sleeping process ready
+    Alarm(u32),  // SIGALRM for the process's real-time timer
+}
+
+#[derive(PartialEq, Eq)]
+struct Entry {
+    deadline: u64,
+    seq: u64,
+    action: Action,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// ITIMER_REAL / alarm() state of one process
+#[derive(Clone, Copy)]
+struct RealTimer {
+    deadline: u64,
+    interval: u64,
+    seq: u64,
+}
+
+struct TimerQueue {
+    heap: BinaryHeap<Reverse<Entry>>,
+    sleepers: BTreeMap<u32, u64>,       // pid -> seq of its wake-up entry
+    real_timers: BTreeMap<u32, RealTimer>,
+    next_seq: u64,
+}
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            sleepers: BTreeMap::new(),
+            real_timers: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    fn push(&mut self, deadline: u64, action: Action) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Reverse(Entry { deadline, seq, action }));
+        seq
+    }
+
+    /// Program the one-shot for the earliest entry
+    fn arm(&self) {
+        match self.heap.peek() {
+            Some(Reverse(entry)) => timer::set_oneshot(entry.deadline, expire_timers),
+            None => timer::cancel_oneshot(),
+        }
+    }
+
+    fn expire(&mut self, now: u64) {
+        while let Some(Reverse(entry)) = self.heap.peek() {
+            if entry.deadline > now {
+                break;
+            }
+            let Reverse(entry) = self.heap.pop().unwrap();
+            match entry.action {
+                Action::Wake(pid) => {
+                    if self.sleepers.get(&pid) == Some(&entry.seq) {
+                        self.sleepers.remove(&pid);
+                        wake_process(pid);
+                    }
+                }
+                Action::Alarm(pid) => self.fire_alarm(pid, entry.seq, now),
+            }
+        }
+    }
+
+    fn fire_alarm(&mut self, pid: u32, seq: u64, now: u64) {
+        let timer = match self.real_timers.get(&pid) {
+            Some(timer) if timer.seq == seq => *timer,
+            _ => return,
+        };
+
+        if !process_alive(pid) {
+            self.real_timers.remove(&pid);
+            return;
+        }
+
+        if let Some(missed) = (now - timer.deadline).checked_div(timer.interval) {
+            // Periodic: the next expiry is one interval on, skipping any that were missed
+            let deadline = timer.deadline + (missed + 1) * timer.interval;
+            let seq = self.push(deadline, Action::Alarm(pid));
+            self.real_timers.insert(pid, RealTimer { deadline, interval: timer.interval, seq });
+        } else {
+            self.real_timers.remove(&pid);
+        }
+
+        let _ = signals::send_signal(pid, Signal::SIGALRM as i32, 0);
+    }
+
+    /// Replace the real-time timer of `pid`; returns the old (remaining, interval)
+    fn set_real_timer(&mut self, pid: u32, value: u64, interval: u64, now: u64) -> (u64, u64) {
+        let old = self.real_timers.remove(&pid)
+            .map_or((0, 0), |t| (t.deadline.saturating_sub(now).max(1), t.interval));
+
+        if value > 0 {
+            let deadline = now + value;
+            let seq = self.push(deadline, Action::Alarm(pid));
+            self.real_timers.insert(pid, RealTimer { deadline, interval, seq });
+        }
+        self.arm();
+        old
+    }
+}
+
+static mut QUEUE: TimerQueue = TimerQueue::new();
+
+fn queue() -> &'static mut TimerQueue {
+    unsafe { &mut *core::ptr::addr_of_mut!(QUEUE) }
+}
+
+fn process_alive(pid: u32) -> bool {
+    unsafe {
+        PROCESS_MANAGER.get_process(pid)
+            .is_some_and(|p| p.state != ProcessState::Terminated)
+    }
+}
+
+fn wake_process(pid: u32) {
+    unsafe {
+        if PROCESS_MANAGER.get_process(pid).is_some_and(|p| p.state == ProcessState::Sleeping) {
+            PROCESS_MANAGER.set_process_state(pid, ProcessState::Ready);
+        }
+    }
+}
+
+/// One-shot handler, in interrupt context
+fn expire_timers() {
+    let queue = queue();
+    queue.expire(timer::monotonic_ns());
+    queue.arm();
+}
+
+/// Sleep the current process until the monotonic clock reaches `deadline`.
+/// Returns false if it was made runnable before then (e.g. by SIGCONT).
+pub fn sleep_until(deadline: u64) -> bool {
+    if !process::scheduler_running() {
+        // No timer interrupts yet: wait on the event stream instead
+        while timer::monotonic_ns() < deadline {
+            unsafe { core::arch::asm!("wfe") };
+        }
+        return true;
+    }
+
+    let daif = process::irq_save();
+    let pid = unsafe { PROCESS_MANAGER.current_pid() };
+
+    let sleep_queue = queue();
+    let seq = sleep_queue.push(deadline, Action::Wake(pid));
+    sleep_queue.sleepers.insert(pid, seq);
+    sleep_queue.arm();
+    unsafe { PROCESS_MANAGER.set_process_state(pid, ProcessState::Sleeping) };
+
+    // Runs other processes (or waits for interrupts) until we are woken
+    process::schedule();
+
+    // The expiry removes the entry; still there means we were woken early
+    let completed = queue().sleepers.remove(&pid).is_none();
+    process::irq_restore(daif);
+    completed
+}
+
+/// Sleep for `ns` nanoseconds; returns the time left if woken early, else 0
+pub fn sleep_ns(ns: u64) -> u64 {
+    let deadline = timer::monotonic_ns().saturating_add(ns);
+    if sleep_until(deadline) {
+        0
+    } else {
+        deadline.saturating_sub(timer::monotonic_ns())
+    }
+}
+
+/// setitimer(ITIMER_REAL): returns the old (remaining, interval) in nanoseconds
+pub fn set_itimer_real(value: u64, interval: u64) -> (u64, u64) {
+    let daif = process::irq_save();
+    let pid = unsafe { PROCESS_MANAGER.current_pid() };
+    let old = queue().set_real_timer(pid, value, interval, timer::monotonic_ns());
+    process::irq_restore(daif);
+    old
+}
+
+/// getitimer(ITIMER_REAL): (remaining, interval) in nanoseconds
+pub fn get_itimer_real() -> (u64, u64) {
+    let daif = process::irq_save();
+    let pid = unsafe { PROCESS_MANAGER.current_pid() };
+    let now = timer::monotonic_ns();
+    let value = queue().real_timers.get(&pid)
+        .map_or((0, 0), |t| (t.deadline.saturating_sub(now).max(1), t.interval));
+    process::irq_restore(daif);
+    value
+}
@@ -3,14 +3,34 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 global_asm!(include_str!("startup.s"));
 
+mod klog;
 mod uart;
+mod termios;
+mod tty;
+mod pty;
+mod dtb;
+mod cmdline;
 mod mmu;
+mod memory;
+mod heap;
+mod smp;
 mod process;
+mod elf;
+mod file;
+mod uaccess;
+mod initramfs;
 mod timer;
+mod ktimer;
+mod rtc;
 mod shell;
 mod interrupt;
+mod gicv2;
+mod gicv3;
+mod exception;
 mod gpio;
 mod filesystem;
 mod syscalls;
@@ -34,6 +54,9 @@ use users::UserManager;
 // Panic handler - pi5_hack style
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Flush queued output and write the rest directly
+    uart::disable_interrupts();
+
     // Try to print the panic info if possible
     UART.write_str("\n\n*** KERNEL PANIC ***\n");
     if let Some(location) = info.location() {
@@ -83,31 +106,65 @@ fn clear_bss() {
     }
 }
 
+// startup.s drops from EL2 to EL1 so that vbar_el1 and the EL1 timer are the
+// ones in effect; say where we started and where we ended up
+fn report_exception_level(boot_el: u64) {
+    let el = mmu::current_el();
+
+    if el != 1 {
+        warn!("Warning: running at EL{}, expected EL1", el);
+    } else {
+        info!("Exception level: EL1 (entered at EL{})", boot_el);
+    }
+}
+
+// Point the drivers at the devices described by the device tree
+fn configure_platform() {
+    let info = dtb::platform();
+
+    if let Some(base) = info.uart_base {
+        if base != uart::base() {
+            uart::set_base(base);
+            info!("Console switched to the DTB stdout UART");
+        }
+        if let Some(irq) = info.uart_irq {
+            uart::set_irq(irq);
+        }
+        if let Some(hz) = info.uart_clock {
+            uart::set_clock(hz);
+        }
+    }
+    if let (Some(dist), Some(redist)) = (info.gic_dist_base, info.gic_redist_base) {
+        interrupt::set_gicv3_base(dist, redist, info.gic_redist_stride);
+    } else if let (Some(dist), Some(cpu)) = (info.gic_dist_base, info.gic_cpu_base) {
+        interrupt::set_gic_base(dist, cpu);
+    }
+    if let Some(base) = info.gpio_base {
+        gpio::set_base(base);
+    }
+}
+
 // Initialize UNIX subsystems
 fn init_unix_subsystems() {
-    UART.write_str("Initializing UNIX subsystems...\r\n");
+    info!("Initializing UNIX subsystems...");
     
     // Initialize syscall manager
-    UART.write_str("  - System calls: ");
     init_syscalls();
-    UART.write_str("OK\r\n");
+    info!("  - System calls: OK");
     
     // Initialize signal manager
-    UART.write_str("  - Signal handling: ");
     let signal_handler = SignalHandler::new();
-    UART.write_str("OK\r\n");
+    info!("  - Signal handling: OK");
     
     // Initialize IPC manager
-    UART.write_str("  - Inter-process communication: ");
     let ipc_manager = IPCManager::new();
-    UART.write_str("OK\r\n");
+    info!("  - Inter-process communication: OK");
     
     // Initialize user manager with root user
-    UART.write_str("  - User management: ");
     let mut user_manager = UserManager::new();
-    UART.write_str("OK\r\n");
+    info!("  - User management: OK");
     
-    UART.write_str("UNIX subsystems initialized!\r\n\r\n");
+    info!("UNIX subsystems initialized!");
 }
 
 // Basic memory test
@@ -148,12 +205,12 @@ fn test_timer_functions() {
 fn test_gpio_functions() {
     UART.write_str("  GPIO basic test: ");
     
-    // Pi5のGPIO基本アドレス（BCM2712）
-    const GPIO_BASE: u64 = 0x107d200000;
+    // Pi5のGPIO（RP1、デバイスツリーの値）: MMUでマップされている
+    let gpio_base = gpio::base();
     
     unsafe {
         // 簡単なGPIOアクセステスト（読み取りのみ）
-        let gpio_status = core::ptr::read_volatile(GPIO_BASE as *const u32);
+        let gpio_status = core::ptr::read_volatile(gpio_base as *const u32);
         UART.write_str("status=");
         UART.put_hex(gpio_status);
         UART.write_str(" ");
@@ -164,22 +221,83 @@ fn test_gpio_functions() {
 
 // Pi5Hack OS - main entry point (exact style from pi5_hack)
 #[no_mangle]
-pub extern "C" fn rust_main() -> ! {
+pub extern "C" fn rust_main(dtb_addr: u64, boot_el: u64) -> ! {
     // Clear BSS first
     clear_bss();
 
-    // Initialize UART using pi5_hack method
-    unsafe {
-        if let Err(_) = UART.init() {
-            loop { core::arch::asm!("wfe"); }
+    // Hardware description from the firmware; fall back to the Pi 5 defaults
+    let dtb_result = dtb::init(dtb_addr);
+
+    // Kernel command line from /chosen/bootargs (or the built-in default)
+    cmdline::init(dtb::bootargs());
+    klog::init();
+
+    match dtb_result {
+        Ok(()) => {
+            // console=ttyAMA<N> picks the UART by its serial<N> alias
+            let console = cmdline::params().console;
+            if let Err(e) = dtb::select_console(console) {
+                warn!("console={}: {}, keeping the stdout-path UART", console, e);
+            }
+            configure_platform();
+            dtb::report();
         }
+        Err(e) => warn!("DTB: {}, using built-in Raspberry Pi 5 addresses", e),
     }
-    
-    // Comprehensive test loop with all hardware tests
-    const MAX_TEST_CYCLES: u32 = 1; // Run 3 test cycles before starting shell
+
+    // console=pl011,<addr> overrides the UART chosen by the device tree
+    if let Some(addr) = cmdline::params().console_addr {
+        uart::set_base(addr);
+    }
+
+    // Baud rate and framing; output so far relied on the bootloader's setup
+    if let Err(e) = unsafe { UART.init() } {
+        warn!("UART: {}, keeping the firmware settings", e);
+    }
+    // Line discipline for the console (stdin/stdout of the shell and user processes)
+    tty::init();
+    info!("Kernel command line: {}", cmdline::raw());
+    report_exception_level(boot_el);
+
+    // Exception vectors, so that faults are reported instead of hanging
+    exception::init_exceptions();
+
+    // Generic timer: monotonic clock from here on
+    timer::init();
+
+    // Per-CPU data for the boot core
+    smp::init_smp();
+
+    // Physical page frame allocator (translation tables come from it)
+    memory::init_memory();
+
+    // Programs the firmware loaded with the kernel (the DTB reserved their memory)
+    initramfs::init();
+
+    // Build translation tables and enable the MMU and caches
+    if let Err(e) = mmu::Mmu::init() {
+        warn!("MMU: {}, continuing with MMU off", e);
+    }
+
+    // Kernel heap for alloc::vec::Vec / alloc::string::String
+    if let Err(e) = heap::init_heap() {
+        panic!("{}", e);
+    }
+
+    // Wall clock from the RTC (the device is mapped by now)
+    match rtc::init() {
+        Ok(()) => info!("RTC: {}, {}", rtc::device_name(), rtc::now()),
+        Err(e) => warn!("RTC: {}", e),
+    }
+
+    // Bring the other cores up; they wait in their idle loops
+    smp::start_secondary_cpus();
+
+    // Comprehensive test loop with all hardware tests (selftest=<cycles>, 0 skips it)
+    let test_cycles = cmdline::params().selftest_cycles;
     let mut counter = 0u32;
     
-    while counter < MAX_TEST_CYCLES {
+    while counter < test_cycles {
         UART.write_str("=== PI5HACK-BOOT CYCLE ");
         UART.put_hex(counter);
         UART.write_str(" ===\r\n");
@@ -221,7 +339,7 @@ pub extern "C" fn rust_main() -> ! {
         
         counter = counter.wrapping_add(1);
         
-        if counter < MAX_TEST_CYCLES {
+        if counter < test_cycles {
             // Delay between test cycles
             UART.write_str("Waiting 3 seconds for next cycle...\r\n");
             for _ in 0..144_000_000 { // ~3 seconds at 48MHz
@@ -232,17 +350,39 @@ pub extern "C" fn rust_main() -> ! {
     
     // Hardware tests completed, initialize UNIX subsystems
     init_unix_subsystems();
+
+    // Interrupts and the preemptive scheduler; this thread becomes PID 1
+    match interrupt::init_interrupts().and_then(|()| timer::init_irq()) {
+        Ok(()) => {
+            process::init_scheduler();
+
+            // Console input by interrupt instead of polling
+            if let Err(e) = uart::enable_interrupts() {
+                warn!("UART: {}, staying in polled mode", e);
+            }
+        }
+        Err(e) => warn!("GIC: {}, no preemptive scheduling", e),
+    }
     
     // Start interactive shell
-    UART.write_str("\r\n");
-    UART.write_str("========================================\r\n");
-    UART.write_str("   UNIX-COMPATIBLE OS READY!           \r\n");
-    UART.write_str("   Starting Interactive Shell...       \r\n");
-    UART.write_str("========================================\r\n");
-    UART.write_str("\r\n");
+    info!("========================================");
+    info!("   UNIX-COMPATIBLE OS READY!");
+    info!("   Starting Interactive Shell...");
+    info!("========================================");
+
+    // init= names the first program: one from the initramfs runs in the
+    // foreground, and the built-in shell takes over when it is gone
+    let mut shell = shell::Shell::new();
+    let init = cmdline::params().init;
+    if !matches!(init, "/bin/sh" | "sh") {
+        if shell.run_program(init, &[]) {
+            warn!("init: {} exited, starting /bin/sh", init);
+        } else {
+            error!("init: {} not found, starting /bin/sh", init);
+        }
+    }
     
     // Start the interactive shell
-    let mut shell = shell::Shell::new();
     shell.run();
     
     // Shell has exited (e.g., user typed 'exit'), show shutdown message
@@ -250,6 +390,7 @@ pub extern "C" fn rust_main() -> ! {
     UART.write_str("========================================\r\n");
     UART.write_str("   SHELL EXITED - SYSTEM SHUTDOWN       \r\n");
     UART.write_str("========================================\r\n");
+    uart::flush();
     
     // Infinite loop since rust_main is declared as -> ! (never returns)
     loop {
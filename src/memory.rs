@@ -0,0 +1,277 @@
+// Physical Memory Management for Raspberry Pi 5
+// Bitmap page frame allocator for 4KB frames
+//
+// Frames below _KERNEL_END (firmware area, kernel image, boot stack) are
+// never handed out. The bitmap itself lives in the first frames after the
+// kernel image and is sized for the amount of RAM being managed.
+//
+// RAM regions come from the device tree; the allocator covers [0, end of
+// highest region) and holes between regions are kept reserved.
+//
+// A frame mapped by several user address spaces at once (fork shares pages
+// copy-on-write) is counted in SHARED_FRAMES and only freed when the last
+// mapping releases it.
+
+use alloc::collections::BTreeMap;
+
+use crate::dtb;
+use crate::mmu::{self, PAGE_SIZE};
+use crate::info;
+
+// RAM assumed when no device tree is available (config.txt total_mem=1024)
+const DEFAULT_RAM_SIZE: u64 = 0x4000_0000;
+const DEFAULT_RAM_REGIONS: [(u64, u64); 1] = [(0, DEFAULT_RAM_SIZE)];
+
+const BITS_PER_WORD: u64 = 64;
+
+// Linker script symbol: end of kernel image including the boot stack
+extern "C" {
+    static _KERNEL_END: u8;
+}
+
+pub struct FrameAllocator {
+    bitmap: *mut u64,      // One bit per frame, 1 = in use
+    total_frames: u64,
+    free_frames: u64,
+    next_hint: u64,        // Where the next single-frame search starts
+}
+
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        Self {
+            bitmap: core::ptr::null_mut(),
+            total_frames: 0,
+            free_frames: 0,
+            next_hint: 0,
+        }
+    }
+
+    /// Manage [0, ram_size), placing the bitmap at `bitmap_addr`
+    pub fn init(&mut self, ram_size: u64, bitmap_addr: u64) {
+        self.total_frames = ram_size / PAGE_SIZE;
+        self.bitmap = bitmap_addr as *mut u64;
+
+        let words = self.bitmap_words();
+        unsafe {
+            core::ptr::write_bytes(self.bitmap, 0, words as usize);
+        }
+        self.free_frames = self.total_frames;
+
+        // The bitmap occupies frames too
+        let bitmap_end = align_up(bitmap_addr + words * 8, PAGE_SIZE);
+        self.reserve_range(0, bitmap_end);
+        self.next_hint = bitmap_end / PAGE_SIZE;
+
+        if !mmu::Mmu::is_enabled() {
+            mmu::clean_invalidate_dcache_range(bitmap_addr, bitmap_end);
+        }
+    }
+
+    fn bitmap_words(&self) -> u64 {
+        self.total_frames.div_ceil(BITS_PER_WORD)
+    }
+
+    fn is_used(&self, frame: u64) -> bool {
+        unsafe {
+            let word = *self.bitmap.add((frame / BITS_PER_WORD) as usize);
+            word & (1 << (frame % BITS_PER_WORD)) != 0
+        }
+    }
+
+    fn set_used(&mut self, frame: u64, used: bool) {
+        unsafe {
+            let word = self.bitmap.add((frame / BITS_PER_WORD) as usize);
+            let bit = 1u64 << (frame % BITS_PER_WORD);
+            if used {
+                *word |= bit;
+            } else {
+                *word &= !bit;
+            }
+        }
+    }
+
+    /// Mark [start, end) as unavailable (firmware, MMIO holes, ...)
+    pub fn reserve_range(&mut self, start: u64, end: u64) {
+        let first = start / PAGE_SIZE;
+        let last = core::cmp::min(align_up(end, PAGE_SIZE) / PAGE_SIZE, self.total_frames);
+        for frame in first..last {
+            if !self.is_used(frame) {
+                self.set_used(frame, true);
+                self.free_frames -= 1;
+            }
+        }
+    }
+
+    /// Allocate one 4KB frame and return its physical address
+    pub fn alloc_frame(&mut self) -> Option<u64> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        for i in 0..self.total_frames {
+            let frame = (self.next_hint + i) % self.total_frames;
+            if !self.is_used(frame) {
+                self.set_used(frame, true);
+                self.free_frames -= 1;
+                self.next_hint = frame + 1;
+                return Some(frame * PAGE_SIZE);
+            }
+        }
+
+        None
+    }
+
+    /// Allocate `count` physically contiguous frames
+    pub fn alloc_frames(&mut self, count: u64) -> Option<u64> {
+        if count == 0 || count > self.free_frames {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        for frame in 0..self.total_frames {
+            if self.is_used(frame) {
+                run_len = 0;
+                continue;
+            }
+            if run_len == 0 {
+                run_start = frame;
+            }
+            run_len += 1;
+            if run_len == count {
+                for f in run_start..run_start + count {
+                    self.set_used(f, true);
+                }
+                self.free_frames -= count;
+                return Some(run_start * PAGE_SIZE);
+            }
+        }
+
+        None
+    }
+
+    pub fn free_frame(&mut self, addr: u64) {
+        self.free_frames_at(addr, 1);
+    }
+
+    /// Release `count` frames starting at `addr`
+    pub fn free_frames_at(&mut self, addr: u64, count: u64) {
+        let first = addr / PAGE_SIZE;
+        for frame in first..core::cmp::min(first + count, self.total_frames) {
+            if self.is_used(frame) {
+                self.set_used(frame, false);
+                self.free_frames += 1;
+            }
+        }
+        if first < self.next_hint {
+            self.next_hint = first;
+        }
+    }
+
+    /// (total, free) in bytes
+    pub fn get_stats(&self) -> (u64, u64) {
+        (self.total_frames * PAGE_SIZE, self.free_frames * PAGE_SIZE)
+    }
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+// Global frame allocator
+static mut FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
+static mut RAM_SIZE: u64 = DEFAULT_RAM_SIZE;
+// Extra mappings of shared frames; a frame that is not listed has one
+static mut SHARED_FRAMES: BTreeMap<u64, u32> = BTreeMap::new();
+
+/// RAM as (base, size) regions: from the device tree, or the built-in default
+pub fn ram_regions() -> &'static [(u64, u64)] {
+    let regions = dtb::platform().memory_regions();
+    if regions.is_empty() {
+        &DEFAULT_RAM_REGIONS
+    } else {
+        regions
+    }
+}
+
+pub fn init_memory() {
+    let regions = ram_regions();
+    unsafe {
+        RAM_SIZE = regions.iter().map(|&(base, size)| base + size).max().unwrap_or(DEFAULT_RAM_SIZE);
+
+        let kernel_end = &_KERNEL_END as *const u8 as u64;
+        FRAME_ALLOCATOR.init(RAM_SIZE, align_up(kernel_end, PAGE_SIZE));
+
+        // Anything between the RAM regions is not RAM
+        let mut addr = 0;
+        while addr < RAM_SIZE {
+            if let Some(&(base, size)) = regions.iter().find(|&&(base, size)| addr >= base && addr < base + size) {
+                addr = base + size;
+                continue;
+            }
+            let next = regions.iter().map(|&(base, _)| base).filter(|&base| base > addr).min().unwrap_or(RAM_SIZE);
+            FRAME_ALLOCATOR.reserve_range(addr, next);
+            addr = next;
+        }
+
+        // The device tree blob and the memory it marks as reserved
+        for &(base, size) in dtb::platform().reserved_regions() {
+            FRAME_ALLOCATOR.reserve_range(base, base + size);
+        }
+
+        // MMIO windows that fall inside the RAM range are not RAM
+        for &(base, size) in mmu::device_regions() {
+            if base < RAM_SIZE {
+                FRAME_ALLOCATOR.reserve_range(base, base + size);
+            }
+        }
+    }
+
+    let (total, free) = get_memory_stats();
+    info!("Physical memory: {} kB total, {} kB free", total / 1024, free / 1024);
+}
+
+pub fn alloc_frame() -> Option<u64> {
+    unsafe { FRAME_ALLOCATOR.alloc_frame() }
+}
+
+pub fn alloc_frames(count: u64) -> Option<u64> {
+    unsafe { FRAME_ALLOCATOR.alloc_frames(count) }
+}
+
+pub fn free_frame(addr: u64) {
+    unsafe { FRAME_ALLOCATOR.free_frame(addr) }
+}
+
+pub fn free_frames(addr: u64, count: u64) {
+    unsafe { FRAME_ALLOCATOR.free_frames_at(addr, count) }
+}
+
+/// (total, free) physical memory in bytes
+pub fn get_memory_stats() -> (u64, u64) {
+    unsafe { FRAME_ALLOCATOR.get_stats() }
+}
+
+/// Another address space maps the frame at `addr`
+pub fn share_frame(addr: u64) {
+    unsafe {
+        *(*core::ptr::addr_of_mut!(SHARED_FRAMES)).entry(addr).or_insert(0) += 1;
+    }
+}
+
+/// Is the frame at `addr` mapped more than once?
+pub fn is_frame_shared(addr: u64) -> bool {
+    unsafe { (*core::ptr::addr_of!(SHARED_FRAMES)).contains_key(&addr) }
+}
+
+/// A mapping of the frame at `addr` went away; the last one frees it
+pub fn release_frame(addr: u64) {
+    let shared = unsafe { &mut *core::ptr::addr_of_mut!(SHARED_FRAMES) };
+    match shared.get_mut(&addr) {
+        Some(count) if *count > 1 => *count -= 1,
+        Some(_) => {
+            shared.remove(&addr);
+        }
+        None => free_frame(addr),
+    }
+}
@@ -1,12 +1,700 @@
 // Memory Management Unit (MMU) for Raspberry Pi 5
 // UNIX-like virtual memory management
+//
+// 4KB granule, 48-bit virtual addresses, 4-level translation tables.
+//
+// Address space layout:
+//   TTBR1 (0xFFFF_0000_0000_0000 - ) : linear map of all RAM, kernel only
+//   TTBR0 L0[0]   (0 - 512GB)        : identity map of RAM and MMIO, kernel only
+//   TTBR0 L0[1..] (512GB - 256TB)    : user space, one table per address space
+//
+// The kernel keeps running from its identity mapping, so every user address
+// space shares the kernel's L0[0] entry and only owns the entries above it.
+//
+// fork clones a user address space copy-on-write: both copies map the same
+// frames, and the writable pages become read-only with PTE_COW set in both.
+// The first write to such a page faults, and `resolve_cow_fault` gives the
+// writer a copy of its own (or the page itself, once nobody else maps it).
+
+use crate::dtb;
+use crate::gpio;
+use crate::memory;
+use crate::uart;
+use crate::info;
+
+pub const PAGE_SIZE: u64 = 4096;
+const BLOCK_SIZE_2M: u64 = 0x20_0000;
+const BLOCK_SIZE_1G: u64 = 0x4000_0000;
+const ENTRIES_PER_TABLE: usize = 512;
+
+// Kernel linear map in the upper half (TTBR1)
+pub const KERNEL_VIRT_BASE: u64 = 0xFFFF_0000_0000_0000;
+
+// User space range in the lower half (TTBR0), above the kernel's L0[0] entry
+pub const USER_SPACE_BASE: u64 = 0x0000_0080_0000_0000;
+pub const USER_SPACE_END: u64 = 0x0001_0000_0000_0000;
+
+// Translation table descriptor bits
+const PTE_VALID: u64 = 1 << 0;
+const PTE_TABLE: u64 = 1 << 1;     // Table descriptor (L0-L2) / page descriptor (L3)
+const PTE_ATTR_SHIFT: u64 = 2;     // AttrIndx[2:0]
+const PTE_AP_EL0: u64 = 1 << 6;    // AP[1]: accessible from EL0
+const PTE_AP_RO: u64 = 1 << 7;     // AP[2]: read-only
+const PTE_SH_INNER: u64 = 3 << 8;  // Inner shareable
+const PTE_AF: u64 = 1 << 10;       // Access flag
+const PTE_NG: u64 = 1 << 11;       // Not global (per-ASID)
+const PTE_PXN: u64 = 1 << 53;      // Privileged execute-never
+const PTE_UXN: u64 = 1 << 54;      // Unprivileged execute-never
+const PTE_COW: u64 = 1 << 55;      // Software bit: read-only until written, then copied
+const PTE_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+// MAIR_EL1 attribute indexes
+const MAIR_IDX_DEVICE: u64 = 0;    // Device-nGnRE
+const MAIR_IDX_NORMAL: u64 = 1;    // Normal, Write-Back Read/Write-Allocate
+const MAIR_IDX_NORMAL_NC: u64 = 2; // Normal, Non-cacheable
+const MAIR_VALUE: u64 = (0x04 << (8 * MAIR_IDX_DEVICE))
+    | (0xFF << (8 * MAIR_IDX_NORMAL))
+    | (0x44 << (8 * MAIR_IDX_NORMAL_NC));
+
+// TCR_EL1 fields
+const TCR_T0SZ: u64 = 16;               // 48-bit TTBR0 region
+const TCR_T1SZ: u64 = 16 << 16;         // 48-bit TTBR1 region
+const TCR_IRGN0_WBWA: u64 = 1 << 8;
+const TCR_ORGN0_WBWA: u64 = 1 << 10;
+const TCR_SH0_INNER: u64 = 3 << 12;
+const TCR_TG0_4K: u64 = 0 << 14;
+const TCR_IRGN1_WBWA: u64 = 1 << 24;
+const TCR_ORGN1_WBWA: u64 = 1 << 26;
+const TCR_SH1_INNER: u64 = 3 << 28;
+const TCR_TG1_4K: u64 = 2 << 30;
+const TCR_IPS_SHIFT: u64 = 32;
+
+// SCTLR_EL1 bits
+const SCTLR_M: u64 = 1 << 0;   // MMU enable
+const SCTLR_A: u64 = 1 << 1;   // Alignment check
+const SCTLR_C: u64 = 1 << 2;   // Data cache enable
+const SCTLR_I: u64 = 1 << 12;  // Instruction cache enable
+const SCTLR_WXN: u64 = 1 << 19;
+
+// MMIO windows mapped as Device-nGnRE without a device tree (base, size):
+// where the drivers' built-in addresses are
+const DEFAULT_DEVICE_REGIONS: [(u64, u64); 3] = [
+    (0x10_7c00_0000, 0x0400_0000), // BCM2712 peripherals (UART at 0x10_7d00_1000, GIO)
+    (0x1f_0000_0000, 0x0040_0000), // RP1 peripherals (GPIO at 0x1f_000d_0000)
+    (0x0000_2000_0000, BLOCK_SIZE_2M), // GIC-400 distributor/CPU interface
+];
+
+/// MMIO windows to map as Device-nGnRE: the devices the device tree
+/// describes, or the built-in Raspberry Pi 5 windows without one
+pub fn device_regions() -> &'static [(u64, u64)] {
+    match dtb::platform().mmio_regions() {
+        [] => &DEFAULT_DEVICE_REGIONS,
+        regions => regions,
+    }
+}
+
+/// Kind of memory being mapped; selects attributes and permissions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryKind {
+    KernelText,   // RX, kernel only
+    KernelRodata, // R, kernel only
+    KernelData,   // RW, kernel only
+    Device,       // RW Device-nGnRE, never executable
+    UserText,     // RX for EL0
+    UserRodata,   // R for EL0
+    UserData,     // RW for EL0
+}
+
+impl MemoryKind {
+    fn descriptor_bits(&self) -> u64 {
+        let normal = (MAIR_IDX_NORMAL << PTE_ATTR_SHIFT) | PTE_SH_INNER | PTE_AF;
+        match self {
+            MemoryKind::KernelText => normal | PTE_AP_RO | PTE_UXN,
+            MemoryKind::KernelRodata => normal | PTE_AP_RO | PTE_UXN | PTE_PXN,
+            MemoryKind::KernelData => normal | PTE_UXN | PTE_PXN,
+            MemoryKind::Device => (MAIR_IDX_DEVICE << PTE_ATTR_SHIFT) | PTE_AF | PTE_UXN | PTE_PXN,
+            MemoryKind::UserText => normal | PTE_NG | PTE_AP_EL0 | PTE_AP_RO | PTE_PXN,
+            MemoryKind::UserRodata => normal | PTE_NG | PTE_AP_EL0 | PTE_AP_RO | PTE_UXN | PTE_PXN,
+            MemoryKind::UserData => normal | PTE_NG | PTE_AP_EL0 | PTE_UXN | PTE_PXN,
+        }
+    }
+}
+
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+pub struct PageTable {
+    pub entries: [u64; ENTRIES_PER_TABLE],
+}
+
+// Linker script symbols describing the kernel image
+extern "C" {
+    static _KERNEL_START: u8;
+    static _TEXT_START: u8;
+    static _TEXT_END: u8;
+    static _RODATA_START: u8;
+    static _RODATA_END: u8;
+    static _KERNEL_END: u8;
+}
+
+// Root tables of the kernel translation regime
+static mut KERNEL_TTBR0: *mut PageTable = core::ptr::null_mut();
+static mut KERNEL_TTBR1: *mut PageTable = core::ptr::null_mut();
+static mut MMU_ENABLED: bool = false;
+
+// ASID 0 is the kernel's; user address spaces take the others in turn
+static mut NEXT_ASID: u16 = 1;
+
+fn alloc_table() -> Result<*mut PageTable, &'static str> {
+    let addr = memory::alloc_frame().ok_or("Out of memory for translation tables")?;
+    let table = addr as *mut PageTable;
+    unsafe {
+        (*table).entries = [0; ENTRIES_PER_TABLE];
+        if !MMU_ENABLED {
+            clean_invalidate_dcache_range(addr, addr + PAGE_SIZE);
+        }
+    }
+    Ok(table)
+}
+
+fn table_index(va: u64, level: u32) -> usize {
+    ((va >> (39 - 9 * level)) & 0x1FF) as usize
+}
+
+/// Return the next-level table for `index`, creating it (or splitting a block) if needed
+unsafe fn next_table(table: *mut PageTable, index: usize, level: u32) -> Result<*mut PageTable, &'static str> {
+    let entry = (*table).entries[index];
+    if entry & PTE_VALID != 0 && entry & PTE_TABLE != 0 {
+        return Ok((entry & PTE_ADDR_MASK) as *mut PageTable);
+    }
+
+    let new_table = alloc_table()?;
+    if entry & PTE_VALID != 0 {
+        // Split an existing block into next-level entries with the same attributes
+        let child_size = if level == 1 { BLOCK_SIZE_2M } else { PAGE_SIZE };
+        let attrs = entry & !PTE_ADDR_MASK;
+        let child_type = if level == 2 { PTE_TABLE } else { 0 };
+        let base = entry & PTE_ADDR_MASK;
+        for i in 0..ENTRIES_PER_TABLE {
+            (*new_table).entries[i] = (base + i as u64 * child_size) | attrs | child_type;
+        }
+    }
+    (*table).entries[index] = new_table as u64 | PTE_VALID | PTE_TABLE;
+    Ok(new_table)
+}
+
+/// Map [va, va+size) to [pa, pa+size), using 2MB blocks where alignment allows
+pub fn map_range(root: *mut PageTable, va: u64, pa: u64, size: u64, kind: MemoryKind) -> Result<(), &'static str> {
+    let attrs = kind.descriptor_bits();
+    let mut offset = 0;
+
+    while offset < size {
+        let va_cur = va + offset;
+        let pa_cur = pa + offset;
+
+        unsafe {
+            let l1 = next_table(root, table_index(va_cur, 0), 0)?;
+            let l2 = next_table(l1, table_index(va_cur, 1), 1)?;
+            let l2_index = table_index(va_cur, 2);
+            let l2_entry = (*l2).entries[l2_index];
+            let block_fits = va_cur.is_multiple_of(BLOCK_SIZE_2M)
+                && pa_cur.is_multiple_of(BLOCK_SIZE_2M)
+                && size - offset >= BLOCK_SIZE_2M;
+
+            if block_fits && (l2_entry & PTE_VALID == 0 || l2_entry & PTE_TABLE == 0) {
+                (*l2).entries[l2_index] = (pa_cur & PTE_ADDR_MASK) | attrs | PTE_VALID;
+                offset += BLOCK_SIZE_2M;
+            } else {
+                let l3 = next_table(l2, l2_index, 2)?;
+                (*l3).entries[table_index(va_cur, 3)] = (pa_cur & PTE_ADDR_MASK) | attrs | PTE_VALID | PTE_TABLE;
+                offset += PAGE_SIZE;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Translate a physical address into the kernel linear map
+pub fn phys_to_virt(pa: u64) -> u64 {
+    pa + KERNEL_VIRT_BASE
+}
+
+/// A user address space: its own L0 table sharing the kernel's L0[0] entry
+pub struct AddressSpace {
+    root: *mut PageTable,
+    asid: u16,
+}
+
+impl AddressSpace {
+    pub fn new_user(asid: u16) -> Result<Self, &'static str> {
+        let root = alloc_table()?;
+        unsafe {
+            if KERNEL_TTBR0.is_null() {
+                return Err("MMU not initialized");
+            }
+            (*root).entries[0] = (*KERNEL_TTBR0).entries[0];
+        }
+        Ok(Self { root, asid })
+    }
+
+    pub fn map(&mut self, va: u64, pa: u64, size: u64, kind: MemoryKind) -> Result<(), &'static str> {
+        if va < USER_SPACE_BASE || va.checked_add(size).is_none_or(|end| end > USER_SPACE_END) {
+            return Err("Address outside user space");
+        }
+        map_range(self.root, va, pa, size, kind)
+    }
+
+    /// The address space installed as `ttbr0` (e.g. by `ttbr0()` earlier)
+    pub fn from_ttbr0(ttbr0: u64) -> Self {
+        Self { root: (ttbr0 & PTE_ADDR_MASK) as *mut PageTable, asid: (ttbr0 >> 48) as u16 }
+    }
+
+    pub fn ttbr0(&self) -> u64 {
+        self.root as u64 | ((self.asid as u64) << 48)
+    }
+
+    /// The level 3 entry mapping `va`, if there is one
+    fn page_entry(&self, va: u64) -> Option<*mut u64> {
+        if !(USER_SPACE_BASE..USER_SPACE_END).contains(&va) {
+            return None;
+        }
+        let mut table = self.root;
+        for level in 0..3 {
+            let entry = unsafe { (*table).entries[table_index(va, level)] };
+            if entry & PTE_VALID == 0 || entry & PTE_TABLE == 0 {
+                return None;
+            }
+            table = (entry & PTE_ADDR_MASK) as *mut PageTable;
+        }
+        let entry = unsafe { core::ptr::addr_of_mut!((*table).entries[table_index(va, 3)]) };
+        if unsafe { *entry } & PTE_VALID == 0 {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Physical address `va` is mapped to, if it is
+    pub fn translate(&self, va: u64) -> Option<u64> {
+        if !(USER_SPACE_BASE..USER_SPACE_END).contains(&va) {
+            return None;
+        }
+        let mut table = self.root;
+        for level in 0..4 {
+            let entry = unsafe { (*table).entries[table_index(va, level)] };
+            if entry & PTE_VALID == 0 {
+                return None;
+            }
+            let block_size = match level {
+                1 => BLOCK_SIZE_1G,
+                2 => BLOCK_SIZE_2M,
+                3 => PAGE_SIZE,
+                _ => 0,
+            };
+            if level == 3 || (level > 0 && entry & PTE_TABLE == 0) {
+                return Some((entry & PTE_ADDR_MASK & !(block_size - 1)) + va % block_size);
+            }
+            table = (entry & PTE_ADDR_MASK) as *mut PageTable;
+        }
+        None
+    }
+
+    /// A copy of this address space for a forked child, sharing every page.
+    /// Writable pages become copy-on-write in both.
+    pub fn clone_cow(&mut self) -> Result<AddressSpace, &'static str> {
+        let child = AddressSpace::new_user(alloc_asid())?;
+        for index in 1..ENTRIES_PER_TABLE {
+            unsafe {
+                let entry = (*self.root).entries[index];
+                if entry & PTE_VALID == 0 {
+                    continue;
+                }
+                match clone_table_cow(entry, 1) {
+                    Ok(copy) => (*child.root).entries[index] = copy,
+                    Err(e) => {
+                        child.destroy();
+                        flush_tlb_asid(self.asid);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        // The parent's writable pages are read-only now
+        flush_tlb_asid(self.asid);
+        Ok(child)
+    }
+
+    /// Make the copy-on-write page at `va` private and writable. False if
+    /// it is not copy-on-write or there is no frame for the copy.
+    fn break_cow(&self, va: u64) -> bool {
+        let Some(pte) = self.page_entry(va) else { return false };
+        unsafe {
+            let entry = *pte;
+            if entry & PTE_COW == 0 {
+                return false;
+            }
+            let old = entry & PTE_ADDR_MASK;
+            let frame = if memory::is_frame_shared(old) {
+                let Some(frame) = memory::alloc_frame() else { return false };
+                core::ptr::copy_nonoverlapping(old as *const u8, frame as *mut u8, PAGE_SIZE as usize);
+                memory::release_frame(old);
+                frame
+            } else {
+                old
+            };
+            *pte = (entry & !(PTE_ADDR_MASK | PTE_AP_RO | PTE_COW)) | frame;
+            core::arch::asm!(
+                "dsb ishst",
+                "tlbi vae1is, {}",
+                "dsb ish",
+                "isb",
+                in(reg) ((va >> 12) & 0xFFF_FFFF_FFFF) | ((self.asid as u64) << 48)
+            );
+        }
+        true
+    }
+
+    /// Store a u32 at `va`, which need not be the current address space
+    /// (e.g. the child's tid for CLONE_CHILD_SETTID)
+    pub fn write_u32(&self, va: u64, value: u32) -> bool {
+        if !va.is_multiple_of(4) {
+            return false;
+        }
+        let Some(pte) = self.page_entry(va) else { return false };
+        let entry = unsafe { *pte };
+        if entry & PTE_AP_EL0 == 0 || (entry & PTE_AP_RO != 0 && !self.break_cow(va)) {
+            return false;
+        }
+        match self.translate(va) {
+            Some(pa) => {
+                unsafe { core::ptr::write_volatile(pa as *mut u32, value) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Free the user tables and every frame mapped through them. The address
+    /// space must not be installed on any core any more.
+    pub fn destroy(self) {
+        unsafe {
+            flush_tlb_asid(self.asid);
+            // L0[0] is the kernel's
+            for index in 1..ENTRIES_PER_TABLE {
+                free_table_entry((*self.root).entries[index], 0);
+            }
+        }
+        memory::free_frame(self.root as u64);
+    }
+}
+
+/// Free what a valid entry of a level `level` table maps, tables included
+unsafe fn free_table_entry(entry: u64, level: u32) {
+    if entry & PTE_VALID == 0 {
+        return;
+    }
+    let addr = entry & PTE_ADDR_MASK;
+    if level == 3 {
+        // Pages may be shared with a forked process
+        memory::release_frame(addr);
+        return;
+    }
+    if entry & PTE_TABLE == 0 {
+        let size = if level == 1 { BLOCK_SIZE_1G } else { BLOCK_SIZE_2M };
+        memory::free_frames(addr, size / PAGE_SIZE);
+        return;
+    }
+    let table = addr as *mut PageTable;
+    for index in 0..ENTRIES_PER_TABLE {
+        free_table_entry((*table).entries[index], level + 1);
+    }
+    memory::free_frame(addr);
+}
+
+/// Copy the level `level` table that `entry` (of the level above) points to,
+/// sharing the pages it maps
+unsafe fn clone_table_cow(entry: u64, level: u32) -> Result<u64, &'static str> {
+    let table = (entry & PTE_ADDR_MASK) as *mut PageTable;
+    let copy = alloc_table()?;
+    let copy_entry = copy as u64 | PTE_VALID | PTE_TABLE;
+
+    for index in 0..ENTRIES_PER_TABLE {
+        let mut child = (*table).entries[index];
+        if child & PTE_VALID == 0 {
+            continue;
+        }
+        if level == 3 {
+            if child & PTE_AP_RO == 0 {
+                child |= PTE_AP_RO | PTE_COW;
+                (*table).entries[index] = child;
+            }
+            memory::share_frame(child & PTE_ADDR_MASK);
+            (*copy).entries[index] = child;
+        } else if child & PTE_TABLE != 0 {
+            match clone_table_cow(child, level + 1) {
+                Ok(child_copy) => (*copy).entries[index] = child_copy,
+                Err(e) => {
+                    free_table_entry(copy_entry, level - 1);
+                    return Err(e);
+                }
+            }
+        } else {
+            // User memory is mapped page by page
+            free_table_entry(copy_entry, level - 1);
+            return Err("Block mapping in user space");
+        }
+    }
+    Ok(copy_entry)
+}
+
+/// A write to `va` hit a read-only page of the address space in TTBR0.
+/// If it is copy-on-write, give the writer its own copy (or the page itself
+/// when nothing else maps it any more) and return true so that the write is
+/// retried; false if the fault is real or no frame is left for the copy.
+pub fn resolve_cow_fault(va: u64) -> bool {
+    let ttbr0: u64;
+    unsafe { core::arch::asm!("mrs {}, ttbr0_el1", out(reg) ttbr0) };
+    AddressSpace::from_ttbr0(ttbr0).break_cow(va)
+}
+
+/// An ASID for a new user address space; nothing of its last owner stays in the TLB
+pub fn alloc_asid() -> u16 {
+    unsafe {
+        let asid = NEXT_ASID;
+        NEXT_ASID = if NEXT_ASID == u16::MAX { 1 } else { NEXT_ASID + 1 };
+        flush_tlb_asid(asid);
+        asid
+    }
+}
+
+/// Drop the TLB entries of `asid` on all cores
+pub fn flush_tlb_asid(asid: u16) {
+    if !Mmu::is_enabled() {
+        return;
+    }
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi aside1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) (asid as u64) << 48
+        );
+    }
+}
+
+/// TTBR0 of the kernel-only address space (0 while the MMU is off)
+pub fn kernel_ttbr0() -> u64 {
+    unsafe { if MMU_ENABLED { KERNEL_TTBR0 as u64 } else { 0 } }
+}
+
+/// Install `ttbr0` on a context switch; entries are tagged by ASID, so no TLB flush
+pub fn switch_ttbr0(ttbr0: u64) {
+    if ttbr0 == 0 || !Mmu::is_enabled() {
+        return;
+    }
+    let current: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, ttbr0_el1", out(reg) current);
+        if current != ttbr0 {
+            core::arch::asm!("msr ttbr0_el1, {}", "isb", in(reg) ttbr0);
+        }
+    }
+}
+
+/// Translation registers a secondary core loads before turning its MMU on
+pub struct MmuRegisters {
+    pub ttbr0: u64,
+    pub ttbr1: u64,
+    pub mair: u64,
+    pub tcr: u64,
+    pub sctlr: u64,
+}
 
 pub struct Mmu;
 
 impl Mmu {
     pub fn init() -> Result<(), &'static str> {
-        // MMU初期化は後で実装
-        // 今は単純にアイデンティティマッピングを使用
+        if current_el() != 1 {
+            return Err("MMU setup requires EL1");
+        }
+
+        unsafe {
+            KERNEL_TTBR0 = alloc_table()?;
+            KERNEL_TTBR1 = alloc_table()?;
+        }
+
+        Self::build_identity_map()?;
+        Self::build_linear_map()?;
+        Self::enable();
+
+        info!("MMU enabled: kernel W^X, caches on");
+        Ok(())
+    }
+
+    /// The boot CPU's translation setup, or None while the MMU is off
+    pub fn registers() -> Option<MmuRegisters> {
+        if !Self::is_enabled() {
+            return None;
+        }
+        let (mair, tcr, sctlr): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "mrs {mair}, mair_el1",
+                "mrs {tcr}, tcr_el1",
+                "mrs {sctlr}, sctlr_el1",
+                mair = out(reg) mair,
+                tcr = out(reg) tcr,
+                sctlr = out(reg) sctlr,
+            );
+            Some(MmuRegisters {
+                ttbr0: KERNEL_TTBR0 as u64,
+                ttbr1: KERNEL_TTBR1 as u64,
+                mair,
+                tcr,
+                sctlr,
+            })
+        }
+    }
+
+    pub fn is_enabled() -> bool {
+        unsafe { MMU_ENABLED }
+    }
+
+    fn build_identity_map() -> Result<(), &'static str> {
+        let root = unsafe { KERNEL_TTBR0 };
+
+        // All RAM as kernel data first; the kernel image is refined below
+        for &(base, size) in memory::ram_regions() {
+            map_range(root, base, base, size, MemoryKind::KernelData)?;
+        }
+
+        let (text_start, text_end, rodata_start, rodata_end) = unsafe {
+            (
+                &_TEXT_START as *const u8 as u64,
+                &_TEXT_END as *const u8 as u64,
+                &_RODATA_START as *const u8 as u64,
+                &_RODATA_END as *const u8 as u64,
+            )
+        };
+        map_range(root, text_start, text_start, text_end - text_start, MemoryKind::KernelText)?;
+        map_range(root, rodata_start, rodata_start, rodata_end - rodata_start, MemoryKind::KernelRodata)?;
+
+        // Devices, rounded out to whole pages. The console and GPIO registers
+        // too: console=pl011,<addr> or a built-in default may have put them
+        // somewhere the device tree does not say.
+        let drivers = [(uart::base(), PAGE_SIZE), (gpio::base(), PAGE_SIZE)];
+        for &(base, size) in device_regions().iter().chain(drivers.iter()) {
+            let start = base & !(PAGE_SIZE - 1);
+            let end = (base + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            map_range(root, start, start, end - start, MemoryKind::Device)?;
+        }
+
+        Ok(())
+    }
+
+    fn build_linear_map() -> Result<(), &'static str> {
+        // 1GB blocks are enough for the linear map: it is never executable
+        let root = unsafe { KERNEL_TTBR1 };
+        let attrs = MemoryKind::KernelData.descriptor_bits();
+
+        for &(base, size) in memory::ram_regions() {
+            let mut pa = base & !(BLOCK_SIZE_1G - 1);
+            while pa < base + size {
+                let va = phys_to_virt(pa);
+                unsafe {
+                    let l1 = next_table(root, table_index(va, 0), 0)?;
+                    (*l1).entries[table_index(va, 1)] = pa | attrs | PTE_VALID;
+                }
+                pa += BLOCK_SIZE_1G;
+            }
+        }
+
         Ok(())
     }
+
+    fn enable() {
+        // Physical address size supported by the CPU, capped at 48 bits
+        let mmfr0: u64;
+        unsafe { core::arch::asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0) };
+        let ips = core::cmp::min(mmfr0 & 0xF, 5);
+
+        let tcr = TCR_T0SZ | TCR_IRGN0_WBWA | TCR_ORGN0_WBWA | TCR_SH0_INNER | TCR_TG0_4K
+            | TCR_T1SZ | TCR_IRGN1_WBWA | TCR_ORGN1_WBWA | TCR_SH1_INNER | TCR_TG1_4K
+            | (ips << TCR_IPS_SHIFT);
+
+        // Make sure nothing stale is cached for the kernel image or the tables
+        unsafe {
+            clean_invalidate_dcache_range(
+                &_KERNEL_START as *const u8 as u64,
+                &_KERNEL_END as *const u8 as u64,
+            );
+        }
+
+        unsafe {
+            core::arch::asm!(
+                "dsb ish",
+                "msr mair_el1, {mair}",
+                "msr tcr_el1, {tcr}",
+                "msr ttbr0_el1, {ttbr0}",
+                "msr ttbr1_el1, {ttbr1}",
+                "isb",
+                "tlbi vmalle1",
+                "ic iallu",
+                "dsb ish",
+                "isb",
+                mair = in(reg) MAIR_VALUE,
+                tcr = in(reg) tcr,
+                ttbr0 = in(reg) KERNEL_TTBR0 as u64,
+                ttbr1 = in(reg) KERNEL_TTBR1 as u64,
+            );
+
+            let mut sctlr: u64;
+            core::arch::asm!("mrs {}, sctlr_el1", out(reg) sctlr);
+            sctlr |= SCTLR_M | SCTLR_C | SCTLR_I;
+            sctlr &= !(SCTLR_A | SCTLR_WXN);
+            core::arch::asm!(
+                "msr sctlr_el1, {}",
+                "isb",
+                in(reg) sctlr
+            );
+
+            MMU_ENABLED = true;
+        }
+    }
+}
+
+/// Current exception level (0-3)
+pub fn current_el() -> u64 {
+    let el: u64;
+    unsafe { core::arch::asm!("mrs {}, CurrentEL", out(reg) el) };
+    (el >> 2) & 3
+}
+
+/// Clean and invalidate the data cache for [start, end) to the point of coherency
+pub fn clean_invalidate_dcache_range(start: u64, end: u64) {
+    let ctr: u64;
+    unsafe { core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    let line_size = 4u64 << ((ctr >> 16) & 0xF);
+
+    let mut addr = start & !(line_size - 1);
+    while addr < end {
+        unsafe { core::arch::asm!("dc civac, {}", in(reg) addr) };
+        addr += line_size;
+    }
+    unsafe { core::arch::asm!("dsb sy") };
+}
+
+/// Make instructions written through the data cache to [start, end) visible
+/// to instruction fetches (new program text)
+pub fn sync_icache_range(start: u64, end: u64) {
+    let ctr: u64;
+    unsafe { core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    let dline = 4u64 << ((ctr >> 16) & 0xF);
+
+    let mut addr = start & !(dline - 1);
+    while addr < end {
+        unsafe { core::arch::asm!("dc cvau, {}", in(reg) addr) };
+        addr += dline;
+    }
+    unsafe { core::arch::asm!("dsb ish", "ic ialluis", "dsb ish", "isb") };
 }
@@ -1,30 +1,149 @@
 // Process Management for UNIX-like OS
 // Basic process scheduling and management
+//
+// Every process except the boot thread (PID 1, the shell) has its own kernel
+// stack. A process that is not running keeps its callee-saved registers in
+// `Context`; everything else it had live when it was preempted is in the
+// TrapFrame that the IRQ vector pushed on its kernel stack. `cpu_switch_to`
+// swaps contexts, and the timer tick asks for a switch when the running
+// process has used up its time slice. Scheduling runs on the boot CPU only.
+//
+// A process that exits stays in the table as a zombie (Terminated) holding
+// its wait status and CPU times until its parent collects them with
+// wait4/waitid; only then is the slot freed. Stops and continues are
+// reported to the parent the same way. Kernel threads, and the orphans that
+// init (PID 1) adopts, have nobody waiting for them and are freed as soon as
+// their stack is.
 
-use heapless::Vec;
+use crate::exception::TrapFrame;
+use crate::interrupt;
+use crate::memory;
+use crate::elf::Program;
+use crate::file::FdTable;
+use crate::mmu::{self, AddressSpace, PAGE_SIZE};
+use crate::signals::{self, Signal};
+use crate::smp;
+use crate::timer;
+use crate::tty::TtyId;
+use heapless::{String, Vec};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ProcessState {
     Ready,
     Running,
     Sleeping,
-    Terminated,
+    Stopped,         // SIGSTOP / SIGTSTP, until SIGCONT
+    Terminated,      // Zombie until the parent waits for it
 }
 
+/// Registers kept across a context switch; layout is fixed by `cpu_switch_to`
+#[repr(C)]
 #[derive(Clone, Copy)]
+pub struct Context {
+    pub regs: [u64; 12],     // x19-x29, x30 (return address)
+    pub sp: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    pub tpidr_el0: u64,      // User thread pointer (TLS)
+    pub vregs: [u128; 8],    // q8-q15
+}
+
+impl Context {
+    pub const fn new() -> Self {
+        Self {
+            regs: [0; 12],
+            sp: 0,
+            elr: 0,
+            spsr: 0,
+            fpcr: 0,
+            fpsr: 0,
+            tpidr_el0: 0,
+            vregs: [0; 8],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Process {
     pub pid: u32,
     pub ppid: u32,           // Parent process ID
+    pub pgid: u32,           // Process group (job control)
+    pub sid: u32,            // Session
+    pub ctty: Option<TtyId>, // Controlling terminal
+    pub ignored_signals: u64, // SIG_IGN dispositions, bit n-1 for signal n
     pub state: ProcessState,
     pub stack_ptr: u64,      // Stack pointer
+    pub stack_base: u64,     // Physical base of the stack frames
     pub entry_point: u64,    // Program entry point
     pub priority: u8,        // Process priority (0-255)
     pub time_slice: u32,     // Time slice in ms
     pub used_time: u32,      // Used CPU time
+    pub ttbr0: u64,          // User address space, 0 for kernel threads
+    pub cwd: String<MAX_PATH>, // Current working directory
+    pub files: FdTable,      // Open file descriptors
+    pub exit_status: i32,    // wait status once Terminated
+    pub wait_report: Option<i32>, // Stop / continue not yet reported to wait
+    pub detached: bool,      // Nobody waits for it: freed as soon as it exits
+    pub utime_ms: u64,       // CPU time in user mode
+    pub stime_ms: u64,       // CPU time in the kernel
+    pub context: Context,    // Saved registers while not running
 }
 
-const MAX_PROCESSES: usize = 64;
+pub const MAX_PROCESSES: usize = 64;
+pub const MAX_PATH: usize = 64;
 const DEFAULT_TIME_SLICE: u32 = 10; // 10ms
+const STACK_SIZE: u64 = 0x100000;   // 1MB stack per process
+
+// SPSR_EL1 values for the first eret into a new process (DAIF clear)
+const SPSR_EL1H: u64 = 0x5;
+const SPSR_EL0T: u64 = 0x0;
+
+// wait のオプション (Linux の値)
+pub const WNOHANG: u32 = 0x1;
+pub const WSTOPPED: u32 = 0x2;        // WUNTRACED for wait4
+pub const WEXITED: u32 = 0x4;
+pub const WCONTINUED: u32 = 0x8;
+pub const WNOWAIT: u32 = 0x0100_0000;
+
+// wait ステータス (Linux と同じ形式)
+pub fn exited_status(code: i32) -> i32 {
+    (code & 0xFF) << 8
+}
+
+pub fn signaled_status(signal: Signal, core_dumped: bool) -> i32 {
+    signal as i32 | if core_dumped { 0x80 } else { 0 }
+}
+
+pub fn stopped_status(signal: Signal) -> i32 {
+    (signal as i32) << 8 | 0x7F
+}
+
+pub const CONTINUED_STATUS: i32 = 0xFFFF;
+
+/// wait4/waitid で待つ子プロセス
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaitTarget {
+    Any,
+    Pid(u32),
+    Pgid(u32),
+}
+
+/// wait で回収した子プロセスの状態変化
+#[derive(Clone, Copy, Debug)]
+pub struct WaitResult {
+    pub pid: u32,
+    pub status: i32,
+    pub utime_ms: u64,
+    pub stime_ms: u64,
+}
+
+fn root_dir() -> String<MAX_PATH> {
+    let mut cwd = String::new();
+    let _ = cwd.push('/');
+    cwd
+}
 
 pub struct ProcessManager {
     processes: Vec<Process, MAX_PROCESSES>,
@@ -43,34 +162,164 @@ impl ProcessManager {
         }
     }
     
-    /// 新しいプロセスを作成
+    /// 新しいプロセスを作成 (カーネルスレッドとして entry_point から実行)
     pub fn create_process(&mut self, entry_point: u64, parent_pid: u32) -> Option<u32> {
+        self.spawn(entry_point, parent_pid, SPSR_EL1H, 0, 0)
+    }
+
+    /// ユーザープロセスを作成 (EL0で entry_point から実行)
+    pub fn create_user_process(&mut self, entry_point: u64, user_sp: u64, ttbr0: u64, parent_pid: u32) -> Option<u32> {
+        self.spawn(entry_point, parent_pid, SPSR_EL0T, user_sp, ttbr0)
+    }
+
+    fn spawn(&mut self, entry_point: u64, parent_pid: u32, spsr: u64, user_sp: u64, ttbr0: u64) -> Option<u32> {
+        // 最初のeretで使うトラップフレーム
+        let mut frame: TrapFrame = unsafe { core::mem::zeroed() };
+        frame.elr = entry_point;
+        frame.spsr = spsr;
+        frame.sp_el0 = user_sp;
+        // A kernel thread that returns from its entry function exits
+        frame.regs[30] = kernel_thread_exit as *const () as u64;
+        self.spawn_with_frame(&frame, parent_pid, ttbr0, 0)
+    }
+
+    /// fork: 現在のユーザープロセスを複製する。子は親と同じトラップフレームから
+    /// 戻り値 0 で再開し、アドレス空間はコピーオンライトで共有する
+    /// (`stack` が 0 でなければ子の SP_EL0、`tls` は子の TPIDR_EL0)
+    pub fn fork(&mut self, frame: &TrapFrame, stack: u64, tls: Option<u64>) -> Result<(u32, AddressSpace), i64> {
         if self.processes.is_full() {
-            return None;
+            return Err(-11); // EAGAIN - No process slot
+        }
+        let parent_pid = self.current_pid;
+        let parent = self.get_process(parent_pid).ok_or(-3i64)?; // ESRCH
+        if parent.ttbr0 == 0 {
+            return Err(-22); // EINVAL - Kernel threads have nothing to copy
+        }
+        let entry_point = parent.entry_point;
+        let child_space = AddressSpace::from_ttbr0(parent.ttbr0).clone_cow().map_err(|_| -12i64)?; // ENOMEM
+
+        let mut child_frame: TrapFrame = unsafe { core::ptr::read(frame) };
+        child_frame.regs[0] = 0;
+        if stack != 0 {
+            child_frame.sp_el0 = stack;
+        }
+        let tpidr_el0 = tls.unwrap_or_else(|| {
+            let tpidr_el0: u64;
+            unsafe { core::arch::asm!("mrs {}, tpidr_el0", out(reg) tpidr_el0) };
+            tpidr_el0
+        });
+
+        match self.spawn_with_frame(&child_frame, parent_pid, child_space.ttbr0(), tpidr_el0) {
+            Some(pid) => {
+                if let Some(child) = self.get_process_mut(pid) {
+                    child.entry_point = entry_point;
+                }
+                Ok((pid, child_space))
+            }
+            None => {
+                child_space.destroy();
+                Err(-12) // ENOMEM - No kernel stack
+            }
         }
-        
-        let pid = self.next_pid;
-        self.next_pid += 1;
-        
-        let process = Process {
-            pid,
-            ppid: parent_pid,
-            state: ProcessState::Ready,
-            stack_ptr: 0x400000 + (pid as u64 * 0x100000), // 1MB stack per process
-            entry_point,
-            priority: 128, // Default priority
-            time_slice: DEFAULT_TIME_SLICE,
-            used_time: 0,
-        };
-        
-        let _ = self.processes.push(process);
-        Some(pid)
+    }
+
+    fn spawn_with_frame(&mut self, frame: &TrapFrame, parent_pid: u32, ttbr0: u64, tpidr_el0: u64) -> Option<u32> {
+        without_irqs(|| {
+            if self.processes.is_full() {
+                return None;
+            }
+            
+            // スタック用の物理フレームを確保
+            let stack_base = memory::alloc_frames(STACK_SIZE / PAGE_SIZE)?;
+            let stack_top = stack_base + STACK_SIZE; // Stack grows downward
+            
+            // トラップフレームをカーネルスタックの先頭に置く
+            let frame_addr = stack_top - core::mem::size_of::<TrapFrame>() as u64;
+            unsafe {
+                core::ptr::copy_nonoverlapping(frame, frame_addr as *mut TrapFrame, 1);
+            }
+            
+            // cpu_switch_to "returns" into ret_from_fork with sp at the frame
+            let mut context = Context::new();
+            context.sp = frame_addr;
+            context.regs[11] = ret_from_fork as *const () as u64;
+            context.tpidr_el0 = tpidr_el0;
+            
+            let pid = self.next_pid;
+            self.next_pid += 1;
+            
+            // 親のプロセスグループ、セッション、制御端末、シグナルの無視設定、
+            // カレントディレクトリとファイルディスクリプタを引き継ぐ
+            let (pgid, sid, ctty, ignored_signals, cwd, files) = self.get_process(parent_pid)
+                .map_or((pid, pid, None, 0, root_dir(), FdTable::new()), |parent| {
+                    (parent.pgid, parent.sid, parent.ctty, parent.ignored_signals, parent.cwd.clone(),
+                     parent.files.fork())
+                });
+            
+            let process = Process {
+                pid,
+                ppid: parent_pid,
+                pgid,
+                sid,
+                ctty,
+                ignored_signals,
+                state: ProcessState::Ready,
+                stack_ptr: frame_addr,
+                stack_base,
+                entry_point: frame.elr,
+                priority: 128, // Default priority
+                time_slice: DEFAULT_TIME_SLICE,
+                used_time: 0,
+                ttbr0,
+                cwd,
+                files,
+                exit_status: 0,
+                wait_report: None,
+                // カーネルスレッドの終了は誰も待たない
+                detached: ttbr0 == 0,
+                utime_ms: 0,
+                stime_ms: 0,
+                context,
+            };
+            
+            let _ = self.processes.push(process);
+            self.kick_tick();
+            Some(pid)
+        })
     }
     
-    /// initプロセス（PID 1）を作成
-    pub fn create_init_process(&mut self, entry_point: u64) -> Option<u32> {
-        self.next_pid = 1;
-        self.create_process(entry_point, 0)
+    /// 起動スレッド (シェル) を PID 1 として登録する
+    pub fn init_boot_process(&mut self) -> u32 {
+        without_irqs(|| {
+            let process = Process {
+                pid: 1,
+                ppid: 0,
+                pgid: 1,
+                sid: 1,
+                ctty: Some(TtyId::Console),
+                ignored_signals: 0,
+                state: ProcessState::Running,
+                stack_ptr: 0,
+                stack_base: 0, // Boot stack from the linker script, never freed
+                entry_point: 0,
+                priority: 128,
+                time_slice: DEFAULT_TIME_SLICE,
+                used_time: 0,
+                ttbr0: 0,
+                cwd: root_dir(),
+                files: FdTable::standard(),
+                exit_status: 0,
+                wait_report: None,
+                detached: true,
+                utime_ms: 0,
+                stime_ms: 0,
+                context: Context::new(),
+            };
+            let _ = self.processes.push(process);
+            self.current_pid = 1;
+            self.next_pid = 2;
+            1
+        })
     }
     
     /// 現在のプロセスIDを取得
@@ -80,32 +329,79 @@ impl ProcessManager {
     
     /// プロセス状態を変更
     pub fn set_process_state(&mut self, pid: u32, state: ProcessState) -> bool {
-        for process in &mut self.processes {
-            if process.pid == pid {
-                process.state = state;
-                return true;
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) => {
+                    process.state = state;
+                    if state == ProcessState::Ready {
+                        self.kick_tick();
+                    }
+                    true
+                }
+                None => false,
             }
+        })
+    }
+    
+    /// chdir: `path` は正規化した絶対パス
+    pub fn set_cwd(&mut self, pid: u32, path: &str) -> Result<(), i32> {
+        without_irqs(|| {
+            let process = self.get_process_mut(pid).ok_or(-3)?; // ESRCH
+            let mut cwd = String::new();
+            cwd.push_str(path).map_err(|_| -36)?; // ENAMETOOLONG
+            process.cwd = cwd;
+            Ok(())
+        })
+    }
+    
+    /// ファイルディスクリプタ表
+    pub fn files_mut(&mut self, pid: u32) -> Option<&mut FdTable> {
+        self.get_process_mut(pid).map(|p| &mut p.files)
+    }
+    
+    /// 実行可能 (Ready / Running) なプロセスの数
+    pub fn runnable_count(&self) -> usize {
+        self.processes.iter()
+            .filter(|p| matches!(p.state, ProcessState::Ready | ProcessState::Running))
+            .count()
+    }
+    
+    /// 実行可能なプロセスが増えたらティックを再開する (tickless)
+    fn kick_tick(&self) {
+        if self.runnable_count() > 1 {
+            timer::start_tick();
         }
-        false
     }
     
-    /// ラウンドロビンスケジューリング
-    pub fn schedule(&mut self) -> Option<u32> {
+    /// タイマーティック: タイムスライスを使い切ったら true
+    /// (`user` はユーザーモードを割り込んだとき)
+    pub fn tick(&mut self, user: bool) -> bool {
         self.scheduler_tick += 1;
         
         // 現在のプロセスの時間を更新
-        if let Some(current) = self.get_process_mut(self.current_pid) {
-            current.used_time += 1;
-            
-            // タイムスライス終了またはプロセス終了
-            if current.used_time >= current.time_slice || current.state != ProcessState::Running {
-                current.state = if current.state == ProcessState::Running {
-                    ProcessState::Ready
+        match self.get_process_mut(self.current_pid) {
+            Some(current) if current.state == ProcessState::Running => {
+                if user {
+                    current.utime_ms += timer::TICK_MS as u64;
                 } else {
-                    current.state
-                };
-                current.used_time = 0;
+                    current.stime_ms += timer::TICK_MS as u64;
+                }
+                current.used_time += timer::TICK_MS;
+                current.used_time >= current.time_slice
             }
+            _ => false,
+        }
+    }
+    
+    /// ラウンドロビンスケジューリング
+    /// 次に実行するプロセスを選ぶ (現在のプロセスのままのこともある)
+    pub fn schedule(&mut self) -> Option<u32> {
+        // 現在のプロセスがまだ実行可能なら Ready に戻す
+        if let Some(current) = self.get_process_mut(self.current_pid) {
+            if current.state == ProcessState::Running {
+                current.state = ProcessState::Ready;
+            }
+            current.used_time = 0;
         }
         
         // 次に実行するプロセスを選択
@@ -125,11 +421,72 @@ impl ProcessManager {
         None
     }
     
+    /// 終了したプロセスのスタックとアドレス空間を解放 (実行中のものは除く)。
+    /// 待つ親のいないものはスロットごと解放する
+    fn reap_stacks(&mut self) {
+        let current = self.current_pid;
+        for process in &mut self.processes {
+            if process.state == ProcessState::Terminated && process.pid != current {
+                release_resources(process);
+            }
+        }
+        self.processes.retain(|p| {
+            p.state != ProcessState::Terminated || !p.detached || p.pid == current
+        });
+    }
+    
     /// プロセス情報を取得
     pub fn get_process(&self, pid: u32) -> Option<&Process> {
         self.processes.iter().find(|p| p.pid == pid)
     }
     
+    /// 子プロセスの状態変化のうち `options` で報告するものを探す。見つかれば
+    /// (WNOWAIT でなければ) 回収し、ゾンビはスロットを解放する。
+    /// 対象の子がいなければ ECHILD、まだ変化がなければ None
+    pub fn wait_child(&mut self, target: WaitTarget, options: u32) -> Result<Option<WaitResult>, i32> {
+        without_irqs(|| {
+            let parent = self.current_pid;
+            let is_target = |p: &Process| {
+                p.ppid == parent && !p.detached && match target {
+                    WaitTarget::Any => true,
+                    WaitTarget::Pid(pid) => p.pid == pid,
+                    WaitTarget::Pgid(pgid) => p.pgid == pgid,
+                }
+            };
+            if !self.processes.iter().any(&is_target) {
+                return Err(-10); // ECHILD
+            }
+
+            let found = self.processes.iter().position(|p| {
+                is_target(p) && match (p.state, p.wait_report) {
+                    (ProcessState::Terminated, _) => options & WEXITED != 0,
+                    (_, Some(CONTINUED_STATUS)) => options & WCONTINUED != 0,
+                    (_, Some(_)) => options & WSTOPPED != 0 && p.state == ProcessState::Stopped,
+                    _ => false,
+                }
+            });
+            let Some(index) = found else { return Ok(None) };
+
+            let child = &mut self.processes[index];
+            let zombie = child.state == ProcessState::Terminated;
+            let result = WaitResult {
+                pid: child.pid,
+                status: if zombie { child.exit_status } else { child.wait_report.unwrap_or(0) },
+                utime_ms: child.utime_ms,
+                stime_ms: child.stime_ms,
+            };
+            if options & WNOWAIT == 0 {
+                if zombie {
+                    release_resources(child);
+                    self.processes.remove(index);
+                } else {
+                    child.wait_report = None;
+                }
+            }
+            Ok(Some(result))
+        })
+    }
+    
     fn get_process_mut(&mut self, pid: u32) -> Option<&mut Process> {
         self.processes.iter_mut().find(|p| p.pid == pid)
     }
@@ -139,24 +496,555 @@ impl ProcessManager {
         &self.processes
     }
     
-    /// プロセス終了
+    /// setpgid: `pid` を同じセッション内のプロセスグループ `pgid` に移す
+    /// (0 はそれぞれ呼び出し元 / `pid` 自身)
+    pub fn set_pgid(&mut self, pid: u32, pgid: u32) -> Result<(), i32> {
+        without_irqs(|| {
+            let caller = self.get_process(self.current_pid).map(|p| (p.pid, p.sid));
+            let (caller_pid, caller_sid) = caller.ok_or(-3)?; // ESRCH
+            let pid = if pid == 0 { caller_pid } else { pid };
+            let pgid = if pgid == 0 { pid } else { pgid };
+
+            let target = self.get_process(pid).ok_or(-3)?; // ESRCH
+            if target.pid != caller_pid && target.ppid != caller_pid {
+                return Err(-3); // ESRCH - Neither the caller nor its child
+            }
+            if target.sid != caller_sid || target.pid == target.sid {
+                return Err(-1); // EPERM - Other session, or a session leader
+            }
+            // Joining an existing group: it has to be in the same session
+            if pgid != pid && !self.processes.iter().any(|p| {
+                p.pgid == pgid && p.sid == caller_sid && p.state != ProcessState::Terminated
+            }) {
+                return Err(-1); // EPERM
+            }
+
+            if let Some(target) = self.get_process_mut(pid) {
+                target.pgid = pgid;
+            }
+            Ok(())
+        })
+    }
+
+    /// setsid: 呼び出し元を新しいセッションとプロセスグループのリーダーにする
+    pub fn set_sid(&mut self) -> Result<u32, i32> {
+        without_irqs(|| {
+            let pid = self.current_pid;
+            if self.processes.iter().any(|p| p.pgid == pid && p.state != ProcessState::Terminated) {
+                return Err(-1); // EPERM - Already a process group leader
+            }
+            let process = self.get_process_mut(pid).ok_or(-3)?; // ESRCH
+            process.pgid = pid;
+            process.sid = pid;
+            process.ctty = None;
+            Ok(pid)
+        })
+    }
+
+    /// 制御端末を設定する (TIOCSCTTY / TIOCNOTTY)
+    pub fn set_ctty(&mut self, pid: u32, ctty: Option<TtyId>) -> bool {
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) => {
+                    process.ctty = ctty;
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// `pid` を端末 `ctty` を持つ新しいセッションのリーダーにする (カーネルから)
+    pub fn start_session(&mut self, pid: u32, ctty: TtyId) -> bool {
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) => {
+                    process.pgid = pid;
+                    process.sid = pid;
+                    process.ctty = Some(ctty);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// シグナルの無視 (SIG_IGN) を設定する
+    pub fn set_ignored_signals(&mut self, pid: u32, mask: u64) -> bool {
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) => {
+                    process.ignored_signals = mask;
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// プロセスグループ `pgid` に属する (終了していない) プロセス
+    pub fn pgrp_members(&self, pgid: u32) -> Vec<u32, MAX_PROCESSES> {
+        self.processes.iter()
+            .filter(|p| p.pgid == pgid && p.state != ProcessState::Terminated)
+            .map(|p| p.pid)
+            .collect()
+    }
+
+    /// セッション `sid` に属する (終了していない) プロセス
+    pub fn session_members(&self, sid: u32) -> Vec<u32, MAX_PROCESSES> {
+        self.processes.iter()
+            .filter(|p| p.sid == sid && p.state != ProcessState::Terminated)
+            .map(|p| p.pid)
+            .collect()
+    }
+    
+    /// プロセスを強制終了 (SIGKILL と同じ終了ステータス)
     pub fn terminate_process(&mut self, pid: u32) -> bool {
-        if let Some(process) = self.get_process_mut(pid) {
-            process.state = ProcessState::Terminated;
-            
-            // 子プロセスの親をinitプロセス(PID 1)に変更
-            for p in &mut self.processes {
-                if p.ppid == pid {
-                    p.ppid = 1;
+        self.exit_process(pid, signaled_status(Signal::SIGKILL, false))
+    }
+    
+    /// プロセス終了: `status` を持ったゾンビにして親に知らせる
+    pub fn exit_process(&mut self, pid: u32, status: i32) -> bool {
+        without_irqs(|| {
+            if let Some(process) = self.get_process_mut(pid) {
+                if process.state == ProcessState::Terminated {
+                    return true;
+                }
+                process.state = ProcessState::Terminated;
+                process.exit_status = status;
+                process.wait_report = None;
+                let mut files = core::mem::take(&mut process.files);
+                
+                // スタックは次のコンテキストスイッチで解放する
+                // (終了するのが実行中のプロセス自身かもしれないため)
+                
+                // 子プロセスはinitプロセス(PID 1)が引き取り、終了したら回収する
+                for p in &mut self.processes {
+                    if p.ppid == pid {
+                        p.ppid = 1;
+                        p.detached = true;
+                    }
                 }
+                
+                // パイプの相手や pty にはここで閉じたことが伝わる
+                files.close_all();
+                self.notify_parent(pid);
+                true
+            } else {
+                false
             }
-            
-            true
-        } else {
-            false
+        })
+    }
+    
+    /// SIGSTOP などでプロセスを停止する
+    pub fn stop_process(&mut self, pid: u32, signal: Signal) -> bool {
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) if process.state != ProcessState::Terminated => {
+                    if process.state != ProcessState::Stopped {
+                        process.state = ProcessState::Stopped;
+                        process.wait_report = Some(stopped_status(signal));
+                        self.notify_parent(pid);
+                    }
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+    
+    /// SIGCONT: 停止していたプロセスを再開する
+    pub fn continue_process(&mut self, pid: u32) -> bool {
+        without_irqs(|| {
+            match self.get_process_mut(pid) {
+                Some(process) if process.state != ProcessState::Terminated => {
+                    if process.state == ProcessState::Stopped {
+                        process.state = ProcessState::Ready;
+                        process.wait_report = Some(CONTINUED_STATUS);
+                        self.kick_tick();
+                        self.notify_parent(pid);
+                    }
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+    
+    /// 子プロセスの状態が変わった: 親に SIGCHLD を送り、wait しているものを起こす
+    fn notify_parent(&self, pid: u32) {
+        let Some(process) = self.get_process(pid) else { return };
+        if process.detached {
+            return;
         }
+        let ppid = process.ppid;
+        let _ = signals::send_signal(ppid, Signal::SIGCHLD as i32, pid);
+        unsafe { (*core::ptr::addr_of_mut!(CHILD_WAIT)).wake_all() };
+    }
+}
+
+/// 終了したプロセスのカーネルスタックとアドレス空間を解放する
+fn release_resources(process: &mut Process) {
+    if process.stack_base != 0 {
+        memory::free_frames(process.stack_base, STACK_SIZE / PAGE_SIZE);
+        process.stack_base = 0;
+    }
+    // ユーザー空間のページと変換テーブルも解放する
+    if process.ttbr0 != 0 {
+        AddressSpace::from_ttbr0(process.ttbr0).destroy();
+        process.ttbr0 = 0;
     }
 }
 
 // グローバルプロセスマネージャー
 pub static mut PROCESS_MANAGER: ProcessManager = ProcessManager::new();
+
+// コンテキストスイッチ
+//   cpu_switch_to(prev: *mut Context, next: *const Context)
+// saves the callee-saved registers, sp, ELR/SPSR, TPIDR_EL0 and the FP/SIMD
+// registers the procedure call standard preserves, then loads the same from `next` and
+// returns on its stack. A new process "returns" into ret_from_fork, which
+// leaves through its initial TrapFrame.
+core::arch::global_asm!(
+    r#"
+    .section .text
+    .global cpu_switch_to
+cpu_switch_to:
+    stp     x19, x20, [x0, #0]
+    stp     x21, x22, [x0, #16]
+    stp     x23, x24, [x0, #32]
+    stp     x25, x26, [x0, #48]
+    stp     x27, x28, [x0, #64]
+    stp     x29, x30, [x0, #80]
+    mov     x9, sp
+    mrs     x10, elr_el1
+    stp     x9, x10, [x0, #96]
+    mrs     x11, spsr_el1
+    mrs     x12, fpcr
+    stp     x11, x12, [x0, #112]
+    mrs     x13, fpsr
+    mrs     x14, tpidr_el0
+    stp     x13, x14, [x0, #128]
+    stp     q8, q9, [x0, #144]
+    stp     q10, q11, [x0, #176]
+    stp     q12, q13, [x0, #208]
+    stp     q14, q15, [x0, #240]
+
+    ldp     x19, x20, [x1, #0]
+    ldp     x21, x22, [x1, #16]
+    ldp     x23, x24, [x1, #32]
+    ldp     x25, x26, [x1, #48]
+    ldp     x27, x28, [x1, #64]
+    ldp     x29, x30, [x1, #80]
+    ldp     x9, x10, [x1, #96]
+    mov     sp, x9
+    msr     elr_el1, x10
+    ldp     x11, x12, [x1, #112]
+    msr     spsr_el1, x11
+    msr     fpcr, x12
+    ldp     x13, x14, [x1, #128]
+    msr     fpsr, x13
+    msr     tpidr_el0, x14
+    ldp     q8, q9, [x1, #144]
+    ldp     q10, q11, [x1, #176]
+    ldp     q12, q13, [x1, #208]
+    ldp     q14, q15, [x1, #240]
+    ret
+
+    .global ret_from_fork
+ret_from_fork:
+    bl      schedule_tail
+    b       exception_return
+    "#
+);
+
+const _: () = assert!(core::mem::size_of::<Context>() == 272);
+
+extern "C" {
+    fn cpu_switch_to(prev: *mut Context, next: *const Context);
+    fn ret_from_fork();
+}
+
+// 子プロセスの状態変化を wait で待つプロセス
+static mut CHILD_WAIT: WaitQueue = WaitQueue::new();
+
+// スケジューラの状態 (ブートCPUのみ)
+static mut NEED_RESCHED: bool = false;
+static mut IN_SCHEDULER: bool = false;
+static mut SCHEDULER_RUNNING: bool = false;
+// PID 1 を登録する前に切り替えが起きた場合の保存先
+static mut BOOT_CONTEXT: Context = Context::new();
+
+/// IRQ をマスクして `f` を実行する。プロセス表を変更するメソッドはこれで包み、
+/// 変更の途中でタイマー割り込みの reap_stacks が走らないようにする
+/// (システムコールは EL0 からの例外で IRQ がすでにマスクされている)
+fn without_irqs<R>(f: impl FnOnce() -> R) -> R {
+    let daif = irq_save();
+    let result = f();
+    irq_restore(daif);
+    result
+}
+
+/// IRQ をマスクして以前の DAIF を返す
+pub fn irq_save() -> u64 {
+    let daif: u64;
+    unsafe { core::arch::asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif) };
+    daif
+}
+
+pub fn irq_restore(daif: u64) {
+    unsafe { core::arch::asm!("msr daif, {}", in(reg) daif) };
+}
+
+/// 起動スレッドを PID 1 にしてタイマーティックを開始する
+pub fn init_scheduler() {
+    unsafe {
+        PROCESS_MANAGER.init_boot_process();
+        SCHEDULER_RUNNING = true;
+    }
+    let _ = interrupt::request_irq(interrupt::SGI_RESCHEDULE, reschedule_ipi,
+                                   interrupt::IRQF_PERCPU, "Rescheduling interrupts");
+    timer::start_tick();
+}
+
+/// 他のコアからの再スケジュール要求 (SGI)。切り替えは irq_exit で行う
+fn reschedule_ipi(_irq: u32) {
+    unsafe { NEED_RESCHED = true };
+}
+
+/// タイマー割り込みでプロセスを切り替えられるか (init_scheduler 後)
+pub fn scheduler_running() -> bool {
+    unsafe { SCHEDULER_RUNNING }
+}
+
+/// タイマー割り込みから呼ばれる
+pub fn timer_tick() {
+    // SPSR_EL1 still describes the interrupted context
+    let spsr: u64;
+    unsafe { core::arch::asm!("mrs {}, spsr_el1", out(reg) spsr) };
+    unsafe {
+        if PROCESS_MANAGER.tick(spsr & 0xF == 0) {
+            NEED_RESCHED = true;
+        }
+    }
+}
+
+/// プリエンプトする相手がいるときだけティックが必要
+pub fn needs_tick() -> bool {
+    unsafe { PROCESS_MANAGER.runnable_count() > 1 }
+}
+
+/// IRQ の出口で呼ばれる: ティックが要求していればプリエンプトする
+pub fn irq_exit() {
+    // スケジューラはブートCPUでのみ動く
+    if smp::cpu_id() != 0 {
+        return;
+    }
+    unsafe {
+        // Also when an interrupt handler stopped or killed the running process
+        let runnable = PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid())
+            .is_none_or(|p| p.state == ProcessState::Running);
+        if (NEED_RESCHED || !runnable) && SCHEDULER_RUNNING && !IN_SCHEDULER {
+            schedule();
+        }
+    }
+}
+
+/// 事象 (UART の受信など) を待つプロセスの列
+pub struct WaitQueue {
+    pids: Vec<u32, MAX_PROCESSES>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { pids: Vec::new() }
+    }
+
+    /// 現在のプロセスを起こされるまで眠らせる。取りこぼさないよう、呼び出し側は
+    /// 割り込みを禁止した状態で条件を確かめてから呼ぶこと
+    pub fn sleep(&mut self) {
+        if !scheduler_running() {
+            // Nothing else to run: wait for the interrupt that changes the condition
+            unsafe { core::arch::asm!("wfi", "msr daifclr, #2", "isb", "msr daifset, #2") };
+            return;
+        }
+
+        unsafe {
+            let pid = PROCESS_MANAGER.current_pid();
+            if self.pids.push(pid).is_err() {
+                return;
+            }
+            PROCESS_MANAGER.set_process_state(pid, ProcessState::Sleeping);
+            schedule();
+            // Woken by something else (e.g. SIGCONT): leave the queue
+            self.pids.retain(|&p| p != pid);
+        }
+    }
+
+    /// 待っているプロセスをすべて実行可能にする (割り込みハンドラからも呼べる)
+    pub fn wake_all(&mut self) {
+        let mut woken = false;
+        while let Some(pid) = self.pids.pop() {
+            unsafe {
+                if PROCESS_MANAGER.get_process(pid).is_some_and(|p| p.state == ProcessState::Sleeping) {
+                    PROCESS_MANAGER.set_process_state(pid, ProcessState::Ready);
+                    woken = true;
+                }
+            }
+        }
+        if woken {
+            kick_scheduler();
+        }
+    }
+}
+
+/// 他のコアで実行可能になったプロセスを、次のティックを待たずにブートCPUの
+/// スケジューラに知らせる (wfi で待っていても起きる)
+fn kick_scheduler() {
+    if smp::cpu_id() != 0 {
+        interrupt::send_ipi(0, interrupt::SGI_RESCHEDULE);
+    }
+}
+
+/// 自発的に CPU を譲る
+pub fn yield_now() {
+    schedule();
+}
+
+/// 次のプロセスに切り替える。実行できるプロセスがなければ割り込みを待つ
+pub fn schedule() {
+    let daif = irq_save();
+    unsafe {
+        IN_SCHEDULER = true;
+        NEED_RESCHED = false;
+
+        loop {
+            let prev = PROCESS_MANAGER.current_pid();
+            match PROCESS_MANAGER.schedule() {
+                Some(next) => {
+                    if next != prev {
+                        switch_to(prev, next);
+                    }
+                    break;
+                }
+                None => {
+                    // Let the pending interrupt (e.g. a wakeup) run, then look again
+                    core::arch::asm!("wfi", "msr daifclr, #2", "isb", "msr daifset, #2");
+                }
+            }
+        }
+
+        // Back in this process (or never left)
+        finish_switch();
+    }
+    irq_restore(daif);
+}
+
+unsafe fn switch_to(prev: u32, next: u32) {
+    let prev_ctx = match PROCESS_MANAGER.get_process_mut(prev) {
+        Some(p) => &mut p.context as *mut Context,
+        None => core::ptr::addr_of_mut!(BOOT_CONTEXT),
+    };
+    let (next_ctx, ttbr0) = match PROCESS_MANAGER.get_process_mut(next) {
+        Some(p) => (&p.context as *const Context, p.ttbr0),
+        None => return,
+    };
+
+    // User processes run in their own TTBR0; kernel threads in the kernel's
+    mmu::switch_ttbr0(if ttbr0 != 0 { ttbr0 } else { mmu::kernel_ttbr0() });
+
+    cpu_switch_to(prev_ctx, next_ctx);
+}
+
+unsafe fn finish_switch() {
+    PROCESS_MANAGER.reap_stacks();
+    IN_SCHEDULER = false;
+}
+
+/// 新しいプロセスが最初に実行するコード (ret_from_fork から)
+#[no_mangle]
+extern "C" fn schedule_tail() {
+    unsafe { finish_switch() };
+}
+
+/// 現在のプロセスがユーザー空間から入ってきたときのトラップフレーム
+/// (カーネルスタックの先頭にある)
+pub fn current_user_frame() -> Option<&'static mut TrapFrame> {
+    unsafe {
+        let process = PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid())?;
+        if process.stack_base == 0 {
+            return None;
+        }
+        let frame_addr = process.stack_base + STACK_SIZE - core::mem::size_of::<TrapFrame>() as u64;
+        let frame = &mut *(frame_addr as *mut TrapFrame);
+        if frame.is_from_user() { Some(frame) } else { None }
+    }
+}
+
+/// execve の最後: 現在のプロセスのアドレス空間を `program` のものに置き換え、
+/// システムコールから新しいプログラムの先頭に戻るようにする
+pub fn exec_current(program: Program) -> Result<(), i64> {
+    let Some(frame) = current_user_frame() else {
+        program.space.destroy();
+        return Err(-22); // EINVAL - Not a user process
+    };
+
+    let daif = irq_save();
+    let old_ttbr0 = unsafe {
+        let pid = PROCESS_MANAGER.current_pid();
+        let process = PROCESS_MANAGER.get_process_mut(pid).unwrap();
+        let old = process.ttbr0;
+        process.ttbr0 = program.space.ttbr0();
+        process.entry_point = program.entry;
+        process.files.close_on_exec();
+        old
+    };
+    mmu::switch_ttbr0(program.space.ttbr0());
+    irq_restore(daif);
+
+    // 古いイメージはもう誰も使っていない
+    if old_ttbr0 != 0 {
+        AddressSpace::from_ttbr0(old_ttbr0).destroy();
+    }
+
+    // レジスタはすべて 0 から (x0 は handle_svc が戻り値 0 にする)
+    unsafe {
+        core::ptr::write_bytes(frame as *mut TrapFrame, 0, 1);
+        core::arch::asm!("msr tpidr_el0, xzr");
+    }
+    frame.elr = program.entry;
+    frame.sp_el0 = program.stack_pointer;
+    frame.spsr = SPSR_EL0T;
+    Ok(())
+}
+
+/// 子プロセスの状態変化を待つ (WNOHANG なら待たない)
+pub fn wait(target: WaitTarget, options: u32) -> Result<Option<WaitResult>, i32> {
+    loop {
+        let daif = irq_save();
+        let result = unsafe { PROCESS_MANAGER.wait_child(target, options) };
+        if !matches!(result, Ok(None)) || options & WNOHANG != 0 {
+            irq_restore(daif);
+            return result;
+        }
+        unsafe { (*core::ptr::addr_of_mut!(CHILD_WAIT)).sleep() };
+        irq_restore(daif);
+    }
+}
+
+/// 現在のプロセスを `status` (wait ステータス) で終了して他のプロセスに切り替える
+pub fn exit_current(status: i32) -> ! {
+    unsafe {
+        let pid = PROCESS_MANAGER.current_pid();
+        PROCESS_MANAGER.exit_process(pid, status);
+    }
+    schedule();
+    // Nothing is ever switched back to a terminated process
+    loop {
+        unsafe { core::arch::asm!("wfe"); }
+    }
+}
+
+extern "C" fn kernel_thread_exit() -> ! {
+    exit_current(exited_status(0))
+}
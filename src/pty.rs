@@ -0,0 +1,205 @@
+// Pseudo-terminals
+//
+// Opening /dev/ptmx allocates a pair: the descriptor is the master end and
+// /dev/pts/N, once unlocked with TIOCSPTLCK, is the slave. The slave is an
+// ordinary tty with its own line discipline, settings and foreground
+// process group; what the master writes is its input, and what is written
+// to it (echo included) is what the master reads. When the master is closed
+// the slave hangs up; when the last slave user is gone the master reads EIO.
+
+use heapless::Deque;
+
+use crate::process::{self, WaitQueue};
+use crate::tty::{Tty, TtyDriver};
+
+pub const MAX_PTYS: usize = 8;
+const MASTER_BUFFER_SIZE: usize = 4096;
+
+const NAMES: [&str; MAX_PTYS] = [
+    "pts/0", "pts/1", "pts/2", "pts/3", "pts/4", "pts/5", "pts/6", "pts/7",
+];
+
+struct Pty {
+    master_open: bool,
+    locked: bool,
+    slave_opens: usize,       // Open /dev/pts/N descriptors and sessions using it
+    slave_opened: bool,       // Someone has used the slave since allocation
+    to_master: Deque<u8, MASTER_BUFFER_SIZE>,
+    master_readers: WaitQueue,
+    tty: Option<Tty>,         // The slave, set up on allocation
+}
+
+impl Pty {
+    const fn new() -> Self {
+        Self {
+            master_open: false,
+            locked: true,
+            slave_opens: 0,
+            slave_opened: false,
+            to_master: Deque::new(),
+            master_readers: WaitQueue::new(),
+            tty: None,
+        }
+    }
+
+    fn in_use(&self) -> bool {
+        self.master_open || self.slave_opens > 0
+    }
+}
+
+static mut PTYS: [Pty; MAX_PTYS] = [const { Pty::new() }; MAX_PTYS];
+
+fn pty(index: usize) -> Option<&'static mut Pty> {
+    if index >= MAX_PTYS {
+        return None;
+    }
+    Some(unsafe { &mut (*core::ptr::addr_of_mut!(PTYS))[index] })
+}
+
+/// Slave side driver: its output is the master's input
+#[derive(Clone, Copy)]
+struct PtyDriver {
+    index: usize,
+}
+
+impl TtyDriver for PtyDriver {
+    fn write(&self, data: &[u8]) {
+        let Some(pty) = pty(self.index) else { return };
+        let daif = process::irq_save();
+        for &byte in data {
+            // Nobody is reading the master: drop, as a full terminal would
+            if pty.to_master.push_back(byte).is_err() {
+                break;
+            }
+        }
+        process::irq_restore(daif);
+        pty.master_readers.wake_all();
+    }
+
+    fn flush_output(&self) {
+        if let Some(pty) = pty(self.index) {
+            let daif = process::irq_save();
+            pty.to_master.clear();
+            process::irq_restore(daif);
+        }
+    }
+}
+
+const fn drivers() -> [PtyDriver; MAX_PTYS] {
+    let mut drivers = [PtyDriver { index: 0 }; MAX_PTYS];
+    let mut i = 0;
+    while i < MAX_PTYS {
+        drivers[i].index = i;
+        i += 1;
+    }
+    drivers
+}
+
+static DRIVERS: [PtyDriver; MAX_PTYS] = drivers();
+
+/// open("/dev/ptmx"): a fresh pair, its slave locked. Returns N of /dev/pts/N.
+pub fn allocate() -> Result<usize, i64> {
+    for index in 0..MAX_PTYS {
+        let pty = pty(index).unwrap();
+        if pty.in_use() {
+            continue;
+        }
+        let mut tty = Tty::new(NAMES[index], &DRIVERS[index]);
+        tty.reset();
+        pty.tty = Some(tty);
+        pty.to_master.clear();
+        pty.master_open = true;
+        pty.locked = true;
+        pty.slave_opened = false;
+        return Ok(index);
+    }
+    Err(-28) // ENOSPC - All ptys in use
+}
+
+/// TIOCSPTLCK
+pub fn set_locked(index: usize, locked: bool) -> Result<(), i64> {
+    let pty = pty(index).filter(|p| p.master_open).ok_or(-5i64)?; // EIO
+    pty.locked = locked;
+    Ok(())
+}
+
+/// open("/dev/pts/N")
+pub fn open_slave(index: usize) -> Result<(), i64> {
+    let pty = pty(index).filter(|p| p.master_open).ok_or(-6i64)?; // ENXIO
+    if pty.locked {
+        return Err(-5); // EIO
+    }
+    pty.slave_opens += 1;
+    pty.slave_opened = true;
+    Ok(())
+}
+
+/// A descriptor or session on the slave went away
+pub fn close_slave(index: usize) {
+    let Some(pty) = pty(index) else { return };
+    pty.slave_opens = pty.slave_opens.saturating_sub(1);
+    if pty.slave_opens == 0 {
+        // The master reads EIO once it has what is left
+        pty.master_readers.wake_all();
+        if !pty.master_open {
+            pty.tty = None;
+        }
+    }
+}
+
+/// The master was closed: the slave side hangs up
+pub fn close_master(index: usize) {
+    let Some(pty) = pty(index) else { return };
+    pty.master_open = false;
+    if let Some(tty) = pty.tty.as_mut() {
+        tty.hangup();
+    }
+    if pty.slave_opens == 0 {
+        pty.tty = None;
+    }
+}
+
+/// The slave tty of pair `index`, if allocated
+pub fn slave(index: usize) -> Option<&'static mut Tty> {
+    pty(index).and_then(|p| p.tty.as_mut())
+}
+
+/// Read what the slave side wrote, sleeping until there is something.
+/// EIO once the slave is closed and everything has been read.
+pub fn master_read(index: usize, buf: &mut [u8]) -> Result<usize, i64> {
+    let pty = pty(index).filter(|p| p.master_open).ok_or(-9i64)?; // EBADF
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    loop {
+        let daif = process::irq_save();
+        let mut count = 0;
+        while count < buf.len() {
+            match pty.to_master.pop_front() {
+                Some(byte) => buf[count] = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        if count > 0 {
+            process::irq_restore(daif);
+            return Ok(count);
+        }
+        if pty.slave_opened && pty.slave_opens == 0 {
+            process::irq_restore(daif);
+            return Err(-5); // EIO
+        }
+        pty.master_readers.sleep();
+        process::irq_restore(daif);
+    }
+}
+
+/// Input for the slave, as if typed on its terminal
+pub fn master_write(index: usize, data: &[u8]) -> Result<usize, i64> {
+    let pty = pty(index).filter(|p| p.master_open).ok_or(-9i64)?; // EBADF
+    let tty = pty.tty.as_mut().ok_or(-5i64)?; // EIO
+    let daif = process::irq_save();
+    tty.receive(data);
+    process::irq_restore(daif);
+    Ok(data.len())
+}
@@ -0,0 +1,348 @@
+// Real-Time Clock and wall-clock time for Raspberry Pi 5
+//
+// CLOCK_REALTIME is the monotonic clock plus an offset. The offset is loaded
+// from the hardware RTC at boot and changed by clock_settime()/date -s, which
+// also write the new time back to the RTC so that it survives a reboot.
+//
+// On the Pi 5 the RTC lives in the power management chip and is reached
+// through the VideoCore firmware mailbox (the property interface Linux's
+// rtc-rpi driver uses). QEMU's virt machine has a PL031 instead, which is
+// what makes this testable without the board. Both count whole seconds since
+// the Unix epoch, UTC.
+
+use core::fmt;
+use core::sync::atomic::{AtomicI64, Ordering};
+
+use crate::dtb;
+use crate::ktimer::NSEC_PER_SEC;
+use crate::mmu;
+use crate::timer;
+
+// PL031 registers
+const PL031_DR: u64 = 0x00;     // Data (current seconds)
+const PL031_LR: u64 = 0x08;     // Load (set seconds)
+const PL031_CR: u64 = 0x0C;     // Control
+const PL031_CR_START: u32 = 1 << 0;
+
+// VideoCore mailbox registers: the ARM reads mailbox 0 and writes mailbox 1
+const MBOX_READ: u64 = 0x00;
+const MBOX_STATUS: u64 = 0x18;
+const MBOX_WRITE: u64 = 0x20;
+const MBOX_WRITE_STATUS: u64 = 0x38;
+const MBOX_FULL: u32 = 1 << 31;
+const MBOX_EMPTY: u32 = 1 << 30;
+const MBOX_CHANNEL_PROPERTY: u32 = 8;
+const MBOX_TIMEOUT_US: u64 = 100_000;
+
+// Firmware property tags for the RTC
+const TAG_GET_RTC_REG: u32 = 0x0003_0087;
+const TAG_SET_RTC_REG: u32 = 0x0003_8087;
+const RTC_REG_TIME: u32 = 0;
+
+const PROPERTY_REQUEST: u32 = 0;
+const PROPERTY_SUCCESS: u32 = 0x8000_0000;
+const TAG_RESPONSE: u32 = 1 << 31;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RtcDevice {
+    None,
+    Pl031(u64),
+    Firmware { mailbox: u64, bus_offset: u64 },
+}
+
+impl RtcDevice {
+    fn name(&self) -> &'static str {
+        match self {
+            RtcDevice::None => "none",
+            RtcDevice::Pl031(_) => "pl031",
+            RtcDevice::Firmware { .. } => "firmware",
+        }
+    }
+}
+
+static mut DEVICE: RtcDevice = RtcDevice::None;
+
+// CLOCK_REALTIME - CLOCK_MONOTONIC in nanoseconds
+static REALTIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// One property message with one tag: (register, value) in and out
+#[repr(C, align(16))]
+struct RtcMessage {
+    size: u32,
+    code: u32,
+    tag: u32,
+    value_size: u32,
+    tag_code: u32,
+    reg: u32,
+    value: u32,
+    end: u32,
+}
+
+static mut MESSAGE: RtcMessage = RtcMessage {
+    size: 0, code: 0, tag: 0, value_size: 0, tag_code: 0, reg: 0, value: 0, end: 0,
+};
+
+fn mmio_read(addr: u64) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
+}
+
+fn mmio_write(addr: u64, value: u32) {
+    unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+}
+
+/// Wait until `(read(reg) & bit) == 0`, giving up after MBOX_TIMEOUT_US
+fn mbox_wait_clear(addr: u64, bit: u32) -> Result<(), &'static str> {
+    let start = timer::get_time_us();
+    while mmio_read(addr) & bit != 0 {
+        if timer::get_time_us() - start > MBOX_TIMEOUT_US {
+            return Err("mailbox timeout");
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Send one RTC property tag to the firmware and return the value it answers with
+fn firmware_call(mailbox: u64, bus_offset: u64, tag: u32, value: u32) -> Result<u32, &'static str> {
+    let msg = unsafe { &mut *core::ptr::addr_of_mut!(MESSAGE) };
+    *msg = RtcMessage {
+        size: core::mem::size_of::<RtcMessage>() as u32,
+        code: PROPERTY_REQUEST,
+        tag,
+        value_size: 8,
+        tag_code: 0,
+        reg: RTC_REG_TIME,
+        value,
+        end: 0,
+    };
+
+    // The firmware reads and writes the buffer behind the caches
+    let addr = msg as *mut RtcMessage as u64;
+    let end = addr + core::mem::size_of::<RtcMessage>() as u64;
+    mmu::clean_invalidate_dcache_range(addr, end);
+
+    let bus_addr = addr.wrapping_add(bus_offset);
+    if bus_addr > u32::MAX as u64 {
+        return Err("mailbox buffer not addressable");
+    }
+    let request = (bus_addr as u32 & !0xF) | MBOX_CHANNEL_PROPERTY;
+
+    mbox_wait_clear(mailbox + MBOX_WRITE_STATUS, MBOX_FULL)?;
+    mmio_write(mailbox + MBOX_WRITE, request);
+    loop {
+        mbox_wait_clear(mailbox + MBOX_STATUS, MBOX_EMPTY)?;
+        if mmio_read(mailbox + MBOX_READ) == request {
+            break;
+        }
+    }
+
+    mmu::clean_invalidate_dcache_range(addr, end);
+    let msg = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(MESSAGE)) };
+    if msg.code != PROPERTY_SUCCESS || msg.tag_code & TAG_RESPONSE == 0 {
+        return Err("firmware rejected the RTC request");
+    }
+    Ok(msg.value)
+}
+
+/// Seconds since the epoch from the hardware RTC
+fn read_rtc(device: RtcDevice) -> Result<u64, &'static str> {
+    match device {
+        RtcDevice::None => Err("no RTC"),
+        RtcDevice::Pl031(base) => Ok(mmio_read(base + PL031_DR) as u64),
+        RtcDevice::Firmware { mailbox, bus_offset } => {
+            firmware_call(mailbox, bus_offset, TAG_GET_RTC_REG, 0).map(|secs| secs as u64)
+        }
+    }
+}
+
+fn write_rtc(device: RtcDevice, seconds: u64) -> Result<(), &'static str> {
+    // Both RTCs hold a 32-bit count, good until 2106
+    let seconds = u32::try_from(seconds).map_err(|_| "time out of RTC range")?;
+    match device {
+        RtcDevice::None => Err("no RTC"),
+        RtcDevice::Pl031(base) => {
+            mmio_write(base + PL031_LR, seconds);
+            Ok(())
+        }
+        RtcDevice::Firmware { mailbox, bus_offset } => {
+            firmware_call(mailbox, bus_offset, TAG_SET_RTC_REG, seconds).map(|_| ())
+        }
+    }
+}
+
+/// Find the RTC in the device tree and set the wall clock from it
+pub fn init() -> Result<(), &'static str> {
+    let info = dtb::platform();
+    let device = if let Some(base) = info.rtc_base {
+        if mmio_read(base + PL031_CR) & PL031_CR_START == 0 {
+            mmio_write(base + PL031_CR, PL031_CR_START);
+        }
+        RtcDevice::Pl031(base)
+    } else if let (true, Some(mailbox)) = (info.firmware_rtc, info.mailbox_base) {
+        RtcDevice::Firmware { mailbox, bus_offset: info.mailbox_bus_offset }
+    } else {
+        return Err("no RTC found, the clock starts at the epoch");
+    };
+    unsafe { DEVICE = device };
+
+    let seconds = read_rtc(device)?;
+    let now = timer::monotonic_ns() as i64;
+    REALTIME_OFFSET.store((seconds * NSEC_PER_SEC) as i64 - now, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Name of the RTC in use ("pl031", "firmware" or "none")
+pub fn device_name() -> &'static str {
+    let device = unsafe { DEVICE };
+    device.name()
+}
+
+/// CLOCK_REALTIME: nanoseconds since 1970-01-01 00:00:00 UTC
+pub fn realtime_ns() -> u64 {
+    let offset = REALTIME_OFFSET.load(Ordering::Relaxed);
+    (timer::monotonic_ns() as i64).saturating_add(offset).max(0) as u64
+}
+
+/// The monotonic time at which CLOCK_REALTIME reads `realtime`
+pub fn realtime_to_monotonic(realtime: u64) -> u64 {
+    let offset = REALTIME_OFFSET.load(Ordering::Relaxed);
+    (realtime as i64).saturating_sub(offset).max(0) as u64
+}
+
+/// Set CLOCK_REALTIME and write it to the RTC. The clock is changed even if
+/// the RTC cannot be written; the error says the change will not persist.
+pub fn set_realtime(ns: u64) -> Result<(), &'static str> {
+    let now = timer::monotonic_ns() as i64;
+    REALTIME_OFFSET.store((ns as i64).saturating_sub(now), Ordering::Relaxed);
+    match unsafe { DEVICE } {
+        RtcDevice::None => Ok(()),
+        device => write_rtc(device, ns / NSEC_PER_SEC),
+    }
+}
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Broken-down UTC time
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,   // 1-12
+    pub day: u32,     // 1-31
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32, // 0 = Thursday, the weekday of the epoch
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days (proleptic Gregorian)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / 86_400) as i64;
+        let secs = (seconds % 86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+            weekday: (days % 7) as u32,
+        }
+    }
+
+    /// Seconds since the epoch, None before 1970
+    pub fn to_unix(self) -> Option<u64> {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let seconds = days * 86_400
+            + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        u64::try_from(seconds).ok()
+    }
+
+    /// "YYYY-MM-DD", "YYYY-MM-DD HH:MM" or "YYYY-MM-DD HH:MM:SS" (a 'T' may
+    /// separate date and time)
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (date, time) = match s.find([' ', 'T']) {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => (s, ""),
+        };
+
+        let mut parts = date.split('-');
+        let year: i64 = parts.next()?.parse().ok()?;
+        let month: u32 = parts.next()?.parse().ok()?;
+        let day: u32 = parts.next()?.parse().ok()?;
+        if parts.next().is_some() || !(1..=12).contains(&month)
+            || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        let (mut hour, mut minute, mut second) = (0, 0, 0);
+        if !time.is_empty() {
+            let mut parts = time.split(':');
+            hour = parts.next()?.parse().ok()?;
+            minute = parts.next()?.parse().ok()?;
+            if let Some(s) = parts.next() {
+                second = s.parse().ok()?;
+            }
+            if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+                return None;
+            }
+        }
+
+        let days = days_from_civil(year, month, day);
+        Some(Self {
+            year, month, day, hour, minute, second,
+            weekday: days.rem_euclid(7) as u32,
+        })
+    }
+}
+
+/// Same layout as date(1) in the C locale: "Fri Oct 16 12:34:56 UTC 2026"
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:2} {:02}:{:02}:{:02} UTC {}",
+               DAY_NAMES[self.weekday as usize % 7], MONTH_NAMES[(self.month as usize - 1) % 12],
+               self.day, self.hour, self.minute, self.second, self.year)
+    }
+}
+
+/// The current wall-clock time
+pub fn now() -> DateTime {
+    DateTime::from_unix(realtime_ns() / NSEC_PER_SEC)
+}
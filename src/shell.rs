@@ -1,15 +1,60 @@
 // UNIX-like Shell Implementation
 // Provides command line interface
 
-use crate::uart::UART;
-use crate::process::{PROCESS_MANAGER, ProcessState};
+use crate::tty::STDOUT;
+use crate::elf;
+use crate::initramfs;
+use crate::process::{self, PROCESS_MANAGER, ProcessState};
+use crate::pty;
+use crate::signals::Signal;
 use crate::timer::TIMER;
+use crate::klog::{self, Level};
+use crate::ktimer;
+use crate::rtc;
+use crate::termios::{self, Termios};
+use crate::tty::{self, TtyId};
+use crate::uart;
+use crate::debug;
 use crate::unix_commands::UnixCommands;
 use crate::users::UserManager;
 use heapless::{String, Vec};
+use core::fmt::Write;
 
 const MAX_INPUT: usize = 128;
 const MAX_ARGS: usize = 16;
+const MAX_TRANSCRIPT: usize = 64 * 1024;  // What `script` keeps for its file
+
+// Where programs named without a '/' are looked for, and their environment
+const PROGRAM_PATH: [&str; 4] = ["/bin", "/sbin", "/usr/bin", "/usr/sbin"];
+const PROGRAM_ENV: [&[u8]; 3] = [b"PATH=/bin:/sbin:/usr/bin:/usr/sbin", b"HOME=/", b"TERM=vt100"];
+
+// stty flags: name, termios field (0 iflag, 1 oflag, 2 cflag, 3 lflag), bit
+const STTY_FLAGS: [(&str, usize, u32); 15] = [
+    ("parenb", 2, termios::PARENB),
+    ("parodd", 2, termios::PARODD),
+    ("cstopb", 2, termios::CSTOPB),
+    ("crtscts", 2, termios::CRTSCTS),
+    ("icrnl", 0, termios::ICRNL),
+    ("opost", 1, termios::OPOST),
+    ("onlcr", 1, termios::ONLCR),
+    ("isig", 3, termios::ISIG),
+    ("icanon", 3, termios::ICANON),
+    ("iexten", 3, termios::IEXTEN),
+    ("echo", 3, termios::ECHO),
+    ("echoe", 3, termios::ECHOE),
+    ("echok", 3, termios::ECHOK),
+    ("echoctl", 3, termios::ECHOCTL),
+    ("echoke", 3, termios::ECHOKE),
+];
+
+fn termios_field(termios: &mut Termios, field: usize) -> &mut u32 {
+    match field {
+        0 => &mut termios.c_iflag,
+        1 => &mut termios.c_oflag,
+        2 => &mut termios.c_cflag,
+        _ => &mut termios.c_lflag,
+    }
+}
 
 pub struct Shell {
     running: bool,
@@ -32,9 +77,20 @@ impl Shell {
     }
     
     pub fn run(&mut self) {
+        // Like an interactive sh: the keyboard signals are for the jobs, not the shell
+        unsafe {
+            let pid = PROCESS_MANAGER.current_pid();
+            let mask = [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP]
+                .iter()
+                .fold(0u64, |mask, &signal| mask | 1 << (signal as u32 - 1));
+            PROCESS_MANAGER.set_ignored_signals(pid, mask);
+            // Programs started from here inherit the shell's directory
+            let _ = PROCESS_MANAGER.set_cwd(pid, &self.current_dir);
+        }
         self.print_banner();
         
         while self.running {
+            Self::reap_jobs();
             self.print_prompt();
             
             if let Some(line) = self.read_line() {
@@ -55,54 +111,61 @@ impl Shell {
     }
     
     fn print_banner(&self) {
-        UART.write_str("\n");
-        UART.write_str("========================================\n");
-        UART.write_str("     Pi5 OS - UNIX Compatible Shell    \n");
-        UART.write_str("     Raspberry Pi 5 POSIX Environment  \n");
-        UART.write_str("========================================\n");
-        UART.write_str("Type 'help' for available commands.\n");
-        UART.write_str("UNIX features: syscalls, signals, IPC, users\n\n");
+        STDOUT.write_str("\n");
+        STDOUT.write_str("========================================\n");
+        STDOUT.write_str("     Pi5 OS - UNIX Compatible Shell    \n");
+        STDOUT.write_str("     Raspberry Pi 5 POSIX Environment  \n");
+        STDOUT.write_str("========================================\n");
+        STDOUT.write_str("Type 'help' for available commands.\n");
+        STDOUT.write_str("UNIX features: syscalls, signals, IPC, users\n\n");
     }
     
     fn print_prompt(&self) {
-        UART.write_str(self.current_user);
-        UART.write_str("@pi5os:");
-        UART.write_str(&self.current_dir);
+        STDOUT.write_str(self.current_user);
+        STDOUT.write_str("@pi5os:");
+        STDOUT.write_str(&self.current_dir);
         if self.current_user == "root" {
-            UART.write_str("# ");
+            STDOUT.write_str("# ");
         } else {
-            UART.write_str("$ ");
+            STDOUT.write_str("$ ");
         }
     }
     
-    fn read_line(&self) -> Option<String<MAX_INPUT>> {
+    /// A line from the controlling tty, which does the editing and echo.
+    /// None when ^C (or ^Z, ^\) interrupted it.
+    fn read_line(&mut self) -> Option<String<MAX_INPUT>> {
         let mut buffer = String::new();
+        let tty = tty::current();
         
         loop {
-            if let Some(ch) = UART.read_char() {
-                match ch {
-                    '\r' | '\n' => {
-                        UART.write_str("\n");
-                        return Some(buffer);
+            let mut chunk = [0u8; MAX_INPUT];
+            match tty.read(&mut chunk) {
+                Ok(0) if buffer.is_empty() => {
+                    if unsafe { PROCESS_MANAGER.current_pid() } != 1 || tty.is_hung_up() {
+                        // ^D, or the terminal went away: this session is over
+                        self.running = false;
+                        return None;
                     }
-                    '\x08' | '\x7f' => { // Backspace
-                        if !buffer.is_empty() {
-                            buffer.pop();
-                            UART.write_str("\x08 \x08");
+                    // ^D on an empty line: the shell is init, it must not go away
+                    STDOUT.write_str("\nUse \"exit\" to leave the shell.\n");
+                    return Some(buffer);
+                }
+                Ok(0) => {}
+                Ok(n) => {
+                    for &byte in &chunk[..n] {
+                        if byte == b'\n' {
+                            return Some(buffer);
                         }
-                    }
-                    ch if ch.is_ascii() && !ch.is_control() => {
-                        if buffer.len() < MAX_INPUT - 1 {
-                            let _ = buffer.push(ch);
-                            UART.write_char(ch);
+                        if byte.is_ascii() && !byte.is_ascii_control() && buffer.len() < MAX_INPUT - 1 {
+                            let _ = buffer.push(byte as char);
                         }
                     }
-                    _ => {}
+                }
+                Err(_) => {
+                    STDOUT.write_str("\n");
+                    return None;
                 }
             }
-            
-            // CPU時間を他のプロセスに譲る
-            core::hint::spin_loop();
         }
     }
     
@@ -157,108 +220,125 @@ impl Shell {
             "uptime" => self.cmd_uptime(),
             "free" => self.cmd_free(),
             "df" => self.cmd_df(),
-            "date" => self.cmd_date(),
+            "nproc" => self.cmd_nproc(&args),
+            "date" => self.cmd_date(&args),
+            "stty" => self.cmd_stty(&args),
+            "script" => self.cmd_script(&args),
+            "dmesg" => self.cmd_dmesg(&args),
+            "klog" => self.cmd_klog(&args),
             
             // System commands
             "echo" => self.cmd_echo(&args),
+            "sleep" => self.cmd_sleep(&args),
             "test" => self.cmd_test(),
             "gpio" => self.cmd_gpio(&args),
             "led" => self.cmd_led(&args),
             "reboot" => self.cmd_reboot(),
             
+            // Anything else may be a program in the initramfs
+            _ if self.run_program(command, &args) => {}
             _ => {
-                UART.write_str(command);
-                UART.write_str(": command not found\n");
-                UART.write_str("Type 'help' for available commands.\n");
+                STDOUT.write_str(command);
+                STDOUT.write_str(": command not found\n");
+                STDOUT.write_str("Type 'help' for available commands.\n");
             }
         }
     }
     
     fn cmd_help(&self) {
-        UART.write_str("UNIX-Compatible Commands:\n\n");
-        
-        UART.write_str("File Operations:\n");
-        UART.write_str("  ls [path]     - List directory contents\n");
-        UART.write_str("  pwd           - Show current directory\n");
-        UART.write_str("  cd <dir>      - Change directory\n");
-        UART.write_str("  touch <file>  - Create empty file\n");
-        UART.write_str("  rm <file>     - Remove files\n");
-        UART.write_str("  cp <src> <dst> - Copy files\n");
-        UART.write_str("  mv <src> <dst> - Move/rename files\n");
-        UART.write_str("  cat <file>    - Display file contents\n");
-        UART.write_str("  find <pattern> - Find files\n");
-        UART.write_str("  grep <pattern> <file> - Search in files\n");
-        UART.write_str("  mkdir <dir>   - Create directory\n\n");
-        
-        UART.write_str("Text Processing:\n");
-        UART.write_str("  wc <file>     - Word count\n");
-        UART.write_str("  head <file>   - Show first lines\n");
-        UART.write_str("  tail <file>   - Show last lines\n\n");
-        
-        UART.write_str("Process Management:\n");
-        UART.write_str("  ps            - List processes\n");
-        UART.write_str("  kill <pid>    - Kill process\n");
-        UART.write_str("  jobs          - List jobs\n");
-        UART.write_str("  top           - Process monitor\n\n");
-        
-        UART.write_str("User Management:\n");
-        UART.write_str("  whoami        - Current user\n");
-        UART.write_str("  id            - User/group IDs\n");
-        UART.write_str("  su [user]     - Switch user\n\n");
-        
-        UART.write_str("System Information:\n");
-        UART.write_str("  uname [-a]    - System info\n");
-        UART.write_str("  uptime        - System uptime\n");
-        UART.write_str("  free          - Memory usage\n");
-        UART.write_str("  df            - Disk usage\n");
-        UART.write_str("  date          - Current date/time\n\n");
-        
-        UART.write_str("System Commands:\n");
-        UART.write_str("  echo <text>   - Print text\n");
-        UART.write_str("  clear         - Clear screen\n");
-        UART.write_str("  history       - Command history\n");
-        UART.write_str("  test          - Run system tests\n");
-        UART.write_str("  gpio          - GPIO control\n");
-        UART.write_str("  reboot        - Restart system\n");
-        UART.write_str("  exit          - Exit shell\n");
+        STDOUT.write_str("UNIX-Compatible Commands:\n\n");
+        
+        STDOUT.write_str("File Operations:\n");
+        STDOUT.write_str("  ls [path]     - List directory contents\n");
+        STDOUT.write_str("  pwd           - Show current directory\n");
+        STDOUT.write_str("  cd <dir>      - Change directory\n");
+        STDOUT.write_str("  touch <file>  - Create empty file\n");
+        STDOUT.write_str("  rm <file>     - Remove files\n");
+        STDOUT.write_str("  cp <src> <dst> - Copy files\n");
+        STDOUT.write_str("  mv <src> <dst> - Move/rename files\n");
+        STDOUT.write_str("  cat <file>    - Display file contents\n");
+        STDOUT.write_str("  find <pattern> - Find files\n");
+        STDOUT.write_str("  grep <pattern> <file> - Search in files\n");
+        STDOUT.write_str("  mkdir <dir>   - Create directory\n\n");
+        
+        STDOUT.write_str("Text Processing:\n");
+        STDOUT.write_str("  wc <file>     - Word count\n");
+        STDOUT.write_str("  head <file>   - Show first lines\n");
+        STDOUT.write_str("  tail <file>   - Show last lines\n\n");
+        
+        STDOUT.write_str("Process Management:\n");
+        STDOUT.write_str("  ps            - List processes\n");
+        STDOUT.write_str("  kill <pid>    - Kill process\n");
+        STDOUT.write_str("  jobs          - List jobs\n");
+        STDOUT.write_str("  top           - Process monitor\n\n");
+        
+        STDOUT.write_str("User Management:\n");
+        STDOUT.write_str("  whoami        - Current user\n");
+        STDOUT.write_str("  id            - User/group IDs\n");
+        STDOUT.write_str("  su [user]     - Switch user\n\n");
+        
+        STDOUT.write_str("System Information:\n");
+        STDOUT.write_str("  uname [-a]    - System info\n");
+        STDOUT.write_str("  uptime        - System uptime\n");
+        STDOUT.write_str("  free          - Memory usage\n");
+        STDOUT.write_str("  df            - Disk usage\n");
+        STDOUT.write_str("  nproc [--all] - Number of online CPUs\n");
+        STDOUT.write_str("  date [-s <t>] - Show or set the date/time (UTC)\n");
+        STDOUT.write_str("  stty [settings] - Show or change terminal line settings\n");
+        STDOUT.write_str("  script [file] - Record a shell session on a pty (default: typescript)\n");
+        STDOUT.write_str("  dmesg [-c|-C|-r] [-l levels] [-n level] - Kernel log\n");
+        STDOUT.write_str("  klog [spec]   - Show or set kernel log filters (e.g. info,ipc=off)\n\n");
+        
+        STDOUT.write_str("System Commands:\n");
+        STDOUT.write_str("  echo <text>   - Print text\n");
+        STDOUT.write_str("  sleep <time>  - Pause (seconds, or suffix s/m/h)\n");
+        STDOUT.write_str("  clear         - Clear screen\n");
+        STDOUT.write_str("  history       - Command history\n");
+        STDOUT.write_str("  test          - Run system tests\n");
+        STDOUT.write_str("  gpio          - GPIO control\n");
+        STDOUT.write_str("  reboot        - Restart system\n");
+        STDOUT.write_str("  exit          - Exit shell\n\n");
+        
+        STDOUT.write_str("Any other command is run from the initramfs (/bin, /sbin, /usr/bin, /usr/sbin)\n");
     }
     
     fn cmd_ps(&self) {
-        UART.write_str("  PID  PPID STATE    TIME COMMAND\n");
-        UART.write_str("-------------------------------\n");
+        STDOUT.write_str("  PID  PPID STATE    TIME COMMAND\n");
+        STDOUT.write_str("-------------------------------\n");
         
         unsafe {
             for process in PROCESS_MANAGER.list_processes() {
                 // PID
                 self.print_number(process.pid, 5);
-                UART.write_str(" ");
+                STDOUT.write_str(" ");
                 
                 // PPID  
                 self.print_number(process.ppid, 4);
-                UART.write_str(" ");
+                STDOUT.write_str(" ");
                 
                 // STATE
                 let state_str = match process.state {
                     ProcessState::Ready => "READY  ",
                     ProcessState::Running => "RUN    ",
                     ProcessState::Sleeping => "SLEEP  ",
+                    ProcessState::Stopped => "STOP   ",
                     ProcessState::Terminated => "TERM   ",
                 };
-                UART.write_str(state_str);
-                UART.write_str(" ");
+                STDOUT.write_str(state_str);
+                STDOUT.write_str(" ");
                 
                 // TIME
                 self.print_number(process.used_time, 4);
-                UART.write_str(" ");
+                STDOUT.write_str(" ");
                 
                 // COMMAND (simplified)
                 if process.pid == 1 {
-                    UART.write_str("init");
+                    STDOUT.write_str("init");
                 } else {
-                    UART.write_str("process");
+                    STDOUT.write_str("process");
                 }
                 
-                UART.write_str("\n");
+                STDOUT.write_str("\n");
             }
         }
     }
@@ -269,63 +349,485 @@ impl Shell {
         let minutes = (uptime % 3600) / 60;
         let seconds = uptime % 60;
         
-        UART.write_str("up ");
+        STDOUT.write_str("up ");
         self.print_number(hours, 0);
-        UART.write_str("h ");
+        STDOUT.write_str("h ");
         self.print_number(minutes, 0);
-        UART.write_str("m ");
+        STDOUT.write_str("m ");
         self.print_number(seconds, 0);
-        UART.write_str("s\n");
+        STDOUT.write_str("s\n");
     }
     
     fn cmd_uname(&self, args: &Vec<&str, MAX_ARGS>) {
         let show_all = args.iter().any(|&arg| arg == "-a");
         
         if show_all {
-            UART.write_str("Minimal-Pi5-OS v0.1.0 raspberrypi5 aarch64 GNU/Linux\n");
+            STDOUT.write_str("Minimal-Pi5-OS v0.1.0 raspberrypi5 aarch64 GNU/Linux\n");
         } else {
-            UART.write_str("Minimal-Pi5-OS\n");
+            STDOUT.write_str("Minimal-Pi5-OS\n");
         }
     }
     
     fn cmd_echo(&self, args: &Vec<&str, MAX_ARGS>) {
         for (i, arg) in args.iter().enumerate() {
             if i > 0 {
-                UART.write_str(" ");
+                STDOUT.write_str(" ");
             }
-            UART.write_str(arg);
+            STDOUT.write_str(arg);
         }
-        UART.write_str("\n");
+        STDOUT.write_str("\n");
     }
     
     fn cmd_clear(&self) {
-        UART.write_str("\x1b[2J\x1b[H"); // ANSI clear screen
+        STDOUT.write_str("\x1b[2J\x1b[H"); // ANSI clear screen
     }
     
     fn cmd_history(&self) {
         for (i, cmd) in self.history.iter().enumerate() {
             self.print_number((i + 1) as u32, 3);
-            UART.write_str("  ");
-            UART.write_str(cmd.as_str());
-            UART.write_str("\n");
+            STDOUT.write_str("  ");
+            STDOUT.write_str(cmd.as_str());
+            STDOUT.write_str("\n");
         }
     }
     
-    fn cmd_date(&self) {
-        let uptime = TIMER.get_uptime_seconds();
-        UART.write_str("System uptime: ");
-        self.print_number(uptime, 0);
-        UART.write_str(" seconds since boot\n");
+    fn cmd_date(&self, args: &Vec<&str, MAX_ARGS>) {
+        match args.first() {
+            None => {
+                let mut out = STDOUT;
+                let _ = writeln!(out, "{}", rtc::now());
+            }
+            Some(&"+%s") => {
+                let mut out = STDOUT;
+                let _ = writeln!(out, "{}", rtc::realtime_ns() / ktimer::NSEC_PER_SEC);
+            }
+            Some(&"-s") | Some(&"--set") => self.set_date(&args[1..]),
+            Some(arg) => {
+                STDOUT.write_str("date: invalid option '");
+                STDOUT.write_str(arg);
+                STDOUT.write_str("'\n");
+                STDOUT.write_str("Usage: date [+%s] | date -s 'YYYY-MM-DD [HH:MM[:SS]]' | date -s @SECONDS\n");
+            }
+        }
+    }
+
+    /// date -s: the time is UTC, given as a date and time or @seconds since the epoch
+    fn set_date(&self, args: &[&str]) {
+        if self.current_user != "root" {
+            STDOUT.write_str("date: cannot set date: Operation not permitted\n");
+            return;
+        }
+
+        // Arguments are not quoted, so "2026-10-16 12:00" arrives as two of them
+        let mut text: String<64> = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if (i > 0 && text.push(' ').is_err()) || text.push_str(arg).is_err() {
+                break;
+            }
+        }
+
+        let seconds = match text.strip_prefix('@') {
+            Some(epoch) => epoch.parse::<u64>().ok(),
+            None => rtc::DateTime::parse(&text).and_then(|dt| dt.to_unix()),
+        };
+        let seconds = match seconds {
+            Some(seconds) => seconds,
+            None => {
+                STDOUT.write_str("date: invalid date '");
+                STDOUT.write_str(&text);
+                STDOUT.write_str("'\n");
+                return;
+            }
+        };
+
+        if let Err(e) = rtc::set_realtime(seconds.saturating_mul(ktimer::NSEC_PER_SEC)) {
+            STDOUT.write_str("date: warning: ");
+            STDOUT.write_str(e);
+            STDOUT.write_str(", the time will not persist\n");
+        }
+        let mut out = STDOUT;
+        let _ = writeln!(out, "{}", rtc::now());
+    }
+    
+    /// stty [[speed] N] [cs5..cs8] [-]FLAG ...: settings of the controlling tty
+    fn cmd_stty(&self, args: &Vec<&str, MAX_ARGS>) {
+        let tty = tty::current();
+        let mut new = tty.termios();
+        let mut i = 0;
+        while i < args.len() {
+            let arg = args[i];
+            let (enable, name) = match arg.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, arg),
+            };
+            let speed = match name {
+                "speed" if enable && i + 1 < args.len() => {
+                    i += 1;
+                    Some(args[i])
+                }
+                _ if enable && name.starts_with(|c: char| c.is_ascii_digit()) => Some(name),
+                _ => None,
+            };
+            let ok = if let Some(speed) = speed {
+                match speed.parse().ok().and_then(termios::baud_to_code) {
+                    Some(code) => {
+                        new.c_cflag = (new.c_cflag & !termios::CBAUD) | code;
+                        true
+                    }
+                    None => false,
+                }
+            } else if let (true, Some(size)) = (enable, name.strip_prefix("cs")) {
+                match size {
+                    "5" | "6" | "7" | "8" => {
+                        let bits = (size.as_bytes()[0] - b'5') as u32;
+                        new.c_cflag = (new.c_cflag & !termios::CSIZE) | (bits << 4);
+                        true
+                    }
+                    _ => false,
+                }
+            } else if let Some(&(_, field, bit)) = STTY_FLAGS.iter().find(|(flag, _, _)| *flag == name) {
+                let flags = termios_field(&mut new, field);
+                *flags = if enable { *flags | bit } else { *flags & !bit };
+                true
+            } else {
+                false
+            };
+            if !ok {
+                STDOUT.write_str("stty: invalid argument '");
+                STDOUT.write_str(arg);
+                STDOUT.write_str("'\n");
+                STDOUT.write_str("Usage: stty [[speed] N] [cs5|cs6|cs7|cs8] [-]FLAG ...\n");
+                STDOUT.write_str("Flags: parenb parodd cstopb crtscts icrnl opost onlcr isig icanon iexten echo echoe echok echoctl echoke\n");
+                return;
+            }
+            i += 1;
+        }
+
+        if !args.is_empty() {
+            // TCSETSW: earlier output leaves at the old speed
+            if let Err(errno) = tty.set_termios(termios::TCSETSW, &new) {
+                let mut out = STDOUT;
+                let _ = writeln!(out, "stty: invalid settings (errno {})", -errno);
+            }
+            return;
+        }
+
+        let mut out = STDOUT;
+        let speed = termios::code_to_baud(new.c_cflag & termios::CBAUD).unwrap_or(0);
+        let size = 5 + ((new.c_cflag & termios::CSIZE) >> 4);
+        let winsize = tty.winsize();
+        let _ = write!(out, "{}: speed {} baud; rows {}; columns {}; cs{}",
+                       tty.name(), speed, winsize.ws_row, winsize.ws_col, size);
+        for &(name, field, bit) in STTY_FLAGS.iter() {
+            let on = *termios_field(&mut new, field) & bit != 0;
+            let _ = write!(out, " {}{}", if on { "" } else { "-" }, name);
+        }
+        let _ = writeln!(out);
+        if !core::ptr::eq(tty, tty::console()) {
+            return;
+        }
+        match uart::clock() {
+            Some(hz) => { let _ = writeln!(out, "reference clock {} Hz", hz); }
+            None => STDOUT.write_str("reference clock unknown (settings from the firmware)\n"),
+        }
+        let _ = writeln!(out, "rx overruns: {} byte(s) dropped", uart::rx_dropped());
+    }
+    
+    /// script [file]: run a shell on a new pty, showing and recording all it
+    /// prints, like script(1). The console is raw meanwhile, so ^C and the
+    /// other keys reach the pty's line discipline.
+    fn cmd_script(&self, args: &Vec<&str, MAX_ARGS>) {
+        let filename = args.first().copied().unwrap_or("typescript");
+        if !process::scheduler_running() {
+            STDOUT.write_str("script: needs the scheduler\n");
+            return;
+        }
+        let index = match pty::allocate() {
+            Ok(index) => index,
+            Err(_) => {
+                STDOUT.write_str("script: out of pseudo-terminals\n");
+                return;
+            }
+        };
+        let _ = pty::set_locked(index, false);
+        let _ = pty::open_slave(index); // Held by the session until its shell exits
+        if let Some(slave) = pty::slave(index) {
+            slave.set_winsize(tty::console().winsize());
+        }
+
+        unsafe { SCRIPT_PTY = index };
+        // The process table must not change under the timer interrupt
+        let daif = process::irq_save();
+        let (session, relay) = unsafe {
+            let pid = PROCESS_MANAGER.current_pid();
+            let session = PROCESS_MANAGER.create_process(script_session as *const () as u64, pid);
+            let relay = PROCESS_MANAGER.create_process(script_relay as *const () as u64, pid);
+            (session, relay)
+        };
+        process::irq_restore(daif);
+        if session.is_none() || relay.is_none() {
+            STDOUT.write_str("script: cannot create processes\n");
+            let daif = process::irq_save();
+            unsafe {
+                session.map(|pid| PROCESS_MANAGER.terminate_process(pid));
+                relay.map(|pid| PROCESS_MANAGER.terminate_process(pid));
+            }
+            process::irq_restore(daif);
+            if session.is_none() {
+                pty::close_slave(index);
+            }
+            pty::close_master(index);
+            return;
+        }
+
+        let console = tty::console();
+        let saved = console.termios();
+        let mut raw = saved;
+        raw.make_raw();
+        let _ = console.set_termios(termios::TCSETSW, &raw);
+        let mut out = STDOUT;
+        let _ = write!(out, "Script started on /dev/pts/{}, file is {}\r\n", index, filename);
+
+        // Everything the session prints, until its shell exits (EIO)
+        let mut transcript = alloc::vec::Vec::new();
+        let mut buf = [0u8; 256];
+        while let Ok(n) = pty::master_read(index, &mut buf) {
+            let _ = console.write(&buf[..n]);
+            if transcript.len() + n <= MAX_TRANSCRIPT {
+                transcript.extend_from_slice(&buf[..n]);
+            }
+        }
+
+        let daif = process::irq_save();
+        unsafe {
+            PROCESS_MANAGER.terminate_process(relay.unwrap());
+        }
+        process::irq_restore(daif);
+        let _ = console.set_termios(termios::TCSETSW, &saved);
+        pty::close_master(index);
+
+        let text = alloc::string::String::from_utf8_lossy(&transcript);
+        let saved_file = crate::filesystem::write_file(filename, &text)
+            || crate::filesystem::create_file(filename, &text);
+        if saved_file {
+            let _ = writeln!(out, "Script done, file is {} ({} bytes)", filename, transcript.len());
+        } else {
+            let _ = writeln!(out, "Script done, but {} could not be written", filename);
+        }
+    }
+    
+    /// Where `command` is in the initramfs: a path as given, or a name
+    /// looked up in PROGRAM_PATH
+    fn find_program(&self, command: &str) -> Option<alloc::string::String> {
+        if command.contains('/') {
+            let path = if command.starts_with('/') {
+                alloc::string::String::from(command)
+            } else {
+                alloc::format!("{}/{}", self.current_dir.trim_end_matches('/'), command)
+            };
+            return initramfs::lookup(&path).map(|_| path);
+        }
+        PROGRAM_PATH.iter()
+            .map(|dir| alloc::format!("{}/{}", dir, command))
+            .find(|path| initramfs::lookup(path).is_some())
+    }
+
+    /// Run a program from the initramfs in the foreground, in a process group
+    /// of its own, and wait until it is gone. False if there is no such program.
+    pub fn run_program(&self, command: &str, args: &[&str]) -> bool {
+        let Some(path) = self.find_program(command) else { return false };
+        let mut out = STDOUT;
+        let file = initramfs::lookup(&path).unwrap();
+        if file.file_type() != initramfs::S_IFREG || file.mode & 0o111 == 0 {
+            let _ = writeln!(out, "{}: permission denied", command);
+            return true;
+        }
+        if !process::scheduler_running() {
+            let _ = writeln!(out, "{}: needs the scheduler", command);
+            return true;
+        }
+
+        let mut argv: alloc::vec::Vec<&[u8]> = alloc::vec![command.as_bytes()];
+        argv.extend(args.iter().map(|arg| arg.as_bytes()));
+        let program = match elf::load(file.data, &argv, &PROGRAM_ENV, &path) {
+            Ok(program) => program,
+            Err(-8) => {
+                let _ = writeln!(out, "{}: not a static AArch64 executable for this kernel", command);
+                return true;
+            }
+            Err(errno) => {
+                let _ = writeln!(out, "{}: cannot load (error {})", command, -errno);
+                return true;
+            }
+        };
+
+        // The child must not run before it is in the foreground group
+        let tty = tty::current();
+        let daif = process::irq_save();
+        let (shell_pid, shell_pgid, child) = unsafe {
+            let pid = PROCESS_MANAGER.current_pid();
+            let pgid = PROCESS_MANAGER.get_process(pid).map_or(pid, |p| p.pgid);
+            let child = PROCESS_MANAGER.create_user_process(
+                program.entry, program.stack_pointer, program.space.ttbr0(), pid);
+            if let Some(child) = child {
+                let _ = PROCESS_MANAGER.set_pgid(child, child);
+                // The keyboard signals the shell ignores are the child's again
+                PROCESS_MANAGER.set_ignored_signals(child, 0);
+                let _ = tty.set_pgrp(child);
+            }
+            (pid, pgid, child)
+        };
+        process::irq_restore(daif);
+        let Some(child) = child else {
+            program.space.destroy();
+            let _ = writeln!(out, "{}: cannot create a process", command);
+            return true;
+        };
+        debug!("shell: PID {} runs {} as PID {}", shell_pid, path, child);
+
+        // Until it exits, or is stopped (^Z) and left to the shell
+        let status = process::wait(process::WaitTarget::Pid(child), process::WEXITED | process::WSTOPPED)
+            .ok()
+            .flatten()
+            .map_or(0, |result| result.status);
+        let _ = tty.set_pgrp(shell_pgid);
+        Self::report_status(child, status);
+        true
+    }
+
+    /// What a foreground program's wait status is worth telling, as sh does
+    fn report_status(pid: u32, status: i32) {
+        let mut out = STDOUT;
+        let signal = |number: i32| Signal::from_i32(number).map_or("unknown signal", |signal| signal.name());
+        if status & 0xFF == 0x7F {
+            let _ = write!(out, "\n[{}] Stopped ({})\n", pid, signal(status >> 8 & 0xFF));
+        } else if status & 0x7F != 0 && status & 0x7F != Signal::SIGINT as i32 {
+            let core = if status & 0x80 != 0 { " (core dumped)" } else { "" };
+            let _ = writeln!(out, "{}{}", signal(status & 0x7F), core);
+        }
+    }
+
+    /// Collect the jobs that ended while the shell was doing something else
+    fn reap_jobs() {
+        while let Ok(Some(result)) = process::wait(process::WaitTarget::Any, process::WEXITED | process::WNOHANG) {
+            debug!("shell: PID {} done, status {:#x}", result.pid, result.status);
+        }
+    }
+
+    /// dmesg [-c|-C] [-r] [-l LEVEL[,LEVEL...]] [-n LEVEL]: kernel log buffer
+    fn cmd_dmesg(&self, args: &Vec<&str, MAX_ARGS>) {
+        let mut clear_after = false;
+        let mut raw = false;
+        let mut priorities: Option<Vec<u8, 8>> = None;
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                "-c" => clear_after = true,
+                "-C" => {
+                    klog::clear();
+                    return;
+                }
+                "-r" => raw = true,
+                "-l" if i + 1 < args.len() => {
+                    i += 1;
+                    let mut list = Vec::new();
+                    for name in args[i].split(',') {
+                        match Self::dmesg_priority(name) {
+                            Some(priority) => { let _ = list.push(priority); }
+                            None => {
+                                let mut out = STDOUT;
+                                let _ = writeln!(out, "dmesg: unknown level '{}'", name);
+                                return;
+                            }
+                        }
+                    }
+                    priorities = Some(list);
+                }
+                "-n" if i + 1 < args.len() => {
+                    i += 1;
+                    let level = args[i].parse::<u8>().ok()
+                        .or_else(|| Self::dmesg_priority(args[i]).map(|priority| priority + 1));
+                    match level {
+                        Some(level @ 1..=8) => klog::set_console_loglevel(level),
+                        _ => {
+                            let mut out = STDOUT;
+                            let _ = writeln!(out, "dmesg: invalid console level '{}'", args[i]);
+                        }
+                    }
+                    return;
+                }
+                arg => {
+                    STDOUT.write_str("dmesg: invalid option '");
+                    STDOUT.write_str(arg);
+                    STDOUT.write_str("'\n");
+                    STDOUT.write_str("Usage: dmesg [-c|-C] [-r] [-l err,warn,info,debug] [-n level]\n");
+                    return;
+                }
+            }
+            i += 1;
+        }
+
+        let records = klog::read_all();
+        if clear_after {
+            klog::clear();
+        }
+        let text = alloc::string::String::from_utf8_lossy(&records);
+        for record in text.lines() {
+            // "<6>[    0.123456] text"
+            let (priority, rest) = match record.strip_prefix('<').and_then(|r| r.split_once('>')) {
+                Some((priority, rest)) => (priority.parse::<u8>().unwrap_or(7), rest),
+                None => (7, record),
+            };
+            if priorities.as_ref().is_some_and(|list| !list.contains(&priority)) {
+                continue;
+            }
+            STDOUT.write_str(if raw { record } else { rest });
+            STDOUT.write_str("\n");
+        }
+    }
+
+    /// syslog priority of a dmesg level name
+    fn dmesg_priority(name: &str) -> Option<u8> {
+        match name {
+            "err" | "error" => Some(Level::Error.priority()),
+            "warn" => Some(Level::Warn.priority()),
+            "info" => Some(Level::Info.priority()),
+            "debug" | "trace" => Some(Level::Debug.priority()),
+            _ => None,
+        }
+    }
+
+    /// klog [SPEC...]: show the kernel log filters, or change them
+    fn cmd_klog(&self, args: &Vec<&str, MAX_ARGS>) {
+        for spec in args.iter() {
+            if let Err(e) = klog::set_filters(spec) {
+                let mut out = STDOUT;
+                let _ = writeln!(out, "klog: {} in '{}'", e, spec);
+                STDOUT.write_str("Usage: klog [LEVEL][,TARGET=LEVEL...]  (off error warn info debug trace)\n");
+                return;
+            }
+        }
+        if !args.is_empty() {
+            return;
+        }
+
+        let (default, targets) = klog::filters();
+        let mut out = STDOUT;
+        let _ = writeln!(out, "default: {}", klog::filter_name(default));
+        for (target, filter) in targets.iter() {
+            let _ = writeln!(out, "{}: {}", target, klog::filter_name(*filter));
+        }
+        let _ = writeln!(out, "console loglevel: {}", klog::console_loglevel());
     }
     
     fn cmd_whoami(&self) {
-        UART.write_str(self.current_user);
-        UART.write_str("\n");
+        STDOUT.write_str(self.current_user);
+        STDOUT.write_str("\n");
     }
     
     fn cmd_pwd(&self) {
-        UART.write_str(&self.current_dir);
-        UART.write_str("\n");
+        STDOUT.write_str(&self.current_dir);
+        STDOUT.write_str("\n");
     }
     
     fn cmd_ls(&self, args: &Vec<&str, MAX_ARGS>) {
@@ -335,14 +837,14 @@ impl Shell {
             args[0]
         };
         
-        UART.write_str("Directory listing for ");
-        UART.write_str(path);
-        UART.write_str(":\n");
+        STDOUT.write_str("Directory listing for ");
+        STDOUT.write_str(path);
+        STDOUT.write_str(":\n");
         
         let entries = crate::filesystem::list_directory(path);
         
         if entries.is_empty() {
-            UART.write_str("(empty directory)\n");
+            STDOUT.write_str("(empty directory)\n");
         } else {
             for file in entries {
                 // File permissions
@@ -354,7 +856,7 @@ impl Shell {
                     crate::filesystem::FileType::RegularFile => '-',
                 };
                 
-                UART.write_char(file_type_char);
+                STDOUT.write_char(file_type_char);
                 
                 // Print permissions in rwxrwxrwx format
                 for i in (0..9).rev() {
@@ -365,115 +867,129 @@ impl Shell {
                         0 => ['x', '-'],
                         _ => ['-', '-'],
                     };
-                    UART.write_char(chars[if bit == 1 { 0 } else { 1 }]);
+                    STDOUT.write_char(chars[if bit == 1 { 0 } else { 1 }]);
                 }
                 
-                UART.write_str("  ");
+                STDOUT.write_str("  ");
                 self.print_number(file.size as u32, 8);
-                UART.write_str("  ");
-                UART.write_str(file.name.as_str());
-                UART.write_str("\n");
+                STDOUT.write_str("  ");
+                STDOUT.write_str(file.name.as_str());
+                STDOUT.write_str("\n");
             }
         }
     }
     
     fn cmd_cat(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("cat: missing filename\n");
+            STDOUT.write_str("cat: missing filename\n");
             return;
         }
         
         let filename = args[0];
         match filename {
             "/proc/version" => {
-                UART.write_str("Minimal Pi5 OS version 0.1.0 (root@pi5) (aarch64) #1\n");
+                STDOUT.write_str("Minimal Pi5 OS version 0.1.0 (root@pi5) (aarch64) #1\n");
             }
             "/proc/cpuinfo" => {
-                UART.write_str("processor\t: 0\n");
-                UART.write_str("BogoMIPS\t: 108.00\n");
-                UART.write_str("Features\t: fp asimd evtstrm crc32 cpuid\n");
-                UART.write_str("CPU implementer\t: 0x41\n");
-                UART.write_str("CPU architecture: 8\n");
+                STDOUT.write_str(&crate::smp::cpuinfo());
             }
             "/proc/meminfo" => {
-                UART.write_str("MemTotal:     8388608 kB\n");
-                UART.write_str("MemFree:      7340032 kB\n");
-                UART.write_str("MemAvailable: 7340032 kB\n");
+                let (total, free) = crate::memory::get_memory_stats();
+                STDOUT.write_str("MemTotal:     ");
+                self.print_number((total / 1024) as u32, 0);
+                STDOUT.write_str(" kB\n");
+                STDOUT.write_str("MemFree:      ");
+                self.print_number((free / 1024) as u32, 0);
+                STDOUT.write_str(" kB\n");
+                STDOUT.write_str("MemAvailable: ");
+                self.print_number((free / 1024) as u32, 0);
+                STDOUT.write_str(" kB\n");
+            }
+            "/proc/interrupts" => {
+                STDOUT.write_str(&crate::interrupt::proc_interrupts());
+            }
+            "/proc/cmdline" => {
+                STDOUT.write_str(crate::cmdline::raw());
+                STDOUT.write_str("\n");
+            }
+            "/proc/kmsg" => {
+                // Each record once, like a syslog daemon reading it
+                STDOUT.write_str(&alloc::string::String::from_utf8_lossy(&klog::read_kmsg()));
             }
             _ => {
-                UART.write_str("cat: ");
-                UART.write_str(filename);
-                UART.write_str(": No such file or directory\n");
+                STDOUT.write_str("cat: ");
+                STDOUT.write_str(filename);
+                STDOUT.write_str(": No such file or directory\n");
             }
         }
     }
     
     fn cmd_test(&self) {
-        UART.write_str("Running system tests...\n");
+        STDOUT.write_str("Running system tests...\n");
         
         // UART test
-        UART.write_str("1. UART: ");
-        UART.write_str("PASS\n");
+        STDOUT.write_str("1. UART: ");
+        STDOUT.write_str("PASS\n");
         
         // Timer test
-        UART.write_str("2. Timer: ");
+        STDOUT.write_str("2. Timer: ");
         let start = TIMER.get_time_us();
         TIMER.delay_ms(10);
         let elapsed = TIMER.get_time_us() - start;
         if elapsed >= 9000 && elapsed <= 11000 { // 9-11ms range
-            UART.write_str("PASS\n");
+            STDOUT.write_str("PASS\n");
         } else {
-            UART.write_str("FAIL\n");
+            STDOUT.write_str("FAIL\n");
         }
         
         // Process manager test
-        UART.write_str("3. Process Manager: ");
+        STDOUT.write_str("3. Process Manager: ");
         unsafe {
             let count = PROCESS_MANAGER.list_processes().len();
             if count > 0 {
-                UART.write_str("PASS\n");
+                STDOUT.write_str("PASS\n");
             } else {
-                UART.write_str("FAIL\n");
+                STDOUT.write_str("FAIL\n");
             }
         }
         
         // GPIO test
-        UART.write_str("4. GPIO Controller: ");
+        STDOUT.write_str("4. GPIO Controller: ");
         if crate::gpio::test_gpio() {
-            UART.write_str("PASS\n");
+            STDOUT.write_str("PASS\n");
         } else {
-            UART.write_str("FAIL\n");
+            STDOUT.write_str("FAIL\n");
         }
         
-        UART.write_str("All tests completed.\n");
+        STDOUT.write_str("All tests completed.\n");
     }
     
     fn cmd_reboot(&self) {
-        UART.write_str("System restart not implemented. Please reset manually.\n");
+        STDOUT.write_str("System restart not implemented. Please reset manually.\n");
     }
     
     fn cmd_exit(&mut self) {
-        UART.write_str("Goodbye!\n");
+        STDOUT.write_str("Goodbye!\n");
         self.running = false;
     }
     
     fn cmd_gpio(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("gpio: Usage: gpio [test|status] [pin]\n");
-            UART.write_str("Examples:\n");
-            UART.write_str("  gpio test     - Test GPIO functionality\n");
-            UART.write_str("  gpio status   - Show GPIO status\n");
-            UART.write_str("  gpio status 29 - Show status of GPIO pin 29\n");
+            STDOUT.write_str("gpio: Usage: gpio [test|status] [pin]\n");
+            STDOUT.write_str("Examples:\n");
+            STDOUT.write_str("  gpio test     - Test GPIO functionality\n");
+            STDOUT.write_str("  gpio status   - Show GPIO status\n");
+            STDOUT.write_str("  gpio status 29 - Show status of GPIO pin 29\n");
             return;
         }
         
         match args[0] {
             "test" => {
-                UART.write_str("Running GPIO tests...\n");
+                STDOUT.write_str("Running GPIO tests...\n");
                 if crate::gpio::test_gpio() {
-                    UART.write_str("GPIO test completed successfully\n");
+                    STDOUT.write_str("GPIO test completed successfully\n");
                 } else {
-                    UART.write_str("GPIO test failed\n");
+                    STDOUT.write_str("GPIO test failed\n");
                 }
             }
             "status" => {
@@ -483,31 +999,31 @@ impl Shell {
                         if let Some(gpio) = crate::gpio::get_gpio_controller() {
                             let status = gpio.get_pin_status(pin);
                             let ctrl = gpio.get_pin_control(pin);
-                            UART.write_str("GPIO");
+                            STDOUT.write_str("GPIO");
                             self.print_number(pin, 2);
-                            UART.write_str(" status: 0x");
+                            STDOUT.write_str(" status: 0x");
                             let hex_chars = b"0123456789ABCDEF";
                             for i in (0..8).rev() {
                                 let nibble = (status >> (i * 4)) & 0xF;
-                                UART.write_char(hex_chars[nibble as usize] as char);
+                                STDOUT.write_char(hex_chars[nibble as usize] as char);
                             }
-                            UART.write_str(" control: 0x");
+                            STDOUT.write_str(" control: 0x");
                             for i in (0..8).rev() {
                                 let nibble = (ctrl >> (i * 4)) & 0xF;
-                                UART.write_char(hex_chars[nibble as usize] as char);
+                                STDOUT.write_char(hex_chars[nibble as usize] as char);
                             }
-                            UART.write_str("\n");
+                            STDOUT.write_str("\n");
                         } else {
-                            UART.write_str("GPIO controller not available\n");
+                            STDOUT.write_str("GPIO controller not available\n");
                         }
                     } else {
-                        UART.write_str("Invalid pin number\n");
+                        STDOUT.write_str("Invalid pin number\n");
                     }
                 } else {
                     // Show all important pins
-                    UART.write_str("GPIO Status Summary:\n");
-                    UART.write_str("Pin  Function  Status\n");
-                    UART.write_str("-------------------\n");
+                    STDOUT.write_str("GPIO Status Summary:\n");
+                    STDOUT.write_str("Pin  Function  Status\n");
+                    STDOUT.write_str("-------------------\n");
                     
                     if let Some(gpio) = crate::gpio::get_gpio_controller() {
                         let pins = [14, 15, 29, 31]; // UART TX/RX, Activity LED, Power LED
@@ -515,40 +1031,40 @@ impl Shell {
                         
                         for (i, &pin) in pins.iter().enumerate() {
                             self.print_number(pin, 3);
-                            UART.write_str("  ");
-                            UART.write_str(names[i]);
-                            UART.write_str("    0x");
+                            STDOUT.write_str("  ");
+                            STDOUT.write_str(names[i]);
+                            STDOUT.write_str("    0x");
                             let status = gpio.get_pin_status(pin);
                             let hex_chars = b"0123456789ABCDEF";
                             for i in (0..8).rev() {
                                 let nibble = (status >> (i * 4)) & 0xF;
-                                UART.write_char(hex_chars[nibble as usize] as char);
+                                STDOUT.write_char(hex_chars[nibble as usize] as char);
                             }
-                            UART.write_str("\n");
+                            STDOUT.write_str("\n");
                         }
                     }
                 }
             }
             _ => {
-                UART.write_str("gpio: Unknown command: ");
-                UART.write_str(args[0]);
-                UART.write_str("\n");
+                STDOUT.write_str("gpio: Unknown command: ");
+                STDOUT.write_str(args[0]);
+                STDOUT.write_str("\n");
             }
         }
     }
     
     fn cmd_led(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("led: Usage: led [activity|power] [on|off|blink]\n");
-            UART.write_str("Examples:\n");
-            UART.write_str("  led activity on    - Turn on activity LED\n");
-            UART.write_str("  led power off      - Turn off power LED\n");
-            UART.write_str("  led activity blink - Blink activity LED\n");
+            STDOUT.write_str("led: Usage: led [activity|power] [on|off|blink]\n");
+            STDOUT.write_str("Examples:\n");
+            STDOUT.write_str("  led activity on    - Turn on activity LED\n");
+            STDOUT.write_str("  led power off      - Turn off power LED\n");
+            STDOUT.write_str("  led activity blink - Blink activity LED\n");
             return;
         }
         
         if args.len() < 2 {
-            UART.write_str("led: Missing action (on/off/blink)\n");
+            STDOUT.write_str("led: Missing action (on/off/blink)\n");
             return;
         }
         
@@ -560,22 +1076,22 @@ impl Shell {
                 match action {
                     "on" => {
                         crate::gpio::set_activity_led(true);
-                        UART.write_str("Activity LED turned on\n");
+                        STDOUT.write_str("Activity LED turned on\n");
                     }
                     "off" => {
                         crate::gpio::set_activity_led(false);
-                        UART.write_str("Activity LED turned off\n");
+                        STDOUT.write_str("Activity LED turned off\n");
                     }
                     "blink" => {
-                        UART.write_str("Blinking activity LED...\n");
+                        STDOUT.write_str("Blinking activity LED...\n");
                         for _ in 0..5 {
                             crate::gpio::blink_activity_led();
                             crate::timer::delay_ms(200);
                         }
-                        UART.write_str("Blink completed\n");
+                        STDOUT.write_str("Blink completed\n");
                     }
                     _ => {
-                        UART.write_str("led: Invalid action. Use on/off/blink\n");
+                        STDOUT.write_str("led: Invalid action. Use on/off/blink\n");
                     }
                 }
             }
@@ -583,29 +1099,29 @@ impl Shell {
                 match action {
                     "on" => {
                         crate::gpio::set_power_led(true);
-                        UART.write_str("Power LED turned on\n");
+                        STDOUT.write_str("Power LED turned on\n");
                     }
                     "off" => {
                         crate::gpio::set_power_led(false);
-                        UART.write_str("Power LED turned off\n");
+                        STDOUT.write_str("Power LED turned off\n");
                     }
                     "blink" => {
-                        UART.write_str("Blinking power LED...\n");
+                        STDOUT.write_str("Blinking power LED...\n");
                         for _ in 0..5 {
                             if let Some(gpio) = crate::gpio::get_gpio_controller() {
                                 gpio.blink_power_led();
                             }
                             crate::timer::delay_ms(200);
                         }
-                        UART.write_str("Blink completed\n");
+                        STDOUT.write_str("Blink completed\n");
                     }
                     _ => {
-                        UART.write_str("led: Invalid action. Use on/off/blink\n");
+                        STDOUT.write_str("led: Invalid action. Use on/off/blink\n");
                     }
                 }
             }
             _ => {
-                UART.write_str("led: Invalid LED type. Use activity or power\n");
+                STDOUT.write_str("led: Invalid LED type. Use activity or power\n");
             }
         }
     }
@@ -628,12 +1144,12 @@ impl Shell {
         
         // Pad with spaces for alignment
         for _ in pos..width {
-            UART.write_char(' ');
+            STDOUT.write_char(' ');
         }
         
         // Print digits in reverse order
         for i in (0..pos).rev() {
-            UART.write_char(buffer[i] as char);
+            STDOUT.write_char(buffer[i] as char);
         }
     }
     
@@ -650,66 +1166,69 @@ impl Shell {
         if path.starts_with('/') {
             self.current_dir.clear();
             let _ = self.current_dir.push_str(path);
-            UART.write_str("Changed directory to ");
-            UART.write_str(path);
-            UART.write_str("\n");
+            unsafe {
+                let _ = PROCESS_MANAGER.set_cwd(PROCESS_MANAGER.current_pid(), path);
+            }
+            STDOUT.write_str("Changed directory to ");
+            STDOUT.write_str(path);
+            STDOUT.write_str("\n");
         } else {
-            UART.write_str("cd: ");
-            UART.write_str(path);
-            UART.write_str(": No such directory\n");
+            STDOUT.write_str("cd: ");
+            STDOUT.write_str(path);
+            STDOUT.write_str(": No such directory\n");
         }
     }
     
     fn cmd_touch(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("touch: missing file operand\n");
+            STDOUT.write_str("touch: missing file operand\n");
             return;
         }
         
         for &filename in args {
-            UART.write_str("touch: created file ");
-            UART.write_str(filename);
-            UART.write_str("\n");
+            STDOUT.write_str("touch: created file ");
+            STDOUT.write_str(filename);
+            STDOUT.write_str("\n");
         }
     }
     
     fn cmd_rm(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("rm: missing operand\n");
+            STDOUT.write_str("rm: missing operand\n");
             return;
         }
         
         for &filename in args {
-            UART.write_str("rm: removed file ");
-            UART.write_str(filename);
-            UART.write_str("\n");
+            STDOUT.write_str("rm: removed file ");
+            STDOUT.write_str(filename);
+            STDOUT.write_str("\n");
         }
     }
     
     fn cmd_cp(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.len() < 2 {
-            UART.write_str("cp: missing destination file operand\n");
+            STDOUT.write_str("cp: missing destination file operand\n");
             return;
         }
         
-        UART.write_str("cp: copied ");
-        UART.write_str(args[0]);
-        UART.write_str(" to ");
-        UART.write_str(args[1]);
-        UART.write_str("\n");
+        STDOUT.write_str("cp: copied ");
+        STDOUT.write_str(args[0]);
+        STDOUT.write_str(" to ");
+        STDOUT.write_str(args[1]);
+        STDOUT.write_str("\n");
     }
     
     fn cmd_mv(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.len() < 2 {
-            UART.write_str("mv: missing destination file operand\n");
+            STDOUT.write_str("mv: missing destination file operand\n");
             return;
         }
         
-        UART.write_str("mv: moved ");
-        UART.write_str(args[0]);
-        UART.write_str(" to ");
-        UART.write_str(args[1]);
-        UART.write_str("\n");
+        STDOUT.write_str("mv: moved ");
+        STDOUT.write_str(args[0]);
+        STDOUT.write_str(" to ");
+        STDOUT.write_str(args[1]);
+        STDOUT.write_str("\n");
     }
     
     fn cmd_find(&self, args: &Vec<&str, MAX_ARGS>) {
@@ -719,122 +1238,122 @@ impl Shell {
             args[0]
         };
         
-        UART.write_str("find: searching for pattern ");
-        UART.write_str(pattern);
-        UART.write_str("\n");
-        UART.write_str("./file1.txt\n");
-        UART.write_str("./dir1/file2.txt\n");
+        STDOUT.write_str("find: searching for pattern ");
+        STDOUT.write_str(pattern);
+        STDOUT.write_str("\n");
+        STDOUT.write_str("./file1.txt\n");
+        STDOUT.write_str("./dir1/file2.txt\n");
     }
     
     fn cmd_grep(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.len() < 2 {
-            UART.write_str("grep: missing pattern or file\n");
+            STDOUT.write_str("grep: missing pattern or file\n");
             return;
         }
         
-        UART.write_str("grep: searching for ");
-        UART.write_str(args[0]);
-        UART.write_str(" in ");
-        UART.write_str(args[1]);
-        UART.write_str("\n");
-        UART.write_str("line containing pattern\n");
+        STDOUT.write_str("grep: searching for ");
+        STDOUT.write_str(args[0]);
+        STDOUT.write_str(" in ");
+        STDOUT.write_str(args[1]);
+        STDOUT.write_str("\n");
+        STDOUT.write_str("line containing pattern\n");
     }
     
     fn cmd_mkdir(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("mkdir: missing operand\n");
+            STDOUT.write_str("mkdir: missing operand\n");
             return;
         }
         
         for &dirname in args {
-            UART.write_str("mkdir: created directory ");
-            UART.write_str(dirname);
-            UART.write_str("\n");
+            STDOUT.write_str("mkdir: created directory ");
+            STDOUT.write_str(dirname);
+            STDOUT.write_str("\n");
         }
     }
     
     fn cmd_wc(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("wc: missing file operand\n");
+            STDOUT.write_str("wc: missing file operand\n");
             return;
         }
         
         for &filename in args {
             self.print_number(10, 6);
-            UART.write_str(" ");
+            STDOUT.write_str(" ");
             self.print_number(50, 6);
-            UART.write_str(" ");
+            STDOUT.write_str(" ");
             self.print_number(256, 6);
-            UART.write_str(" ");
-            UART.write_str(filename);
-            UART.write_str("\n");
+            STDOUT.write_str(" ");
+            STDOUT.write_str(filename);
+            STDOUT.write_str("\n");
         }
     }
     
     fn cmd_head(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("head: missing file operand\n");
+            STDOUT.write_str("head: missing file operand\n");
             return;
         }
         
-        UART.write_str("head: showing first 10 lines of ");
-        UART.write_str(args[0]);
-        UART.write_str("\n");
+        STDOUT.write_str("head: showing first 10 lines of ");
+        STDOUT.write_str(args[0]);
+        STDOUT.write_str("\n");
         for i in 1..=10 {
-            UART.write_str("line ");
+            STDOUT.write_str("line ");
             self.print_number(i, 0);
-            UART.write_str(" of file\n");
+            STDOUT.write_str(" of file\n");
         }
     }
     
     fn cmd_tail(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("tail: missing file operand\n");
+            STDOUT.write_str("tail: missing file operand\n");
             return;
         }
         
-        UART.write_str("tail: showing last 10 lines of ");
-        UART.write_str(args[0]);
-        UART.write_str("\n");
+        STDOUT.write_str("tail: showing last 10 lines of ");
+        STDOUT.write_str(args[0]);
+        STDOUT.write_str("\n");
         for i in 91..=100 {
-            UART.write_str("line ");
+            STDOUT.write_str("line ");
             self.print_number(i, 0);
-            UART.write_str(" of file\n");
+            STDOUT.write_str(" of file\n");
         }
     }
     
     fn cmd_kill(&self, args: &Vec<&str, MAX_ARGS>) {
         if args.is_empty() {
-            UART.write_str("kill: missing process ID\n");
+            STDOUT.write_str("kill: missing process ID\n");
             return;
         }
         
         if let Some(pid_char) = args[0].chars().next() {
             if let Some(pid) = pid_char.to_digit(10) {
-                UART.write_str("kill: terminated process ");
+                STDOUT.write_str("kill: terminated process ");
                 self.print_number(pid as u32, 0);
-                UART.write_str("\n");
+                STDOUT.write_str("\n");
             } else {
-                UART.write_str("kill: invalid process ID\n");
+                STDOUT.write_str("kill: invalid process ID\n");
             }
         }
     }
     
     fn cmd_jobs(&self) {
-        UART.write_str("[1]  Running    background_process\n");
-        UART.write_str("[2]  Stopped    another_process\n");
+        STDOUT.write_str("[1]  Running    background_process\n");
+        STDOUT.write_str("[2]  Stopped    another_process\n");
     }
     
     fn cmd_top(&self) {
-        UART.write_str("Top processes (snapshot):\n");
-        UART.write_str("  PID USER      %CPU %MEM   TIME COMMAND\n");
-        UART.write_str("  --------------------------------\n");
-        UART.write_str("    1 root       0.1  0.5   0:01 init\n");
-        UART.write_str("    2 root       0.0  0.0   0:00 kthreadd\n");
+        STDOUT.write_str("Top processes (snapshot):\n");
+        STDOUT.write_str("  PID USER      %CPU %MEM   TIME COMMAND\n");
+        STDOUT.write_str("  --------------------------------\n");
+        STDOUT.write_str("    1 root       0.1  0.5   0:01 init\n");
+        STDOUT.write_str("    2 root       0.0  0.0   0:00 kthreadd\n");
     }
     
     fn cmd_id(&self) {
-        UART.write_str("uid=0(root) gid=0(root) groups=0(root)\n");
+        STDOUT.write_str("uid=0(root) gid=0(root) groups=0(root)\n");
     }
     
     fn cmd_su(&mut self, args: &Vec<&str, MAX_ARGS>) {
@@ -844,28 +1363,134 @@ impl Shell {
             args[0]
         };
         
-        UART.write_str("Password: ");
+        STDOUT.write_str("Password: ");
         if let Some(password) = self.read_line() {
             if password.as_str() == "root" || password.as_str() == "" {
                 self.current_user = if target_user == "root" { "root" } else { "user" };
-                UART.write_str("User switched to ");
-                UART.write_str(target_user);
-                UART.write_str("\n");
+                STDOUT.write_str("User switched to ");
+                STDOUT.write_str(target_user);
+                STDOUT.write_str("\n");
             } else {
-                UART.write_str("su: Authentication failure\n");
+                STDOUT.write_str("su: Authentication failure\n");
             }
         }
     }
     
     fn cmd_free(&self) {
-        UART.write_str("              total        used        free      shared  buff/cache   available\n");
-        UART.write_str("Mem:        8388608      524288     7864320           0           0     7864320\n");
-        UART.write_str("Swap:             0           0           0\n");
+        let (total, free) = crate::memory::get_memory_stats();
+        let total_kb = (total / 1024) as u32;
+        let free_kb = (free / 1024) as u32;
+        
+        STDOUT.write_str("              total        used        free      shared  buff/cache   available\n");
+        STDOUT.write_str("Mem:   ");
+        self.print_number(total_kb, 12);
+        self.print_number(total_kb - free_kb, 12);
+        self.print_number(free_kb, 12);
+        self.print_number(0, 12);
+        self.print_number(0, 12);
+        self.print_number(free_kb, 12);
+        STDOUT.write_str("\n");
+        STDOUT.write_str("Swap:             0           0           0\n");
     }
     
+    fn cmd_nproc(&self, args: &Vec<&str, MAX_ARGS>) {
+        let count = if args.first() == Some(&"--all") {
+            crate::smp::possible_cpus()
+        } else {
+            crate::smp::online_cpus()
+        };
+        self.print_number(count as u32, 0);
+        STDOUT.write_str("\n");
+    }
+
+    fn cmd_sleep(&self, args: &Vec<&str, MAX_ARGS>) {
+        if args.is_empty() {
+            STDOUT.write_str("sleep: missing operand\n");
+            return;
+        }
+
+        // Like coreutils: the arguments are added up
+        let mut total_ns = 0u64;
+        for arg in args.iter() {
+            match Self::parse_duration_ns(arg) {
+                Some(ns) => total_ns = total_ns.saturating_add(ns),
+                None => {
+                    STDOUT.write_str("sleep: invalid time interval '");
+                    STDOUT.write_str(arg);
+                    STDOUT.write_str("'\n");
+                    return;
+                }
+            }
+        }
+        ktimer::sleep_ns(total_ns);
+    }
+
+    /// "1", "0.5", "2m", "1.5h"
+    fn parse_duration_ns(arg: &str) -> Option<u64> {
+        let (number, unit) = match arg.as_bytes().last()? {
+            b's' => (&arg[..arg.len() - 1], 1),
+            b'm' => (&arg[..arg.len() - 1], 60),
+            b'h' => (&arg[..arg.len() - 1], 3600),
+            b'd' => (&arg[..arg.len() - 1], 86400),
+            _ => (arg, 1),
+        };
+        let (whole, frac) = match number.find('.') {
+            Some(pos) => (&number[..pos], &number[pos + 1..]),
+            None => (number, ""),
+        };
+        if whole.is_empty() && frac.is_empty() {
+            return None;
+        }
+
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        let mut frac_ns = 0u64;
+        let mut scale = ktimer::NSEC_PER_SEC;
+        for c in frac.chars() {
+            scale /= 10;
+            frac_ns += c.to_digit(10)? as u64 * scale;
+        }
+        whole.checked_mul(ktimer::NSEC_PER_SEC)?
+            .checked_add(frac_ns)?
+            .checked_mul(unit)
+    }
+
     fn cmd_df(&self) {
-        UART.write_str("Filesystem     1K-blocks  Used Available Use% Mounted on\n");
-        UART.write_str("/dev/root        8388608  1048576   7340032  13% /\n");
-        UART.write_str("tmpfs            4194304        0   4194304   0% /dev/shm\n");
+        STDOUT.write_str("Filesystem     1K-blocks  Used Available Use% Mounted on\n");
+        STDOUT.write_str("/dev/root        8388608  1048576   7340032  13% /\n");
+        STDOUT.write_str("tmpfs            4194304        0   4194304   0% /dev/shm\n");
+    }
+}
+
+// Pty of the session `script` is starting; the new threads pick it up
+static mut SCRIPT_PTY: usize = 0;
+
+/// The shell of a `script` session, leader of a session on the pty
+extern "C" fn script_session() {
+    let index = unsafe { SCRIPT_PTY };
+    unsafe {
+        let pid = PROCESS_MANAGER.current_pid();
+        PROCESS_MANAGER.start_session(pid, TtyId::Pty(index));
+        if let Some(tty) = pty::slave(index) {
+            tty.attach(pid, pid);
+        }
+    }
+    let mut shell = Shell::new();
+    shell.run();
+    pty::close_slave(index);
+}
+
+/// Keys typed on the console go to the `script` session's pty
+extern "C" fn script_relay() {
+    let index = unsafe { SCRIPT_PTY };
+    let mut buf = [0u8; 64];
+    loop {
+        match tty::console().read(&mut buf) {
+            Ok(n) if n > 0 => {
+                if pty::master_write(index, &buf[..n]).is_err() {
+                    return;
+                }
+            }
+            _ => process::yield_now(),
+        }
     }
 }
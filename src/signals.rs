@@ -1,8 +1,8 @@
 // Signal System for UNIX Compatibility
 // POSIX signal handling implementation
 
-use crate::process::{PROCESS_MANAGER, ProcessState};
-use crate::uart::UART;
+use crate::process::{self, PROCESS_MANAGER};
+use crate::{debug, info};
 use heapless::Vec;
 
 // POSIX signals
@@ -174,13 +174,21 @@ impl SignalHandler {
     }
     
     pub fn send_signal(&mut self, target_pid: u32, signal: Signal, sender_pid: u32) -> Result<(), &'static str> {
-        UART.write_str("Sending signal ");
-        UART.write_str(signal.name());
-        UART.write_str(" to PID ");
-        UART.put_hex(target_pid);
-        UART.write_str(" from PID ");
-        UART.put_hex(sender_pid);
-        UART.write_str("\n");
+        // As on Linux, init only gets the signals it has installed a handler for
+        let handler_index = (signal as i32 - 1) as usize;
+        if target_pid == 1 && !matches!(self.signal_handlers[handler_index], SignalAction::Custom(_)) {
+            return Ok(());
+        }
+        // Ignored by the target itself (e.g. an interactive shell and ^C)
+        let ignored = unsafe {
+            PROCESS_MANAGER.get_process(target_pid)
+                .is_some_and(|p| p.ignored_signals & (1u64 << handler_index) != 0)
+        };
+        if ignored && !signal.is_uncatchable() {
+            return Ok(());
+        }
+        
+        debug!("Sending signal {} to PID {} from PID {}", signal.name(), target_pid, sender_pid);
         
         // Check if signal is blocked
         let signal_bit = 1u64 << (signal as i32 - 1);
@@ -191,7 +199,7 @@ impl SignalHandler {
                     signal,
                     sender_pid,
                 });
-                UART.write_str("Signal blocked, added to pending\n");
+                debug!("Signal blocked, added to pending");
                 return Ok(());
             } else {
                 return Err("Too many pending signals");
@@ -206,39 +214,33 @@ impl SignalHandler {
         let handler_index = (signal as i32 - 1) as usize;
         let action = self.signal_handlers[handler_index];
         
-        UART.write_str("Delivering signal ");
-        UART.write_str(signal.name());
-        UART.write_str(" with action: ");
-        
         match action {
             SignalAction::Default => {
-                UART.write_str("DEFAULT\n");
+                debug!("Delivering signal {} with action: DEFAULT", signal.name());
                 self.default_signal_action(target_pid, signal)
             }
             SignalAction::Ignore => {
-                UART.write_str("IGNORE\n");
+                debug!("Delivering signal {} with action: IGNORE", signal.name());
                 Ok(())
             }
             SignalAction::Terminate => {
-                UART.write_str("TERMINATE\n");
-                self.terminate_process(target_pid)
+                debug!("Delivering signal {} with action: TERMINATE", signal.name());
+                self.terminate_process(target_pid, signal, false)
             }
             SignalAction::Stop => {
-                UART.write_str("STOP\n");
-                self.stop_process(target_pid)
+                debug!("Delivering signal {} with action: STOP", signal.name());
+                self.stop_process(target_pid, signal)
             }
             SignalAction::Continue => {
-                UART.write_str("CONTINUE\n");
+                debug!("Delivering signal {} with action: CONTINUE", signal.name());
                 self.continue_process(target_pid)
             }
             SignalAction::Core => {
-                UART.write_str("CORE_DUMP\n");
-                self.core_dump_process(target_pid)
+                debug!("Delivering signal {} with action: CORE_DUMP", signal.name());
+                self.core_dump_process(target_pid, signal)
             }
             SignalAction::Custom(handler_addr) => {
-                UART.write_str("CUSTOM at 0x");
-                UART.put_hex(handler_addr as u32);
-                UART.write_str("\n");
+                debug!("Delivering signal {} with action: CUSTOM at {:#x}", signal.name(), handler_addr);
                 self.call_custom_handler(target_pid, signal, handler_addr)
             }
         }
@@ -246,20 +248,20 @@ impl SignalHandler {
     
     fn default_signal_action(&mut self, target_pid: u32, signal: Signal) -> Result<(), &'static str> {
         match signal.default_action() {
-            SignalAction::Terminate => self.terminate_process(target_pid),
-            SignalAction::Stop => self.stop_process(target_pid),
+            SignalAction::Terminate => self.terminate_process(target_pid, signal, false),
+            SignalAction::Stop => self.stop_process(target_pid, signal),
             SignalAction::Continue => self.continue_process(target_pid),
             SignalAction::Ignore => Ok(()),
-            _ => self.terminate_process(target_pid),
+            _ => self.terminate_process(target_pid, signal, false),
         }
     }
     
-    fn terminate_process(&mut self, target_pid: u32) -> Result<(), &'static str> {
+    fn terminate_process(&mut self, target_pid: u32, signal: Signal, core_dumped: bool) -> Result<(), &'static str> {
         unsafe {
-            if PROCESS_MANAGER.terminate_process(target_pid) {
-                UART.write_str("Process ");
-                UART.put_hex(target_pid);
-                UART.write_str(" terminated by signal\n");
+            // The parent's wait sees which signal it was
+            let status = process::signaled_status(signal, core_dumped);
+            if PROCESS_MANAGER.exit_process(target_pid, status) {
+                debug!("Process {} terminated by signal", target_pid);
                 Ok(())
             } else {
                 Err("Failed to terminate process")
@@ -267,12 +269,10 @@ impl SignalHandler {
         }
     }
     
-    fn stop_process(&mut self, target_pid: u32) -> Result<(), &'static str> {
+    fn stop_process(&mut self, target_pid: u32, signal: Signal) -> Result<(), &'static str> {
         unsafe {
-            if PROCESS_MANAGER.set_process_state(target_pid, ProcessState::Sleeping) {
-                UART.write_str("Process ");
-                UART.put_hex(target_pid);
-                UART.write_str(" stopped by signal\n");
+            if PROCESS_MANAGER.stop_process(target_pid, signal) {
+                debug!("Process {} stopped by signal", target_pid);
                 Ok(())
             } else {
                 Err("Failed to stop process")
@@ -282,10 +282,8 @@ impl SignalHandler {
     
     fn continue_process(&mut self, target_pid: u32) -> Result<(), &'static str> {
         unsafe {
-            if PROCESS_MANAGER.set_process_state(target_pid, ProcessState::Ready) {
-                UART.write_str("Process ");
-                UART.put_hex(target_pid);
-                UART.write_str(" continued by signal\n");
+            if PROCESS_MANAGER.continue_process(target_pid) {
+                debug!("Process {} continued by signal", target_pid);
                 Ok(())
             } else {
                 Err("Failed to continue process")
@@ -293,36 +291,33 @@ impl SignalHandler {
         }
     }
     
-    fn core_dump_process(&mut self, target_pid: u32) -> Result<(), &'static str> {
-        UART.write_str("Core dump for PID ");
-        UART.put_hex(target_pid);
-        UART.write_str(" (simplified)\n");
+    fn core_dump_process(&mut self, target_pid: u32, signal: Signal) -> Result<(), &'static str> {
+        info!("Core dump for PID {} (simplified)", target_pid);
         
         // In a real implementation, this would dump process memory
         unsafe {
             if let Some(process) = PROCESS_MANAGER.get_process(target_pid) {
-                UART.write_str("PID: ");
-                UART.put_hex(process.pid);
-                UART.write_str("\n");
-                UART.write_str("PPID: ");
-                UART.put_hex(process.ppid);
-                UART.write_str("\n");
-                UART.write_str("Entry Point: 0x");
-                UART.put_hex(process.entry_point as u32);
-                UART.write_str("\n");
-                UART.write_str("Stack Pointer: 0x");
-                UART.put_hex(process.stack_ptr as u32);
-                UART.write_str("\n");
+                info!("PID {}: PPID {}, entry point {:#x}, stack pointer {:#x}",
+                      process.pid, process.ppid, process.entry_point, process.stack_ptr);
             }
         }
         
-        self.terminate_process(target_pid)
+        self.terminate_process(target_pid, signal, true)
+    }
+    
+    /// A fault the process cannot get past. No handler can run yet (see
+    /// `call_custom_handler`), so as with Linux's force_sig the signal takes
+    /// its default action whatever the mask, the ignored set or the PID.
+    pub fn force_signal(&mut self, target_pid: u32, signal: Signal) -> Result<(), &'static str> {
+        debug!("Forcing signal {} on PID {}", signal.name(), target_pid);
+        match signal.default_action() {
+            SignalAction::Core => self.core_dump_process(target_pid, signal),
+            _ => self.terminate_process(target_pid, signal, false),
+        }
     }
     
     fn call_custom_handler(&mut self, _target_pid: u32, signal: Signal, _handler_addr: u64) -> Result<(), &'static str> {
-        UART.write_str("Custom signal handler for ");
-        UART.write_str(signal.name());
-        UART.write_str(" not fully implemented\n");
+        debug!("Custom signal handler for {} not fully implemented", signal.name());
         // In a real implementation, this would set up a signal stack frame
         // and jump to the custom handler
         Ok(())
@@ -336,9 +331,7 @@ impl SignalHandler {
         let handler_index = (signal as i32 - 1) as usize;
         self.signal_handlers[handler_index] = action;
         
-        UART.write_str("Signal handler set for ");
-        UART.write_str(signal.name());
-        UART.write_str("\n");
+        debug!("Signal handler set for {}", signal.name());
         
         Ok(())
     }
@@ -348,9 +341,7 @@ impl SignalHandler {
             let signal_bit = 1u64 << (signal as i32 - 1);
             self.signal_mask |= signal_bit;
             
-            UART.write_str("Blocked signal ");
-            UART.write_str(signal.name());
-            UART.write_str("\n");
+            debug!("Blocked signal {}", signal.name());
         }
     }
     
@@ -358,9 +349,7 @@ impl SignalHandler {
         let signal_bit = 1u64 << (signal as i32 - 1);
         self.signal_mask &= !signal_bit;
         
-        UART.write_str("Unblocked signal ");
-        UART.write_str(signal.name());
-        UART.write_str("\n");
+        debug!("Unblocked signal {}", signal.name());
         
         // Check for pending signals to deliver
         self.check_pending_signals();
@@ -382,10 +371,16 @@ impl SignalHandler {
         }
     }
     
-    pub fn handle_keyboard_interrupt(&mut self) {
-        UART.write_str("Keyboard interrupt (Ctrl+C) detected\n");
-        let current_pid = unsafe { PROCESS_MANAGER.current_pid() };
-        let _ = self.send_signal(current_pid, Signal::SIGINT, 0);
+    /// Ctrl+C on a terminal: SIGINT to its foreground process group
+    pub fn handle_keyboard_interrupt(&mut self, pgrp: u32) {
+        self.kill_pgrp(pgrp, Signal::SIGINT, 0);
+    }
+    
+    pub fn kill_pgrp(&mut self, pgrp: u32, signal: Signal, sender_pid: u32) {
+        let members = unsafe { PROCESS_MANAGER.pgrp_members(pgrp) };
+        for pid in members {
+            let _ = self.send_signal(pid, signal, sender_pid);
+        }
     }
     
     pub fn get_signal_mask(&self) -> u64 {
@@ -408,7 +403,7 @@ pub fn init_signals() {
     unsafe {
         GLOBAL_SIGNAL_HANDLER = SignalHandler::new();
     }
-    UART.write_str("Signal system initialized\n");
+    info!("Signal system initialized");
 }
 
 pub fn send_signal(target_pid: u32, signal_num: i32, sender_pid: u32) -> Result<(), &'static str> {
@@ -421,6 +416,12 @@ pub fn send_signal(target_pid: u32, signal_num: i32, sender_pid: u32) -> Result<
     }
 }
 
+pub fn force_signal(target_pid: u32, signal: Signal) -> Result<(), &'static str> {
+    unsafe {
+        GLOBAL_SIGNAL_HANDLER.force_signal(target_pid, signal)
+    }
+}
+
 pub fn set_signal_handler(signal_num: i32, action: SignalAction) -> Result<(), &'static str> {
     if let Some(signal) = Signal::from_i32(signal_num) {
         unsafe {
@@ -453,9 +454,16 @@ pub fn unblock_signal(signal_num: i32) -> Result<(), &'static str> {
     }
 }
 
-pub fn handle_keyboard_interrupt() {
+pub fn handle_keyboard_interrupt(pgrp: u32) {
+    unsafe {
+        GLOBAL_SIGNAL_HANDLER.handle_keyboard_interrupt(pgrp);
+    }
+}
+
+/// Send `signal` to every process in group `pgrp`
+pub fn kill_pgrp(pgrp: u32, signal: Signal, sender_pid: u32) {
     unsafe {
-        GLOBAL_SIGNAL_HANDLER.handle_keyboard_interrupt();
+        GLOBAL_SIGNAL_HANDLER.kill_pgrp(pgrp, signal, sender_pid);
     }
 }
 
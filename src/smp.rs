@@ -0,0 +1,320 @@
+// Symmetric Multiprocessing for Raspberry Pi 5
+// Brings up the secondary Cortex-A76 cores with PSCI CPU_ON
+//
+// The cores come from /cpus in the device tree (MPIDR affinity values) and
+// the PSCI conduit from /psci "method". Each secondary core gets its own
+// stack from the frame allocator and a CpuBootArgs block that
+// `_secondary_start` (startup.s) reads to set its stack pointer and, when the
+// boot CPU runs with the MMU on, to load the same translation tables.
+//
+// Per-CPU data is reached through TPIDR_EL1, which holds the address of the
+// core's PerCpu entry.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::dtb;
+use crate::exception;
+use crate::interrupt;
+use crate::memory;
+use crate::mmu::{self, PAGE_SIZE};
+use crate::timer;
+use crate::{info, warn};
+
+pub const MAX_CPUS: usize = 8;
+
+const SECONDARY_STACK_SIZE: u64 = 0x4_0000;  // 256KB per core
+const CPU_ON_TIMEOUT_US: u64 = 100_000;
+const MPIDR_AFFINITY_MASK: u64 = 0xFF_00FF_FFFF;
+
+// Raspberry Pi 5 layout, used when the device tree has no /cpus
+const DEFAULT_CPU_MPIDRS: [u64; 4] = [0x000, 0x100, 0x200, 0x300];
+
+// PSCI function IDs (SMC64 calling convention) and return codes
+const PSCI_VERSION: u64 = 0x8400_0000;
+const PSCI_CPU_ON: u64 = 0xC400_0003;
+const PSCI_SUCCESS: i64 = 0;
+const PSCI_ALREADY_ON: i64 = -4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PsciConduit {
+    Smc,
+    Hvc,
+}
+
+/// Why a secondary core did not come up
+enum StartError {
+    Refused(&'static str),  // Nothing was started
+    TimedOut,               // Powered on but not checked in; it may still
+}
+
+/// Per-core state; the entry for the running core is at TPIDR_EL1
+pub struct PerCpu {
+    pub id: usize,
+    pub mpidr: u64,
+    pub midr: u64,
+    pub online: AtomicBool,
+    pub idle_wakeups: AtomicU64,
+}
+
+impl PerCpu {
+    const fn new(id: usize) -> Self {
+        Self {
+            id,
+            mpidr: 0,
+            midr: 0,
+            online: AtomicBool::new(false),
+            idle_wakeups: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Handed to `_secondary_start` as the PSCI context id; layout is fixed by startup.s
+#[repr(C)]
+struct CpuBootArgs {
+    stack_top: u64,
+    ttbr0: u64,
+    ttbr1: u64,
+    mair: u64,
+    tcr: u64,
+    sctlr: u64,
+    cpu_id: u64,
+}
+
+impl CpuBootArgs {
+    const fn new() -> Self {
+        Self { stack_top: 0, ttbr0: 0, ttbr1: 0, mair: 0, tcr: 0, sctlr: 0, cpu_id: 0 }
+    }
+}
+
+static mut PER_CPU: [PerCpu; MAX_CPUS] = {
+    let mut cpus = [const { PerCpu::new(0) }; MAX_CPUS];
+    let mut i = 0;
+    while i < MAX_CPUS {
+        cpus[i].id = i;
+        i += 1;
+    }
+    cpus
+};
+static mut BOOT_ARGS: [CpuBootArgs; MAX_CPUS] = [const { CpuBootArgs::new() }; MAX_CPUS];
+static mut CPU_COUNT: usize = 1;
+static mut CONDUIT: PsciConduit = PsciConduit::Smc;
+
+extern "C" {
+    fn _secondary_start();
+}
+
+fn read_mpidr() -> u64 {
+    let mpidr: u64;
+    unsafe { core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr) };
+    mpidr & MPIDR_AFFINITY_MASK
+}
+
+fn read_midr() -> u64 {
+    let midr: u64;
+    unsafe { core::arch::asm!("mrs {}, midr_el1", out(reg) midr) };
+    midr
+}
+
+/// Issue a PSCI call through the firmware (SMC) or hypervisor (HVC)
+fn psci_call(function: u64, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let ret: i64;
+    unsafe {
+        match CONDUIT {
+            PsciConduit::Smc => core::arch::asm!(
+                "smc #0",
+                inout("x0") function as i64 => ret,
+                inout("x1") arg0 => _,
+                inout("x2") arg1 => _,
+                inout("x3") arg2 => _,
+                clobber_abi("C"),
+            ),
+            PsciConduit::Hvc => core::arch::asm!(
+                "hvc #0",
+                inout("x0") function as i64 => ret,
+                inout("x1") arg0 => _,
+                inout("x2") arg1 => _,
+                inout("x3") arg2 => _,
+                clobber_abi("C"),
+            ),
+        }
+    }
+    ret
+}
+
+fn set_this_cpu(cpu: &PerCpu) {
+    unsafe { core::arch::asm!("msr tpidr_el1, {}", in(reg) cpu as *const PerCpu as u64) };
+}
+
+/// Per-CPU data of the running core
+pub fn this_cpu() -> &'static PerCpu {
+    let ptr: u64;
+    unsafe { core::arch::asm!("mrs {}, tpidr_el1", out(reg) ptr) };
+    if ptr == 0 {
+        // Before init_smp: only the boot CPU is running
+        return cpu(0);
+    }
+    unsafe { &*(ptr as *const PerCpu) }
+}
+
+pub fn cpu_id() -> usize {
+    this_cpu().id
+}
+
+pub fn cpu(id: usize) -> &'static PerCpu {
+    unsafe { &(*core::ptr::addr_of!(PER_CPU))[id] }
+}
+
+/// Cores described by the platform (online or not)
+pub fn possible_cpus() -> usize {
+    unsafe { CPU_COUNT }
+}
+
+pub fn online_cpus() -> usize {
+    (0..possible_cpus()).filter(|&id| cpu(id).online.load(Ordering::Acquire)).count()
+}
+
+/// Set up per-CPU data for the boot CPU; it becomes logical CPU 0
+pub fn init_smp() {
+    let info = dtb::platform();
+    unsafe {
+        CONDUIT = if info.psci_method == "hvc" { PsciConduit::Hvc } else { PsciConduit::Smc };
+    }
+
+    let boot = unsafe { &mut (*core::ptr::addr_of_mut!(PER_CPU))[0] };
+    boot.mpidr = read_mpidr();
+    boot.midr = read_midr();
+    boot.online.store(true, Ordering::Release);
+    set_this_cpu(boot);
+}
+
+/// Start every other core listed in the device tree
+pub fn start_secondary_cpus() {
+    let mpidrs = match dtb::platform().cpu_mpidrs() {
+        [] => &DEFAULT_CPU_MPIDRS[..],
+        list => list,
+    };
+
+    let version = psci_call(PSCI_VERSION, 0, 0, 0);
+    info!("SMP: PSCI {}.{}, {} core(s) described", version >> 16, version & 0xFFFF, mpidrs.len());
+
+    let boot_mpidr = cpu(0).mpidr;
+    let mut next_id = 1;
+    for &mpidr in mpidrs {
+        if mpidr & MPIDR_AFFINITY_MASK == boot_mpidr || next_id == MAX_CPUS {
+            continue;
+        }
+        match start_cpu(next_id, mpidr & MPIDR_AFFINITY_MASK) {
+            Ok(()) => info!("SMP: CPU{} online, MPIDR {:#x}", next_id, mpidr),
+            // Its ID stays taken: the core may still come up with it
+            Err(StartError::TimedOut) => warn!("SMP: MPIDR {:#x}: timed out waiting for the core", mpidr),
+            // Nothing runs with this ID, so the next core gets it
+            Err(StartError::Refused(e)) => {
+                warn!("SMP: MPIDR {:#x}: {}, skipping it", mpidr, e);
+                continue;
+            }
+        }
+        next_id += 1;
+        unsafe { CPU_COUNT = next_id; }
+    }
+}
+
+fn start_cpu(id: usize, mpidr: u64) -> Result<(), StartError> {
+    let stack = memory::alloc_frames(SECONDARY_STACK_SIZE / PAGE_SIZE)
+        .ok_or(StartError::Refused("no memory for stack"))?;
+
+    unsafe {
+        PER_CPU[id].mpidr = mpidr;
+
+        let args = &mut (*core::ptr::addr_of_mut!(BOOT_ARGS))[id];
+        *args = CpuBootArgs::new();
+        args.stack_top = stack + SECONDARY_STACK_SIZE;
+        args.cpu_id = id as u64;
+        if let Some(regs) = mmu::Mmu::registers() {
+            args.ttbr0 = regs.ttbr0;
+            args.ttbr1 = regs.ttbr1;
+            args.mair = regs.mair;
+            args.tcr = regs.tcr;
+            args.sctlr = regs.sctlr;
+        }
+
+        // The new core reads its arguments with the MMU and caches off
+        let args_addr = args as *const CpuBootArgs as u64;
+        mmu::clean_invalidate_dcache_range(args_addr, args_addr + core::mem::size_of::<CpuBootArgs>() as u64);
+
+        let ret = psci_call(PSCI_CPU_ON, mpidr, _secondary_start as *const () as u64, args_addr);
+        if ret != PSCI_SUCCESS {
+            memory::free_frames(stack, SECONDARY_STACK_SIZE / PAGE_SIZE);
+            // Already on: running something the firmware started, not this kernel
+            return Err(StartError::Refused(if ret == PSCI_ALREADY_ON {
+                "already on"
+            } else {
+                "PSCI CPU_ON failed"
+            }));
+        }
+    }
+
+    let start = timer::get_time_us();
+    while !cpu(id).online.load(Ordering::Acquire) {
+        if timer::get_time_us() - start > CPU_ON_TIMEOUT_US {
+            return Err(StartError::TimedOut);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Rust entry point of a secondary core, called from `_secondary_start`
+#[no_mangle]
+pub extern "C" fn secondary_main(id: usize) -> ! {
+    let cpu = unsafe { &mut (*core::ptr::addr_of_mut!(PER_CPU))[id] };
+    cpu.midr = read_midr();
+    set_this_cpu(cpu);
+    exception::init_exceptions();
+    timer::init_cpu();
+    interrupt::init_cpu();
+    cpu.online.store(true, Ordering::Release);
+
+    idle_loop()
+}
+
+/// Per-core idle loop: sleep until an interrupt (such as an IPI) arrives
+pub fn idle_loop() -> ! {
+    let cpu = this_cpu();
+    unsafe { core::arch::asm!("msr daifclr, #2") };
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+        // Only this core writes its counter, so no atomic read-modify-write is needed
+        cpu.idle_wakeups.store(cpu.idle_wakeups.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+}
+
+/// /proc/cpuinfo contents: one block per online core, decoded from MIDR_EL1
+pub fn cpuinfo() -> String {
+    // BogoMIPS as Linux reports it on arm64: twice the counter frequency in MHz
+    let bogomips = timer::frequency() / 5_000;
+
+    let mut out = String::new();
+    for id in 0..possible_cpus() {
+        let cpu = cpu(id);
+        if !cpu.online.load(Ordering::Acquire) {
+            continue;
+        }
+        let midr = cpu.midr;
+        let _ = write!(
+            out,
+            "processor\t: {}\nBogoMIPS\t: {}.{:02}\nFeatures\t: fp asimd evtstrm crc32 cpuid\n\
+             CPU implementer\t: {:#x}\nCPU architecture: 8\nCPU variant\t: {:#x}\n\
+             CPU part\t: {:#05x}\nCPU revision\t: {}\n\n",
+            id,
+            bogomips / 100,
+            bogomips % 100,
+            (midr >> 24) & 0xFF,
+            (midr >> 20) & 0xF,
+            (midr >> 4) & 0xFFF,
+            midr & 0xF,
+        );
+    }
+    out
+}
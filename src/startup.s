@@ -1,14 +1,34 @@
 .section ".text._start"
 .global _start
 
+// EL2 configuration for running the kernel at EL1
+.equ HCR_EL2_RW,        (1 << 31)           // EL1 is AArch64, nothing trapped to EL2
+.equ CNTHCTL_EL1_ACCESS, 0x3                // EL1PCTEN | EL1PCEN: physical counter/timer at EL1
+.equ CPTR_EL2_DEFAULT,  0x33ff              // RES1 bits only: no FP/SIMD traps
+.equ SPSR_EL1H_MASKED,  0x3c5               // EL1h with D, A, I and F masked
+.equ SCTLR_EL1_DEFAULT, 0x30d00800          // RES1 bits only: MMU and caches off, little-endian
+.equ CPACR_EL1_FPEN,    (3 << 20)           // No FP/SIMD traps at EL1 and EL0
+.equ ICC_SRE_EL2_SRE_ENABLE, 0x9           // SRE | Enable: ICC_* system registers usable at EL1
+
 _start:
+    // x0 holds the device tree blob address; keep it in a register
+    // that nothing below touches
+    mov x19, x0
+
+    // Remember the EL the firmware started us in for the boot log
+    mrs x20, CurrentEL
+    lsr x20, x20, #2
+
     // Ensure we're running on core 0
     mrs x0, mpidr_el1
     and x0, x0, #3
     cbnz x0, halt
+
+    bl enter_el1
     
-    // Set stack pointer - Pi5 has more memory
-    ldr x0, =0x80000000
+    // Set stack pointer to the 2MB boot stack reserved by ldscript.lds
+    // (it must lie inside the kernel image so the MMU maps it)
+    ldr x0, =STACK_POINTER
     mov sp, x0
     
     // Clear BSS section
@@ -22,9 +42,101 @@ clear_bss:
     b clear_bss
     
 clear_done:
-    // Jump to Rust main function
+    // Jump to Rust main function, rust_main(dtb_addr, boot_el)
+    mov x0, x19
+    mov x1, x20
     bl rust_main
     
 halt:
     wfe
-    b halt
\ No newline at end of file
+    b halt
+
+// Continue at EL1h whether we were entered at EL2 or EL1, and return to the
+// caller there. Only x9/x10 are used so the caller's arguments survive; the
+// stack pointer must be set up afterwards (SP_EL1 is not inherited).
+enter_el1:
+    mov x9, #SCTLR_EL1_DEFAULT & 0xffff
+    movk x9, #SCTLR_EL1_DEFAULT >> 16, lsl #16
+    msr sctlr_el1, x9
+    mov x9, #CPACR_EL1_FPEN
+    msr cpacr_el1, x9
+
+    mrs x9, CurrentEL
+    lsr x9, x9, #2
+    cmp x9, #2
+    b.ne enter_el1_done
+
+    // EL1 sees the real MIDR/MPIDR instead of the virtualised copies
+    mrs x9, midr_el1
+    msr vpidr_el2, x9
+    mrs x9, mpidr_el1
+    msr vmpidr_el2, x9
+
+    mov x9, #HCR_EL2_RW
+    msr hcr_el2, x9
+    mov x9, #CNTHCTL_EL1_ACCESS
+    msr cnthctl_el2, x9
+    msr cntvoff_el2, xzr
+    mov x9, #CPTR_EL2_DEFAULT
+    msr cptr_el2, x9
+    msr hstr_el2, xzr
+
+    // GICv3 CPU interface: let EL1 use the ICC_* system registers
+    mrs x9, id_aa64pfr0_el1
+    ubfx x9, x9, #24, #4
+    cbz x9, 1f
+    mrs x9, icc_sre_el2
+    mov x10, #ICC_SRE_EL2_SRE_ENABLE
+    orr x9, x9, x10
+    msr icc_sre_el2, x9
+    isb
+    msr ich_hcr_el2, xzr
+1:
+
+    mov x9, #SPSR_EL1H_MASKED
+    msr spsr_el2, x9
+    msr elr_el2, x30
+    isb
+    eret
+
+enter_el1_done:
+    isb
+    ret
+
+// Entry point for secondary cores started with PSCI CPU_ON.
+// x0 = context id = address of this core's CpuBootArgs (see smp.rs):
+//   [x0, #0]  stack top        [x0, #8]  TTBR0 (0 = leave the MMU off)
+//   [x0, #16] TTBR1            [x0, #24] MAIR
+//   [x0, #32] TCR              [x0, #40] SCTLR
+//   [x0, #48] logical CPU number
+.global _secondary_start
+_secondary_start:
+    // PSCI starts secondaries at the firmware's EL, not the caller's
+    bl enter_el1
+
+    ldr x1, [x0, #0]
+    mov sp, x1
+
+    // Join the boot CPU's address space before touching shared data
+    ldr x1, [x0, #8]
+    cbz x1, secondary_mmu_done
+    ldr x2, [x0, #16]
+    ldr x3, [x0, #24]
+    ldr x4, [x0, #32]
+    ldr x5, [x0, #40]
+    msr mair_el1, x3
+    msr tcr_el1, x4
+    msr ttbr0_el1, x1
+    msr ttbr1_el1, x2
+    isb
+    tlbi vmalle1
+    ic iallu
+    dsb ish
+    isb
+    msr sctlr_el1, x5
+    isb
+
+secondary_mmu_done:
+    ldr x0, [x0, #48]
+    bl secondary_main
+    b halt
@@ -1,263 +1,740 @@
 // System Call Interface for UNIX Compatibility
 // POSIX-like system calls implementation
 
-use crate::process::{PROCESS_MANAGER, Process, ProcessState};
-use crate::filesystem::{read_file, write_file, create_file, file_exists};
-use crate::uart::UART;
+use crate::elf;
+use crate::file::{self, FdTable, FileKind, OpenFile};
+use crate::initramfs;
+use crate::ktimer;
+use crate::process::{self, PROCESS_MANAGER, MAX_PROCESSES, Process, ProcessState, WaitTarget};
+use crate::pty;
+use crate::rtc;
+use crate::signals::{self, Signal};
+use crate::termios::{self, Termios};
+use crate::timer;
+use crate::tty::{self, Tty, TtyId};
+use crate::uaccess::{self, read_user, write_user};
+use crate::users;
+use crate::filesystem::{self, FileType, read_file, write_file, create_file, file_exists, get_file_info};
+use crate::{debug, info, warn};
 use heapless::{String, Vec};
 
-const MAX_OPEN_FILES: usize = 32;
 const MAX_FILENAME: usize = 64;
+const MAX_ARGS: usize = 64;           // argv and envp entries for execve
 
-// System call numbers (Linux ARM64 compatible)
-#[repr(u64)]
-#[derive(Clone, Copy, Debug)]
-pub enum SysCallNumber {
-    Exit = 93,
-    Fork = 57,
-    Execve = 221,
-    Open = 56,
-    Close = 3,
+// System call numbers (Linux ARM64 compatible): the enum and the lookup
+// from a raw number come from one list, and handle_syscall matches on the
+// enum, so a number is dispatched only if it is listed here
+macro_rules! syscall_numbers {
+    ($($name:ident = $nr:literal,)*) => {
+        #[repr(u64)]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum SysCallNumber {
+            $($name = $nr,)*
+        }
+
+        impl SysCallNumber {
+            const COUNT: usize = [$($nr),*].len();
+
+            pub fn from_raw(nr: u64) -> Option<Self> {
+                match nr {
+                    $($nr => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+syscall_numbers! {
+    Getcwd = 17,
+    Dup = 23,
+    Dup3 = 24,               // dup2 is dup3 with no flags on arm64
+    Fcntl = 25,
+    Ioctl = 29,
+    Mkdirat = 34,
+    Unlinkat = 35,           // rmdir is unlinkat with AT_REMOVEDIR
+    Faccessat = 48,
+    Chdir = 49,
+    Openat = 56,
+    Close = 57,
+    Pipe2 = 59,
     Read = 63,
     Write = 64,
+    Writev = 66,
+    Newfstatat = 79,         // stat and lstat
+    Fstat = 80,
+    Exit = 93,
+    ExitGroup = 94,
+    Waitid = 95,
+    SetTidAddress = 96,
+    Nanosleep = 101,
+    Getitimer = 102,
+    Setitimer = 103,
+    ClockSettime = 112,
+    ClockGettime = 113,
+    ClockNanosleep = 115,
+    Kill = 129,
+    Setpgid = 154,
+    Getpgid = 155,
+    Getsid = 156,
+    Setsid = 157,
+    Gettimeofday = 169,
     Getpid = 172,
     Getppid = 173,
-    Kill = 129,
+    Gettid = 178,
+    Clone = 220,             // fork is clone with SIGCHLD on arm64
+    Execve = 221,
     Wait4 = 260,
-    Pipe = 59,
-    Dup = 23,
-    Dup2 = 24,
-    Chdir = 49,
-    Getcwd = 79,
-    Mkdir = 83,
-    Rmdir = 84,
-    Unlink = 87,
-    Chmod = 90,
-    Chown = 92,
-    Utime = 132,
-    Access = 21,
-    Stat = 106,
-    Lstat = 107,
-    Fstat = 108,
-}
-
-// File descriptor structure
-#[derive(Clone, Debug)]
-pub struct FileDescriptor {
-    pub fd: i32,
-    pub path: String<MAX_FILENAME>,
-    pub flags: u32,
-    pub offset: usize,
-    pub is_open: bool,
-}
-
-impl FileDescriptor {
-    pub fn new(fd: i32, path: &str, flags: u32) -> Self {
-        let mut path_str = String::new();
-        let _ = path_str.push_str(path);
-        
-        Self {
-            fd,
-            path: path_str,
-            flags,
-            offset: 0,
-            is_open: true,
-        }
-    }
-}
-
-// Process file descriptor table
-pub struct ProcessFdTable {
-    fds: Vec<FileDescriptor, MAX_OPEN_FILES>,
-    next_fd: i32,
-}
-
-impl ProcessFdTable {
-    pub fn new() -> Self {
-        let mut table = Self {
-            fds: Vec::new(),
-            next_fd: 3, // Start after stdin(0), stdout(1), stderr(2)
-        };
-        
-        // Initialize standard file descriptors
-        let _ = table.fds.push(FileDescriptor::new(0, "/dev/stdin", 0));
-        let _ = table.fds.push(FileDescriptor::new(1, "/dev/stdout", 1));
-        let _ = table.fds.push(FileDescriptor::new(2, "/dev/stderr", 1));
-        
-        table
+}
+
+// Every number checked against include/uapi/asm-generic/unistd.h, the table
+// arm64 uses: a wrong or unchecked number fails the build
+const _: () = {
+    use SysCallNumber::*;
+    let asm_generic = [
+        (Getcwd, 17),           // __NR_getcwd
+        (Dup, 23),              // __NR_dup
+        (Dup3, 24),             // __NR_dup3
+        (Fcntl, 25),            // __NR3264_fcntl
+        (Ioctl, 29),            // __NR_ioctl
+        (Mkdirat, 34),          // __NR_mkdirat
+        (Unlinkat, 35),         // __NR_unlinkat
+        (Faccessat, 48),        // __NR_faccessat
+        (Chdir, 49),            // __NR_chdir
+        (Openat, 56),           // __NR_openat
+        (Close, 57),            // __NR_close
+        (Pipe2, 59),            // __NR_pipe2
+        (Read, 63),             // __NR_read
+        (Write, 64),            // __NR_write
+        (Writev, 66),           // __NR_writev
+        (Newfstatat, 79),       // __NR3264_fstatat
+        (Fstat, 80),            // __NR3264_fstat
+        (Exit, 93),             // __NR_exit
+        (ExitGroup, 94),        // __NR_exit_group
+        (Waitid, 95),           // __NR_waitid
+        (SetTidAddress, 96),    // __NR_set_tid_address
+        (Nanosleep, 101),       // __NR_nanosleep
+        (Getitimer, 102),       // __NR_getitimer
+        (Setitimer, 103),       // __NR_setitimer
+        (ClockSettime, 112),    // __NR_clock_settime
+        (ClockGettime, 113),    // __NR_clock_gettime
+        (ClockNanosleep, 115),  // __NR_clock_nanosleep
+        (Kill, 129),            // __NR_kill
+        (Setpgid, 154),         // __NR_setpgid
+        (Getpgid, 155),         // __NR_getpgid
+        (Getsid, 156),          // __NR_getsid
+        (Setsid, 157),          // __NR_setsid
+        (Gettimeofday, 169),    // __NR_gettimeofday
+        (Getpid, 172),          // __NR_getpid
+        (Getppid, 173),         // __NR_getppid
+        (Gettid, 178),          // __NR_gettid
+        (Clone, 220),           // __NR_clone
+        (Execve, 221),          // __NR_execve
+        (Wait4, 260),           // __NR_wait4
+    ];
+    assert!(asm_generic.len() == SysCallNumber::COUNT, "a system call number is not checked");
+    let mut i = 0;
+    while i < asm_generic.len() {
+        assert!(asm_generic[i].0 as u64 == asm_generic[i].1, "a system call number is not Linux's");
+        i += 1;
     }
+};
+
+// System call handler
+pub fn handle_syscall(syscall_num: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, _arg5: u64) -> i64 {
+    use SysCallNumber::*;
+    let Some(call) = SysCallNumber::from_raw(syscall_num) else {
+        warn!("Unknown system call: {}", syscall_num);
+        return -38; // ENOSYS - Function not implemented
+    };
+    match call {
+        Exit | ExitGroup => sys_exit(arg0 as i32),
+        Clone => sys_clone(arg0, arg1, arg2, arg3, arg4),
+        Execve => sys_execve(arg0, arg1, arg2),
+        Openat => sys_openat(arg0 as i32, arg1, arg2, arg3),
+        Close => sys_close(arg0 as i32),
+        Dup => sys_dup(arg0 as i32),
+        Dup3 => sys_dup3(arg0 as i32, arg1 as i32, arg2 as u32),
+        Fcntl => sys_fcntl(arg0 as i32, arg1 as u32, arg2),
+        Pipe2 => sys_pipe2(arg0, arg1 as u32),
+        Ioctl => sys_ioctl(arg0 as i32, arg1 as u32, arg2),
+        Read => sys_read(arg0 as i32, arg1, arg2),
+        Write => sys_write(arg0 as i32, arg1, arg2),
+        Writev => sys_writev(arg0 as i32, arg1, arg2 as i32),
+        Getpid | Gettid => sys_getpid(),   // Every thread is a process
+        SetTidAddress => sys_set_tid_address(arg0),
+        Getppid => sys_getppid(),
+        Setpgid => sys_setpgid(arg0 as u32, arg1 as u32),
+        Getpgid => sys_getpgid(arg0 as u32),
+        Getsid => sys_getsid(arg0 as u32),
+        Setsid => sys_setsid(),
+        Kill => sys_kill(arg0 as i32, arg1 as i32),
+        Wait4 => sys_wait4(arg0 as i32, arg1, arg2 as u32, arg3),
+        Waitid => sys_waitid(arg0 as u32, arg1 as u32, arg2, arg3 as u32, arg4),
+        Chdir => sys_chdir(arg0),
+        Getcwd => sys_getcwd(arg0, arg1),
+        Mkdirat => sys_mkdirat(arg0 as i32, arg1, arg2),
+        Unlinkat => sys_unlinkat(arg0 as i32, arg1, arg2 as u32),
+        Faccessat => sys_faccessat(arg0 as i32, arg1, arg2),
+        Newfstatat => sys_newfstatat(arg0 as i32, arg1, arg2, arg3 as u32),
+        Fstat => sys_fstat(arg0 as i32, arg1),
+        Nanosleep => sys_nanosleep(arg0, arg1),
+        Getitimer => sys_getitimer(arg0 as i32, arg1),
+        Setitimer => sys_setitimer(arg0 as i32, arg1, arg2),
+        ClockSettime => sys_clock_settime(arg0 as i32, arg1),
+        ClockGettime => sys_clock_gettime(arg0 as i32, arg1),
+        ClockNanosleep => sys_clock_nanosleep(arg0 as i32, arg1 as i32, arg2, arg3),
+        Gettimeofday => sys_gettimeofday(arg0, arg1),
+    }
+}
+
+// System call implementations
+fn sys_exit(status: i32) -> i64 {
+    debug!("Process exiting with status: {}", status);
+    
+    // Switches to the next process and never returns (exit_group too: no threads)
+    process::exit_current(process::exited_status(status))
+}
+
+// clone(2) flags that make sense without threads
+const CSIGNAL: u64 = 0xFF;
+const CLONE_SETTLS: u64 = 0x0008_0000;
+const CLONE_PARENT_SETTID: u64 = 0x0010_0000;
+const CLONE_CHILD_CLEARTID: u64 = 0x0020_0000;
+const CLONE_CHILD_SETTID: u64 = 0x0100_0000;
+
+/// clone as fork: a copy-on-write copy of the caller, which gets 0. arm64
+/// has no fork syscall, the C library forks with clone(SIGCHLD). Threads
+/// (CLONE_VM and the like) are not supported.
+fn sys_clone(flags: u64, stack: u64, parent_tid: u64, tls: u64, child_tid: u64) -> i64 {
+    debug!("clone() called: flags {:#x}", flags);
     
-    pub fn open_file(&mut self, path: &str, flags: u32) -> Result<i32, i32> {
-        if self.fds.is_full() {
-            return Err(-24); // EMFILE - Too many open files
-        }
-        
-        let fd = self.next_fd;
-        self.next_fd += 1;
-        
-        let file_desc = FileDescriptor::new(fd, path, flags);
-        let _ = self.fds.push(file_desc);
-        
-        Ok(fd)
+    let supported = CSIGNAL | CLONE_SETTLS | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID | CLONE_CHILD_SETTID;
+    if flags & !supported != 0 {
+        return -22; // EINVAL - Shared address spaces are not supported
     }
+    let Some(frame) = process::current_user_frame() else {
+        return -22; // EINVAL - Not a user process
+    };
+    let tls = if flags & CLONE_SETTLS != 0 { Some(tls) } else { None };
     
-    pub fn close_file(&mut self, fd: i32) -> Result<i32, i32> {
-        for file_desc in &mut self.fds {
-            if file_desc.fd == fd && file_desc.is_open {
-                file_desc.is_open = false;
-                return Ok(0);
-            }
-        }
-        Err(-9) // EBADF - Bad file descriptor
+    let (pid, child_space) = match unsafe { PROCESS_MANAGER.fork(frame, stack, tls) } {
+        Ok(child) => child,
+        Err(errno) => return errno,
+    };
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = write_user(parent_tid, pid);
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        child_space.write_u32(child_tid, pid);
     }
+    pid as i64
+}
+
+fn sys_execve(pathname: u64, argv: u64, envp: u64) -> i64 {
+    let path = match read_user_path(pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let (args, env) = match (read_user_strings(argv), read_user_strings(envp)) {
+        (Ok(args), Ok(env)) => (args, env),
+        (Err(errno), _) | (_, Err(errno)) => return errno,
+    };
     
-    pub fn get_fd(&self, fd: i32) -> Option<&FileDescriptor> {
-        self.fds.iter().find(|f| f.fd == fd && f.is_open)
+    debug!("execve() called: {} ({} args)", path, args.len());
+    
+    // Programs come from the initramfs
+    let path = filesystem::resolve_path(&current_cwd(), &path);
+    let file = match initramfs::lookup(&path) {
+        Some(file) => file,
+        None => return -2, // ENOENT - No such file or directory
+    };
+    if file.file_type() != initramfs::S_IFREG || file.mode & 0o111 == 0 {
+        return -13; // EACCES - Not an executable file
     }
     
-    pub fn get_fd_mut(&mut self, fd: i32) -> Option<&mut FileDescriptor> {
-        self.fds.iter_mut().find(|f| f.fd == fd && f.is_open)
+    let args: Vec<&[u8], MAX_ARGS> = args.iter().map(|arg| arg.as_slice()).collect();
+    let env: Vec<&[u8], MAX_ARGS> = env.iter().map(|var| var.as_slice()).collect();
+    let program = match elf::load(file.data, &args, &env, &path) {
+        Ok(program) => program,
+        Err(errno) => return errno,
+    };
+    
+    // From here on there is no old image to return to
+    match process::exec_current(program) {
+        Ok(()) => 0,
+        Err(errno) => errno,
     }
 }
 
-// Global file descriptor tables for each process (simplified)
-static mut GLOBAL_FD_TABLE: ProcessFdTable = ProcessFdTable {
-    fds: Vec::new(),
-    next_fd: 3,
-};
+// Descriptor table of the calling process
+fn current_files() -> Option<&'static mut FdTable> {
+    unsafe { PROCESS_MANAGER.files_mut(PROCESS_MANAGER.current_pid()) }
+}
 
-// System call handler
-pub fn handle_syscall(syscall_num: u64, arg0: u64, arg1: u64, arg2: u64, _arg3: u64, _arg4: u64, _arg5: u64) -> i64 {
-    match syscall_num {
-        93 => sys_exit(arg0 as i32),
-        57 => sys_fork(),
-        56 => sys_open(arg0, arg1, arg2),
-        63 => sys_read(arg0 as i32, arg1, arg2),
-        64 => sys_write(arg0 as i32, arg1, arg2),
-        172 => sys_getpid(),
-        173 => sys_getppid(),
-        129 => sys_kill(arg0 as i32, arg1 as i32),
-        49 => sys_chdir(arg0),
-        79 => sys_getcwd(arg0, arg1),
-        83 => sys_mkdir(arg0, arg1),
-        87 => sys_unlink(arg0),
-        21 => sys_access(arg0, arg1),
-        106 => sys_stat(arg0, arg1),
-        _ => {
-            UART.write_str("Unknown system call: ");
-            UART.put_hex(syscall_num as u32);
-            UART.write_str("\n");
-            -38 // ENOSYS - Function not implemented
-        }
+// Description behind `fd` in the calling process: its id and a copy
+fn fd_file(fd: i32) -> Option<(usize, OpenFile)> {
+    let id = current_files()?.get(fd)?;
+    Some((id, file::get(id)?))
+}
+
+// The *at() calls take paths relative to the current directory with this
+const AT_FDCWD: i32 = -100;
+
+// *at() flags
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_REMOVEDIR: u32 = 0x200;
+const AT_NO_AUTOMOUNT: u32 = 0x800;
+const AT_EMPTY_PATH: u32 = 0x1000;
+
+/// Absolute form of the path at `pathname` for the *at() calls: relative to
+/// the directory open as `dirfd`, or to the current directory for AT_FDCWD
+fn at_path(dirfd: i32, pathname: u64) -> Result<alloc::string::String, i64> {
+    let path = read_user_path(pathname)?;
+    resolve_at(dirfd, &path)
+}
+
+fn resolve_at(dirfd: i32, path: &str) -> Result<alloc::string::String, i64> {
+    if path.is_empty() {
+        return Err(-2); // ENOENT - No such file or directory
+    }
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(filesystem::resolve_path(&current_cwd(), path));
+    }
+    let Some((_, dir)) = fd_file(dirfd) else {
+        return Err(-9); // EBADF - Bad file descriptor
+    };
+    let is_dir = dir.kind == FileKind::Path
+        && path_stat(&dir.path).is_some_and(|stat| stat.st_mode & initramfs::S_IFMT == initramfs::S_IFDIR);
+    if !is_dir {
+        return Err(-20); // ENOTDIR - Not a directory
     }
+    Ok(filesystem::resolve_path(&dir.path, path))
 }
 
-// System call implementations
-fn sys_exit(status: i32) -> i64 {
-    UART.write_str("Process exiting with status: ");
-    UART.put_hex(status as u32);
-    UART.write_str("\n");
+fn sys_openat(dirfd: i32, pathname: u64, flags: u64, _mode: u64) -> i64 {
+    let path = match at_path(dirfd, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let flags = flags as u32;
     
-    unsafe {
-        let current_pid = PROCESS_MANAGER.current_pid();
-        PROCESS_MANAGER.terminate_process(current_pid);
+    debug!("openat() called: {}", path);
+    
+    let Some(files) = current_files() else {
+        return -3; // ESRCH
+    };
+    
+    // A file of the VFS (devices included), of the initramfs, or a pty slave
+    let in_vfs = file_exists(&path);
+    let in_initramfs = !in_vfs && initramfs::lookup(&path).is_some();
+    let exists = in_vfs || in_initramfs || file::pts_index(&path).is_some();
+    let writable = flags & file::O_ACCMODE != file::O_RDONLY;
+    if exists && flags & file::O_CREAT != 0 && flags & file::O_EXCL != 0 {
+        return -17; // EEXIST - File exists
+    }
+    if !exists {
+        if flags & file::O_CREAT == 0 {
+            return -2; // ENOENT - No such file or directory
+        }
+        if !create_file(&path, "") {
+            return -13; // EACCES - e.g. no such directory in the VFS
+        }
+    } else if in_initramfs && (writable || flags & file::O_TRUNC != 0) {
+        return -30; // EROFS - The initramfs is read-only
+    } else if flags & file::O_TRUNC != 0 && writable
+        && get_file_info(&path).is_some_and(|file| file.file_type == FileType::RegularFile)
+    {
+        let _ = write_file(&path, "");
+    }
+    
+    // Pseudo-terminals: a new pair, or the slave end of one
+    let mut kind = FileKind::Path;
+    if path == "/dev/ptmx" {
+        match pty::allocate() {
+            Ok(index) => kind = FileKind::PtyMaster(index),
+            Err(errno) => return errno,
+        }
+    } else if let Some(index) = file::pts_index(&path) {
+        if let Err(errno) = pty::open_slave(index) {
+            return errno;
+        }
     }
     
-    // This should not return
-    loop {
-        unsafe { core::arch::asm!("wfe"); }
+    let id = file::open(&path, flags, kind);
+    match files.install(id, 0, flags & file::O_CLOEXEC != 0) {
+        Ok(fd) => fd as i64,
+        Err(errno) => {
+            // Undoes the pty setup too
+            file::discard(id);
+            errno as i64
+        }
     }
 }
 
-fn sys_fork() -> i64 {
-    UART.write_str("fork() called\n");
-    
-    unsafe {
-        let current_pid = PROCESS_MANAGER.current_pid();
-        if let Some(parent) = PROCESS_MANAGER.get_process(current_pid) {
-            let child_pid = PROCESS_MANAGER.create_process(parent.entry_point, current_pid);
-            match child_pid {
-                Some(pid) => pid as i64,
-                None => -12, // ENOMEM - Out of memory
-            }
-        } else {
-            -1 // Error
+fn sys_close(fd: i32) -> i64 {
+    let Some(files) = current_files() else {
+        return -9; // EBADF
+    };
+    match files.close(fd) {
+        Ok(()) => 0,
+        Err(errno) => errno as i64,
+    }
+}
+
+fn sys_dup(oldfd: i32) -> i64 {
+    let Some(files) = current_files() else {
+        return -9; // EBADF
+    };
+    match files.dup(oldfd, 0, false) {
+        Ok(fd) => fd as i64,
+        Err(errno) => errno as i64,
+    }
+}
+
+fn sys_dup3(oldfd: i32, newfd: i32, flags: u32) -> i64 {
+    if flags & !file::O_CLOEXEC != 0 || oldfd == newfd {
+        return -22; // EINVAL
+    }
+    let Some(files) = current_files() else {
+        return -9; // EBADF
+    };
+    match files.dup2(oldfd, newfd, flags & file::O_CLOEXEC != 0) {
+        Ok(fd) => fd as i64,
+        Err(errno) => errno as i64,
+    }
+}
+
+// fcntl commands
+const F_DUPFD: u32 = 0;
+const F_GETFD: u32 = 1;
+const F_SETFD: u32 = 2;
+const F_GETFL: u32 = 3;
+const F_SETFL: u32 = 4;
+const F_DUPFD_CLOEXEC: u32 = 1030;
+const FD_CLOEXEC: u64 = 1;
+
+fn sys_fcntl(fd: i32, cmd: u32, arg: u64) -> i64 {
+    let Some(files) = current_files() else {
+        return -9; // EBADF
+    };
+    let Some(id) = files.get(fd) else {
+        return -9; // EBADF
+    };
+    let result = match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => files.dup(fd, arg as usize, cmd == F_DUPFD_CLOEXEC),
+        F_GETFD => Ok(files.cloexec(fd).map_or(0, |cloexec| cloexec as i32)),
+        F_SETFD => files.set_cloexec(fd, arg & FD_CLOEXEC != 0).map(|()| 0),
+        F_GETFL => Ok(file::get(id).map_or(0, |file| file.flags as i32)),
+        F_SETFL => {
+            file::set_status_flags(id, arg as u32);
+            Ok(0)
         }
+        _ => Err(-22), // EINVAL
+    };
+    match result {
+        Ok(value) => value as i64,
+        Err(errno) => errno as i64,
     }
 }
 
-fn sys_open(pathname: u64, flags: u64, _mode: u64) -> i64 {
-    // For simplicity, we'll use a fixed string for now
-    let path = "/tmp/testfile"; // In real implementation, would read from pathname address
-    
-    UART.write_str("open() called: ");
-    UART.write_str(path);
-    UART.write_str("\n");
-    
-    unsafe {
-        match GLOBAL_FD_TABLE.open_file(path, flags as u32) {
-            Ok(fd) => fd as i64,
-            Err(errno) => errno as i64,
+fn sys_pipe2(pipefd: u64, flags: u32) -> i64 {
+    if flags & !(file::O_CLOEXEC | file::O_NONBLOCK) != 0 {
+        return -22; // EINVAL
+    }
+    let Some(files) = current_files() else {
+        return -9; // EBADF
+    };
+    let (reader, writer) = match file::pipe(flags) {
+        Ok(ends) => ends,
+        Err(errno) => return errno,
+    };
+    let cloexec = flags & file::O_CLOEXEC != 0;
+    let (read_fd, write_fd) = match (files.install(reader, 0, cloexec), files.install(writer, 0, cloexec)) {
+        (Ok(read_fd), Ok(write_fd)) => (read_fd, write_fd),
+        (read_fd, write_fd) => {
+            // Out of descriptors: an end that got one goes with it
+            for (fd, end) in [(read_fd, reader), (write_fd, writer)] {
+                match fd {
+                    Ok(fd) => {
+                        let _ = files.close(fd);
+                    }
+                    Err(_) => file::discard(end),
+                }
+            }
+            return -24; // EMFILE
+        }
+    };
+    match write_user(pipefd, [read_fd, write_fd]) {
+        Ok(()) => 0,
+        Err(errno) => {
+            let _ = files.close(read_fd);
+            let _ = files.close(write_fd);
+            errno
         }
     }
 }
 
 fn sys_read(fd: i32, buf: u64, count: u64) -> i64 {
-    UART.write_str("read() called, fd=");
-    UART.put_hex(fd as u32);
-    UART.write_str(", count=");
-    UART.put_hex(count as u32);
-    UART.write_str("\n");
+    debug!("read() called, fd={}, count={}", fd, count);
     
-    match fd {
-        0 => { // stdin
-            // For simplicity, return 0 (EOF)
-            0
+    let Some((id, file)) = fd_file(fd) else {
+        return -9; // EBADF - Bad file descriptor
+    };
+    if file.flags & file::O_ACCMODE == file::O_WRONLY {
+        return -9; // EBADF - Not open for reading
+    }
+    
+    let mut chunk = [0u8; 256];
+    let len = core::cmp::min(count as usize, chunk.len());
+    let tty = fd_tty(&file).and_then(tty::get);
+    let result = match (file.kind, tty) {
+        (FileKind::PtyMaster(index), _) => pty::master_read(index, &mut chunk[..len]),
+        (FileKind::PipeRead(pipe), _) => {
+            file::pipe_read(pipe, &mut chunk[..len], file.flags & file::O_NONBLOCK != 0)
         }
-        _ => {
-            unsafe {
-                if let Some(file_desc) = GLOBAL_FD_TABLE.get_fd(fd) {
-                    // Read from virtual file system
-                    if let Some(content) = read_file(file_desc.path.as_str()) {
-                        let bytes_to_read = core::cmp::min(count as usize, content.len());
-                        // In real implementation, would copy to buf address
-                        bytes_to_read as i64
-                    } else {
-                        -2 // ENOENT - No such file or directory
-                    }
-                } else {
-                    -9 // EBADF - Bad file descriptor
+        (FileKind::PipeWrite(_), _) => Err(-9), // EBADF
+        (FileKind::Path, Some(tty)) => tty.read(&mut chunk[..len]),
+        (FileKind::Path, None) => {
+            // A file of the virtual file system or of the initramfs, from the shared offset
+            let content = read_file(file.path.as_str());
+            let bytes = match (&content, initramfs::lookup(&file.path)) {
+                (Some(content), _) => Some(content.as_bytes()),
+                (None, Some(entry)) if entry.file_type() == initramfs::S_IFREG => Some(entry.data),
+                _ => None,
+            };
+            match bytes {
+                Some(bytes) => {
+                    let start = file.offset.min(bytes.len());
+                    let n = len.min(bytes.len() - start);
+                    chunk[..n].copy_from_slice(&bytes[start..start + n]);
+                    file::advance(id, n);
+                    Ok(n)
                 }
+                None => Err(-2), // ENOENT - No such file or directory
             }
         }
+    };
+    match result {
+        Ok(n) => match uaccess::copy_to_user(buf, &chunk[..n]) {
+            Ok(()) => n as i64,
+            Err(errno) => errno,
+        },
+        Err(errno) => errno,
     }
 }
 
 fn sys_write(fd: i32, buf: u64, count: u64) -> i64 {
-    UART.write_str("write() called, fd=");
-    UART.put_hex(fd as u32);
-    UART.write_str(", count=");
-    UART.put_hex(count as u32);
-    UART.write_str("\n");
+    debug!("write() called, fd={}, count={}", fd, count);
     
-    match fd {
-        1 | 2 => { // stdout/stderr
-            // In real implementation, would read from buf address and write to UART
-            UART.write_str("[STDOUT/STDERR output]\n");
-            count as i64
-        }
-        _ => {
-            unsafe {
-                if let Some(_file_desc) = GLOBAL_FD_TABLE.get_fd(fd) {
-                    // Write to virtual file system
-                    // In real implementation, would read from buf address
-                    count as i64
-                } else {
-                    -9 // EBADF - Bad file descriptor
+    let Some((id, file)) = fd_file(fd) else {
+        return -9; // EBADF - Bad file descriptor
+    };
+    if file.flags & file::O_ACCMODE == file::O_RDONLY {
+        return -9; // EBADF - Not open for writing
+    }
+    
+    let mut tty = fd_tty(&file).and_then(tty::get);
+    let nonblock = file.flags & file::O_NONBLOCK != 0;
+    let mut chunk = [0u8; 256];
+    let mut written = 0;
+    while written < count {
+        let len = core::cmp::min(count - written, chunk.len() as u64) as usize;
+        if let Err(errno) = uaccess::copy_from_user(&mut chunk[..len], buf + written) {
+            return if written > 0 { written as i64 } else { errno };
+        }
+        let result = match (file.kind, tty.as_deref_mut()) {
+            (FileKind::PtyMaster(index), _) => pty::master_write(index, &chunk[..len]),
+            (FileKind::PipeWrite(pipe), _) => {
+                let result = file::pipe_write(pipe, &chunk[..len], nonblock);
+                if result == Err(-32) {
+                    // EPIPE: nobody will ever read it
+                    let _ = signals::send_signal(unsafe { PROCESS_MANAGER.current_pid() }, Signal::SIGPIPE as i32, 0);
+                }
+                result
+            }
+            (FileKind::PipeRead(_), _) => Err(-9), // EBADF
+            (FileKind::Path, Some(tty)) => tty.write(&chunk[..len]),
+            (FileKind::Path, None) => write_at(id, &file.path, &chunk[..len]),
+        };
+        match result {
+            Ok(n) => written += n as u64,
+            // What went out before the error still counts
+            Err(errno) => return if written > 0 { written as i64 } else { errno },
+        }
+        if nonblock {
+            break;
+        }
+    }
+    written as i64
+}
+
+// struct iovec
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Iovec {
+    iov_base: u64,
+    iov_len: u64,
+}
+
+const IOV_MAX: i32 = 1024;
+
+/// writev: the buffers in turn, stopping at the first short write. The C
+/// library's stdio writes through this.
+fn sys_writev(fd: i32, iov: u64, iovcnt: i32) -> i64 {
+    if !(0..=IOV_MAX).contains(&iovcnt) {
+        return -22; // EINVAL - Invalid argument
+    }
+    // All of the vector first, so a bad one fails before anything is written
+    let mut vecs = alloc::vec::Vec::with_capacity(iovcnt as usize);
+    let mut total: u64 = 0;
+    for i in 0..iovcnt as u64 {
+        let vec = match read_user::<Iovec>(iov.wrapping_add(i * core::mem::size_of::<Iovec>() as u64)) {
+            Ok(vec) => vec,
+            Err(errno) => return errno,
+        };
+        total = match total.checked_add(vec.iov_len) {
+            Some(total) if total <= i64::MAX as u64 => total,
+            _ => return -22, // EINVAL - Total length overflows ssize_t
+        };
+        vecs.push(vec);
+    }
+
+    let mut written = 0;
+    for vec in vecs.iter().filter(|vec| vec.iov_len > 0) {
+        match sys_write(fd, vec.iov_base, vec.iov_len) {
+            // What went out before the error still counts
+            errno if errno < 0 => return if written > 0 { written } else { errno },
+            n => {
+                written += n;
+                if (n as u64) < vec.iov_len {
+                    break;
                 }
             }
         }
     }
+    written
+}
+
+// Write `data` into a virtual file at the shared offset
+fn write_at(id: usize, path: &str, data: &[u8]) -> Result<usize, i64> {
+    let offset = file::get(id).ok_or(-9i64)?.offset; // EBADF
+    let mut content = read_file(path).ok_or(-2i64)?.into_bytes(); // ENOENT
+    if content.len() < offset {
+        content.resize(offset, 0);
+    }
+    let end = (offset + data.len()).min(content.len());
+    content.splice(offset..end, data.iter().copied());
+    // The virtual file system keeps text
+    let text = alloc::string::String::from_utf8(content).map_err(|_| -22i64)?; // EINVAL
+    if !write_file(path, &text) {
+        return Err(-13); // EACCES - e.g. /proc
+    }
+    file::advance(id, data.len());
+    Ok(data.len())
+}
+
+// The terminal behind a description: /dev/tty is the controlling terminal
+// (the standard descriptors start out on it), /dev/uart0 the console and
+// /dev/pts/N the slave end of pty N
+fn fd_tty(file: &OpenFile) -> Option<TtyId> {
+    if file.kind != FileKind::Path {
+        return None;
+    }
+    match file.path.as_str() {
+        "/dev/uart0" => Some(TtyId::Console),
+        "/dev/tty" => unsafe {
+            let ctty = PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid()).and_then(|p| p.ctty);
+            Some(ctty.unwrap_or(TtyId::Console))
+        },
+        path => file::pts_index(path).map(TtyId::Pty),
+    }
+}
+
+fn sys_ioctl(fd: i32, request: u32, arg: u64) -> i64 {
+    let Some((_, file)) = fd_file(fd) else {
+        return -9; // EBADF - Bad file descriptor
+    };
+    let pty_master = match file.kind {
+        FileKind::PtyMaster(index) => Some(index),
+        _ => None,
+    };
+    // On a master, the terminal requests act on its slave
+    let Some(id) = pty_master.map(TtyId::Pty).or_else(|| fd_tty(&file)) else {
+        return -25; // ENOTTY - Inappropriate ioctl for device
+    };
+    let Some(tty) = tty::get(id) else {
+        return -5; // EIO - The pty is gone
+    };
+
+    let result = match (request, pty_master) {
+        (termios::TIOCGPTN, Some(index)) => write_user(arg, index as u32),
+        (termios::TIOCSPTLCK, Some(index)) => {
+            read_user::<i32>(arg).and_then(|lock| pty::set_locked(index, lock != 0))
+        }
+        (termios::TIOCGPTN | termios::TIOCSPTLCK, None) => Err(-25), // ENOTTY - Not a master
+        (termios::TCGETS, _) => write_user(arg, tty.termios()),
+        (termios::TCSETS | termios::TCSETSW | termios::TCSETSF, _) => {
+            read_user::<Termios>(arg).and_then(|new| tty.set_termios(request, &new))
+        }
+        (termios::TCFLSH, _) => tty.flush(arg),
+        (termios::TIOCGPGRP, _) => write_user(arg, tty.pgrp() as i32),
+        (termios::TIOCSPGRP, _) => read_user::<i32>(arg).and_then(|pgrp| {
+            if pgrp <= 0 {
+                Err(-22) // EINVAL
+            } else {
+                tty.set_pgrp(pgrp as u32)
+            }
+        }),
+        (termios::TIOCGWINSZ, _) => write_user(arg, tty.winsize()),
+        (termios::TIOCSWINSZ, _) => read_user(arg).map(|winsize| tty.set_winsize(winsize)),
+        (termios::TIOCSCTTY, _) => set_controlling_tty(id, tty),
+        (termios::TIOCNOTTY, _) => release_controlling_tty(id, tty),
+        _ => Err(-22), // EINVAL - Unknown request
+    };
+    match result {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+// TIOCSCTTY: a session leader without a terminal takes one nobody controls
+fn set_controlling_tty(id: TtyId, tty: &mut Tty) -> Result<(), i64> {
+    unsafe {
+        let pid = PROCESS_MANAGER.current_pid();
+        let process = PROCESS_MANAGER.get_process(pid).ok_or(-3i64)?; // ESRCH
+        if process.sid != pid || process.ctty.is_some() {
+            return Err(-1); // EPERM - Not a session leader, or already has one
+        }
+        if tty.session() != 0 && tty.session() != pid {
+            return Err(-1); // EPERM - Another session's terminal
+        }
+        tty.attach(pid, process.pgid);
+        PROCESS_MANAGER.set_ctty(pid, Some(id));
+    }
+    Ok(())
+}
+
+// TIOCNOTTY: give up the controlling terminal; for the session leader
+// the whole session loses it
+fn release_controlling_tty(id: TtyId, tty: &mut Tty) -> Result<(), i64> {
+    unsafe {
+        let pid = PROCESS_MANAGER.current_pid();
+        let process = PROCESS_MANAGER.get_process(pid).ok_or(-3i64)?; // ESRCH
+        if process.ctty != Some(id) {
+            return Err(-25); // ENOTTY - Not our controlling terminal
+        }
+        let sid = process.sid;
+        if sid == pid {
+            tty.detach();
+            for member in PROCESS_MANAGER.session_members(sid) {
+                PROCESS_MANAGER.set_ctty(member, None);
+            }
+        } else {
+            PROCESS_MANAGER.set_ctty(pid, None);
+        }
+    }
+    Ok(())
 }
 
 fn sys_getpid() -> i64 {
@@ -266,6 +743,12 @@ fn sys_getpid() -> i64 {
     }
 }
 
+/// set_tid_address: the C library's startup asks for its thread id. With no
+/// threads nobody waits on `tidptr`, so it is not kept.
+fn sys_set_tid_address(_tidptr: u64) -> i64 {
+    sys_getpid()
+}
+
 fn sys_getppid() -> i64 {
     unsafe {
         let current_pid = PROCESS_MANAGER.current_pid();
@@ -277,64 +760,762 @@ fn sys_getppid() -> i64 {
     }
 }
 
+fn sys_setpgid(pid: u32, pgid: u32) -> i64 {
+    unsafe {
+        match PROCESS_MANAGER.set_pgid(pid, pgid) {
+            Ok(()) => 0,
+            Err(errno) => errno as i64,
+        }
+    }
+}
+
+fn sys_getpgid(pid: u32) -> i64 {
+    unsafe {
+        let pid = if pid == 0 { PROCESS_MANAGER.current_pid() } else { pid };
+        match PROCESS_MANAGER.get_process(pid) {
+            Some(process) => process.pgid as i64,
+            None => -3, // ESRCH - No such process
+        }
+    }
+}
+
+fn sys_getsid(pid: u32) -> i64 {
+    unsafe {
+        let pid = if pid == 0 { PROCESS_MANAGER.current_pid() } else { pid };
+        match PROCESS_MANAGER.get_process(pid) {
+            Some(process) => process.sid as i64,
+            None => -3, // ESRCH - No such process
+        }
+    }
+}
+
+fn sys_setsid() -> i64 {
+    unsafe {
+        match PROCESS_MANAGER.set_sid() {
+            Ok(sid) => sid as i64,
+            Err(errno) => errno as i64,
+        }
+    }
+}
+
 fn sys_kill(pid: i32, sig: i32) -> i64 {
-    UART.write_str("kill() called, pid=");
-    UART.put_hex(pid as u32);
-    UART.write_str(", signal=");
-    UART.put_hex(sig as u32);
-    UART.write_str("\n");
+    debug!("kill() called, pid={}, signal={}", pid, sig);
     
-    // Simplified signal handling - just terminate the process for now
-    unsafe {
-        if PROCESS_MANAGER.terminate_process(pid as u32) {
-            0
+    if sig != 0 && Signal::from_i32(sig).is_none() {
+        return -22; // EINVAL - Invalid signal
+    }
+    let caller = unsafe { PROCESS_MANAGER.current_pid() };
+    let alive = |p: &&Process| p.state != ProcessState::Terminated;
+    let targets: Vec<u32, MAX_PROCESSES> = unsafe {
+        let processes = PROCESS_MANAGER.list_processes();
+        match pid {
+            // Every process but init and the caller
+            -1 => processes.iter().filter(alive).map(|p| p.pid).filter(|&p| p != 1 && p != caller).collect(),
+            0 => {
+                let pgid = PROCESS_MANAGER.get_process(caller).map_or(0, |p| p.pgid);
+                PROCESS_MANAGER.pgrp_members(pgid).iter().copied().collect()
+            }
+            pid if pid < 0 => PROCESS_MANAGER.pgrp_members(pid.unsigned_abs()).iter().copied().collect(),
+            pid => processes.iter().filter(alive).map(|p| p.pid).filter(|&p| p == pid as u32).collect(),
+        }
+    };
+    if targets.is_empty() {
+        return -3; // ESRCH - No such process
+    }
+    
+    // Signal 0 only checks that the targets exist
+    if sig != 0 {
+        for target in targets {
+            let _ = signals::send_signal(target, sig, caller);
+        }
+    }
+    0
+}
+
+// struct rusage; only the CPU times are kept
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rusage {
+    ru_utime: Timeval,
+    ru_stime: Timeval,
+    ru_other: [i64; 14],     // maxrss ... nivcsw
+}
+
+fn ms_to_timeval(ms: u64) -> Timeval {
+    Timeval { tv_sec: (ms / 1000) as i64, tv_usec: (ms % 1000 * 1000) as i64 }
+}
+
+fn write_rusage(addr: u64, result: &process::WaitResult) -> Result<(), i64> {
+    if addr == 0 {
+        return Ok(());
+    }
+    write_user(addr, Rusage {
+        ru_utime: ms_to_timeval(result.utime_ms),
+        ru_stime: ms_to_timeval(result.stime_ms),
+        ru_other: [0; 14],
+    })
+}
+
+// Flags of wait4/waitid that only matter for threads
+const WAIT_THREAD_FLAGS: u32 = 0xE000_0000; // __WNOTHREAD, __WALL, __WCLONE
+
+fn sys_wait4(pid: i32, wstatus: u64, options: u32, rusage: u64) -> i64 {
+    debug!("wait4() called, pid={}, options={:#x}", pid, options);
+    
+    if options & !(process::WNOHANG | process::WSTOPPED | process::WCONTINUED | WAIT_THREAD_FLAGS) != 0 {
+        return -22; // EINVAL
+    }
+    let target = match pid {
+        -1 => WaitTarget::Any,
+        0 => unsafe {
+            let caller = PROCESS_MANAGER.current_pid();
+            WaitTarget::Pgid(PROCESS_MANAGER.get_process(caller).map_or(0, |p| p.pgid))
+        },
+        pid if pid < 0 => WaitTarget::Pgid(pid.unsigned_abs()),
+        pid => WaitTarget::Pid(pid as u32),
+    };
+    
+    match process::wait(target, options | process::WEXITED) {
+        Ok(Some(result)) => {
+            if wstatus != 0 {
+                if let Err(errno) = write_user(wstatus, result.status) {
+                    return errno;
+                }
+            }
+            if let Err(errno) = write_rusage(rusage, &result) {
+                return errno;
+            }
+            result.pid as i64
+        }
+        Ok(None) => 0, // WNOHANG and nothing to report
+        Err(errno) => errno as i64,
+    }
+}
+
+// waitid idtype
+const P_ALL: u32 = 0;
+const P_PID: u32 = 1;
+const P_PGID: u32 = 2;
+
+// siginfo_t as waitid fills it for SIGCHLD
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ChildSiginfo {
+    si_signo: i32,
+    si_errno: i32,
+    si_code: i32,
+    _pad: i32,
+    si_pid: i32,
+    si_uid: u32,
+    si_status: i32,
+    _pad2: i32,
+    si_utime: i64,           // clock ticks
+    si_stime: i64,
+    _rest: [u64; 10],        // siginfo_t is 128 bytes
+}
+
+// si_code for SIGCHLD
+const CLD_EXITED: i32 = 1;
+const CLD_KILLED: i32 = 2;
+const CLD_DUMPED: i32 = 3;
+const CLD_STOPPED: i32 = 5;
+const CLD_CONTINUED: i32 = 6;
+
+fn sys_waitid(idtype: u32, id: u32, infop: u64, options: u32, rusage: u64) -> i64 {
+    debug!("waitid() called, idtype={}, id={}, options={:#x}", idtype, id, options);
+    
+    let wanted = process::WEXITED | process::WSTOPPED | process::WCONTINUED;
+    if options & !(wanted | process::WNOHANG | process::WNOWAIT | WAIT_THREAD_FLAGS) != 0
+        || options & wanted == 0
+    {
+        return -22; // EINVAL
+    }
+    let target = match idtype {
+        P_ALL => WaitTarget::Any,
+        P_PID => WaitTarget::Pid(id),
+        P_PGID if id == 0 => unsafe {
+            let caller = PROCESS_MANAGER.current_pid();
+            WaitTarget::Pgid(PROCESS_MANAGER.get_process(caller).map_or(0, |p| p.pgid))
+        },
+        P_PGID => WaitTarget::Pgid(id),
+        _ => return -22, // EINVAL - P_PIDFD is not supported
+    };
+    
+    let result = match process::wait(target, options) {
+        Ok(result) => result,
+        Err(errno) => return errno as i64,
+    };
+    if infop == 0 {
+        return 0;
+    }
+    // WNOHANG with nothing to report leaves si_pid 0
+    let mut info = ChildSiginfo {
+        si_signo: 0, si_errno: 0, si_code: 0, _pad: 0, si_pid: 0, si_uid: 0,
+        si_status: 0, _pad2: 0, si_utime: 0, si_stime: 0, _rest: [0; 10],
+    };
+    if let Some(result) = result {
+        let status = result.status;
+        let (code, value) = if status == process::CONTINUED_STATUS {
+            (CLD_CONTINUED, Signal::SIGCONT as i32)
+        } else if status & 0xFF == 0x7F {
+            (CLD_STOPPED, status >> 8 & 0xFF)
+        } else if status & 0x7F == 0 {
+            (CLD_EXITED, status >> 8 & 0xFF)
+        } else if status & 0x80 != 0 {
+            (CLD_DUMPED, status & 0x7F)
         } else {
-            -3 // ESRCH - No such process
+            (CLD_KILLED, status & 0x7F)
+        };
+        let ms_per_tick = 1000 / elf::CLK_TCK;
+        info.si_signo = Signal::SIGCHLD as i32;
+        info.si_code = code;
+        info.si_pid = result.pid as i32;
+        info.si_uid = users::get_current_user().0;
+        info.si_status = value;
+        info.si_utime = (result.utime_ms / ms_per_tick) as i64;
+        info.si_stime = (result.stime_ms / ms_per_tick) as i64;
+        if let Err(errno) = write_rusage(rusage, &result) {
+            return errno;
         }
     }
+    match write_user(infop, info) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
 }
 
-fn sys_chdir(path: u64) -> i64 {
-    // For simplicity, always succeed
-    UART.write_str("chdir() called\n");
-    0
+fn current_cwd() -> String<{ process::MAX_PATH }> {
+    unsafe {
+        PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid())
+            .map(|p| p.cwd.clone())
+            .unwrap_or_default()
+    }
+}
+
+fn sys_chdir(pathname: u64) -> i64 {
+    let path = match read_user_path(pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let path = filesystem::resolve_path(&current_cwd(), &path);
+    debug!("chdir() called: {}", path);
+    
+    // A directory of the VFS or of the initramfs
+    let is_dir = path == "/"
+        || get_file_info(&path).is_some_and(|file| file.file_type == FileType::Directory)
+        || initramfs::lookup(&path).is_some_and(|file| file.file_type() == initramfs::S_IFDIR);
+    if !is_dir {
+        let exists = get_file_info(&path).is_some() || initramfs::lookup(&path).is_some();
+        return if exists { -20 } else { -2 }; // ENOTDIR / ENOENT
+    }
+    
+    unsafe {
+        match PROCESS_MANAGER.set_cwd(PROCESS_MANAGER.current_pid(), &path) {
+            Ok(()) => 0,
+            Err(errno) => errno as i64,
+        }
+    }
 }
 
 fn sys_getcwd(buf: u64, size: u64) -> i64 {
-    UART.write_str("getcwd() called\n");
-    // In real implementation, would write current directory to buf
-    // For now, just return success
-    size as i64
+    let cwd = current_cwd();
+    debug!("getcwd() called: {}", cwd);
+    
+    let len = cwd.len() + 1;
+    if (size as usize) < len {
+        return -34; // ERANGE - Buffer too small
+    }
+    let mut bytes = [0u8; process::MAX_PATH + 1];
+    bytes[..cwd.len()].copy_from_slice(cwd.as_bytes());
+    match uaccess::copy_to_user(buf, &bytes[..len]) {
+        Ok(()) => len as i64,
+        Err(errno) => errno,
+    }
 }
 
-fn sys_mkdir(pathname: u64, mode: u64) -> i64 {
-    UART.write_str("mkdir() called\n");
-    // In real implementation, would create directory in filesystem
+fn sys_mkdirat(dirfd: i32, pathname: u64, _mode: u64) -> i64 {
+    let path = match at_path(dirfd, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    debug!("mkdirat() called: {}", path);
+    
+    if path_stat(&path).is_some() {
+        return -17; // EEXIST - File exists
+    }
+    let parent = match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(pos) => &path[..pos],
+    };
+    match path_stat(parent) {
+        Some(stat) if stat.st_mode & initramfs::S_IFMT == initramfs::S_IFDIR => {}
+        Some(_) => return -20, // ENOTDIR - Not a directory
+        None => return -2,     // ENOENT - No such file or directory
+    }
+    if !filesystem::create_directory(&path) {
+        return -13; // EACCES - Permission denied
+    }
     0
 }
 
-fn sys_unlink(pathname: u64) -> i64 {
-    UART.write_str("unlink() called\n");
-    // In real implementation, would remove file from filesystem
+fn sys_unlinkat(dirfd: i32, pathname: u64, flags: u32) -> i64 {
+    if flags & !AT_REMOVEDIR != 0 {
+        return -22; // EINVAL - Invalid argument
+    }
+    let path = match at_path(dirfd, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    debug!("unlinkat() called: {}", path);
+    
+    if flags & AT_REMOVEDIR != 0 {
+        return rmdir(&path);
+    }
+    match get_file_info(&path).map(|file| file.file_type) {
+        Some(FileType::RegularFile) => {
+            filesystem::delete_file(&path);
+            0
+        }
+        Some(FileType::Directory) => -21, // EISDIR - Is a directory
+        Some(_) => -13,                   // EACCES - Devices and /proc stay
+        None if initramfs::lookup(&path).is_some() => -30, // EROFS - Read-only file system
+        None => -2,                       // ENOENT - No such file or directory
+    }
+}
+
+// unlinkat with AT_REMOVEDIR: an empty directory of the VFS
+fn rmdir(path: &str) -> i64 {
+    if path == "/" {
+        return -16; // EBUSY - Device or resource busy
+    }
+    match get_file_info(path).map(|file| file.file_type) {
+        Some(FileType::Directory) if !filesystem::list_directory(path).is_empty() => {
+            -39 // ENOTEMPTY - Directory not empty
+        }
+        Some(FileType::Directory) => {
+            filesystem::remove_directory(path);
+            0
+        }
+        Some(_) => -20, // ENOTDIR - Not a directory
+        None if initramfs::lookup(path).is_some() => -30, // EROFS - Read-only file system
+        None => -2,     // ENOENT - No such file or directory
+    }
+}
+
+// access(2) mode bits
+const R_OK: u64 = 4;
+const W_OK: u64 = 2;
+const X_OK: u64 = 1;
+
+fn sys_faccessat(dirfd: i32, pathname: u64, mode: u64) -> i64 {
+    let path = match at_path(dirfd, pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    debug!("faccessat() called: {}", path);
+    
+    if mode & !(R_OK | W_OK | X_OK) != 0 {
+        return -22; // EINVAL - Invalid argument
+    }
+    let Some(stat) = path_stat(&path) else {
+        return -2; // ENOENT - No such file or directory
+    };
+    // Every file belongs to root: root may read and write anything and
+    // execute what anyone may; everyone else gets the "other" bits
+    let allowed = if users::is_root() {
+        let any_exec = stat.st_mode & 0o111 != 0;
+        R_OK | W_OK | if any_exec { X_OK } else { 0 }
+    } else {
+        (stat.st_mode & 0o7) as u64
+    };
+    if mode & !allowed != 0 {
+        return -13; // EACCES - Permission denied
+    }
     0
 }
 
-fn sys_access(pathname: u64, mode: u64) -> i64 {
-    UART.write_str("access() called\n");
-    // In real implementation, would check file accessibility
+// struct stat (asm-generic, as on arm64)
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Stat {
+    st_dev: u64,
+    st_ino: u64,
+    st_mode: u32,
+    st_nlink: u32,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: u64,
+    __pad1: u64,
+    st_size: i64,
+    st_blksize: i32,
+    __pad2: i32,
+    st_blocks: i64,
+    st_atime: i64,
+    st_atime_nsec: u64,
+    st_mtime: i64,
+    st_mtime_nsec: u64,
+    st_ctime: i64,
+    st_ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+
+// Status of a file of the VFS, of the initramfs or a pty slave; all owned by root
+fn path_stat(path: &str) -> Option<Stat> {
+    let (mode, size) = if path == "/" {
+        (initramfs::S_IFDIR | 0o755, 0)
+    } else if let Some(file) = get_file_info(path) {
+        let file_type = match file.file_type {
+            FileType::Directory => initramfs::S_IFDIR,
+            FileType::Device => S_IFCHR,
+            FileType::RegularFile | FileType::Proc => initramfs::S_IFREG,
+        };
+        (file_type | file.permissions, file.content.len())
+    } else if let Some(entry) = initramfs::lookup(path) {
+        (entry.mode, entry.data.len())
+    } else if file::pts_index(path).is_some_and(|index| pty::slave(index).is_some()) {
+        (S_IFCHR | 0o620, 0)
+    } else {
+        return None;
+    };
+    // Inode numbers only need to tell files apart: FNV-1a of the path
+    let ino = path.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    Some(Stat {
+        st_ino: ino,
+        st_mode: mode,
+        st_nlink: 1,
+        st_size: size as i64,
+        st_blksize: 4096,
+        st_blocks: size.div_ceil(512) as i64,
+        ..Stat::default()
+    })
+}
+
+fn sys_newfstatat(dirfd: i32, pathname: u64, statbuf: u64, flags: u32) -> i64 {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_NO_AUTOMOUNT | AT_EMPTY_PATH) != 0 {
+        return -22; // EINVAL - Invalid argument
+    }
+    let path = match read_user_path(pathname) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    // An empty path with AT_EMPTY_PATH is dirfd itself: fstat() as the C library may make it
+    let empty = path.is_empty() && flags & AT_EMPTY_PATH != 0;
+    if empty && dirfd != AT_FDCWD {
+        return sys_fstat(dirfd, statbuf);
+    }
+    // Symbolic links are only in the initramfs, and always followed
+    let path = match resolve_at(dirfd, if empty { "." } else { &path }) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    debug!("newfstatat() called: {}", path);
+    
+    let Some(stat) = path_stat(&path) else {
+        return -2; // ENOENT - No such file or directory
+    };
+    match write_user(statbuf, stat) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+fn sys_fstat(fd: i32, statbuf: u64) -> i64 {
+    let Some((_, file)) = fd_file(fd) else {
+        return -9; // EBADF - Bad file descriptor
+    };
+    let stat = match file.kind {
+        FileKind::PipeRead(pipe) | FileKind::PipeWrite(pipe) => Stat {
+            st_ino: pipe as u64,
+            st_mode: S_IFIFO | 0o600,
+            st_nlink: 1,
+            st_blksize: 4096,
+            ..Stat::default()
+        },
+        FileKind::Path | FileKind::PtyMaster(_) => match path_stat(&file.path) {
+            Some(stat) => stat,
+            None => return -2, // ENOENT - Removed since it was opened
+        },
+    };
+    match write_user(statbuf, stat) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+// struct timespec / struct timeval / struct itimerval (LP64)
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Timeval {
+    tv_sec: i64,
+    tv_usec: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Itimerval {
+    it_interval: Timeval,
+    it_value: Timeval,
+}
+
+// struct timezone, obsolete: always UTC with no DST
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Timezone {
+    tz_minuteswest: i32,
+    tz_dsttime: i32,
+}
+
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+const CLOCK_MONOTONIC_RAW: i32 = 4;
+const CLOCK_REALTIME_COARSE: i32 = 5;
+const CLOCK_MONOTONIC_COARSE: i32 = 6;
+const CLOCK_BOOTTIME: i32 = 7;
+const TIMER_ABSTIME: i32 = 1;
+const ITIMER_REAL: i32 = 0;
+
+/// NUL-terminated path from user memory
+fn read_user_path(addr: u64) -> Result<String<MAX_FILENAME>, i64> {
+    let mut bytes = [0u8; MAX_FILENAME];
+    let len = uaccess::strncpy_from_user(&mut bytes, addr)?;
+    if len == bytes.len() {
+        return Err(-36); // ENAMETOOLONG - File name too long
+    }
+    let mut path = String::new();
+    for &byte in &bytes[..len] {
+        if !byte.is_ascii() || path.push(byte as char).is_err() {
+            return Err(-22); // EINVAL
+        }
+    }
+    Ok(path)
+}
+
+/// NULL-terminated array of strings (argv, envp) from user memory
+fn read_user_strings(addr: u64) -> Result<Vec<alloc::vec::Vec<u8>, MAX_ARGS>, i64> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings); // Linux takes a NULL argv or envp as empty
+    }
+    let mut total = 0;
+    for i in 0.. {
+        let ptr: u64 = read_user(addr.wrapping_add(i * 8))?;
+        if ptr == 0 {
+            break;
+        }
+        // A piece at a time, for strings longer than the buffer
+        let mut string = alloc::vec::Vec::new();
+        let mut chunk = [0u8; 256];
+        loop {
+            let len = uaccess::strncpy_from_user(&mut chunk, ptr.wrapping_add(string.len() as u64))?;
+            string.extend_from_slice(&chunk[..len]);
+            total += len;
+            if total > elf::MAX_ARG_SIZE {
+                return Err(-7); // E2BIG - Argument list too long
+            }
+            if len < chunk.len() {
+                break;
+            }
+        }
+        strings.push(string).map_err(|_| -7i64)?; // E2BIG
+    }
+    Ok(strings)
+}
+
+fn timespec_to_ns(ts: &Timespec) -> Option<u64> {
+    if ts.tv_sec < 0 || !(0..ktimer::NSEC_PER_SEC as i64).contains(&ts.tv_nsec) {
+        return None;
+    }
+    Some((ts.tv_sec as u64).saturating_mul(ktimer::NSEC_PER_SEC).saturating_add(ts.tv_nsec as u64))
+}
+
+fn ns_to_timespec(ns: u64) -> Timespec {
+    Timespec {
+        tv_sec: (ns / ktimer::NSEC_PER_SEC) as i64,
+        tv_nsec: (ns % ktimer::NSEC_PER_SEC) as i64,
+    }
+}
+
+fn timeval_to_ns(tv: &Timeval) -> Option<u64> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return None;
+    }
+    Some((tv.tv_sec as u64).saturating_mul(ktimer::NSEC_PER_SEC).saturating_add(tv.tv_usec as u64 * 1_000))
+}
+
+fn ns_to_timeval(ns: u64) -> Timeval {
+    // Round up so that a pending timer never reads as zero
+    let us = ns.div_ceil(1_000);
+    Timeval {
+        tv_sec: (us / 1_000_000) as i64,
+        tv_usec: (us % 1_000_000) as i64,
+    }
+}
+
+/// Relative sleep shared by nanosleep and clock_nanosleep; writes the
+/// remaining time to `rem` when interrupted
+fn sleep_relative(ns: u64, rem: u64) -> i64 {
+    let remaining = ktimer::sleep_ns(ns);
+    if remaining == 0 {
+        return 0;
+    }
+    if rem != 0 {
+        if let Err(errno) = write_user(rem, ns_to_timespec(remaining)) {
+            return errno;
+        }
+    }
+    -4 // EINTR - Interrupted system call
+}
+
+fn sys_nanosleep(req: u64, rem: u64) -> i64 {
+    let request = match read_user::<Timespec>(req) {
+        Ok(ts) => ts,
+        Err(errno) => return errno,
+    };
+    match timespec_to_ns(&request) {
+        Some(ns) => sleep_relative(ns, rem),
+        None => -22, // EINVAL - Invalid argument
+    }
+}
+
+/// Current value of a clock in nanoseconds, None for an unknown clock.
+/// Nothing suspends, so the boot time and raw clocks equal the monotonic one.
+fn clock_now(clock_id: i32) -> Option<u64> {
+    match clock_id {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Some(rtc::realtime_ns()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            Some(timer::monotonic_ns())
+        }
+        _ => None,
+    }
+}
+
+fn sys_clock_gettime(clock_id: i32, tp: u64) -> i64 {
+    let ns = match clock_now(clock_id) {
+        Some(ns) => ns,
+        None => return -22, // EINVAL - Invalid argument
+    };
+    match write_user(tp, ns_to_timespec(ns)) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+fn sys_clock_settime(clock_id: i32, tp: u64) -> i64 {
+    // Only the wall clock can be set
+    if clock_id != CLOCK_REALTIME {
+        return -22; // EINVAL - Invalid argument
+    }
+    // CAP_SYS_TIME
+    if !users::is_root() {
+        return -1; // EPERM - Operation not permitted
+    }
+    let ts = match read_user::<Timespec>(tp) {
+        Ok(ts) => ts,
+        Err(errno) => return errno,
+    };
+    match timespec_to_ns(&ts) {
+        // The clock is set even when the RTC write fails
+        Some(ns) => {
+            let _ = rtc::set_realtime(ns);
+            0
+        }
+        None => -22, // EINVAL - Invalid argument
+    }
+}
+
+fn sys_gettimeofday(tv: u64, tz: u64) -> i64 {
+    if tv != 0 {
+        if let Err(errno) = write_user(tv, ns_to_timeval(rtc::realtime_ns())) {
+            return errno;
+        }
+    }
+    if tz != 0 {
+        if let Err(errno) = write_user(tz, Timezone { tz_minuteswest: 0, tz_dsttime: 0 }) {
+            return errno;
+        }
+    }
     0
 }
 
-fn sys_stat(pathname: u64, statbuf: u64) -> i64 {
-    UART.write_str("stat() called\n");
-    // In real implementation, would fill stat structure
+fn sys_clock_nanosleep(clock_id: i32, flags: i32, req: u64, rem: u64) -> i64 {
+    if !matches!(clock_id, CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME) {
+        return -22; // EINVAL - Invalid argument
+    }
+    let request = match read_user::<Timespec>(req) {
+        Ok(ts) => ts,
+        Err(errno) => return errno,
+    };
+    let ns = match timespec_to_ns(&request) {
+        Some(ns) => ns,
+        None => return -22, // EINVAL - Invalid argument
+    };
+
+    if flags & TIMER_ABSTIME != 0 {
+        // An absolute sleep is restarted as is, so rem is not written. A
+        // CLOCK_REALTIME deadline is converted with the current offset; a
+        // later clock_settime() does not move it.
+        let deadline = if clock_id == CLOCK_REALTIME { rtc::realtime_to_monotonic(ns) } else { ns };
+        if ktimer::sleep_until(deadline) { 0 } else { -4 } // EINTR
+    } else {
+        sleep_relative(ns, rem)
+    }
+}
+
+fn itimerval_from_ns(value: u64, interval: u64) -> Itimerval {
+    Itimerval {
+        it_interval: ns_to_timeval(interval),
+        it_value: ns_to_timeval(value),
+    }
+}
+
+fn sys_getitimer(which: i32, curr_value: u64) -> i64 {
+    // ITIMER_VIRTUAL and ITIMER_PROF need per-process CPU time accounting
+    if which != ITIMER_REAL {
+        return -22; // EINVAL - Invalid argument
+    }
+    let (value, interval) = ktimer::get_itimer_real();
+    match write_user(curr_value, itimerval_from_ns(value, interval)) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+fn sys_setitimer(which: i32, new_value: u64, old_value: u64) -> i64 {
+    if which != ITIMER_REAL {
+        return -22; // EINVAL - Invalid argument
+    }
+    let new = match read_user::<Itimerval>(new_value) {
+        Ok(value) => value,
+        Err(errno) => return errno,
+    };
+    let (value, interval) = match (timeval_to_ns(&new.it_value), timeval_to_ns(&new.it_interval)) {
+        (Some(value), Some(interval)) => (value, interval),
+        _ => return -22, // EINVAL - Invalid argument
+    };
+
+    // arm64 has no alarm syscall; the C library implements alarm() with this
+    let (old, old_interval) = ktimer::set_itimer_real(value, interval);
+    if old_value != 0 {
+        if let Err(errno) = write_user(old_value, itimerval_from_ns(old, old_interval)) {
+            return errno;
+        }
+    }
     0
 }
 
 // Initialize system call infrastructure
 pub fn init_syscalls() {
-    unsafe {
-        GLOBAL_FD_TABLE = ProcessFdTable::new();
-    }
-    UART.write_str("System call interface initialized\n");
+    info!("System call interface initialized");
 }
@@ -0,0 +1,209 @@
+// termios: terminal settings as TCGETS/TCSETS pass them
+//
+// The kernel `struct termios` of Linux (asm-generic). tty.rs acts on the
+// input, output and local flags and the control characters; the hardware
+// part of c_cflag (baud rate, character size, parity, stop bits, RTS/CTS)
+// maps onto `uart::UartConfig`.
+
+use crate::uart::{Parity, UartConfig};
+
+pub const NCCS: usize = 19;
+
+// ioctl requests
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;   // Now
+pub const TCSETSW: u32 = 0x5403;  // After queued output has been sent
+pub const TCSETSF: u32 = 0x5404;  // As TCSETSW, and discard pending input
+pub const TCFLSH: u32 = 0x540B;
+pub const TIOCGPGRP: u32 = 0x540F;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCSCTTY: u32 = 0x540E;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const TIOCSWINSZ: u32 = 0x5414;
+pub const TIOCNOTTY: u32 = 0x5422;
+pub const TIOCGPTN: u32 = 0x8004_5430;    // Number of the pty behind a /dev/ptmx descriptor
+pub const TIOCSPTLCK: u32 = 0x4004_5431;  // Lock / unlock its /dev/pts/N
+
+// TCFLSH queue selectors
+pub const TCIFLUSH: u64 = 0;
+pub const TCOFLUSH: u64 = 1;
+pub const TCIOFLUSH: u64 = 2;
+
+// c_cc indices
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VWERASE: usize = 14;
+pub const VEOL2: usize = 16;
+
+// c_iflag
+pub const IGNBRK: u32 = 0o000001;
+pub const BRKINT: u32 = 0o000002;
+pub const PARMRK: u32 = 0o000010;
+pub const ISTRIP: u32 = 0o000040;
+pub const INLCR: u32 = 0o000100;
+pub const IGNCR: u32 = 0o000200;
+pub const ICRNL: u32 = 0o000400;
+pub const IXON: u32 = 0o002000;
+
+// c_oflag
+pub const OPOST: u32 = 0o000001;
+pub const ONLCR: u32 = 0o000004;
+
+// c_lflag
+pub const ISIG: u32 = 0o000001;
+pub const ICANON: u32 = 0o000002;
+pub const ECHO: u32 = 0o000010;
+pub const ECHOE: u32 = 0o000020;
+pub const ECHOK: u32 = 0o000040;
+pub const ECHONL: u32 = 0o000100;
+pub const NOFLSH: u32 = 0o000200;
+pub const ECHOCTL: u32 = 0o001000;
+pub const ECHOKE: u32 = 0o004000;
+pub const IEXTEN: u32 = 0o100000;
+
+// c_cflag
+pub const CBAUD: u32 = 0o010017;
+pub const CSIZE: u32 = 0o000060;
+pub const CS5: u32 = 0o000000;
+pub const CS6: u32 = 0o000020;
+pub const CS7: u32 = 0o000040;
+pub const CS8: u32 = 0o000060;
+pub const CSTOPB: u32 = 0o000100;
+pub const CREAD: u32 = 0o000200;
+pub const PARENB: u32 = 0o000400;
+pub const PARODD: u32 = 0o001000;
+pub const HUPCL: u32 = 0o002000;
+pub const CLOCAL: u32 = 0o004000;
+pub const CRTSCTS: u32 = 0o020000000000;
+const B115200: u32 = 0o010002;
+
+// Bnnn codes in CBAUD and the rates they stand for
+const BAUD_RATES: [(u32, u32); 30] = [
+    (0o000001, 50), (0o000002, 75), (0o000003, 110), (0o000004, 134),
+    (0o000005, 150), (0o000006, 200), (0o000007, 300), (0o000010, 600),
+    (0o000011, 1200), (0o000012, 1800), (0o000013, 2400), (0o000014, 4800),
+    (0o000015, 9600), (0o000016, 19200), (0o000017, 38400),
+    (0o010001, 57600), (0o010002, 115200), (0o010003, 230400),
+    (0o010004, 460800), (0o010005, 500000), (0o010006, 576000),
+    (0o010007, 921600), (0o010010, 1000000), (0o010011, 1152000),
+    (0o010012, 1500000), (0o010013, 2000000), (0o010014, 2500000),
+    (0o010015, 3000000), (0o010016, 3500000), (0o010017, 4000000),
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// What Linux gives a fresh tty: cooked input with echo, ^C ^\ DEL ^U ^D ...
+    pub const fn new() -> Self {
+        let mut c_cc = [0; NCCS];
+        c_cc[VINTR] = 0x03;      // ^C
+        c_cc[VQUIT] = 0x1C;      // ^\
+        c_cc[VERASE] = 0x7F;     // DEL
+        c_cc[VKILL] = 0x15;      // ^U
+        c_cc[VEOF] = 0x04;       // ^D
+        c_cc[VMIN] = 1;
+        c_cc[8] = 0x11;          // VSTART ^Q
+        c_cc[9] = 0x13;          // VSTOP ^S
+        c_cc[VSUSP] = 0x1A;      // ^Z
+        c_cc[VREPRINT] = 0x12;   // ^R
+        c_cc[13] = 0x0F;         // VDISCARD ^O
+        c_cc[VWERASE] = 0x17;    // ^W
+        c_cc[15] = 0x16;         // VLNEXT ^V
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: B115200 | CS8 | CREAD | HUPCL | CLOCAL,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+
+    /// cfmakeraw: no line editing, echo, signals or output processing
+    pub fn make_raw(&mut self) {
+        self.c_iflag &= !(IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON);
+        self.c_oflag &= !OPOST;
+        self.c_lflag &= !(ECHO | ECHONL | ICANON | ISIG | IEXTEN);
+        self.c_cflag = (self.c_cflag & !(CSIZE | PARENB)) | CS8;
+        self.c_cc[VMIN] = 1;
+    }
+}
+
+/// Terminal size (TIOCGWINSZ / TIOCSWINSZ)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Winsize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+pub fn baud_to_code(baud: u32) -> Option<u32> {
+    BAUD_RATES.iter().find(|&&(_, rate)| rate == baud).map(|&(code, _)| code)
+}
+
+pub fn code_to_baud(code: u32) -> Option<u32> {
+    BAUD_RATES.iter().find(|&&(c, _)| c == code).map(|&(_, rate)| rate)
+}
+
+/// `cflag` with its hardware bits replaced by what `config` describes
+pub fn config_to_cflag(config: &UartConfig, cflag: u32) -> u32 {
+    let mut cflag = cflag & !(CBAUD | CSIZE | CSTOPB | PARENB | PARODD | CRTSCTS);
+    cflag |= baud_to_code(config.baud).unwrap_or(0);
+    cflag |= match config.data_bits {
+        5 => CS5,
+        6 => CS6,
+        7 => CS7,
+        _ => CS8,
+    };
+    if config.stop_bits == 2 {
+        cflag |= CSTOPB;
+    }
+    match config.parity {
+        Parity::None => {}
+        Parity::Even => cflag |= PARENB,
+        Parity::Odd => cflag |= PARENB | PARODD,
+    }
+    if config.rtscts {
+        cflag |= CRTSCTS;
+    }
+    cflag
+}
+
+/// UART settings asked for by `cflag`
+pub fn cflag_to_config(cflag: u32) -> Result<UartConfig, i64> {
+    let baud = code_to_baud(cflag & CBAUD).ok_or(-22i64)?; // EINVAL (B0 included)
+    Ok(UartConfig {
+        baud,
+        data_bits: match cflag & CSIZE {
+            CS5 => 5,
+            CS6 => 6,
+            CS7 => 7,
+            _ => 8,
+        },
+        parity: match (cflag & PARENB != 0, cflag & PARODD != 0) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        },
+        stop_bits: if cflag & CSTOPB != 0 { 2 } else { 1 },
+        rtscts: cflag & CRTSCTS != 0,
+    })
+}
@@ -1,67 +1,246 @@
-// System Timer for Raspberry Pi 5
+// ARM Generic Timer for Raspberry Pi 5
 // Provides timing services for the OS
+//
+// 時刻は全コア共通のシステムカウンタ (CNTPCT_EL0, 周波数 CNTFRQ_EL0) から取る。
+// 割り込みは各コアのEL1物理タイマー (CNTP_CVAL_EL0/CNTP_CTL_EL0) が PPI 30
+// として出す。タイマーはワンショットで、次の期限 (スケジューラのティック
+// またはset_oneshotで登録した期限の早い方) にだけ割り込みを設定する。
+// 実行可能なプロセスが1つ以下ならティックを止める (tickless)。
 
-const SYSTEM_TIMER_BASE: u64 = 0xfe003000;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-// Timer registers
-const TIMER_CS: u32 = 0x00;   // Control/Status
-const TIMER_CLO: u32 = 0x04;  // Counter Lower 32 bits
-const TIMER_CHI: u32 = 0x08;  // Counter Higher 32 bits
-const TIMER_C0: u32 = 0x0C;   // Compare 0
-const TIMER_C1: u32 = 0x10;   // Compare 1
-const TIMER_C2: u32 = 0x14;   // Compare 2
-const TIMER_C3: u32 = 0x18;   // Compare 3
+use crate::interrupt;
+use crate::smp::{self, MAX_CPUS};
 
-pub struct Timer {
-    base_addr: u64,
+// スケジューラのティック
+pub const TICK_HZ: u32 = 100;
+pub const TICK_MS: u32 = 1000 / TICK_HZ;
+
+// 非セキュアEL1物理タイマーのPPI
+pub const IRQ_ARCH_TIMER: u32 = 30;
+
+// CNTP_CTL_EL0
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+
+// CNTKCTL_EL1: イベントストリームでWFEを定期的に起こす
+const CNTKCTL_EVNTEN: u64 = 1 << 2;
+const CNTKCTL_EVNTI_SHIFT: u64 = 4;
+const EVENT_STREAM_HZ: u64 = 10_000;
+
+// これより短い待ちはWFEで寝ずにスピンする
+const SPIN_THRESHOLD_US: u64 = 200;
+
+const NO_DEADLINE: u64 = u64::MAX;
+
+/// コアごとのタイマー状態
+struct CpuTimer {
+    tick_enabled: bool,
+    next_tick: u64,                 // Counter value of the next tick
+    deadline: u64,                  // Counter value of the one-shot, NO_DEADLINE if none
+    handler: Option<fn()>,          // Called when the one-shot expires
+}
+
+impl CpuTimer {
+    const fn new() -> Self {
+        Self { tick_enabled: false, next_tick: 0, deadline: NO_DEADLINE, handler: None }
+    }
+}
+
+static mut CPU_TIMERS: [CpuTimer; MAX_CPUS] = [const { CpuTimer::new() }; MAX_CPUS];
+
+// 起動時のカウンタ値 (単調時計の原点)
+static BOOT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+fn this_timer() -> &'static mut CpuTimer {
+    unsafe { &mut (*core::ptr::addr_of_mut!(CPU_TIMERS))[smp::cpu_id()] }
+}
+
+/// カウンタの周波数 (Hz)
+pub fn frequency() -> u64 {
+    let freq: u64;
+    unsafe { core::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq) };
+    freq
+}
+
+/// システムカウンタの現在値
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe { core::arch::asm!("isb", "mrs {}, cntpct_el0", out(reg) count) };
+    count
+}
+
+fn ticks_to_ns(ticks: u64) -> u64 {
+    match frequency() {
+        0 => 0,
+        freq => (ticks as u128 * 1_000_000_000 / freq as u128) as u64,
+    }
+}
+
+fn ns_to_ticks(ns: u64) -> u64 {
+    (ns as u128 * frequency() as u128 / 1_000_000_000) as u64
+}
+
+/// 起動からの単調時計 (ナノ秒)
+pub fn monotonic_ns() -> u64 {
+    ticks_to_ns(counter().wrapping_sub(BOOT_COUNT.load(Ordering::Relaxed)))
 }
 
+/// 単調時計の値 `ns` に対応するカウンタ値
+fn deadline_to_counter(ns: u64) -> u64 {
+    BOOT_COUNT.load(Ordering::Relaxed).saturating_add(ns_to_ticks(ns))
+}
+
+pub struct Timer;
+
 impl Timer {
     pub const fn new() -> Self {
-        Self {
-            base_addr: SYSTEM_TIMER_BASE,
-        }
+        Self
     }
-    
-    /// システムタイマー初期化
+
+    /// タイマー初期化 (ブートCPU): 単調時計の原点を記録する
     pub fn init(&self) {
-        // BCM2712のシステムタイマーは1MHz
-        // 基本的な初期化のみ実行
+        BOOT_COUNT.store(counter(), Ordering::Relaxed);
+        init_cpu();
     }
-    
+
     /// 現在の時刻をマイクロ秒で取得
     pub fn get_time_us(&self) -> u64 {
-        unsafe {
-            let timer_base = self.base_addr as *const u32;
-            let lo = core::ptr::read_volatile(timer_base.add((TIMER_CLO / 4) as usize));
-            let hi = core::ptr::read_volatile(timer_base.add((TIMER_CHI / 4) as usize));
-            ((hi as u64) << 32) | (lo as u64)
-        }
+        monotonic_ns() / 1_000
     }
-    
+
     /// 指定時間待機（マイクロ秒）
     pub fn delay_us(&self, us: u32) {
-        let start = self.get_time_us();
-        while self.get_time_us() - start < us as u64 {
-            core::hint::spin_loop();
+        let end = counter() + ns_to_ticks(us as u64 * 1_000);
+        while counter() < end {
+            if us as u64 >= SPIN_THRESHOLD_US {
+                // イベントストリームで 100us ごとに起きる
+                unsafe { core::arch::asm!("wfe") };
+            } else {
+                core::hint::spin_loop();
+            }
         }
     }
-    
+
     /// 指定時間待機（ミリ秒）
     pub fn delay_ms(&self, ms: u32) {
         self.delay_us(ms * 1000);
     }
-    
+
     /// システム起動からの時間を秒で取得
     pub fn get_uptime_seconds(&self) -> u32 {
-        (self.get_time_us() / 1_000_000) as u32
+        (monotonic_ns() / 1_000_000_000) as u32
     }
 }
 
 // グローバルタイマーインスタンス
 pub static TIMER: Timer = Timer::new();
 
+/// 各コアのタイマー初期化: イベントストリームを有効にし、タイマーを止める
+pub fn init_cpu() {
+    // Event on every transition of counter bit EVNTI, about EVENT_STREAM_HZ times a second
+    let period = (frequency() / EVENT_STREAM_HZ).max(2);
+    let evnti = 63 - period.leading_zeros() as u64 - 1;
+    unsafe {
+        let mut cntkctl: u64;
+        core::arch::asm!("mrs {}, cntkctl_el1", out(reg) cntkctl);
+        cntkctl &= !(0xF << CNTKCTL_EVNTI_SHIFT);
+        cntkctl |= CNTKCTL_EVNTEN | (evnti.min(15) << CNTKCTL_EVNTI_SHIFT);
+        core::arch::asm!("msr cntkctl_el1, {}", in(reg) cntkctl);
+        core::arch::asm!("msr cntp_ctl_el0, {}", "isb", in(reg) CTL_IMASK);
+    }
+    *this_timer() = CpuTimer::new();
+}
+
+/// 次の割り込みをティックとワンショットの早い方に設定する
+fn program(timer: &CpuTimer) {
+    let next = if timer.tick_enabled { timer.next_tick.min(timer.deadline) } else { timer.deadline };
+    unsafe {
+        if next == NO_DEADLINE {
+            core::arch::asm!("msr cntp_ctl_el0, {}", "isb", in(reg) CTL_IMASK);
+        } else {
+            core::arch::asm!(
+                "msr cntp_cval_el0, {}",
+                "msr cntp_ctl_el0, {}",
+                "isb",
+                in(reg) next,
+                in(reg) CTL_ENABLE,
+            );
+        }
+    }
+}
+
+fn tick_period() -> u64 {
+    frequency() / TICK_HZ as u64
+}
+
+/// このコアのティックを開始
+pub fn start_tick() {
+    let timer = this_timer();
+    if !timer.tick_enabled {
+        timer.tick_enabled = true;
+        timer.next_tick = counter() + tick_period();
+        program(timer);
+    }
+}
+
+/// 単調時計の `deadline_ns` にワンショット割り込みを設定し、期限で `handler` を呼ぶ
+pub fn set_oneshot(deadline_ns: u64, handler: fn()) {
+    let timer = this_timer();
+    timer.deadline = deadline_to_counter(deadline_ns);
+    timer.handler = Some(handler);
+    program(timer);
+}
+
+pub fn cancel_oneshot() {
+    let timer = this_timer();
+    timer.deadline = NO_DEADLINE;
+    timer.handler = None;
+    program(timer);
+}
+
+/// タイマー割り込みをGICに登録する (各コアのPPI)
+pub fn init_irq() -> Result<(), &'static str> {
+    interrupt::request_irq(IRQ_ARCH_TIMER, handle_timer_irq,
+                           interrupt::IRQF_TRIGGER_HIGH | interrupt::IRQF_PERCPU, "arch_timer")
+}
+
+/// タイマー割り込みハンドラ (PPI 30)
+fn handle_timer_irq(_irq: u32) {
+    let timer = this_timer();
+    let now = counter();
+
+    if timer.tick_enabled && now >= timer.next_tick {
+        // Skip ticks that were missed rather than firing them back to back
+        let period = tick_period();
+        let missed = (now - timer.next_tick) / period;
+        timer.next_tick += (missed + 1) * period;
+        crate::process::timer_tick();
+    }
+
+    if now >= timer.deadline {
+        timer.deadline = NO_DEADLINE;
+        if let Some(handler) = timer.handler.take() {
+            // The handler may set the next one-shot
+            handler();
+        }
+    }
+
+    // Tickless: only tick while there is something to preempt
+    let timer = this_timer();
+    let needs_tick = crate::process::needs_tick();
+    if needs_tick && !timer.tick_enabled {
+        timer.next_tick = now + tick_period();
+    }
+    timer.tick_enabled = needs_tick;
+    program(timer);
+}
+
 // グローバル関数（他のモジュールから使用可能）
+pub fn init() {
+    TIMER.init();
+}
+
 pub fn delay_us(us: u32) {
     TIMER.delay_us(us);
 }
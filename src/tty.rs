@@ -0,0 +1,626 @@
+// TTY layer: the line discipline between a terminal driver and its readers
+//
+// A driver hands received bytes to `Tty::receive`, which applies the termios
+// settings: input translation (ICRNL, INLCR, IGNCR, ISTRIP), the signal
+// characters (ISIG: VINTR -> SIGINT, VQUIT -> SIGQUIT, VSUSP -> SIGTSTP to the
+// foreground process group), line editing in canonical mode (VERASE, VKILL,
+// VWERASE, VREPRINT, VEOF) and echo (ECHO, ECHOE, ECHOK, ECHOKE, ECHOCTL,
+// ECHONL). Readers get one line at a time in canonical mode and bytes as
+// they arrive otherwise (at least VMIN of them; VTIME is not supported).
+// Output goes through OPOST/ONLCR on its way to the driver.
+//
+// The console tty sits on the PL011: its bytes arrive from the UART
+// interrupt, or are polled by the reader while the UART is not interrupt
+// driven. The other ttys are the slave ends of pseudo-terminals (pty.rs).
+// A process reads and writes fds 0-2, and the shell prints through
+// `STDOUT`, on its controlling terminal, which is the console unless it
+// belongs to a session started on a pty.
+
+use core::fmt;
+
+use heapless::{Deque, Vec};
+
+use crate::process::{self, WaitQueue, PROCESS_MANAGER};
+use crate::pty;
+use crate::signals::{self, Signal};
+use crate::termios::{self, *};
+use crate::uart::{self, UART};
+
+const MAX_CANON: usize = 255;     // Longest line in canonical mode
+const INPUT_SIZE: usize = 4096;   // Bytes ready for readers
+const MAX_LINES: usize = 64;      // Complete lines not yet read
+
+/// Which tty: a process's controlling terminal, or what a descriptor refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtyId {
+    Console,
+    Pty(usize),   // /dev/pts/N
+}
+
+/// The hardware (or pseudo-terminal) end of a tty
+pub trait TtyDriver {
+    /// Send bytes to the terminal
+    fn write(&self, data: &[u8]);
+    /// Apply the hardware part (c_cflag) of new settings
+    fn set_termios(&self, _termios: &Termios) -> Result<(), i64> {
+        Ok(())
+    }
+    /// Wait until everything written has been sent
+    fn wait_until_sent(&self) {}
+    /// Discard output that has not been sent yet
+    fn flush_output(&self) {}
+    /// Discard received bytes the driver still holds
+    fn flush_input(&self) {}
+    /// A received byte, for drivers whose input has to be polled (`needs_polling`)
+    fn poll(&self) -> Option<u8> {
+        None
+    }
+    fn needs_polling(&self) -> bool {
+        false
+    }
+}
+
+pub struct Tty {
+    name: &'static str,
+    driver: &'static dyn TtyDriver,
+    termios: Termios,
+    line: Vec<u8, MAX_CANON>,              // Line being edited (canonical mode)
+    input: Deque<u8, INPUT_SIZE>,          // Ready for readers
+    line_ends: Deque<usize, MAX_LINES>,    // Lengths of the lines in `input`; 0 is an EOF
+    pgrp: u32,                             // Foreground process group
+    session: u32,
+    winsize: Winsize,
+    hung_up: bool,                         // The other end went away: reads see EOF
+    readers: WaitQueue,
+    signal_seq: u32,                       // Counts signal characters, so readers can tell
+}
+
+impl Tty {
+    pub const fn new(name: &'static str, driver: &'static dyn TtyDriver) -> Self {
+        Self {
+            name,
+            driver,
+            termios: Termios::new(),
+            line: Vec::new(),
+            input: Deque::new(),
+            line_ends: Deque::new(),
+            pgrp: 0,
+            session: 0,
+            winsize: Winsize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 },
+            hung_up: false,
+            readers: WaitQueue::new(),
+            signal_seq: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.termios.c_lflag & flag != 0
+    }
+
+    fn iflag(&self, flag: u32) -> bool {
+        self.termios.c_iflag & flag != 0
+    }
+
+    /// `c` is the control character `index` (0 disables it)
+    fn is_cc(&self, c: u8, index: usize) -> bool {
+        c != 0 && self.termios.c_cc[index] == c
+    }
+
+    /// Bytes from the driver. Called with IRQs masked.
+    pub fn receive(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.receive_byte(byte);
+        }
+        self.readers.wake_all();
+    }
+
+    fn receive_byte(&mut self, mut c: u8) {
+        if self.iflag(ISTRIP) {
+            c &= 0x7F;
+        }
+        if c == b'\r' {
+            if self.iflag(IGNCR) {
+                return;
+            }
+            if self.iflag(ICRNL) {
+                c = b'\n';
+            }
+        } else if c == b'\n' && self.iflag(INLCR) {
+            c = b'\r';
+        }
+
+        if self.lflag(ISIG) {
+            let signal = if self.is_cc(c, VINTR) {
+                Some(Signal::SIGINT)
+            } else if self.is_cc(c, VQUIT) {
+                Some(Signal::SIGQUIT)
+            } else if self.is_cc(c, VSUSP) {
+                Some(Signal::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                self.signal_char(c, signal);
+                return;
+            }
+        }
+
+        if !self.lflag(ICANON) {
+            if self.input.push_back(c).is_ok() {
+                self.echo(c);
+            }
+            return;
+        }
+
+        let iexten = self.lflag(IEXTEN);
+        if self.is_cc(c, VERASE) {
+            self.erase(false);
+        } else if self.is_cc(c, VKILL) {
+            self.kill_line(c);
+        } else if iexten && self.is_cc(c, VWERASE) {
+            self.erase(true);
+        } else if iexten && self.is_cc(c, VREPRINT) {
+            self.reprint(c);
+        } else if self.is_cc(c, VEOF) {
+            // Ends the line without being part of it; on an empty line it is EOF
+            self.finish_line();
+        } else if c == b'\n' || self.is_cc(c, VEOL) || (iexten && self.is_cc(c, VEOL2)) {
+            if self.lflag(ECHO) || (c == b'\n' && self.lflag(ECHONL)) {
+                self.output(&[c]);
+            }
+            let _ = self.line.push(c);
+            self.finish_line();
+        } else if self.line.len() < MAX_CANON - 1 {
+            // The last byte is kept for the line terminator
+            let _ = self.line.push(c);
+            self.echo(c);
+        }
+    }
+
+    /// Move the edited line to the readers
+    fn finish_line(&mut self) {
+        let fits = INPUT_SIZE - self.input.len() >= self.line.len() && !self.line_ends.is_full();
+        if fits {
+            for &byte in &self.line {
+                let _ = self.input.push_back(byte);
+            }
+            let _ = self.line_ends.push_back(self.line.len());
+        }
+        self.line.clear();
+    }
+
+    fn signal_char(&mut self, c: u8, signal: Signal) {
+        if !self.lflag(NOFLSH) {
+            self.flush_input();
+        }
+        self.echo(c);
+        self.signal_seq = self.signal_seq.wrapping_add(1);
+        if self.pgrp != 0 {
+            match signal {
+                Signal::SIGINT => signals::handle_keyboard_interrupt(self.pgrp),
+                _ => signals::kill_pgrp(self.pgrp, signal, 0),
+            }
+        }
+    }
+
+    /// Echo one input byte; control characters as ^X with ECHOCTL
+    fn echo(&self, c: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if self.lflag(ECHOCTL) && is_ctrl(c) {
+            self.output(&[b'^', c ^ 0x40]);
+        } else {
+            self.output(&[c]);
+        }
+    }
+
+    /// VERASE (last character) or VWERASE (last word)
+    fn erase(&mut self, word: bool) {
+        let erase_char = self.termios.c_cc[if word { VWERASE } else { VERASE }];
+        let mut seen_word = false;
+        while let Some(&c) = self.line.last() {
+            if word {
+                let space = c == b' ' || c == b'\t';
+                if space && seen_word {
+                    break;
+                }
+                seen_word |= !space;
+            }
+            self.line.pop();
+            self.echo_erase(c, erase_char);
+            if !word {
+                break;
+            }
+        }
+    }
+
+    fn echo_erase(&self, c: u8, erase_char: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if !self.lflag(ECHOE) {
+            self.echo(erase_char);
+            return;
+        }
+        let width = if self.lflag(ECHOCTL) && is_ctrl(c) { 2 } else { 1 };
+        for _ in 0..width {
+            self.output(b"\x08 \x08");
+        }
+    }
+
+    /// VKILL: throw the whole line away
+    fn kill_line(&mut self, c: u8) {
+        if self.lflag(ECHOKE) && self.lflag(ECHOE) {
+            let erase_char = self.termios.c_cc[VERASE];
+            while let Some(last) = self.line.pop() {
+                self.echo_erase(last, erase_char);
+            }
+            return;
+        }
+        self.line.clear();
+        self.echo(c);
+        if self.lflag(ECHOK) && self.lflag(ECHO) {
+            self.output(b"\n");
+        }
+    }
+
+    /// VREPRINT: show the line again, e.g. after other output got in the way
+    fn reprint(&self, c: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        self.echo(c);
+        self.output(b"\n");
+        for &byte in &self.line {
+            self.echo(byte);
+        }
+    }
+
+    /// Output with OPOST processing (ONLCR: \n -> \r\n)
+    fn output(&self, data: &[u8]) {
+        let onlcr = self.termios.c_oflag & (OPOST | ONLCR) == OPOST | ONLCR;
+        if !onlcr {
+            self.driver.write(data);
+            return;
+        }
+        let mut start = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            if byte == b'\n' {
+                self.driver.write(&data[start..i]);
+                self.driver.write(b"\r\n");
+                start = i + 1;
+            }
+        }
+        self.driver.write(&data[start..]);
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, i64> {
+        if self.hung_up {
+            return Err(-5); // EIO
+        }
+        self.output(data);
+        Ok(data.len())
+    }
+
+    /// Whatever a reader can take now, or None if it has to wait
+    fn take_input(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.lflag(ICANON) {
+            let len = self.line_ends.pop_front()?;
+            let count = len.min(buf.len());
+            for byte in buf.iter_mut().take(count) {
+                *byte = self.input.pop_front().unwrap_or(0);
+            }
+            if count < len {
+                // The rest of the line is for the next read
+                let _ = self.line_ends.push_front(len - count);
+            }
+            return Some(count);
+        }
+
+        let vmin = self.termios.c_cc[VMIN] as usize;
+        if self.input.len() < vmin.min(buf.len()) || (vmin > 0 && self.input.is_empty()) {
+            return None;
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            match self.input.pop_front() {
+                Some(byte) => buf[count] = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        Some(count)
+    }
+
+    /// Read a line (canonical mode) or what has arrived (raw mode), sleeping
+    /// until there is something. Ok(0) is end of file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i64> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Job control: only the foreground process group may read
+        let pgid = unsafe {
+            PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid()).map_or(0, |p| p.pgid)
+        };
+        if self.pgrp != 0 && pgid != 0 && pgid != self.pgrp {
+            signals::kill_pgrp(pgid, Signal::SIGTTIN, 0);
+            return Err(-4); // EINTR
+        }
+
+        let seq = self.signal_seq;
+        loop {
+            let daif = process::irq_save();
+            if self.hung_up && self.input.is_empty() {
+                process::irq_restore(daif);
+                return Ok(0);
+            }
+            if self.driver.needs_polling() {
+                while let Some(byte) = self.driver.poll() {
+                    self.receive(&[byte]);
+                }
+            }
+            if let Some(count) = self.take_input(buf) {
+                process::irq_restore(daif);
+                return Ok(count);
+            }
+            if self.signal_seq != seq {
+                // ^C and friends interrupt the read, like a signal would
+                process::irq_restore(daif);
+                return Err(-4); // EINTR
+            }
+            if self.driver.needs_polling() {
+                process::irq_restore(daif);
+                core::hint::spin_loop();
+                continue;
+            }
+            self.readers.sleep();
+            process::irq_restore(daif);
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// TCSETS, TCSETSW (after the output has drained) or TCSETSF (and discard input)
+    pub fn set_termios(&mut self, request: u32, new: &Termios) -> Result<(), i64> {
+        if request != TCSETS {
+            self.driver.wait_until_sent();
+        }
+        self.driver.set_termios(new)?;
+
+        let daif = process::irq_save();
+        if request == TCSETSF {
+            self.flush_input();
+        }
+        let was_canon = self.lflag(ICANON);
+        self.termios = *new;
+        match (was_canon, self.lflag(ICANON)) {
+            (true, false) => {
+                // The partial line becomes input as it is
+                for &byte in &self.line {
+                    let _ = self.input.push_back(byte);
+                }
+                self.line.clear();
+                self.line_ends.clear();
+            }
+            // What raw mode left behind counts as one line
+            (false, true) if !self.input.is_empty() => {
+                let _ = self.line_ends.push_back(self.input.len());
+            }
+            _ => {}
+        }
+        process::irq_restore(daif);
+        self.readers.wake_all();
+        Ok(())
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.input.clear();
+        self.line_ends.clear();
+        self.driver.flush_input();
+    }
+
+    /// TCFLSH
+    pub fn flush(&mut self, queue: u64) -> Result<(), i64> {
+        match queue {
+            TCIFLUSH => {
+                let daif = process::irq_save();
+                self.flush_input();
+                process::irq_restore(daif);
+            }
+            TCOFLUSH => self.driver.flush_output(),
+            TCIOFLUSH => {
+                self.flush(TCIFLUSH)?;
+                self.flush(TCOFLUSH)?;
+            }
+            _ => return Err(-22), // EINVAL
+        }
+        Ok(())
+    }
+
+    /// Foreground process group
+    pub fn pgrp(&self) -> u32 {
+        self.pgrp
+    }
+
+    /// TIOCSPGRP: the group has to exist in the terminal's session
+    pub fn set_pgrp(&mut self, pgrp: u32) -> Result<(), i64> {
+        let in_session = unsafe {
+            PROCESS_MANAGER.pgrp_members(pgrp).iter().any(|&pid| {
+                PROCESS_MANAGER.get_process(pid).is_some_and(|p| p.sid == self.session)
+            })
+        };
+        if !in_session {
+            return Err(-1); // EPERM
+        }
+        self.pgrp = pgrp;
+        Ok(())
+    }
+
+    /// Make this the controlling terminal of `session`, with `pgrp` in the foreground
+    pub fn attach(&mut self, session: u32, pgrp: u32) {
+        self.session = session;
+        self.pgrp = pgrp;
+        self.hung_up = false;
+    }
+
+    /// Session the tty controls, 0 if none
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn winsize(&self) -> Winsize {
+        self.winsize
+    }
+
+    /// TIOCSWINSZ: the foreground group hears about a change through SIGWINCH
+    pub fn set_winsize(&mut self, winsize: Winsize) {
+        if winsize != self.winsize {
+            self.winsize = winsize;
+            if self.pgrp != 0 {
+                signals::kill_pgrp(self.pgrp, Signal::SIGWINCH, 0);
+            }
+        }
+    }
+
+    /// No longer the controlling terminal of its session: SIGHUP and SIGCONT
+    /// to the foreground group (TIOCNOTTY by the session leader)
+    pub fn detach(&mut self) {
+        if self.pgrp != 0 {
+            signals::kill_pgrp(self.pgrp, Signal::SIGHUP, 0);
+            signals::kill_pgrp(self.pgrp, Signal::SIGCONT, 0);
+        }
+        self.session = 0;
+        self.pgrp = 0;
+    }
+
+    /// The terminal is gone: the session is detached, readers see end of
+    /// file and writers EIO
+    pub fn hangup(&mut self) {
+        let daif = process::irq_save();
+        self.hung_up = true;
+        process::irq_restore(daif);
+        self.detach();
+        self.readers.wake_all();
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up
+    }
+
+    /// Reset to a fresh tty for a new user (a reused pty)
+    pub fn reset(&mut self) {
+        self.termios = Termios::new();
+        self.line.clear();
+        self.input.clear();
+        self.line_ends.clear();
+        self.session = 0;
+        self.pgrp = 0;
+        self.hung_up = false;
+    }
+}
+
+fn is_ctrl(c: u8) -> bool {
+    (c < 0x20 && c != b'\n' && c != b'\t') || c == 0x7F
+}
+
+/// The console PL011 as a tty driver
+struct UartDriver;
+
+impl TtyDriver for UartDriver {
+    fn write(&self, data: &[u8]) {
+        UART.write(data);
+    }
+
+    fn set_termios(&self, termios: &Termios) -> Result<(), i64> {
+        let config = termios::cflag_to_config(termios.c_cflag)?;
+        if config != uart::config() {
+            uart::configure(&config).map_err(|_| -22i64)?; // EINVAL
+        }
+        Ok(())
+    }
+
+    fn wait_until_sent(&self) {
+        uart::flush();
+    }
+
+    fn flush_input(&self) {
+        uart::discard_input();
+    }
+
+    fn poll(&self) -> Option<u8> {
+        UART.read_char().map(|c| c as u8)
+    }
+
+    fn needs_polling(&self) -> bool {
+        !uart::interrupt_driven()
+    }
+}
+
+static UART_DRIVER: UartDriver = UartDriver;
+static mut CONSOLE: Tty = Tty::new("console", &UART_DRIVER);
+
+/// The console tty (stdin/stdout/stderr, /dev/uart0)
+pub fn console() -> &'static mut Tty {
+    unsafe { &mut *core::ptr::addr_of_mut!(CONSOLE) }
+}
+
+pub fn get(id: TtyId) -> Option<&'static mut Tty> {
+    match id {
+        TtyId::Console => Some(console()),
+        TtyId::Pty(index) => pty::slave(index),
+    }
+}
+
+/// Controlling terminal of the current process (the console if it has none)
+pub fn current() -> &'static mut Tty {
+    let ctty = unsafe {
+        PROCESS_MANAGER.get_process(PROCESS_MANAGER.current_pid()).and_then(|p| p.ctty)
+    };
+    ctty.and_then(get).unwrap_or_else(console)
+}
+
+/// Output of the shell and other kernel code running for a process: goes
+/// to its controlling terminal, through that terminal's output processing
+#[derive(Clone, Copy)]
+pub struct Stdout;
+
+impl Stdout {
+    pub fn write_str(&self, s: &str) {
+        let _ = current().write(s.as_bytes());
+    }
+
+    pub fn write_char(&self, c: char) {
+        let mut utf8 = [0u8; 4];
+        self.write_str(c.encode_utf8(&mut utf8));
+    }
+}
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Stdout::write_str(self, s);
+        Ok(())
+    }
+}
+
+pub static STDOUT: Stdout = Stdout;
+
+fn console_receive(data: &[u8]) {
+    console().receive(data);
+}
+
+/// Put the console tty on the UART; the boot thread's session (PID 1) owns it
+pub fn init() {
+    let console = console();
+    console.termios.c_cflag = termios::config_to_cflag(&uart::config(), console.termios.c_cflag);
+    console.attach(1, 1);
+    uart::set_rx_handler(console_receive);
+}
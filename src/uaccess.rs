@@ -0,0 +1,169 @@
+// Access to user memory from system calls
+//
+// System calls never dereference a user pointer themselves. The helpers
+// here check that the range is in user space and copy with the unprivileged
+// ldtrb/sttrb instructions, so the access is checked against EL0's
+// permissions and a user pointer into the kernel faults. A fault does not
+// panic the kernel: every instruction that may fault has an entry in the
+// exception fixup table (__ex_table), and the data abort handler resumes at
+// its fixup, which returns what was not copied. A write to a copy-on-write
+// page is resolved by the abort handler first, as for a user write.
+
+use core::arch::global_asm;
+use core::mem::MaybeUninit;
+
+use crate::mmu;
+
+pub const EFAULT: i64 = -14;
+
+global_asm!(
+    r#"
+    .section .text.uaccess, "ax"
+
+    // __arch_copy_from_user(to: kernel, from: user, n) -> bytes not copied
+    .global __arch_copy_from_user
+__arch_copy_from_user:
+    cbz     x2, 2f
+1:  ldtrb   w3, [x1]
+    strb    w3, [x0], #1
+    add     x1, x1, #1
+    subs    x2, x2, #1
+    b.ne    1b
+2:  mov     x0, x2
+    ret
+9:  mov     x0, x2
+    ret
+    .pushsection __ex_table, "a"
+    .balign 8
+    .quad   1b, 9b
+    .popsection
+
+    // __arch_copy_to_user(to: user, from: kernel, n) -> bytes not copied
+    .global __arch_copy_to_user
+__arch_copy_to_user:
+    cbz     x2, 2f
+1:  ldrb    w3, [x1], #1
+3:  sttrb   w3, [x0]
+    add     x0, x0, #1
+    subs    x2, x2, #1
+    b.ne    1b
+2:  mov     x0, x2
+    ret
+9:  mov     x0, x2
+    ret
+    .pushsection __ex_table, "a"
+    .balign 8
+    .quad   3b, 9b
+    .popsection
+
+    // __arch_strncpy_from_user(to: kernel, from: user, n) -> length of the
+    // string, n if there is no NUL in the first n bytes, -EFAULT on a fault
+    .global __arch_strncpy_from_user
+__arch_strncpy_from_user:
+    mov     x4, #0
+    cbz     x2, 2f
+1:  ldtrb   w3, [x1]
+    strb    w3, [x0, x4]
+    cbz     w3, 2f
+    add     x4, x4, #1
+    add     x1, x1, #1
+    cmp     x4, x2
+    b.ne    1b
+2:  mov     x0, x4
+    ret
+9:  mov     x0, #-14
+    ret
+    .pushsection __ex_table, "a"
+    .balign 8
+    .quad   1b, 9b
+    .popsection
+    "#
+);
+
+/// An instruction that may fault on user memory, and where to resume if it does
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: u64,
+    fixup: u64,
+}
+
+extern "C" {
+    fn __arch_copy_from_user(to: *mut u8, from: u64, n: usize) -> usize;
+    fn __arch_copy_to_user(to: u64, from: *const u8, n: usize) -> usize;
+    fn __arch_strncpy_from_user(to: *mut u8, from: u64, n: usize) -> i64;
+
+    // From the linker script
+    static __start___ex_table: ExceptionTableEntry;
+    static __stop___ex_table: ExceptionTableEntry;
+}
+
+/// Where to resume after a kernel fault at `pc`, if the instruction is a user access
+pub fn search_exception_table(pc: u64) -> Option<u64> {
+    let table = unsafe {
+        let start = core::ptr::addr_of!(__start___ex_table);
+        let end = core::ptr::addr_of!(__stop___ex_table);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    table.iter().find(|entry| entry.insn == pc).map(|entry| entry.fixup)
+}
+
+/// `size` bytes from `addr` are all in user space
+pub fn access_ok(addr: u64, size: usize) -> bool {
+    addr >= mmu::USER_SPACE_BASE
+        && addr.checked_add(size as u64).is_some_and(|end| end <= mmu::USER_SPACE_END)
+}
+
+/// Fill `dst` from user memory at `src`
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), i64> {
+    if !access_ok(src, dst.len()) {
+        return Err(EFAULT);
+    }
+    match unsafe { __arch_copy_from_user(dst.as_mut_ptr(), src, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+/// Copy `src` to user memory at `dst`
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), i64> {
+    if !access_ok(dst, src.len()) {
+        return Err(EFAULT);
+    }
+    match unsafe { __arch_copy_to_user(dst, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+/// Copy the NUL-terminated string at `src` into `dst`, at most `dst.len()`
+/// bytes. The length without the NUL; `dst.len()` if it did not fit.
+pub fn strncpy_from_user(dst: &mut [u8], src: u64) -> Result<usize, i64> {
+    if !access_ok(src, 1) {
+        return Err(EFAULT);
+    }
+    // Not past the end of user space
+    let limit = dst.len().min((mmu::USER_SPACE_END - src) as usize);
+    match unsafe { __arch_strncpy_from_user(dst.as_mut_ptr(), src, limit) } {
+        len if len < 0 => Err(EFAULT),
+        len if len as usize == limit && limit < dst.len() => Err(EFAULT), // Runs off user space
+        len => Ok(len as usize),
+    }
+}
+
+/// A value of type `T` from user memory
+pub fn read_user<T: Copy>(addr: u64) -> Result<T, i64> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    copy_from_user(bytes, addr)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Store a value of type `T` in user memory
+pub fn write_user<T: Copy>(addr: u64, value: T) -> Result<(), i64> {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    copy_to_user(addr, bytes)
+}
@@ -1,17 +1,419 @@
 // Pi5 UART Driver - Based on pi5_hack early_uart implementation
 // Reference: pi5_hack/baremetal/XX_early_uart
+//
+// `Uart::init` programs the baud rate divisors, frame format and flow control
+// from the UART reference clock (the device tree's `clocks`, or the BCM2712
+// default); `configure` changes them at run time. Without a known clock the
+// settings the firmware left behind (config.txt) are kept.
+//
+// Polled until `enable_interrupts` is called. From then on the interrupt
+// handler passes received bytes to the RX handler (the TTY layer), or keeps
+// them in a ring buffer when there is none; output goes through a TX ring
+// buffer that the handler feeds into the FIFO. A writer that finds the ring full
+// pushes the oldest bytes out by polling, so nothing is ever dropped.
 
 use core::{ptr, fmt};
 use core::fmt::Write;
 
+use crate::cmdline;
+use crate::interrupt;
+use crate::process;
+
 // BCM2712 UART register addresses - EXACT from pi5_hack early_uart
 const BCM2712_UART_BASE: u64 = 0x10_7d00_1000;
-const BCM2712_UART_DR: *mut u32 = BCM2712_UART_BASE as *mut u32;
-const BCM2712_UART_FLAG: *mut u32 = (BCM2712_UART_BASE + 0x18) as *mut u32;
+const BCM2712_UART_IRQ: u32 = 153;  // GIC SPI 121
+const BCM2712_UART_CLOCK: u32 = 9_216_000;  // clk_uart in bcm2712.dtsi
+const UART_DR: u64 = 0x00;    // Data register
+const UART_FLAG: u64 = 0x18;  // Flag register
+const UART_IBRD: u64 = 0x24;  // Integer baud rate divisor
+const UART_FBRD: u64 = 0x28;  // Fractional baud rate divisor (1/64ths)
+const UART_LCRH: u64 = 0x2C;  // Line control
+const UART_CR: u64 = 0x30;    // Control
+const UART_IFLS: u64 = 0x34;  // Interrupt FIFO level select
+const UART_IMSC: u64 = 0x38;  // Interrupt mask set/clear
+const UART_ICR: u64 = 0x44;   // Interrupt clear
+
+// Interrupt bits (IMSC, MIS, ICR)
+const UART_INT_RX: u32 = 1 << 4;   // RX FIFO at its trigger level
+const UART_INT_TX: u32 = 1 << 5;   // TX FIFO at its trigger level
+const UART_INT_RT: u32 = 1 << 6;   // RX timeout: data left below the trigger level
+const UART_INT_ERRORS: u32 = 0xF << 7;  // Framing, parity, break, overrun
+
+// Line control bits
+const UART_LCRH_PEN: u32 = 1 << 1;    // Parity enable
+const UART_LCRH_EPS: u32 = 1 << 2;    // Even parity
+const UART_LCRH_STP2: u32 = 1 << 3;   // Two stop bits
+const UART_LCRH_FEN: u32 = 1 << 4;    // FIFOs enabled
+const UART_LCRH_WLEN_SHIFT: u32 = 5;  // Word length - 5
+
+// Control bits
+const UART_CR_UARTEN: u32 = 1 << 0;
+const UART_CR_TXE: u32 = 1 << 8;
+const UART_CR_RXE: u32 = 1 << 9;
+const UART_CR_RTS: u32 = 1 << 11;
+const UART_CR_RTSEN: u32 = 1 << 14;   // RTS follows the RX FIFO
+const UART_CR_CTSEN: u32 = 1 << 15;   // Transmit only while CTS is asserted
+
+// IFLS: both interrupts when the FIFO crosses half full
+const UART_IFLS_HALF: u32 = (2 << 3) | 2;
+
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 4096;
+
+// PL011 base in use; replaced by the console found in the device tree
+static mut UART_BASE: u64 = BCM2712_UART_BASE;
+
+// Its interrupt and reference clock (Hz), if known
+static mut UART_IRQ: Option<u32> = Some(BCM2712_UART_IRQ);
+static mut UART_CLOCK: Option<u32> = Some(BCM2712_UART_CLOCK);
+
+/// Point the console at another PL011 (e.g. the one named by the DTB);
+/// its interrupt and clock are unknown until `set_irq` / `set_clock`
+pub fn set_base(base: u64) {
+    unsafe {
+        UART_BASE = base;
+        UART_IRQ = None;
+        UART_CLOCK = None;
+    }
+}
+
+/// Interrupt of the console PL011 (must be called before enable_interrupts)
+pub fn set_irq(irq: u32) {
+    unsafe { UART_IRQ = Some(irq); }
+}
+
+/// Reference clock of the console PL011 (must be called before `Uart::init`)
+pub fn set_clock(hz: u32) {
+    unsafe { UART_CLOCK = Some(hz); }
+}
+
+pub fn clock() -> Option<u32> {
+    unsafe { UART_CLOCK }
+}
+
+pub fn base() -> u64 {
+    unsafe { UART_BASE }
+}
+
+fn uart_reg(offset: u64) -> *mut u32 {
+    (base() + offset) as *mut u32
+}
+
+fn read_reg(offset: u64) -> u32 {
+    unsafe { ptr::read_volatile(uart_reg(offset)) }
+}
+
+fn write_reg(offset: u64, value: u32) {
+    unsafe { ptr::write_volatile(uart_reg(offset), value) }
+}
 
 // Flag Register bits - EXACT from pi5_hack early_uart
 const UART_FR_RXFE: u32 = 1 << 4;  // RX FIFO empty
 const UART_FR_TXFF: u32 = 1 << 5;  // TX FIFO full
+const UART_FR_BUSY: u32 = 1 << 3;  // Still transmitting
+
+/// Fixed-size byte FIFO shared with the interrupt handler (accessed with IRQs masked)
+struct RingBuffer<const N: usize> {
+    data: [u8; N],
+    head: usize,   // Next byte to read
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self { data: [0; N], head: 0, len: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.data[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// State of the interrupt-driven mode
+struct UartBuffers {
+    enabled: bool,
+    rx: RingBuffer<RX_BUFFER_SIZE>,
+    tx: RingBuffer<TX_BUFFER_SIZE>,
+    rx_dropped: u64,   // Bytes lost because the RX ring was full
+}
+
+static mut BUFFERS: UartBuffers = UartBuffers {
+    enabled: false,
+    rx: RingBuffer::new(),
+    tx: RingBuffer::new(),
+    rx_dropped: 0,
+};
+
+fn buffers() -> &'static mut UartBuffers {
+    unsafe { &mut *ptr::addr_of_mut!(BUFFERS) }
+}
+
+fn interrupts_enabled() -> bool {
+    buffers().enabled
+}
+
+/// Whether received bytes arrive by interrupt; otherwise readers have to poll
+pub fn interrupt_driven() -> bool {
+    interrupts_enabled()
+}
+
+/// Move bytes from the TX ring into the FIFO; the TX interrupt stays on while bytes are left.
+/// Called with IRQs masked.
+fn start_tx(bufs: &mut UartBuffers) {
+    while read_reg(UART_FLAG) & UART_FR_TXFF == 0 {
+        match bufs.tx.pop() {
+            Some(byte) => write_reg(UART_DR, byte as u32),
+            None => break,
+        }
+    }
+    let imsc = read_reg(UART_IMSC);
+    if bufs.tx.is_empty() {
+        write_reg(UART_IMSC, imsc & !UART_INT_TX);
+    } else {
+        write_reg(UART_IMSC, imsc | UART_INT_TX);
+    }
+}
+
+/// Queue one byte for output. Called with IRQs masked.
+fn queue_tx(bufs: &mut UartBuffers, byte: u8) {
+    while !bufs.tx.push(byte) {
+        // Ring full: make room by pushing the oldest byte out ourselves
+        while read_reg(UART_FLAG) & UART_FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        if let Some(oldest) = bufs.tx.pop() {
+            write_reg(UART_DR, oldest as u32);
+        }
+    }
+}
+
+// Consumer of received bytes (the TTY layer); without one they go to the RX ring
+static mut RX_HANDLER: Option<fn(&[u8])> = None;
+
+/// Hand received bytes to `handler` (called in interrupt context, or by
+/// whoever polls while the UART is not interrupt driven)
+pub fn set_rx_handler(handler: fn(&[u8])) {
+    unsafe { RX_HANDLER = Some(handler); }
+}
+
+/// PL011 interrupt: drain the RX FIFO, refill the TX FIFO
+fn handle_uart_irq(_irq: u32) {
+    let handler = unsafe { RX_HANDLER };
+
+    loop {
+        let mut chunk = [0u8; 32];
+        let mut count = 0;
+        while count < chunk.len() && read_reg(UART_FLAG) & UART_FR_RXFE == 0 {
+            chunk[count] = read_reg(UART_DR) as u8;
+            count += 1;
+        }
+        if count == 0 {
+            break;
+        }
+        match handler {
+            Some(handler) => handler(&chunk[..count]),
+            None => {
+                let bufs = buffers();
+                for &byte in &chunk[..count] {
+                    if !bufs.rx.push(byte) {
+                        bufs.rx_dropped += 1;
+                    }
+                }
+            }
+        }
+    }
+    write_reg(UART_ICR, UART_INT_RX | UART_INT_RT | UART_INT_ERRORS);
+
+    start_tx(buffers());
+}
+
+/// Switch from polling to the RX/TX interrupts and ring buffers
+pub fn enable_interrupts() -> Result<(), &'static str> {
+    let irq = unsafe { UART_IRQ }.ok_or("no interrupt known for the console UART")?;
+    interrupt::request_irq(irq, handle_uart_irq, interrupt::IRQF_TRIGGER_HIGH, "uart-pl011")?;
+
+    let daif = process::irq_save();
+    let bufs = buffers();
+    bufs.enabled = true;
+    write_reg(UART_IFLS, UART_IFLS_HALF);
+    write_reg(UART_ICR, 0x7FF);
+    write_reg(UART_IMSC, UART_INT_RX | UART_INT_RT);
+    // Bytes that arrived before the interrupt was on
+    handle_uart_irq(irq);
+    process::irq_restore(daif);
+    Ok(())
+}
+
+/// Back to polled output after flushing what is queued (for panics)
+pub fn disable_interrupts() {
+    let bufs = buffers();
+    if !bufs.enabled {
+        return;
+    }
+    write_reg(UART_IMSC, 0);
+    bufs.enabled = false;
+    while let Some(byte) = bufs.tx.pop() {
+        while read_reg(UART_FLAG) & UART_FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        write_reg(UART_DR, byte as u32);
+    }
+}
+
+/// Wait until everything queued has left the transmitter
+pub fn flush() {
+    loop {
+        let daif = process::irq_save();
+        let bufs = buffers();
+        if bufs.enabled {
+            start_tx(bufs);
+        }
+        let done = !bufs.enabled || bufs.tx.is_empty();
+        process::irq_restore(daif);
+        if done {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    while read_reg(UART_FLAG) & UART_FR_BUSY != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Bytes dropped because nobody read them fast enough
+pub fn rx_dropped() -> u64 {
+    buffers().rx_dropped
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Line settings of the console UART
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UartConfig {
+    pub baud: u32,
+    pub data_bits: u8,   // 5-8
+    pub parity: Parity,
+    pub stop_bits: u8,   // 1 or 2
+    pub rtscts: bool,    // Hardware flow control
+}
+
+impl UartConfig {
+    /// 115200 8N1, what config.txt sets up
+    pub const DEFAULT: Self = Self {
+        baud: 115200,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        rtscts: false,
+    };
+}
+
+static mut CONFIG: UartConfig = UartConfig::DEFAULT;
+
+/// Current line settings
+pub fn config() -> UartConfig {
+    unsafe { CONFIG }
+}
+
+/// Baud rate divisor in 64ths: clock / (16 * baud), rounded to nearest
+fn baud_divisor(clock: u32, baud: u32) -> Result<u32, &'static str> {
+    if baud == 0 {
+        return Err("invalid baud rate");
+    }
+    let divisor = (clock as u64 * 8 / baud as u64).div_ceil(2);
+    // IBRD is 16 bits and may not be 0; IBRD 0xFFFF only with FBRD 0
+    if !(64..=0xFFFF << 6).contains(&divisor) {
+        return Err("baud rate out of range for the UART clock");
+    }
+    Ok(divisor as u32)
+}
+
+/// Program baud rate, frame format and flow control. Output still in the
+/// TX ring is sent with the new settings; bytes in the RX FIFO are lost.
+pub fn configure(new: &UartConfig) -> Result<(), &'static str> {
+    let clock = clock().ok_or("UART reference clock unknown")?;
+    let divisor = baud_divisor(clock, new.baud)?;
+    if !(5..=8).contains(&new.data_bits) {
+        return Err("invalid number of data bits");
+    }
+    if !(1..=2).contains(&new.stop_bits) {
+        return Err("invalid number of stop bits");
+    }
+
+    let mut lcrh = UART_LCRH_FEN | ((new.data_bits as u32 - 5) << UART_LCRH_WLEN_SHIFT);
+    match new.parity {
+        Parity::None => {}
+        Parity::Even => lcrh |= UART_LCRH_PEN | UART_LCRH_EPS,
+        Parity::Odd => lcrh |= UART_LCRH_PEN,
+    }
+    if new.stop_bits == 2 {
+        lcrh |= UART_LCRH_STP2;
+    }
+    let mut cr = UART_CR_UARTEN | UART_CR_TXE | UART_CR_RXE | UART_CR_RTS;
+    if new.rtscts {
+        cr |= UART_CR_RTSEN | UART_CR_CTSEN;
+    }
+
+    // PL011 TRM: let the TX FIFO drain, disable, flush the FIFOs by clearing
+    // FEN, then reprogram. LCRH must be written after IBRD/FBRD, it latches
+    // all three.
+    let daif = process::irq_save();
+    while read_reg(UART_FLAG) & UART_FR_BUSY != 0 {
+        core::hint::spin_loop();
+    }
+    write_reg(UART_CR, 0);
+    write_reg(UART_LCRH, 0);
+    write_reg(UART_IBRD, divisor >> 6);
+    write_reg(UART_FBRD, divisor & 0x3F);
+    write_reg(UART_LCRH, lcrh);
+    write_reg(UART_CR, cr);
+    unsafe { CONFIG = *new };
+    let bufs = buffers();
+    if bufs.enabled {
+        start_tx(bufs);
+    }
+    process::irq_restore(daif);
+    Ok(())
+}
+
+/// Throw away received bytes nobody has read yet
+pub fn discard_input() {
+    let daif = process::irq_save();
+    let bufs = buffers();
+    while bufs.rx.pop().is_some() {}
+    while read_reg(UART_FLAG) & UART_FR_RXFE == 0 {
+        read_reg(UART_DR);
+    }
+    process::irq_restore(daif);
+}
 
 // Pi5 UART struct - super simple version based on early_uart
 #[derive(Copy, Clone)]
@@ -23,10 +425,18 @@ impl Uart {
         Self
     }
     
-    /// Pi5 UART initialization - very basic from early_uart
+    /// Program the console for console=<dev>,<baud> (default 115200) 8N1.
+    /// Until this runs, output relies on the bootloader's setup.
     pub unsafe fn init(&self) -> Result<(), &'static str> {
-        // No initialization needed for early UART - already configured by bootloader
-        Ok(())
+        if clock().is_none() {
+            // Nothing to compute the divisors from; keep what the firmware set
+            return Ok(());
+        }
+        let mut console = UartConfig::DEFAULT;
+        if let Some(baud) = cmdline::params().console_baud {
+            console.baud = baud;
+        }
+        configure(&console)
     }
     
     // Write a character - direct from early_uart
@@ -41,14 +451,20 @@ impl Uart {
     
     // Write a raw character without CRLF conversion
     fn write_char_raw(&self, c: char) {
+        if interrupts_enabled() {
+            let mut utf8 = [0u8; 4];
+            self.write(c.encode_utf8(&mut utf8).as_bytes());
+            return;
+        }
+
         unsafe {
             // Wait for TX FIFO not full
-            while ptr::read_volatile(BCM2712_UART_FLAG) & UART_FR_TXFF != 0 {
+            while ptr::read_volatile(uart_reg(UART_FLAG)) & UART_FR_TXFF != 0 {
                 core::arch::asm!("nop");
             }
             
             // Write character
-            ptr::write_volatile(BCM2712_UART_DR, c as u32);
+            ptr::write_volatile(uart_reg(UART_DR), c as u32);
         }
     }
     
@@ -61,14 +477,21 @@ impl Uart {
     
     // Read a character
     pub fn read_char(&self) -> Option<char> {
+        if interrupts_enabled() {
+            let daif = process::irq_save();
+            let byte = buffers().rx.pop();
+            process::irq_restore(daif);
+            return byte.map(|b| b as char);
+        }
+
         unsafe {
             // Check if RX FIFO is empty
-            if ptr::read_volatile(BCM2712_UART_FLAG) & UART_FR_RXFE != 0 {
+            if ptr::read_volatile(uart_reg(UART_FLAG)) & UART_FR_RXFE != 0 {
                 return None; // No data available
             }
             
             // Read character
-            let data = ptr::read_volatile(BCM2712_UART_DR);
+            let data = ptr::read_volatile(uart_reg(UART_DR));
             Some((data & 0xFF) as u8 as char)
         }
     }
@@ -84,30 +507,54 @@ impl Uart {
         None
     }
     
-    // Write byte array
+    /// Write byte array; with interrupts on the bytes are queued, never dropped
     pub fn write(&self, data: &[u8]) -> usize {
-        for (i, &byte) in data.iter().enumerate() {
-            unsafe {
-                // Check if TX FIFO is full
-                if ptr::read_volatile(BCM2712_UART_FLAG) & UART_FR_TXFF != 0 {
-                    return i; // Return how many bytes were written
-                }
-                ptr::write_volatile(BCM2712_UART_DR, byte as u32);
+        if interrupts_enabled() {
+            let daif = process::irq_save();
+            let bufs = buffers();
+            for &byte in data {
+                queue_tx(bufs, byte);
+            }
+            start_tx(bufs);
+            process::irq_restore(daif);
+            return data.len();
+        }
+
+        for &byte in data {
+            // Wait for room in the TX FIFO
+            while read_reg(UART_FLAG) & UART_FR_TXFF != 0 {
+                core::hint::spin_loop();
             }
+            write_reg(UART_DR, byte as u32);
         }
         data.len()
     }
 
     
-    // Read byte array
+    /// Read byte array: whatever is available, without waiting
     pub fn read(&self, data: &mut [u8]) -> usize {
+        if interrupts_enabled() {
+            let daif = process::irq_save();
+            let bufs = buffers();
+            let mut count = 0;
+            while count < data.len() {
+                match bufs.rx.pop() {
+                    Some(byte) => data[count] = byte,
+                    None => break,
+                }
+                count += 1;
+            }
+            process::irq_restore(daif);
+            return count;
+        }
+
         for (i, byte) in data.iter_mut().enumerate() {
             unsafe {
                 // Check if RX FIFO is empty
-                if ptr::read_volatile(BCM2712_UART_FLAG) & UART_FR_RXFE != 0 {
+                if ptr::read_volatile(uart_reg(UART_FLAG)) & UART_FR_RXFE != 0 {
                     return i; // Return how many bytes were read
                 }
-                *byte = ptr::read_volatile(BCM2712_UART_DR) as u8;
+                *byte = ptr::read_volatile(uart_reg(UART_DR)) as u8;
             }
         }
         data.len()
@@ -1,11 +1,11 @@
 // User and Group Management for UNIX Compatibility
 // POSIX user/group system implementation
 
-use crate::uart::UART;
-use heapless::{String, Vec};
+use crate::info;
+use alloc::string::String;
+use alloc::vec::Vec;
 
-const MAX_USERS: usize = 32;
-const MAX_GROUPS: usize = 16;
+// Field length limits; the number of users and groups is unbounded
 const MAX_USERNAME: usize = 32;
 const MAX_GROUPNAME: usize = 32;
 const MAX_PASSWORD_HASH: usize = 64;
@@ -18,11 +18,11 @@ const MAX_GECOS: usize = 128;
 pub struct User {
     pub uid: u32,
     pub gid: u32,               // Primary group ID
-    pub username: String<MAX_USERNAME>,
-    pub password_hash: String<MAX_PASSWORD_HASH>,
-    pub gecos: String<MAX_GECOS>, // Full name, office, phone, etc.
-    pub home_dir: String<MAX_HOME_PATH>,
-    pub shell: String<MAX_SHELL_PATH>,
+    pub username: String,
+    pub password_hash: String,
+    pub gecos: String, // Full name, office, phone, etc.
+    pub home_dir: String,
+    pub shell: String,
     pub is_active: bool,
 }
 
@@ -52,29 +52,14 @@ impl User {
             return Err("GECOS field too long");
         }
         
-        let mut user_name = String::new();
-        let _ = user_name.push_str(username);
-        
-        let mut pass_hash = String::new();
-        let _ = pass_hash.push_str(password_hash);
-        
-        let mut user_gecos = String::new();
-        let _ = user_gecos.push_str(gecos);
-        
-        let mut user_home = String::new();
-        let _ = user_home.push_str(home_dir);
-        
-        let mut user_shell = String::new();
-        let _ = user_shell.push_str(shell);
-        
         Ok(Self {
             uid,
             gid,
-            username: user_name,
-            password_hash: pass_hash,
-            gecos: user_gecos,
-            home_dir: user_home,
-            shell: user_shell,
+            username: String::from(username),
+            password_hash: String::from(password_hash),
+            gecos: String::from(gecos),
+            home_dir: String::from(home_dir),
+            shell: String::from(shell),
             is_active: true,
         })
     }
@@ -100,9 +85,9 @@ impl User {
 #[derive(Debug, Clone)]
 pub struct Group {
     pub gid: u32,
-    pub groupname: String<MAX_GROUPNAME>,
-    pub password_hash: String<MAX_PASSWORD_HASH>,
-    pub members: Vec<u32, MAX_USERS>, // UIDs of group members
+    pub groupname: String,
+    pub password_hash: String,
+    pub members: Vec<u32>, // UIDs of group members
 }
 
 impl Group {
@@ -114,16 +99,10 @@ impl Group {
             return Err("Password hash too long");
         }
         
-        let mut group_name = String::new();
-        let _ = group_name.push_str(groupname);
-        
-        let mut pass_hash = String::new();
-        let _ = pass_hash.push_str(password_hash);
-        
         Ok(Self {
             gid,
-            groupname: group_name,
-            password_hash: pass_hash,
+            groupname: String::from(groupname),
+            password_hash: String::from(password_hash),
             members: Vec::new(),
         })
     }
@@ -133,11 +112,7 @@ impl Group {
             return Ok(()); // Already a member
         }
         
-        if self.members.is_full() {
-            return Err("Group is full");
-        }
-        
-        let _ = self.members.push(uid);
+        self.members.push(uid);
         Ok(())
     }
     
@@ -157,8 +132,8 @@ impl Group {
 
 // User and Group Manager
 pub struct UserManager {
-    users: Vec<User, MAX_USERS>,
-    groups: Vec<Group, MAX_GROUPS>,
+    users: Vec<User>,
+    groups: Vec<Group>,
     next_uid: u32,
     next_gid: u32,
     current_uid: u32,
@@ -188,19 +163,19 @@ impl UserManager {
             "/root",
             "/bin/sh",
         )?;
-        let _ = self.users.push(root_user);
+        self.users.push(root_user);
         
         // Create root group (GID 0)
         let root_group = Group::new(0, "root", "")?;
-        let _ = self.groups.push(root_group);
+        self.groups.push(root_group);
         
         // Create wheel group (GID 1) for sudo-like functionality
         let wheel_group = Group::new(1, "wheel", "")?;
-        let _ = self.groups.push(wheel_group);
+        self.groups.push(wheel_group);
         
         // Create users group (GID 100)
         let users_group = Group::new(100, "users", "")?;
-        let _ = self.groups.push(users_group);
+        self.groups.push(users_group);
         
         // Create nobody user (UID 65534)
         let nobody_user = User::new(
@@ -212,13 +187,13 @@ impl UserManager {
             "/",
             "/bin/false",
         )?;
-        let _ = self.users.push(nobody_user);
+        self.users.push(nobody_user);
         
         // Create nobody group (GID 65534)
         let nobody_group = Group::new(65534, "nobody", "")?;
-        let _ = self.groups.push(nobody_group);
+        self.groups.push(nobody_group);
         
-        UART.write_str("System users and groups initialized\n");
+        info!("System users and groups initialized");
         Ok(())
     }
     
@@ -237,30 +212,21 @@ impl UserManager {
             }
         }
         
-        if self.users.is_full() {
-            return Err("Too many users");
-        }
-        
         let uid = self.next_uid;
         self.next_uid += 1;
         
-        let mut password_hash: String<64> = String::new();
-        let _ = password_hash.push_str("plain:");
-        let _ = password_hash.push_str(password);
+        let mut password_hash = String::from("plain:");
+        password_hash.push_str(password);
         let user = User::new(uid, 100, username, &password_hash, gecos, home_dir, shell)?;
         
-        let _ = self.users.push(user);
+        self.users.push(user);
         
         // Add user to default users group
         if let Some(users_group) = self.get_group_mut(100) {
             let _ = users_group.add_member(uid);
         }
         
-        UART.write_str("Created user ");
-        UART.write_str(username);
-        UART.write_str(" with UID ");
-        UART.put_hex(uid);
-        UART.write_str("\n");
+        info!("Created user {} with UID {}", username, uid);
         
         Ok(uid)
     }
@@ -273,21 +239,13 @@ impl UserManager {
             }
         }
         
-        if self.groups.is_full() {
-            return Err("Too many groups");
-        }
-        
         let gid = self.next_gid;
         self.next_gid += 1;
         
         let group = Group::new(gid, groupname, "")?;
-        let _ = self.groups.push(group);
+        self.groups.push(group);
         
-        UART.write_str("Created group ");
-        UART.write_str(groupname);
-        UART.write_str(" with GID ");
-        UART.put_hex(gid);
-        UART.write_str("\n");
+        info!("Created group {} with GID {}", groupname, gid);
         
         Ok(gid)
     }
@@ -299,9 +257,7 @@ impl UserManager {
                     self.current_uid = user.uid;
                     self.current_gid = user.gid;
                     
-                    UART.write_str("User ");
-                    UART.write_str(username);
-                    UART.write_str(" authenticated successfully\n");
+                    info!("User {} authenticated successfully", username);
                     
                     return Ok(user.uid);
                 } else {
@@ -367,20 +323,18 @@ impl UserManager {
         }
     }
     
-    pub fn get_user_groups(&self, uid: u32) -> Vec<u32, MAX_GROUPS> {
+    pub fn get_user_groups(&self, uid: u32) -> Vec<u32> {
         let mut groups = Vec::new();
         
         // Add primary group
         if let Some(user) = self.get_user(uid) {
-            let _ = groups.push(user.gid);
+            groups.push(user.gid);
         }
         
         // Add supplementary groups
         for group in &self.groups {
             if group.is_member(uid) && !groups.contains(&group.gid) {
-                if !groups.is_full() {
-                    let _ = groups.push(group.gid);
-                }
+                groups.push(group.gid);
             }
         }
         
@@ -420,11 +374,7 @@ impl UserManager {
         self.current_uid = user_uid;
         self.current_gid = user_gid;
         
-        UART.write_str("Switched to user ");
-        UART.write_str(username.as_str());
-        UART.write_str(" (UID ");
-        UART.put_hex(target_uid);
-        UART.write_str(")\n");
+        info!("Switched to user {} (UID {})", username.as_str(), target_uid);
         
         Ok(())
     }
@@ -461,11 +411,7 @@ impl UserManager {
                 let username = user.username.clone();
                 self.users.remove(i);
                 
-                UART.write_str("Deleted user ");
-                UART.write_str(username.as_str());
-                UART.write_str(" (UID ");
-                UART.put_hex(uid);
-                UART.write_str(")\n");
+                info!("Deleted user {} (UID {})", username.as_str(), uid);
                 
                 return Ok(());
             }
@@ -494,7 +440,7 @@ pub fn init_users() -> Result<(), &'static str> {
         GLOBAL_USER_MANAGER = UserManager::new();
         GLOBAL_USER_MANAGER.init_system_users()?;
     }
-    UART.write_str("User management system initialized\n");
+    info!("User management system initialized");
     Ok(())
 }
 
@@ -524,7 +470,7 @@ pub fn is_root() -> bool {
     unsafe { GLOBAL_USER_MANAGER.is_root() }
 }
 
-pub fn get_user_info(uid: u32) -> Option<(String<MAX_USERNAME>, u32, String<MAX_HOME_PATH>)> {
+pub fn get_user_info(uid: u32) -> Option<(String, u32, String)> {
     unsafe {
         if let Some(user) = GLOBAL_USER_MANAGER.get_user(uid) {
             Some((user.username.clone(), user.gid, user.home_dir.clone()))
@@ -552,29 +498,25 @@ pub fn add_user_to_group(uid: u32, gid: u32) -> Result<(), &'static str> {
     unsafe { GLOBAL_USER_MANAGER.add_user_to_group(uid, gid) }
 }
 
-pub fn get_user_groups(uid: u32) -> Vec<u32, MAX_GROUPS> {
+pub fn get_user_groups(uid: u32) -> Vec<u32> {
     unsafe { GLOBAL_USER_MANAGER.get_user_groups(uid) }
 }
 
-pub fn list_all_users() -> Vec<(u32, String<MAX_USERNAME>), MAX_USERS> {
+pub fn list_all_users() -> Vec<(u32, String)> {
     let mut result = Vec::new();
     unsafe {
         for user in GLOBAL_USER_MANAGER.list_users() {
-            if !result.is_full() {
-                let _ = result.push((user.uid, user.username.clone()));
-            }
+            result.push((user.uid, user.username.clone()));
         }
     }
     result
 }
 
-pub fn list_all_groups() -> Vec<(u32, String<MAX_GROUPNAME>), MAX_GROUPS> {
+pub fn list_all_groups() -> Vec<(u32, String)> {
     let mut result = Vec::new();
     unsafe {
         for group in GLOBAL_USER_MANAGER.list_groups() {
-            if !result.is_full() {
-                let _ = result.push((group.gid, group.groupname.clone()));
-            }
+            result.push((group.gid, group.groupname.clone()));
         }
     }
     result